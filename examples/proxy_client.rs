@@ -0,0 +1,46 @@
+// ============================================================================
+// Proxy Client Example
+// ============================================================================
+//
+// Calls a running proxy through the typed client in `client_sdk`.
+// Start the proxy without any external services first:
+//
+//   cargo run --features mock
+//   cargo run --example proxy_client --features client-sdk
+//
+// ============================================================================
+
+use llm_cache_proxy::client_sdk::ProxyClient;
+use llm_cache_proxy::models::{LLMRequest, Message};
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+
+    let base_url = std::env::var("PROXY_URL").unwrap_or_else(|_| "http://localhost:3000".to_string());
+    let client = ProxyClient::new(&base_url, "example-key").cache_ttl(600);
+
+    let request = LLMRequest {
+        messages: vec![Message {
+            role: "user".to_string(),
+            content: "What is the capital of France?".into(),
+            ..Default::default()
+        }],
+        model: "llama-3.3-70b-versatile".to_string(),
+        temperature: Some(0.0),
+        max_tokens: None,
+        ..Default::default()
+    };
+
+    // first call goes upstream, the identical second one should be a cache hit
+    for attempt in 1..=2 {
+        let result = client.chat(&request).await?;
+        println!("call {}: tier={:?} age={:?}", attempt, result.cache.tier, result.cache.age_secs);
+    }
+
+    // bypass the cache for one call
+    let fresh = client.clone().bypass_cache(true).chat(&request).await?;
+    println!("bypass: tier={:?} -> {}", fresh.cache.tier, fresh.response.choices[0].message.content.as_text());
+
+    Ok(())
+
+}
@@ -35,7 +35,7 @@ impl Cache {
 
         // create instance of CacheEntry and set fields values of fn set arguments
         let cache_entry = CacheEntry {
-            value: value,
+            value,
             inserted_at: Instant::now(), // time at insertion using Instant
             ttl: Duration::new(ttl_seconds, 0) // specified time to live using Duration
         }; 
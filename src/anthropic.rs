@@ -0,0 +1,216 @@
+// Translation between OpenAI chat completions and Anthropic's Messages API,
+// so `anthropic/` models go through the same cache tiers and are cached and
+// answered in the OpenAI format like every other upstream.
+
+use chrono::Utc;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use crate::client::LLMError;
+use crate::models::{Choice, ContentPart, LLMRequest, LLMResponse, Message, MessageContent, Usage};
+
+pub const ANTHROPIC_VERSION: &str = "2023-06-01";
+
+// the Messages API requires max_tokens; used when the client sent none
+const DEFAULT_MAX_TOKENS: u32 = 1024;
+
+/// The Messages API body for `request`. System messages move to the
+/// top-level `system` prompt, joined in order
+pub fn messages_request(request: &LLMRequest) -> Value {
+
+    let system: Vec<String> = request.messages.iter()
+        .filter(|message| message.role == "system")
+        .map(|message| message.content.as_text())
+        .collect();
+    let messages: Vec<Value> = request.messages.iter()
+        .filter(|message| message.role != "system")
+        .map(|message| json!({"role": message.role, "content": content_blocks(&message.content)}))
+        .collect();
+
+    let mut body = json!({
+        "model": request.model,
+        "max_tokens": request.max_tokens.unwrap_or(DEFAULT_MAX_TOKENS),
+        "messages": messages
+    });
+    if !system.is_empty() {
+        body["system"] = json!(system.join("\n\n"));
+    }
+    if let Some(temperature) = request.temperature {
+        body["temperature"] = json!(temperature);
+    }
+    if let Some(top_p) = request.top_p {
+        body["top_p"] = json!(top_p);
+    }
+    // Anthropic only takes a list
+    match &request.stop {
+        Some(Value::String(stop)) => body["stop_sequences"] = json!([stop]),
+        Some(stop @ Value::Array(_)) => body["stop_sequences"] = stop.clone(),
+        _ => {}
+    }
+    body
+
+}
+
+// plain text stays a string; parts become text and image blocks, with
+// base64 data URLs split into media type and data
+fn content_blocks(content: &MessageContent) -> Value {
+
+    let MessageContent::Parts(parts) = content else {
+        return json!(content.as_text());
+    };
+
+    let blocks: Vec<Value> = parts.iter()
+        .map(|part| match part {
+            ContentPart::Text { text } => json!({"type": "text", "text": text}),
+            ContentPart::ImageUrl { image_url } => match image_url.url.strip_prefix("data:").and_then(|data| data.split_once(";base64,")) {
+                Some((media_type, data)) => json!({"type": "image", "source": {"type": "base64", "media_type": media_type, "data": data}}),
+                None => json!({"type": "image", "source": {"type": "url", "url": image_url.url}})
+            }
+        })
+        .collect();
+    json!(blocks)
+
+}
+
+#[derive(Deserialize)]
+struct MessagesResponse {
+    id: String,
+    model: String,
+    content: Vec<ContentBlock>,
+    #[serde(default)]
+    stop_reason: Option<String>,
+    usage: MessagesUsage
+}
+
+#[derive(Deserialize)]
+struct ContentBlock {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    text: String
+}
+
+#[derive(Deserialize)]
+struct MessagesUsage {
+    input_tokens: u32,
+    output_tokens: u32
+}
+
+/// Deserializes a Messages API response into a `chat.completion` with one
+/// choice holding the text blocks. Errors name the offending field like
+/// `parse_llm_response`
+pub fn parse_messages_response(body: &[u8]) -> Result<LLMResponse, LLMError> {
+
+    let deserializer = &mut serde_json::Deserializer::from_slice(body);
+    let response: MessagesResponse = serde_path_to_error::deserialize(deserializer).map_err(|e| LLMError::InvalidResponse {
+        path: e.path().to_string(),
+        message: e.inner().to_string()
+    })?;
+
+    let content = response.content.iter()
+        .filter(|block| block.kind == "text")
+        .map(|block| block.text.as_str())
+        .collect::<String>();
+
+    Ok(LLMResponse {
+        id: response.id,
+        object: "chat.completion".to_string(),
+        created: Utc::now().timestamp(),
+        model: response.model,
+        choices: vec![Choice {
+            message: Message { role: "assistant".to_string(), content: content.into(), ..Default::default() },
+            index: 0,
+            finish_reason: response.stop_reason.as_deref().map(finish_reason),
+            extra: None
+        }],
+        usage: Usage {
+            prompt_tokens: response.usage.input_tokens,
+            completion_tokens: response.usage.output_tokens,
+            total_tokens: response.usage.input_tokens + response.usage.output_tokens,
+            extra: None
+        },
+        extra: None
+    })
+
+}
+
+// Anthropic's stop reasons under their OpenAI names
+fn finish_reason(stop_reason: &str) -> String {
+    match stop_reason {
+        "end_turn" | "stop_sequence" => "stop",
+        "max_tokens" => "length",
+        "tool_use" => "tool_calls",
+        other => other
+    }.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_messages_request_moves_system_prompt() {
+
+        let request = LLMRequest {
+            messages: vec![
+                Message { role: "system".to_string(), content: "Be brief.".into(), ..Default::default() },
+                Message { role: "user".to_string(), content: "What is Rust?".into(), ..Default::default() }
+            ],
+            model: "claude-3-5-sonnet".to_string(),
+            temperature: Some(0.5),
+            max_tokens: None,
+            top_p: Some(0.9),
+            stop: Some(json!("END")),
+            ..Default::default()
+        };
+
+        let body = messages_request(&request);
+
+        assert_eq!(body["system"], "Be brief.");
+        assert_eq!(body["messages"], json!([{"role": "user", "content": "What is Rust?"}]));
+        assert_eq!(body["max_tokens"], DEFAULT_MAX_TOKENS);
+        assert_eq!(body["temperature"], 0.5);
+        assert_eq!(body["stop_sequences"], json!(["END"]));
+        assert!(body["top_p"].is_number());
+
+    }
+
+    #[test]
+    fn test_image_parts_become_image_blocks() {
+
+        let content: MessageContent = serde_json::from_value(json!([
+            {"type": "text", "text": "Compare these"},
+            {"type": "image_url", "image_url": {"url": "data:image/png;base64,iVBORw0KGgo="}},
+            {"type": "image_url", "image_url": {"url": "https://example.com/cat.png"}}
+        ])).unwrap();
+
+        assert_eq!(content_blocks(&content), json!([
+            {"type": "text", "text": "Compare these"},
+            {"type": "image", "source": {"type": "base64", "media_type": "image/png", "data": "iVBORw0KGgo="}},
+            {"type": "image", "source": {"type": "url", "url": "https://example.com/cat.png"}}
+        ]));
+        assert_eq!(content_blocks(&"Hi".into()), json!("Hi"));
+
+    }
+
+    #[test]
+    fn test_parse_messages_response() {
+
+        let body = br#"{
+            "id": "msg_01", "type": "message", "role": "assistant", "model": "claude-3-5-sonnet-20241022",
+            "content": [{"type": "text", "text": "Rust is a "}, {"type": "text", "text": "systems language."}],
+            "stop_reason": "max_tokens",
+            "usage": {"input_tokens": 12, "output_tokens": 5}
+        }"#;
+
+        let response = parse_messages_response(body).unwrap();
+        assert_eq!(response.choices[0].message.content.as_text(), "Rust is a systems language.");
+        assert_eq!(response.choices[0].finish_reason.as_deref(), Some("length"));
+        assert_eq!(response.usage.total_tokens, 17);
+
+        let err = parse_messages_response(br#"{"id": "msg_01", "model": "claude", "content": [], "usage": {}}"#).unwrap_err();
+        assert!(matches!(err, LLMError::InvalidResponse { ref path, .. } if path == "usage"), "{}", err);
+
+    }
+
+}
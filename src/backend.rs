@@ -0,0 +1,499 @@
+// Storage behind the exact-match tier. `CacheBackend` is everything the proxy
+// asks of it: `RedisCache` (src/cache.rs) is the production backend and
+// `MemoryBackend` keeps entries in process, for CI and edge deployments
+// without Redis. EXACT_CACHE_BACKEND picks one at startup; the `mock` feature
+// always uses `MemoryBackend`. TTLs follow Redis: -1 no expiry, -2 missing.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use async_trait::async_trait;
+use crate::cache::{
+    CopyOutcome, QuarantinedEntry, RedisInfo, CACHE_TTL_SECONDS, QUARANTINE_PREFIX, REFRESH_REQUEST_PREFIX, STALE_PREFIX
+};
+
+pub type BackendError = Box<dyn std::error::Error + Send + Sync>;
+
+/// The exact-match tier as stored in `AppState`
+pub type ExactCache = Arc<dyn CacheBackend>;
+
+pub const DEFAULT_MEMORY_CACHE_MAX_ENTRIES: usize = 100_000;
+
+#[async_trait]
+pub trait CacheBackend: Send + Sync {
+
+    /// Shown in logs and /admin/config
+    fn name(&self) -> &'static str;
+
+    async fn get(&self, key: &str) -> Result<Option<String>, BackendError>;
+
+    /// The value and its remaining TTL in seconds
+    async fn get_with_ttl(&self, key: &str) -> Result<Option<(String, i64)>, BackendError>;
+
+    /// Batched `get_with_ttl`, with results in the same order as `keys`
+    async fn get_many_with_ttl(&self, keys: &[&str]) -> Result<Vec<Option<(String, i64)>>, BackendError>;
+
+    async fn set_with_ttl(&self, key: &str, value: &str, ttl: u64) -> Result<(), BackendError>;
+
+    /// Stores `value` with the default `CACHE_TTL_SECONDS`
+    async fn set(&self, key: &str, value: &str) -> Result<(), BackendError> {
+        self.set_with_ttl(key, value, CACHE_TTL_SECONDS).await
+    }
+
+    /// Stores `value` with no expiry
+    async fn set_persistent(&self, key: &str, value: &str) -> Result<(), BackendError>;
+
+    /// Copies `source` to `dest`, keeping the source's TTL unless `reset_ttl`
+    /// gives a new one. An existing `dest` is only overwritten with `replace`
+    async fn copy_key(&self, source: &str, dest: &str, reset_ttl: Option<u64>, replace: bool) -> Result<CopyOutcome, BackendError>;
+
+    async fn health_check(&self) -> bool;
+
+    /// Removes a key along with its stored request and hit count, returning
+    /// whether it existed
+    async fn delete(&self, key: &str) -> Result<bool, BackendError>;
+
+    /// Moves a value to `quarantine:{key}` with the reason attached, expiring
+    /// after `ttl` seconds. Returns false when the key doesn't exist
+    async fn quarantine(&self, key: &str, reason: Option<&str>, ttl: u64) -> Result<bool, BackendError>;
+
+    async fn quarantined(&self) -> Result<Vec<QuarantinedEntry>, BackendError>;
+
+    /// Hard-deletes every quarantined value, returning how many were removed
+    async fn purge_quarantined(&self) -> Result<u64, BackendError>;
+
+    /// Counts a hit on an exact-match entry
+    async fn record_hit(&self, key: &str) -> Result<(), BackendError>;
+
+    /// Increments a counter in one atomic step, starting its `window_secs`
+    /// expiry on the first increment. Returns the new count
+    async fn atomic_increment_or_init(&self, key: &str, window_secs: u32) -> Result<u32, BackendError>;
+
+    /// Stores the request that produced `key`, expiring along with the entry
+    async fn set_refresh_request(&self, key: &str, request_json: &str, ttl: u64) -> Result<(), BackendError>;
+
+    async fn refresh_request(&self, key: &str) -> Result<Option<String>, BackendError>;
+
+    /// Keeps a copy of an entry for `ttl` seconds, to serve if the entry has
+    /// expired and the upstream fails. Removed along with the entry
+    async fn set_stale(&self, key: &str, value: &str, ttl: u64) -> Result<(), BackendError>;
+
+    async fn stale(&self, key: &str) -> Result<Option<String>, BackendError>;
+
+    /// Resets a key's hit count, so it has to become popular again to be refreshed
+    async fn clear_hits(&self, key: &str) -> Result<(), BackendError>;
+
+    /// Up to `limit` entries with at least `min_hits` hits and less than `ttl_below`
+    /// seconds left, most popular first. Keys that have already expired are
+    /// dropped from the hit counts
+    async fn popular_expiring(&self, min_hits: u64, ttl_below: u64, limit: usize) -> Result<Vec<String>, BackendError>;
+
+    /// One step of a key walk: about 100 keys matching `pattern` (a trailing
+    /// `*` glob) and the cursor to continue from, 0 once every key was seen
+    async fn scan_page(&self, pattern: &str, cursor: u64) -> Result<(u64, Vec<String>), BackendError>;
+
+    async fn count_keys_matching(&self, pattern: &str) -> Result<u64, BackendError>;
+
+    /// Key count under `exact_prefix`, plus whatever memory and eviction
+    /// figures the backend can report
+    async fn info(&self, exact_prefix: &str) -> Result<RedisInfo, BackendError>;
+
+    async fn flush_all(&self) -> Result<(), BackendError>;
+
+}
+
+// key -> (value, inserted_at, ttl)
+type MemoryEntries = HashMap<String, (String, Instant, Duration)>;
+
+/// An in-process exact-match backend. Expired entries are dropped lazily,
+/// and once `max_entries` is reached the entry closest to expiry makes room
+#[derive(Clone)]
+pub struct MemoryBackend {
+    entries: Arc<Mutex<MemoryEntries>>,
+    // stands in for Redis's HIT_COUNTS_KEY sorted set
+    hits: Arc<Mutex<HashMap<String, u64>>>,
+    max_entries: usize
+}
+
+impl Default for MemoryBackend {
+    fn default() -> Self {
+        MemoryBackend::new(DEFAULT_MEMORY_CACHE_MAX_ENTRIES)
+    }
+}
+
+impl MemoryBackend {
+
+    pub fn new(max_entries: usize) -> Self {
+        MemoryBackend {
+            entries: Arc::new(Mutex::new(HashMap::new())),
+            hits: Arc::new(Mutex::new(HashMap::new())),
+            max_entries: max_entries.max(1)
+        }
+    }
+
+    fn insert(&self, entries: &mut MemoryEntries, key: &str, entry: (String, Instant, Duration)) {
+
+        if entries.len() >= self.max_entries && !entries.contains_key(key) {
+            entries.retain(|_, (_, inserted_at, ttl)| inserted_at.elapsed() <= *ttl);
+        }
+        if entries.len() >= self.max_entries && !entries.contains_key(key) {
+            let soonest = entries.iter()
+                .min_by_key(|(_, (_, inserted_at, ttl))| ttl.saturating_sub(inserted_at.elapsed()))
+                .map(|(key, _)| key.clone());
+            if let Some(soonest) = soonest {
+                entries.remove(&soonest);
+            }
+        }
+        entries.insert(key.to_string(), entry);
+
+    }
+
+}
+
+#[async_trait]
+impl CacheBackend for MemoryBackend {
+
+    fn name(&self) -> &'static str {
+        "memory"
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<String>, BackendError> {
+
+        let mut entries = self.entries.lock().unwrap();
+
+        match entries.get(key) {
+            Some((_, inserted_at, ttl)) if inserted_at.elapsed() > *ttl => {
+                entries.remove(key);
+                Ok(None)
+            }
+            Some((value, _, _)) => Ok(Some(value.clone())),
+            None => Ok(None)
+        }
+
+    }
+
+    async fn get_with_ttl(&self, key: &str) -> Result<Option<(String, i64)>, BackendError> {
+
+        let entries = self.entries.lock().unwrap();
+
+        Ok(entries.get(key).and_then(|(value, inserted_at, ttl)| {
+            if *ttl == Duration::MAX {
+                return Some((value.clone(), -1));
+            }
+            let remaining = ttl.checked_sub(inserted_at.elapsed())?;
+            Some((value.clone(), remaining.as_secs() as i64))
+        }))
+
+    }
+
+    async fn get_many_with_ttl(&self, keys: &[&str]) -> Result<Vec<Option<(String, i64)>>, BackendError> {
+
+        let mut results = Vec::with_capacity(keys.len());
+        for key in keys {
+            results.push(self.get_with_ttl(key).await?);
+        }
+        Ok(results)
+
+    }
+
+    async fn set_with_ttl(&self, key: &str, value: &str, ttl: u64) -> Result<(), BackendError> {
+
+        let mut entries = self.entries.lock().unwrap();
+        self.insert(&mut entries, key, (value.to_string(), Instant::now(), Duration::from_secs(ttl)));
+        Ok(())
+
+    }
+
+    async fn set_persistent(&self, key: &str, value: &str) -> Result<(), BackendError> {
+
+        let mut entries = self.entries.lock().unwrap();
+        self.insert(&mut entries, key, (value.to_string(), Instant::now(), Duration::MAX));
+        Ok(())
+
+    }
+
+    async fn copy_key(&self, source: &str, dest: &str, reset_ttl: Option<u64>, replace: bool) -> Result<CopyOutcome, BackendError> {
+
+        let mut entries = self.entries.lock().unwrap();
+        for key in [source, dest] {
+            if entries.get(key).is_some_and(|(_, inserted_at, ttl)| inserted_at.elapsed() > *ttl) {
+                entries.remove(key);
+            }
+        }
+
+        let copied = match entries.get(source).cloned() {
+            Some(entry) if replace || !entries.contains_key(dest) => {
+                let entry = match reset_ttl {
+                    Some(ttl) => (entry.0, Instant::now(), Duration::from_secs(ttl)),
+                    None => entry
+                };
+                self.insert(&mut entries, dest, entry);
+                true
+            }
+            _ => false
+        };
+
+        let ttl = |key: &str| entries.get(key)
+            .map(|(_, inserted_at, ttl)| ttl.saturating_sub(inserted_at.elapsed()).as_secs() as i64)
+            .unwrap_or(-2);
+        Ok(CopyOutcome { copied, source_ttl_remaining: ttl(source), dest_ttl: ttl(dest) })
+
+    }
+
+    async fn health_check(&self) -> bool {
+        true
+    }
+
+    async fn delete(&self, key: &str) -> Result<bool, BackendError> {
+
+        let mut entries = self.entries.lock().unwrap();
+        entries.remove(&format!("{}{}", REFRESH_REQUEST_PREFIX, key));
+        entries.remove(&format!("{}{}", STALE_PREFIX, key));
+        self.hits.lock().unwrap().remove(key);
+        Ok(entries.remove(key).is_some())
+
+    }
+
+    async fn quarantine(&self, key: &str, reason: Option<&str>, ttl: u64) -> Result<bool, BackendError> {
+
+        let Some((response, _, _)) = self.entries.lock().unwrap().remove(key) else {
+            return Ok(false);
+        };
+        self.entries.lock().unwrap().remove(&format!("{}{}", REFRESH_REQUEST_PREFIX, key));
+        self.entries.lock().unwrap().remove(&format!("{}{}", STALE_PREFIX, key));
+        self.hits.lock().unwrap().remove(key);
+
+        let entry = QuarantinedEntry {
+            cache_key: key.to_string(),
+            prompt: None,
+            response,
+            reason: reason.map(|r| r.to_string()),
+            quarantined_at: chrono::Utc::now().to_rfc3339()
+        };
+        let entry = serde_json::to_string(&entry).unwrap_or_default();
+        self.set_with_ttl(&format!("{}{}", QUARANTINE_PREFIX, key), &entry, ttl).await?;
+
+        Ok(true)
+
+    }
+
+    async fn quarantined(&self) -> Result<Vec<QuarantinedEntry>, BackendError> {
+
+        let entries = self.entries.lock().unwrap();
+
+        Ok(entries.iter()
+            .filter(|(key, (_, inserted_at, ttl))| key.starts_with(QUARANTINE_PREFIX) && inserted_at.elapsed() <= *ttl)
+            .filter_map(|(_, (value, _, _))| serde_json::from_str(value).ok())
+            .collect())
+
+    }
+
+    async fn purge_quarantined(&self) -> Result<u64, BackendError> {
+
+        let mut entries = self.entries.lock().unwrap();
+        let before = entries.len();
+        entries.retain(|key, _| !key.starts_with(QUARANTINE_PREFIX));
+        Ok((before - entries.len()) as u64)
+
+    }
+
+    async fn record_hit(&self, key: &str) -> Result<(), BackendError> {
+
+        *self.hits.lock().unwrap().entry(key.to_string()).or_insert(0) += 1;
+        Ok(())
+
+    }
+
+    async fn atomic_increment_or_init(&self, key: &str, window_secs: u32) -> Result<u32, BackendError> {
+
+        let mut entries = self.entries.lock().unwrap();
+
+        let current = match entries.get(key) {
+            Some((value, inserted_at, ttl)) if inserted_at.elapsed() <= *ttl => Some((value.parse::<u32>().unwrap_or(0), *inserted_at, *ttl)),
+            _ => None
+        };
+        let (count, inserted_at, ttl) = match current {
+            Some((count, inserted_at, ttl)) => (count + 1, inserted_at, ttl),
+            None => (1, Instant::now(), Duration::from_secs(window_secs as u64))
+        };
+        self.insert(&mut entries, key, (count.to_string(), inserted_at, ttl));
+        Ok(count)
+
+    }
+
+    async fn set_refresh_request(&self, key: &str, request_json: &str, ttl: u64) -> Result<(), BackendError> {
+
+        self.set_with_ttl(&format!("{}{}", REFRESH_REQUEST_PREFIX, key), request_json, ttl).await
+
+    }
+
+    async fn refresh_request(&self, key: &str) -> Result<Option<String>, BackendError> {
+
+        self.get(&format!("{}{}", REFRESH_REQUEST_PREFIX, key)).await
+
+    }
+
+    async fn set_stale(&self, key: &str, value: &str, ttl: u64) -> Result<(), BackendError> {
+
+        self.set_with_ttl(&format!("{}{}", STALE_PREFIX, key), value, ttl).await
+
+    }
+
+    async fn stale(&self, key: &str) -> Result<Option<String>, BackendError> {
+
+        self.get(&format!("{}{}", STALE_PREFIX, key)).await
+
+    }
+
+    async fn clear_hits(&self, key: &str) -> Result<(), BackendError> {
+
+        self.hits.lock().unwrap().remove(key);
+        Ok(())
+
+    }
+
+    async fn popular_expiring(&self, min_hits: u64, ttl_below: u64, limit: usize) -> Result<Vec<String>, BackendError> {
+
+        let mut popular: Vec<(String, u64)> = self.hits.lock().unwrap().iter()
+            .filter(|(_, hits)| **hits >= min_hits)
+            .map(|(key, hits)| (key.clone(), *hits))
+            .collect();
+        popular.sort_by_key(|(_, hits)| std::cmp::Reverse(*hits));
+
+        let mut expiring = Vec::new();
+        for (key, _) in popular {
+            match self.get_with_ttl(&key).await? {
+                Some((_, ttl)) if (ttl as u64) < ttl_below => expiring.push(key),
+                Some(_) => {}
+                None => {
+                    self.hits.lock().unwrap().remove(&key);
+                }
+            }
+        }
+        expiring.truncate(limit);
+        Ok(expiring)
+
+    }
+
+    // only trailing `*` wildcards are supported, which is all the proxy uses.
+    // The cursor is an offset into the sorted matching keys
+    async fn scan_page(&self, pattern: &str, cursor: u64) -> Result<(u64, Vec<String>), BackendError> {
+
+        let entries = self.entries.lock().unwrap();
+        let prefix = pattern.strip_suffix('*').unwrap_or(pattern);
+        let mut keys: Vec<&String> = entries.iter()
+            .filter(|(key, (_, inserted_at, ttl))| key.starts_with(prefix) && inserted_at.elapsed() < *ttl)
+            .map(|(key, _)| key)
+            .collect();
+        keys.sort();
+
+        let start = cursor as usize;
+        let page: Vec<String> = keys.iter().skip(start).take(100).map(|key| key.to_string()).collect();
+        let next = if start + page.len() < keys.len() { (start + page.len()) as u64 } else { 0 };
+        Ok((next, page))
+
+    }
+
+    async fn count_keys_matching(&self, pattern: &str) -> Result<u64, BackendError> {
+
+        let entries = self.entries.lock().unwrap();
+        let matches = |key: &str| match pattern.strip_suffix('*') {
+            Some(prefix) => key.starts_with(prefix),
+            None => key == pattern
+        };
+
+        Ok(entries.iter()
+            .filter(|(key, (_, inserted_at, ttl))| matches(key) && inserted_at.elapsed() < *ttl)
+            .count() as u64)
+
+    }
+
+    async fn info(&self, exact_prefix: &str) -> Result<RedisInfo, BackendError> {
+
+        let entries = self.entries.lock().unwrap();
+        let key_count = entries.keys().filter(|key| key.starts_with(exact_prefix)).count() as u64;
+        let used_memory = entries.iter().map(|(key, (value, _, _))| key.len() + value.len()).sum::<usize>() as u64;
+
+        Ok(RedisInfo {
+            key_count,
+            used_memory_bytes: Some(used_memory),
+            ..Default::default()
+        })
+
+    }
+
+    async fn flush_all(&self) -> Result<(), BackendError> {
+        self.entries.lock().unwrap().clear();
+        self.hits.lock().unwrap().clear();
+        Ok(())
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_memory_backend_expires_entries() {
+
+        let cache = MemoryBackend::default();
+        cache.set_with_ttl("key", "value", 0).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(5)).await;
+
+        assert_eq!(cache.get("key").await.unwrap(), None);
+
+    }
+
+    #[tokio::test]
+    async fn test_atomic_increment_or_init_under_concurrency() {
+
+        let cache = MemoryBackend::default();
+
+        let mut tasks = tokio::task::JoinSet::new();
+        for _ in 0..100 {
+            let cache = cache.clone();
+            tasks.spawn(async move { cache.atomic_increment_or_init("counter", 60).await.unwrap() });
+        }
+        let mut counts = tasks.join_all().await;
+        counts.sort();
+
+        assert_eq!(counts, (1..=100).collect::<Vec<u32>>(), "Every caller should see a distinct count");
+        assert_eq!(cache.get("counter").await.unwrap().as_deref(), Some("100"));
+        let (_, ttl) = cache.get_with_ttl("counter").await.unwrap().unwrap();
+        assert!((59..=60).contains(&ttl), "The window starts on the first increment");
+
+    }
+
+    #[tokio::test]
+    async fn test_memory_backend_get_many_with_ttl() {
+
+        let cache = MemoryBackend::default();
+        cache.set_with_ttl("a", "1", 100).await.unwrap();
+        cache.set_with_ttl("b", "2", 200).await.unwrap();
+
+        let results = cache.get_many_with_ttl(&["a", "missing", "b"]).await.unwrap();
+
+        assert_eq!(results.len(), 3);
+        assert!(matches!(&results[0], Some((value, ttl)) if value == "1" && (99..=100).contains(ttl)));
+        assert_eq!(results[1], None);
+        assert!(matches!(&results[2], Some((value, ttl)) if value == "2" && (199..=200).contains(ttl)));
+
+    }
+
+    #[tokio::test]
+    async fn test_memory_backend_evicts_the_entry_closest_to_expiry() {
+
+        let cache = MemoryBackend::new(2);
+        cache.set_with_ttl("long", "1", 600).await.unwrap();
+        cache.set_with_ttl("short", "2", 60).await.unwrap();
+        cache.set_with_ttl("new", "3", 300).await.unwrap();
+
+        assert_eq!(cache.get("short").await.unwrap(), None);
+        assert_eq!(cache.get("long").await.unwrap().as_deref(), Some("1"));
+        assert_eq!(cache.get("new").await.unwrap().as_deref(), Some("3"));
+
+    }
+
+}
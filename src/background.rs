@@ -0,0 +1,285 @@
+use std::future::Future;
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use crate::AppState;
+use crate::janitor::run_maintenance;
+use crate::config::MetricsPersist;
+use crate::metrics_store;
+use crate::history::HISTORY_RESOLUTION;
+use crate::handlers::{ID_DEDUP_WINDOW, ServiceStatus, check_services, refresh_popular_entries, service_label};
+
+/// Runs `task` every `every` until the state it works on has been dropped.
+/// Only a `Weak` reference is held between runs, so a background task never
+/// keeps `AppState` alive on its own and exits cleanly during shutdown.
+pub fn spawn_periodic<T, F, Fut>(
+    name: &'static str,
+    state: Weak<T>,
+    every: Duration,
+    task: F
+) -> JoinHandle<()>
+where
+    T: Send + Sync + 'static,
+    F: Fn(Arc<T>) -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send
+{
+    tokio::spawn(async move {
+
+        let mut interval = tokio::time::interval(every);
+
+        loop {
+            interval.tick().await;
+
+            // the strong reference only lives for one iteration
+            let Some(state) = state.upgrade() else {
+                tracing::info!("Background task '{}' stopping - app state dropped", name);
+                break;
+            };
+
+            task(state).await;
+        }
+
+    })
+}
+
+/// Periodically probes Redis, Qdrant, and the embedding service and logs
+/// whenever one of them changes between up and down
+pub fn spawn_health_monitor(state: &Arc<AppState>) -> JoinHandle<()> {
+
+    let interval_secs = state.config.health_monitor_interval_secs;
+
+    let last_status = Arc::new(std::sync::Mutex::new(None::<ServiceStatus>));
+
+    spawn_periodic(
+        "health-monitor",
+        Arc::downgrade(state),
+        Duration::from_secs(interval_secs),
+        move |state| {
+            let last_status = last_status.clone();
+            async move {
+                let status = check_services(&state).await;
+
+                let mut last = last_status.lock().unwrap();
+                if *last != Some(status) {
+                    let (redis_up, qdrant_up, embeddings_up) = status;
+                    tracing::info!(
+                        "Health: redis={} qdrant={} embeddings={}",
+                        service_label(redis_up), service_label(qdrant_up), service_label(embeddings_up)
+                    );
+                    *last = Some(status);
+                }
+            }
+        }
+    )
+
+}
+
+/// Clears tier 0's per-key Redis hit counters every `TIER0_TTL_SECS`, so a
+/// key is only promoted when it is hot within one window
+pub fn spawn_tier0_counter_reset(state: &Arc<AppState>) -> Option<JoinHandle<()>> {
+
+    state.tier0_cache.as_ref()?;
+
+    Some(spawn_periodic(
+        "tier0-counter-reset",
+        Arc::downgrade(state),
+        Duration::from_secs(state.config.tier0_ttl_secs),
+        |state| async move {
+            if let Some(tier0) = &state.tier0_cache {
+                tier0.clear_hit_counts();
+            }
+        }
+    ))
+
+}
+
+/// Drops response ids older than `ID_DEDUP_WINDOW` from the dedup map
+pub fn spawn_id_dedup_cleanup(state: &Arc<AppState>) -> JoinHandle<()> {
+
+    spawn_periodic(
+        "id-dedup-cleanup",
+        Arc::downgrade(state),
+        ID_DEDUP_WINDOW,
+        |state| async move {
+            state.id_dedup.retain(|_, (_, _, seen_at)| seen_at.elapsed() < ID_DEDUP_WINDOW);
+        }
+    )
+
+}
+
+// how often callers with full rate-limit buckets are forgotten
+const RATE_LIMIT_PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Drops rate-limiter state for callers that have been idle long enough
+/// to refill. Only started when a rate limit is configured
+pub fn spawn_rate_limit_cleanup(state: &Arc<AppState>) -> Option<JoinHandle<()>> {
+
+    if !state.rate_limiter.enabled() {
+        return None;
+    }
+
+    Some(spawn_periodic(
+        "rate-limit-cleanup",
+        Arc::downgrade(state),
+        RATE_LIMIT_PRUNE_INTERVAL,
+        |state| async move {
+            state.rate_limiter.prune();
+        }
+    ))
+
+}
+
+/// Re-runs popular entries that are close to expiring every
+/// `CACHE_REFRESH_INTERVAL_SECS`. Only started when refresh is enabled and
+/// the exact-match tier is on
+pub fn spawn_cache_refresher(state: &Arc<AppState>) -> Option<JoinHandle<()>> {
+
+    if !state.config.refresh.enabled {
+        return None;
+    }
+    state.exact_cache.as_ref()?;
+
+    Some(spawn_periodic(
+        "cache-refresher",
+        Arc::downgrade(state),
+        Duration::from_secs(state.config.refresh.interval_secs),
+        |state| async move {
+            refresh_popular_entries(&state).await;
+        }
+    ))
+
+}
+
+/// Runs cache maintenance every `JANITOR_INTERVAL_SECS`: expired and
+/// excess semantic points are deleted and the entry counts re-read.
+/// Not started when the interval is 0
+pub fn spawn_janitor(state: &Arc<AppState>) -> Option<JoinHandle<()>> {
+
+    if state.config.janitor_interval_secs == 0 {
+        return None;
+    }
+
+    Some(spawn_periodic(
+        "janitor",
+        Arc::downgrade(state),
+        Duration::from_secs(state.config.janitor_interval_secs),
+        |state| async move {
+            run_maintenance(&state).await;
+        }
+    ))
+
+}
+
+/// Saves the metrics counters every `METRICS_PERSIST_INTERVAL_SECS`, so a
+/// restart loses at most one interval. Only started when METRICS_PERSIST is set
+pub fn spawn_metrics_persist(state: &Arc<AppState>) -> Option<JoinHandle<()>> {
+
+    if state.config.metrics_persist == MetricsPersist::Off {
+        return None;
+    }
+
+    Some(spawn_periodic(
+        "metrics-persist",
+        Arc::downgrade(state),
+        Duration::from_secs(state.config.metrics_persist_interval_secs),
+        |state| async move {
+            metrics_store::save_or_record(&state).await;
+        }
+    ))
+
+}
+
+/// Samples the metrics counters every minute for /metrics/history. The first
+/// sample is taken at startup, so the history covers this process from the start
+pub fn spawn_metrics_history(state: &Arc<AppState>) -> JoinHandle<()> {
+
+    spawn_periodic(
+        "metrics-history",
+        Arc::downgrade(state),
+        HISTORY_RESOLUTION,
+        |state| async move {
+            state.history.record(&state.metrics.snapshot());
+        }
+    )
+
+}
+
+/// Re-reads the runtime-mutable settings from `.env`, the environment and the
+/// config file whenever the process receives SIGHUP. An invalid file keeps the current values
+#[cfg(unix)]
+pub fn spawn_sighup_reload(state: &Arc<AppState>) -> JoinHandle<()> {
+
+    use tokio::signal::unix::{signal, SignalKind};
+    use crate::config::{ConfigFile, RuntimeConfig};
+
+    let state = Arc::downgrade(state);
+
+    tokio::spawn(async move {
+
+        let mut hangups = signal(SignalKind::hangup()).expect("Failed to install SIGHUP handler");
+
+        while hangups.recv().await.is_some() {
+            let Some(state) = state.upgrade() else {
+                break;
+            };
+
+            // values in .env win over the process environment, like a fresh start would see
+            let file_vars: Vec<(String, String)> = dotenvy::dotenv_iter()
+                .map(|iter| iter.filter_map(Result::ok).collect())
+                .unwrap_or_default();
+
+            // and both win over the config file
+            let reloaded = ConfigFile::from_env().and_then(|config_file| RuntimeConfig::from_lookup(|name| {
+                file_vars.iter()
+                    .find(|(key, _)| key == name)
+                    .map(|(_, value)| value.clone())
+                    .or_else(|| std::env::var(name).ok())
+                    .or_else(|| config_file.as_ref().and_then(|config_file| config_file.get(name)))
+            }));
+
+            match reloaded {
+                Ok(runtime) => {
+                    let changes = state.update_runtime(runtime, "SIGHUP");
+                    tracing::info!("Config reloaded on SIGHUP ({} changes)", changes.len());
+                }
+                Err(e) => tracing::error!("Config reload failed, keeping current values: {}", e)
+            }
+        }
+
+    })
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    #[tokio::test]
+    async fn test_task_stops_when_state_dropped() {
+
+        let state = Arc::new(AtomicU64::new(0));
+
+        let handle = spawn_periodic(
+            "test",
+            Arc::downgrade(&state),
+            Duration::from_millis(10),
+            |counter: Arc<AtomicU64>| async move {
+                counter.fetch_add(1, Ordering::Relaxed);
+            }
+        );
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(state.load(Ordering::Relaxed) > 0, "Task should run while state is alive");
+
+        drop(state);
+
+        tokio::time::timeout(Duration::from_secs(1), handle)
+            .await
+            .expect("Background task should terminate within 1 second of state being dropped")
+            .expect("Background task panicked");
+
+    }
+
+}
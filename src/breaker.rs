@@ -0,0 +1,223 @@
+// Circuit breaker for the upstream provider. After
+// CIRCUIT_BREAKER_FAILURE_THRESHOLD upstream calls in a row fail with an
+// outage (5xx or no response at all) the circuit opens, and misses fail fast
+// instead of each waiting out the upstream. Once CIRCUIT_BREAKER_OPEN_SECS
+// have passed it half-opens: one call is let through as a probe, which
+// closes the circuit if it succeeds and reopens it if it fails.
+
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use serde::Serialize;
+
+/// CIRCUIT_BREAKER_FAILURE_THRESHOLD and CIRCUIT_BREAKER_OPEN_SECS
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BreakerConfig {
+    // consecutive failures that open the circuit; 0 turns the breaker off
+    pub failure_threshold: u32,
+    // how long the circuit stays open before a probe is let through
+    pub open_secs: u64
+}
+
+impl Default for BreakerConfig {
+    fn default() -> Self {
+        BreakerConfig { failure_threshold: 5, open_secs: 30 }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    Closed,
+    Open,
+    HalfOpen
+}
+
+#[derive(Debug)]
+struct Circuit {
+    state: CircuitState,
+    consecutive_failures: u32,
+    // when the circuit last opened, or when the current probe was let through
+    since: Instant
+}
+
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    config: BreakerConfig,
+    circuit: Mutex<Circuit>,
+    // times the circuit opened, and calls turned away while it was open
+    opens: AtomicU64,
+    rejected: AtomicU64
+}
+
+impl CircuitBreaker {
+
+    pub fn new(config: BreakerConfig) -> Self {
+        CircuitBreaker {
+            config,
+            circuit: Mutex::new(Circuit { state: CircuitState::Closed, consecutive_failures: 0, since: Instant::now() }),
+            opens: AtomicU64::new(0),
+            rejected: AtomicU64::new(0)
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.config.failure_threshold > 0
+    }
+
+    /// Whether a call may go upstream now, or how long until the next probe.
+    /// While half-open only the probe is let through; a probe that never
+    /// reported back (its request was cancelled) is replaced after `open_secs`
+    pub fn allow(&self) -> Result<(), Duration> {
+        self.allow_at(Instant::now())
+    }
+
+    fn allow_at(&self, now: Instant) -> Result<(), Duration> {
+
+        if !self.enabled() {
+            return Ok(());
+        }
+
+        let mut circuit = self.circuit.lock().unwrap();
+        let open_for = Duration::from_secs(self.config.open_secs);
+        match circuit.state {
+            CircuitState::Closed => Ok(()),
+            CircuitState::Open | CircuitState::HalfOpen if now.duration_since(circuit.since) >= open_for => {
+                tracing::info!("Circuit breaker half-open - probing the upstream");
+                circuit.state = CircuitState::HalfOpen;
+                circuit.since = now;
+                Ok(())
+            }
+            CircuitState::Open | CircuitState::HalfOpen => {
+                self.rejected.fetch_add(1, Ordering::Relaxed);
+                Err(open_for.saturating_sub(now.duration_since(circuit.since)))
+            }
+        }
+
+    }
+
+    pub fn record_success(&self) {
+
+        if !self.enabled() {
+            return;
+        }
+
+        let mut circuit = self.circuit.lock().unwrap();
+        if circuit.state != CircuitState::Closed {
+            tracing::info!("Circuit breaker closed - the upstream has recovered");
+        }
+        circuit.state = CircuitState::Closed;
+        circuit.consecutive_failures = 0;
+
+    }
+
+    pub fn record_failure(&self) {
+        self.record_failure_at(Instant::now())
+    }
+
+    fn record_failure_at(&self, now: Instant) {
+
+        if !self.enabled() {
+            return;
+        }
+
+        let mut circuit = self.circuit.lock().unwrap();
+        circuit.consecutive_failures = circuit.consecutive_failures.saturating_add(1);
+        // a failed probe reopens straight away
+        let opens = match circuit.state {
+            CircuitState::Closed => circuit.consecutive_failures >= self.config.failure_threshold,
+            CircuitState::HalfOpen => true,
+            CircuitState::Open => false
+        };
+        if opens {
+            tracing::warn!(
+                "Circuit breaker open after {} consecutive upstream failures - failing fast for {}s",
+                circuit.consecutive_failures, self.config.open_secs
+            );
+            circuit.state = CircuitState::Open;
+            circuit.since = now;
+            self.opens.fetch_add(1, Ordering::Relaxed);
+        }
+
+    }
+
+    pub fn state(&self) -> CircuitState {
+        self.circuit.lock().unwrap().state
+    }
+
+    /// For /metrics
+    pub fn snapshot(&self) -> serde_json::Value {
+
+        let circuit = self.circuit.lock().unwrap();
+        serde_json::json!({
+            "enabled": self.enabled(),
+            "state": circuit.state,
+            "consecutive_failures": circuit.consecutive_failures,
+            "opens": self.opens.load(Ordering::Relaxed),
+            "rejected": self.rejected.load(Ordering::Relaxed)
+        })
+
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_opens_after_consecutive_failures_and_probes_to_close() {
+
+        let breaker = CircuitBreaker::new(BreakerConfig { failure_threshold: 3, open_secs: 30 });
+        let start = Instant::now();
+
+        // a success in between starts the count over
+        breaker.record_failure_at(start);
+        breaker.record_failure_at(start);
+        breaker.record_success();
+        breaker.record_failure_at(start);
+        breaker.record_failure_at(start);
+        assert_eq!(breaker.state(), CircuitState::Closed);
+
+        breaker.record_failure_at(start);
+        assert_eq!(breaker.state(), CircuitState::Open);
+        assert_eq!(breaker.allow_at(start + Duration::from_secs(10)), Err(Duration::from_secs(20)));
+
+        // one probe, everyone else still fails fast
+        assert_eq!(breaker.allow_at(start + Duration::from_secs(30)), Ok(()));
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+        assert!(breaker.allow_at(start + Duration::from_secs(31)).is_err());
+
+        breaker.record_success();
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        assert!(breaker.allow_at(start + Duration::from_secs(31)).is_ok());
+        assert_eq!(breaker.snapshot()["opens"], 1);
+        assert_eq!(breaker.snapshot()["rejected"], 2);
+
+    }
+
+    #[test]
+    fn test_failed_probe_reopens() {
+
+        let breaker = CircuitBreaker::new(BreakerConfig { failure_threshold: 1, open_secs: 5 });
+        let start = Instant::now();
+
+        breaker.record_failure_at(start);
+        assert!(breaker.allow_at(start + Duration::from_secs(5)).is_ok());
+        breaker.record_failure_at(start + Duration::from_secs(6));
+        assert_eq!(breaker.state(), CircuitState::Open);
+        assert_eq!(breaker.allow_at(start + Duration::from_secs(7)), Err(Duration::from_secs(4)));
+
+        // an abandoned probe is replaced once open_secs have passed
+        assert!(breaker.allow_at(start + Duration::from_secs(11)).is_ok());
+        assert!(breaker.allow_at(start + Duration::from_secs(12)).is_err());
+        assert!(breaker.allow_at(start + Duration::from_secs(16)).is_ok());
+
+        let off = CircuitBreaker::new(BreakerConfig { failure_threshold: 0, open_secs: 5 });
+        off.record_failure_at(start);
+        assert!(off.allow_at(start).is_ok());
+
+    }
+
+}
@@ -0,0 +1,282 @@
+// Spend caps for upstream calls. The estimated cost of every cache miss
+// (from the pricing table) is added to a global total and to the total of the
+// proxy API key that made it. Passing a soft limit only warns, with an
+// x-budget-warning header and a log line; at a hard limit requests are turned
+// away. Totals start over at the beginning of each BUDGET_PERIOD (UTC). Like
+// quotas, the request that crosses a limit still completes.
+
+use chrono::{DateTime, Datelike, Days, Months, NaiveDate, Utc};
+use dashmap::DashMap;
+use std::sync::Mutex;
+
+/// How often spend totals start over
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BudgetPeriod {
+    Daily,
+    // from Monday
+    Weekly,
+    #[default]
+    Monthly
+}
+
+impl BudgetPeriod {
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "daily" => Some(BudgetPeriod::Daily),
+            "weekly" => Some(BudgetPeriod::Weekly),
+            "monthly" => Some(BudgetPeriod::Monthly),
+            _ => None
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BudgetPeriod::Daily => "daily",
+            BudgetPeriod::Weekly => "weekly",
+            BudgetPeriod::Monthly => "monthly"
+        }
+    }
+
+    /// The first day of the period `day` falls in
+    fn start(&self, day: NaiveDate) -> NaiveDate {
+        match self {
+            BudgetPeriod::Daily => day,
+            BudgetPeriod::Weekly => day - Days::new(day.weekday().num_days_from_monday() as u64),
+            BudgetPeriod::Monthly => day.with_day(1).unwrap_or(day)
+        }
+    }
+
+    fn next_start(&self, start: NaiveDate) -> NaiveDate {
+        match self {
+            BudgetPeriod::Daily => start + Days::new(1),
+            BudgetPeriod::Weekly => start + Days::new(7),
+            BudgetPeriod::Monthly => start + Months::new(1)
+        }
+    }
+
+}
+
+/// A soft and a hard limit in USD; `None` is unlimited
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct BudgetLimits {
+    pub soft_usd: Option<f64>,
+    pub hard_usd: Option<f64>
+}
+
+impl BudgetLimits {
+
+    pub fn enabled(&self) -> bool {
+        self.soft_usd.is_some() || self.hard_usd.is_some()
+    }
+
+}
+
+/// BUDGET_* settings
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BudgetConfig {
+    pub global: BudgetLimits,
+    // applied to each proxy API key separately
+    pub per_key: BudgetLimits,
+    pub period: BudgetPeriod,
+    // 402 or 429, sent once a hard limit is reached
+    pub reject_status: u16
+}
+
+impl Default for BudgetConfig {
+    fn default() -> Self {
+        BudgetConfig {
+            global: BudgetLimits::default(),
+            per_key: BudgetLimits::default(),
+            period: BudgetPeriod::default(),
+            reject_status: 402
+        }
+    }
+}
+
+impl BudgetConfig {
+
+    pub fn enabled(&self) -> bool {
+        self.global.enabled() || self.per_key.enabled()
+    }
+
+}
+
+/// A limit the spend has reached: which one ("global" or "key:<name>"),
+/// whether it's the hard one, and the figures
+#[derive(Debug, Clone, PartialEq)]
+pub struct BudgetBreach {
+    pub scope: String,
+    pub hard: bool,
+    pub limit_usd: f64,
+    pub spent_usd: f64
+}
+
+impl BudgetBreach {
+
+    pub fn describe(&self) -> String {
+        format!(
+            "{} spend ${:.4} has reached its {} limit of ${:.4}",
+            self.scope, self.spent_usd, if self.hard { "hard" } else { "soft" }, self.limit_usd
+        )
+    }
+
+}
+
+#[derive(Debug)]
+pub struct BudgetTracker {
+    config: BudgetConfig,
+    // the first day of the period the totals are for
+    period_start: Mutex<NaiveDate>,
+    global_usd: Mutex<f64>,
+    per_key_usd: DashMap<String, f64>
+}
+
+impl BudgetTracker {
+
+    pub fn new(config: BudgetConfig) -> Self {
+        BudgetTracker {
+            config,
+            period_start: Mutex::new(config.period.start(Utc::now().date_naive())),
+            global_usd: Mutex::new(0.0),
+            per_key_usd: DashMap::new()
+        }
+    }
+
+    pub fn config(&self) -> BudgetConfig {
+        self.config
+    }
+
+    /// The limits the global and `key`'s spend have reached, hard ones first
+    pub fn check(&self, key: Option<&str>) -> Vec<BudgetBreach> {
+        self.check_on(key, Utc::now().date_naive())
+    }
+
+    fn check_on(&self, key: Option<&str>, today: NaiveDate) -> Vec<BudgetBreach> {
+
+        self.roll_over(today);
+
+        let mut breaches: Vec<BudgetBreach> = breaches_of("global", self.config.global, *self.global_usd.lock().unwrap()).collect();
+        if let Some(key) = key {
+            let spent = self.per_key_usd.get(key).map(|spent| *spent).unwrap_or(0.0);
+            breaches.extend(breaches_of(&format!("key:{}", key), self.config.per_key, spent));
+        }
+        breaches.sort_by_key(|breach| !breach.hard);
+        breaches
+
+    }
+
+    /// Adds an upstream call's estimated cost to the global and `key`'s totals
+    pub fn record(&self, key: Option<&str>, cost_usd: f64) {
+        self.record_on(key, cost_usd, Utc::now().date_naive());
+    }
+
+    fn record_on(&self, key: Option<&str>, cost_usd: f64, today: NaiveDate) {
+
+        self.roll_over(today);
+        *self.global_usd.lock().unwrap() += cost_usd;
+        if let Some(key) = key {
+            *self.per_key_usd.entry(key.to_string()).or_default() += cost_usd;
+        }
+
+    }
+
+    /// Spend this period: the global total and each key's
+    pub fn spent(&self) -> (f64, Vec<(String, f64)>) {
+
+        self.roll_over(Utc::now().date_naive());
+        let mut keys: Vec<(String, f64)> = self.per_key_usd.iter().map(|entry| (entry.key().clone(), *entry.value())).collect();
+        keys.sort_by(|a, b| a.0.cmp(&b.0));
+        (*self.global_usd.lock().unwrap(), keys)
+
+    }
+
+    /// When the current period began and when the next one begins
+    pub fn period(&self) -> (DateTime<Utc>, DateTime<Utc>) {
+
+        let start = *self.period_start.lock().unwrap();
+        (midnight(start), midnight(self.config.period.next_start(start)))
+
+    }
+
+    // starts the totals over once a new period has begun
+    fn roll_over(&self, today: NaiveDate) {
+
+        let start = self.config.period.start(today);
+        let mut period_start = self.period_start.lock().unwrap();
+        if *period_start != start {
+            tracing::info!("Budget: new {} period from {}, totals reset", self.config.period.as_str(), start);
+            *period_start = start;
+            *self.global_usd.lock().unwrap() = 0.0;
+            self.per_key_usd.clear();
+        }
+
+    }
+
+}
+
+// the hard limit, when reached, stands for the soft one too
+fn breaches_of(scope: &str, limits: BudgetLimits, spent: f64) -> impl Iterator<Item = BudgetBreach> {
+
+    let breach = |hard, limit_usd| BudgetBreach { scope: scope.to_string(), hard, limit_usd, spent_usd: spent };
+    let hard = limits.hard_usd.filter(|limit| spent >= *limit).map(|limit| breach(true, limit));
+    let soft = limits.soft_usd.filter(|limit| spent >= *limit && hard.is_none()).map(|limit| breach(false, limit));
+    hard.into_iter().chain(soft)
+
+}
+
+fn midnight(day: NaiveDate) -> DateTime<Utc> {
+    day.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn day(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    #[test]
+    fn test_soft_then_hard_limits_reset_with_the_period() {
+
+        let tracker = BudgetTracker::new(BudgetConfig {
+            global: BudgetLimits { soft_usd: Some(1.0), hard_usd: Some(2.0) },
+            per_key: BudgetLimits { soft_usd: None, hard_usd: Some(0.5) },
+            ..BudgetConfig::default()
+        });
+        let today = day(2026, 10, 15);
+
+        tracker.record_on(Some("web-app"), 0.6, today);
+        let breaches = tracker.check_on(Some("web-app"), today);
+        assert_eq!(breaches.len(), 1);
+        assert_eq!((breaches[0].scope.as_str(), breaches[0].hard), ("key:web-app", true));
+        assert!(tracker.check_on(Some("batch-jobs"), today).is_empty(), "Keys have separate totals");
+
+        tracker.record_on(Some("batch-jobs"), 0.5, today);
+        let breaches = tracker.check_on(Some("batch-jobs"), today);
+        assert_eq!(breaches.iter().map(|b| (b.scope.as_str(), b.hard)).collect::<Vec<_>>(), [("key:batch-jobs", true), ("global", false)]);
+
+        tracker.record_on(None, 1.0, today);
+        assert!(tracker.check_on(None, today)[0].hard);
+
+        // the same month keeps counting; the next starts over
+        assert!(!tracker.check_on(None, day(2026, 10, 31)).is_empty());
+        assert!(tracker.check_on(Some("web-app"), day(2026, 11, 1)).is_empty());
+
+    }
+
+    #[test]
+    fn test_period_boundaries() {
+
+        let wednesday = day(2026, 10, 14);
+        assert_eq!(BudgetPeriod::Weekly.start(wednesday), day(2026, 10, 12));
+        assert_eq!(BudgetPeriod::Monthly.next_start(day(2026, 12, 1)), day(2027, 1, 1));
+        assert_eq!(BudgetPeriod::Daily.start(wednesday), wednesday);
+        assert_eq!(BudgetPeriod::parse("Weekly"), Some(BudgetPeriod::Weekly));
+        assert_eq!(BudgetPeriod::parse("yearly"), None);
+
+    }
+
+}
@@ -1,28 +1,251 @@
 use sha2::{Sha256, Digest};
-use crate::models::LLMRequest;
+use crate::models::{ImageUrl, LLMRequest, LLMResponse};
 use redis::aio::ConnectionManager;
 use redis::AsyncCommands;
+use async_trait::async_trait;
+use crate::backend::{BackendError, CacheBackend};
+use crate::config::EmbeddingEndpoint;
+use crate::semantic::SemanticStore;
 use reqwest::Client;
+#[cfg(not(feature = "mock"))]
+use crate::config::EmbeddingProvider;
+#[cfg(not(feature = "mock"))]
 use serde_json::{Value, json};
 use qdrant_client::Qdrant;
 use qdrant_client::qdrant::{
     CreateCollectionBuilder, Distance, VectorParamsBuilder,
-    SearchPointsBuilder, PointStruct, UpsertPointsBuilder
+    SearchPointsBuilder, PointStruct, UpsertPointsBuilder, ScrollPointsBuilder,
+    Condition, Filter, DeletePointsBuilder, SetPayloadPointsBuilder, PointId, PayloadIncludeSelector, Range
 };
+use qdrant_client::qdrant::point_id::PointIdOptions;
+use qdrant_client::Payload;
 use qdrant_client::qdrant::value::Kind;
+use qdrant_client::qdrant::vectors_config;
 use uuid::Uuid;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::num::NonZeroUsize;
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use arc_swap::ArcSwap;
+use dashmap::DashMap;
+use lru::LruCache;
+
+pub const CACHE_TTL_SECONDS: u64 = 86400;
+
+// every exact-match key starts with this, so shared Redis instances can be measured.
+// A CACHE_NAMESPACE_VERSION moves keys to cache:<version>:exact:
+pub const EXACT_KEY_PREFIX: &str = "cache:exact:";
+
+pub fn exact_key_prefix(namespace_version: Option<&str>) -> String {
+    match namespace_version {
+        Some(version) => format!("cache:{}:exact:", version),
+        None => EXACT_KEY_PREFIX.to_string()
+    }
+}
+
+// exact-match keys of a BYOK client continue with byok:<hash of its upstream key>:
+pub const BYOK_PARTITION_PREFIX: &str = "byok:";
+
+/// The key partition for a client's upstream key. Only a hash of the key
+/// ends up in Redis and Qdrant
+pub fn byok_partition(upstream_key: &str) -> String {
+
+    let hash = format!("{:x}", Sha256::digest(upstream_key.as_bytes()));
+    format!("{}{}:", BYOK_PARTITION_PREFIX, &hash[..16])
+
+}
+
+// exact-match keys of a tenant (TENANT_SOURCE) continue with tenant:<id>:,
+// ahead of any BYOK partition
+pub const TENANT_PARTITION_PREFIX: &str = "tenant:";
+
+pub fn tenant_partition(tenant: &str) -> String {
+    format!("{}{}:", TENANT_PARTITION_PREFIX, tenant)
+}
+
+// quarantined Redis values are moved under this prefix
+pub const QUARANTINE_PREFIX: &str = "quarantine:";
+
+// sorted set of exact-match key -> hit count, read by the background refresher
+pub const HIT_COUNTS_KEY: &str = "cache:hits";
+
+// the original request behind an exact-match entry, kept so the refresher can re-run it
+pub const REFRESH_REQUEST_PREFIX: &str = "cache:request:";
+
+// a copy of an exact-match entry that outlives it by STALE_GRACE_SECS, served
+// in its place when the upstream call for an expired entry fails
+pub const STALE_PREFIX: &str = "cache:stale:";
+
+// the /metrics counters saved under METRICS_PERSIST=redis, followed by METRICS_INSTANCE_ID
+pub const METRICS_SNAPSHOT_PREFIX: &str = "cache:metrics:";
+
+// embedding vectors by a hash of the embedding URL and the prompt text, so a
+// prompt seen before skips the embedding service (EMBEDDING_CACHE_TTL_SECS)
+pub const EMBEDDING_PREFIX: &str = "cache:embedding:";
+
+// per-caller request counters under RATE_LIMIT_SHARED, followed by the caller
+// and the minute they count
+pub const RATE_LIMIT_PREFIX: &str = "cache:ratelimit:";
+
+const INCREMENT_OR_INIT_SCRIPT: &str =
+    "local v = redis.call('INCR', KEYS[1]); if v == 1 then redis.call('EXPIRE', KEYS[1], ARGV[1]) end; return v";
+
+// most popular keys whose TTL is checked on each refresh pass
+const REFRESH_CANDIDATES: isize = 200;
+
+/// Result of copying one Redis key. TTLs follow Redis: -1 no expiry, -2 missing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct CopyOutcome {
+    pub copied: bool,
+    pub source_ttl_remaining: i64,
+    pub dest_ttl: i64
+}
+
+/// An invalidated entry kept for analysis instead of being deleted
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuarantinedEntry {
+    pub cache_key: String,
+    // only the semantic tier stores the prompt
+    pub prompt: Option<String>,
+    pub response: String,
+    pub reason: Option<String>,
+    pub quarantined_at: String
+}
+
+// bumped whenever the hashed key format changes, so old entries simply stop matching
+const KEY_FORMAT_VERSION: u32 = 2;
+
+/// Formats a float request parameter with exactly 2 decimal places, so values
+/// like 0.7, 0.70 and 0.699999988 (an f32 round-trip) all hash the same
+fn format_float_param(value: f32) -> String {
+
+    let formatted = format!("{:.2}", value);
+    // -0.001 rounds to "-0.00"
+    if formatted == "-0.00" { "0.00".to_string() } else { formatted }
+
+}
+
+/// How message content is normalized before it is hashed into the exact-match key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyNormalization {
+    pub case_sensitive: bool,
+    // collapse whitespace runs and strip zero-width characters
+    pub collapse_whitespace: bool
+}
+
+impl Default for KeyNormalization {
+    fn default() -> Self {
+        KeyNormalization { case_sensitive: false, collapse_whitespace: true }
+    }
+}
+
+impl KeyNormalization {
+
+    pub fn apply(&self, text: &str) -> String {
+
+        let text = if self.collapse_whitespace {
+            // split_whitespace also splits on unicode spaces such as U+00A0
+            text.chars()
+                .filter(|c| !matches!(c, '\u{200B}' | '\u{200C}' | '\u{200D}' | '\u{2060}' | '\u{FEFF}'))
+                .collect::<String>()
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" ")
+        } else {
+            text.trim().to_string()
+        };
+
+        if self.case_sensitive { text } else { text.to_lowercase() }
+
+    }
+
+}
+
+// request fields that don't change the answer, left out of the key
+const KEY_IGNORED_FIELDS: &[&str] = &["user", "stream_options"];
+
+// "|top_p:0.90|seed:7|..." for each other parameter the request sets, so
+// requests without any keep the keys they had before these were hashed
+fn optional_params(request: &LLMRequest) -> String {
+
+    let mut params = Vec::new();
+
+    let floats = [
+        ("top_p", request.top_p),
+        ("frequency_penalty", request.frequency_penalty),
+        ("presence_penalty", request.presence_penalty)
+    ];
+    for (name, value) in floats {
+        if let Some(value) = value {
+            params.push(format!("{}:{}", name, format_float_param(value)));
+        }
+    }
+    if let Some(seed) = request.seed {
+        params.push(format!("seed:{}", seed));
+    }
+    if let Some(n) = request.n {
+        params.push(format!("n:{}", n));
+    }
+    // serde_json objects keep their keys sorted, so equal JSON prints the same
+    let json_params = [
+        ("stop", &request.stop),
+        ("response_format", &request.response_format),
+        ("tools", &request.tools),
+        ("tool_choice", &request.tool_choice)
+    ];
+    for (name, value) in json_params {
+        if let Some(value) = value {
+            params.push(format!("{}:{}", name, value));
+        }
+    }
+    if let Some(extra) = &request.extra {
+        for (name, value) in extra.iter().filter(|(name, _)| !KEY_IGNORED_FIELDS.contains(&name.as_str())) {
+            params.push(format!("{}:{}", name, value));
+        }
+    }
+
+    params.iter().map(|param| format!("|{}", param)).collect()
+
+}
+
+// an image by its URL, or by a digest of the data for a base64 data URL so
+// megabytes of image don't get hashed into every key string
+fn image_key(image: &ImageUrl) -> String {
+
+    let source = match image.url.strip_prefix("data:").and_then(|data| data.split_once(";base64,")) {
+        Some((media_type, data)) => format!("{};sha256:{:x}", media_type, Sha256::digest(data.as_bytes())),
+        None => image.url.clone()
+    };
+    match &image.detail {
+        Some(detail) => format!("{}#{}", source, detail),
+        None => source
+    }
 
-const CACHE_TTL_SECONDS: u64 = 86400;
+}
 
-pub fn generate_cache_key(request: &LLMRequest) -> String {
+pub fn generate_cache_key(request: &LLMRequest, normalization: &KeyNormalization, prefix: &str) -> String {
     
     // Request contains model, temperature, max_tokens, messages
     let normalized_messages: Vec<String> = request.messages
         .iter() // iterate through each message
         .map(|message| {
             // for each message create a "role:content" string 
-            let normalized_content = message.content.trim().to_lowercase();
-            format!("{}:{}", message.role.to_lowercase(), normalized_content)
+            let normalized_content = normalization.apply(&message.content.as_text());
+            let mut normalized = format!("{}:{}", message.role.to_lowercase(), normalized_content);
+            for image in message.content.images() {
+                normalized.push_str(&format!(":image:{}", image_key(image)));
+            }
+            // tool calls and results are appended only when present, so plain messages hash as before
+            if let Some(tool_calls) = &message.tool_calls {
+                normalized.push_str(&format!(":tool_calls:{}", serde_json::to_string(tool_calls).unwrap_or_default()));
+            }
+            if let Some(tool_call_id) = &message.tool_call_id {
+                normalized.push_str(&format!(":tool_call_id:{}", tool_call_id));
+            }
+            normalized
         })
         .collect(); // collect into a vector of strings
 
@@ -30,9 +253,10 @@ pub fn generate_cache_key(request: &LLMRequest) -> String {
 
     let model = request.model.trim().to_lowercase();
 
-    // convert temperature and max_tokens to string while handling case that they might be empty
+    // convert temperature and max_tokens to string while handling case that they might be empty.
+    // None stays distinct from Some(0.0): the provider's default temperature isn't 0
     let temp_str = match request.temperature {
-        Some(t) => format!("temp:{}", t),
+        Some(t) => format!("temp:{}", format_float_param(t)),
         None => "temp:none".to_string()
     };
 
@@ -42,11 +266,13 @@ pub fn generate_cache_key(request: &LLMRequest) -> String {
     };
 
     // concatenate all strings into one hash string
-    let to_hash = format!("{}|model:{}|{}|{}",
+    let to_hash = format!("v{}|{}|model:{}|{}|{}{}",
+        KEY_FORMAT_VERSION,
         combined_messages,
         model,
         temp_str,
-        tokens_str
+        tokens_str,
+        optional_params(request)
     );
 
     // initialize a new sha256 variable
@@ -54,188 +280,1643 @@ pub fn generate_cache_key(request: &LLMRequest) -> String {
     hasher.update(to_hash.as_bytes());
     let hash_bytes = hasher.finalize();
 
-    // convert bytes to hash string
-    let hash_hex = format!("{:x}", hash_bytes);
+    // convert bytes to hash string
+    let hash_hex = format!("{:x}", hash_bytes);
+
+    // return formatted cache key
+    format!("{}{}:{}", prefix, hash_hex, model)
+
+}
+
+/// Where the embedding of `text` is cached. The URL and model are part of the
+/// hash, so switching to another embedding model starts from an empty cache
+pub fn embedding_cache_key(endpoint: &EmbeddingEndpoint, text: &str) -> String {
+
+    let mut hasher = Sha256::new();
+    hasher.update(endpoint.url.as_bytes());
+    hasher.update(b"\n");
+    hasher.update(endpoint.model.as_deref().unwrap_or_default().as_bytes());
+    hasher.update(b"\n");
+    hasher.update(text.as_bytes());
+    format!("{}{:x}", EMBEDDING_PREFIX, hasher.finalize())
+
+}
+
+/// The production exact-match backend
+#[derive(Clone)]
+pub struct RedisCache {
+    conn_manager: ConnectionManager
+}
+
+impl RedisCache {
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    pub async fn new(redis_url: &str) -> Result<Self, redis::RedisError> {
+
+        let client = redis::Client::open(redis_url)?;
+        let conn_manager = ConnectionManager::new(client).await?;
+        Ok(RedisCache { conn_manager })
+
+    }
+
+    // COPY for Redis < 6.2: not atomic, but NX still keeps an existing dest unless replacing
+    async fn copy_with_get_set(&self, source: &str, dest: &str, replace: bool) -> Result<bool, redis::RedisError> {
+
+        let mut connection = self.conn_manager.clone();
+
+        let (value, pttl): (Option<String>, i64) = redis::pipe()
+            .get(source)
+            .pttl(source)
+            .query_async(&mut connection)
+            .await?;
+        let Some(value) = value else {
+            return Ok(false);
+        };
+
+        let mut set = redis::cmd("SET");
+        set.arg(dest).arg(value);
+        if pttl > 0 {
+            set.arg("PX").arg(pttl);
+        }
+        if !replace {
+            set.arg("NX");
+        }
+        let reply: Option<String> = set.query_async(&mut connection).await?;
+        Ok(reply.is_some())
+
+    }
+
+    // collects every key matching `pattern` using SCAN, which unlike KEYS doesn't block Redis
+    async fn scan_keys(&self, pattern: &str) -> Result<Vec<String>, redis::RedisError> {
+
+        let mut connection = self.conn_manager.clone();
+        let mut cursor: u64 = 0;
+        let mut keys = Vec::new();
+
+        loop {
+            let (next, batch): (u64, Vec<String>) = redis::cmd("SCAN")
+                .arg(cursor)
+                .arg("MATCH").arg(pattern)
+                .arg("COUNT").arg(100)
+                .query_async(&mut connection)
+                .await?;
+            keys.extend(batch);
+            cursor = next;
+            if cursor == 0 {
+                break;
+            }
+        }
+
+        Ok(keys)
+
+    }
+
+}
+
+#[async_trait]
+impl CacheBackend for RedisCache {
+
+    fn name(&self) -> &'static str {
+        "redis"
+    }
+
+    #[tracing::instrument(level = "debug", skip_all, fields(cache_key = %key))]
+    async fn get(&self, key: &str) -> Result<Option<String>, BackendError> {
+
+        let mut connection = self.conn_manager.clone();
+        Ok(connection.get(key).await?)
+
+    }
+
+    // one atomic GET + TTL round trip
+    #[tracing::instrument(level = "debug", skip_all, fields(cache_key = %key))]
+    async fn get_with_ttl(&self, key: &str) -> Result<Option<(String, i64)>, BackendError> {
+
+        let mut connection = self.conn_manager.clone();
+
+        let (value, ttl): (Option<String>, i64) = redis::pipe()
+            .atomic()
+            .get(key)
+            .ttl(key)
+            .query_async(&mut connection)
+            .await?;
+
+        Ok(value.map(|value| (value, ttl)))
+
+    }
+
+    // one MGET plus a TTL per key, sent as a single pipeline
+    #[tracing::instrument(level = "debug", skip_all, fields(keys = keys.len()))]
+    async fn get_many_with_ttl(&self, keys: &[&str]) -> Result<Vec<Option<(String, i64)>>, BackendError> {
+
+        if keys.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut connection = self.conn_manager.clone();
+
+        let mut pipe = redis::pipe();
+        pipe.atomic().cmd("MGET").arg(keys);
+        for key in keys {
+            pipe.ttl(*key);
+        }
+
+        let results: Vec<redis::Value> = pipe.query_async(&mut connection).await?;
+        let Some((values, ttls)) = results.split_first() else {
+            return Ok(vec![None; keys.len()]);
+        };
+
+        let values: Vec<Option<String>> = redis::from_redis_value(values)?;
+        let ttls = ttls.iter()
+            .map(redis::from_redis_value::<i64>)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(values.into_iter()
+            .zip(ttls)
+            .map(|(value, ttl)| value.map(|value| (value, ttl)))
+            .collect())
+
+    }
+
+    #[tracing::instrument(level = "debug", skip_all, fields(cache_key = %key, ttl))]
+    async fn set_with_ttl(&self, key: &str, value: &str, ttl: u64) -> Result<(), BackendError> {
+
+        let mut connection = self.conn_manager.clone();
+        Ok(connection.set_ex(key, value, ttl).await?)
+
+    }
+
+    async fn set_persistent(&self, key: &str, value: &str) -> Result<(), BackendError> {
+
+        let mut connection = self.conn_manager.clone();
+        Ok(connection.set(key, value).await?)
+
+    }
+
+    // COPY keeps the source's TTL; falls back to GET + SET on Redis older than 6.2
+    #[tracing::instrument(level = "debug", skip_all, fields(source = %source, dest = %dest))]
+    async fn copy_key(&self, source: &str, dest: &str, reset_ttl: Option<u64>, replace: bool) -> Result<CopyOutcome, BackendError> {
+
+        let mut connection = self.conn_manager.clone();
+
+        let mut copy = redis::cmd("COPY");
+        copy.arg(source).arg(dest);
+        if replace {
+            copy.arg("REPLACE");
+        }
+        let copied = match copy.query_async::<bool>(&mut connection).await {
+            Ok(copied) => copied,
+            Err(e) if e.to_string().to_lowercase().contains("unknown command") => {
+                self.copy_with_get_set(source, dest, replace).await?
+            }
+            Err(e) => return Err(e.into())
+        };
+
+        if copied && let Some(ttl) = reset_ttl {
+            connection.expire::<_, ()>(dest, ttl as i64).await?;
+        }
+
+        let (source_ttl_remaining, dest_ttl): (i64, i64) = redis::pipe()
+            .ttl(source)
+            .ttl(dest)
+            .query_async(&mut connection)
+            .await?;
+
+        Ok(CopyOutcome { copied, source_ttl_remaining, dest_ttl })
+
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn health_check(&self) -> bool {
+        let mut connection = self.conn_manager.clone();
+        redis::cmd("PING")
+            .query_async::<String>(&mut connection)
+            .await
+            .is_ok()
+    }
+
+    #[tracing::instrument(level = "debug", skip_all, fields(cache_key = %key))]
+    async fn delete(&self, key: &str) -> Result<bool, BackendError> {
+
+        let mut connection = self.conn_manager.clone();
+
+        // the stored request and hit count go with the entry
+        let (deleted,): (u64,) = redis::pipe()
+            .atomic()
+            .del(key)
+            .del(format!("{}{}", REFRESH_REQUEST_PREFIX, key)).ignore()
+            .del(format!("{}{}", STALE_PREFIX, key)).ignore()
+            .zrem(HIT_COUNTS_KEY, key).ignore()
+            .query_async(&mut connection)
+            .await?;
+
+        Ok(deleted > 0)
+
+    }
+
+    #[tracing::instrument(level = "debug", skip_all, fields(cache_key = %key))]
+    async fn quarantine(&self, key: &str, reason: Option<&str>, ttl: u64) -> Result<bool, BackendError> {
+
+        let mut connection = self.conn_manager.clone();
+
+        let Some(response) = connection.get::<_, Option<String>>(key).await? else {
+            return Ok(false);
+        };
+
+        let entry = QuarantinedEntry {
+            cache_key: key.to_string(),
+            prompt: None,
+            response,
+            reason: reason.map(|r| r.to_string()),
+            quarantined_at: chrono::Utc::now().to_rfc3339()
+        };
+        let entry = serde_json::to_string(&entry).unwrap_or_default();
+
+        redis::pipe()
+            .atomic()
+            .set_ex(format!("{}{}", QUARANTINE_PREFIX, key), entry, ttl)
+            .del(key)
+            .del(format!("{}{}", REFRESH_REQUEST_PREFIX, key))
+            .del(format!("{}{}", STALE_PREFIX, key))
+            .zrem(HIT_COUNTS_KEY, key)
+            .query_async::<()>(&mut connection)
+            .await?;
+
+        Ok(true)
+
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn quarantined(&self) -> Result<Vec<QuarantinedEntry>, BackendError> {
+
+        let keys = self.scan_keys(&format!("{}*", QUARANTINE_PREFIX)).await?;
+        if keys.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut connection = self.conn_manager.clone();
+        let values: Vec<Option<String>> = redis::cmd("MGET").arg(&keys).query_async(&mut connection).await?;
+
+        Ok(values.into_iter()
+            .flatten()
+            .filter_map(|value| serde_json::from_str(&value).ok())
+            .collect())
+
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn purge_quarantined(&self) -> Result<u64, BackendError> {
+
+        let keys = self.scan_keys(&format!("{}*", QUARANTINE_PREFIX)).await?;
+        if keys.is_empty() {
+            return Ok(0);
+        }
+
+        let mut connection = self.conn_manager.clone();
+        Ok(connection.del(&keys).await?)
+
+    }
+
+    #[tracing::instrument(level = "debug", skip_all, fields(cache_key = %key))]
+    async fn record_hit(&self, key: &str) -> Result<(), BackendError> {
+
+        let mut connection = self.conn_manager.clone();
+        Ok(connection.zincr::<_, _, _, ()>(HIT_COUNTS_KEY, key, 1).await?)
+
+    }
+
+    #[tracing::instrument(level = "debug", skip_all, fields(key = %key, window_secs))]
+    async fn atomic_increment_or_init(&self, key: &str, window_secs: u32) -> Result<u32, BackendError> {
+
+        // INCR and EXPIRE in one script, so concurrent callers can't both see
+        // the first increment or leave a counter without an expiry
+        let script = redis::Script::new(INCREMENT_OR_INIT_SCRIPT);
+        let mut connection = self.conn_manager.clone();
+        Ok(script.key(key).arg(window_secs).invoke_async(&mut connection).await?)
+
+    }
+
+    #[tracing::instrument(level = "debug", skip_all, fields(cache_key = %key, ttl))]
+    async fn set_refresh_request(&self, key: &str, request_json: &str, ttl: u64) -> Result<(), BackendError> {
+
+        let mut connection = self.conn_manager.clone();
+        Ok(connection.set_ex(format!("{}{}", REFRESH_REQUEST_PREFIX, key), request_json, ttl).await?)
+
+    }
+
+    #[tracing::instrument(level = "debug", skip_all, fields(cache_key = %key))]
+    async fn refresh_request(&self, key: &str) -> Result<Option<String>, BackendError> {
+
+        let mut connection = self.conn_manager.clone();
+        Ok(connection.get(format!("{}{}", REFRESH_REQUEST_PREFIX, key)).await?)
+
+    }
+
+    #[tracing::instrument(level = "debug", skip_all, fields(cache_key = %key, ttl))]
+    async fn set_stale(&self, key: &str, value: &str, ttl: u64) -> Result<(), BackendError> {
+
+        let mut connection = self.conn_manager.clone();
+        Ok(connection.set_ex(format!("{}{}", STALE_PREFIX, key), value, ttl).await?)
+
+    }
+
+    #[tracing::instrument(level = "debug", skip_all, fields(cache_key = %key))]
+    async fn stale(&self, key: &str) -> Result<Option<String>, BackendError> {
+
+        let mut connection = self.conn_manager.clone();
+        Ok(connection.get(format!("{}{}", STALE_PREFIX, key)).await?)
+
+    }
+
+    #[tracing::instrument(level = "debug", skip_all, fields(cache_key = %key))]
+    async fn clear_hits(&self, key: &str) -> Result<(), BackendError> {
+
+        let mut connection = self.conn_manager.clone();
+        Ok(connection.zrem(HIT_COUNTS_KEY, key).await?)
+
+    }
+
+    #[tracing::instrument(level = "debug", skip_all, fields(min_hits, ttl_below, limit))]
+    async fn popular_expiring(&self, min_hits: u64, ttl_below: u64, limit: usize) -> Result<Vec<String>, BackendError> {
+
+        let mut connection = self.conn_manager.clone();
+
+        let candidates: Vec<String> = connection
+            .zrevrangebyscore_limit(HIT_COUNTS_KEY, "+inf", min_hits, 0, REFRESH_CANDIDATES)
+            .await?;
+        if candidates.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut pipe = redis::pipe();
+        for key in &candidates {
+            pipe.ttl(key);
+        }
+        let ttls: Vec<i64> = pipe.query_async(&mut connection).await?;
+
+        // -2 means the key is gone, -1 that it never expires
+        let expired: Vec<&String> = candidates.iter().zip(&ttls)
+            .filter(|(_, ttl)| **ttl == -2)
+            .map(|(key, _)| key)
+            .collect();
+        if !expired.is_empty() {
+            connection.zrem::<_, _, ()>(HIT_COUNTS_KEY, expired).await?;
+        }
+
+        Ok(candidates.iter().zip(&ttls)
+            .filter(|(_, ttl)| **ttl >= 0 && (**ttl as u64) < ttl_below)
+            .map(|(key, _)| key.clone())
+            .take(limit)
+            .collect())
+
+    }
+
+    // SCAN with COUNT 100, so a page can hold a few more or fewer keys
+    #[tracing::instrument(level = "debug", skip_all, fields(pattern, cursor))]
+    async fn scan_page(&self, pattern: &str, cursor: u64) -> Result<(u64, Vec<String>), BackendError> {
+
+        let mut connection = self.conn_manager.clone();
+        Ok(redis::cmd("SCAN")
+            .arg(cursor)
+            .arg("MATCH").arg(pattern)
+            .arg("COUNT").arg(100)
+            .query_async(&mut connection)
+            .await?)
+
+    }
+
+    // keeps only a running total so memory use doesn't grow with the number of keys
+    #[tracing::instrument(level = "debug", skip_all, fields(pattern))]
+    async fn count_keys_matching(&self, pattern: &str) -> Result<u64, BackendError> {
+
+        let mut connection = self.conn_manager.clone();
+        let mut cursor: u64 = 0;
+        let mut count: u64 = 0;
+
+        loop {
+            let (next, batch): (u64, Vec<String>) = redis::cmd("SCAN")
+                .arg(cursor)
+                .arg("MATCH").arg(pattern)
+                .arg("COUNT").arg(100)
+                .query_async(&mut connection)
+                .await?;
+            count += batch.len() as u64;
+            cursor = next;
+            if cursor == 0 {
+                break;
+            }
+        }
+
+        Ok(count)
+
+    }
+
+    // memory and eviction figures come from INFO. When the Redis user lacks
+    // permission for INFO only the key count is returned
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn info(&self, exact_prefix: &str) -> Result<RedisInfo, BackendError> {
+
+        let mut connection = self.conn_manager.clone();
+
+        let key_count = self.count_keys_matching(&format!("{}*", exact_prefix)).await?;
+
+        let sections: Result<(String, String), _> = redis::pipe()
+            .cmd("INFO").arg("memory")
+            .cmd("INFO").arg("stats")
+            .query_async(&mut connection)
+            .await;
+
+        let mut info = RedisInfo { key_count, ..Default::default() };
+        match sections {
+            Ok((memory, stats)) => {
+                info.used_memory_bytes = info_field(&memory, "used_memory").and_then(|v| v.parse().ok());
+                info.used_memory_human = info_field(&memory, "used_memory_human").map(|v| v.to_string());
+                info.maxmemory_bytes = info_field(&memory, "maxmemory").and_then(|v| v.parse().ok());
+                info.evicted_keys = info_field(&stats, "evicted_keys").and_then(|v| v.parse().ok());
+            }
+            Err(e) => tracing::warn!("Redis INFO unavailable, reporting key count only: {}", e)
+        }
+
+        Ok(info)
+
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn flush_all(&self) -> Result<(), BackendError> {
+        let mut connection = self.conn_manager.clone();
+        redis::cmd("FLUSHDB")
+            .query_async::<redis::Value>(&mut connection)
+            .await?;
+        Ok(())
+    }
+
+}
+
+// Redis hits a key needs before it is copied into tier 0
+const TIER0_PROMOTE_AFTER: u32 = 3;
+
+/// Tier 0: a small in-process LRU in front of Redis for the hottest exact-match
+/// entries, saving the Redis round trip. Only keys that keep getting Redis hits
+/// are promoted, and entries expire after `ttl` so invalidations catch up quickly
+pub struct InMemoryCache {
+    entries: Mutex<LruCache<String, (LLMResponse, Instant)>>,
+    // Redis hits per key since the counters were last cleared
+    redis_hits: DashMap<String, u32>,
+    ttl: Duration
+}
+
+impl InMemoryCache {
+
+    pub fn new(capacity: NonZeroUsize, ttl: Duration) -> Self {
+
+        InMemoryCache {
+            entries: Mutex::new(LruCache::new(capacity)),
+            redis_hits: DashMap::new(),
+            ttl
+        }
+
+    }
+
+    /// Looks up a key, evicting every expired entry first
+    pub fn get(&self, key: &str) -> Option<LLMResponse> {
+
+        let mut entries = self.entries.lock().unwrap();
+
+        let expired: Vec<String> = entries.iter()
+            .filter(|(_, (_, stored_at))| stored_at.elapsed() >= self.ttl)
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in expired {
+            entries.pop(&key);
+        }
+
+        entries.get(key).map(|(response, _)| response.clone())
+
+    }
+
+    /// Whether `key` holds a live entry, without touching its LRU position
+    pub fn contains(&self, key: &str) -> bool {
+        self.entries.lock().unwrap().peek(key)
+            .is_some_and(|(_, stored_at)| stored_at.elapsed() < self.ttl)
+    }
+
+    /// Counts a Redis hit for `key` and promotes the response into tier 0 on
+    /// the `TIER0_PROMOTE_AFTER`th one. Returns whether it was promoted
+    pub fn record_redis_hit(&self, key: &str, response: &LLMResponse) -> bool {
+
+        let hits = {
+            let mut hits = self.redis_hits.entry(key.to_string()).or_insert(0);
+            *hits += 1;
+            *hits
+        };
+
+        if hits < TIER0_PROMOTE_AFTER {
+            return false;
+        }
+
+        self.redis_hits.remove(key);
+        self.entries.lock().unwrap().put(key.to_string(), (response.clone(), Instant::now()));
+        true
+
+    }
+
+    /// Resets the per-key Redis hit counters, so promotion needs
+    /// `TIER0_PROMOTE_AFTER` hits within one counting window
+    pub fn clear_hit_counts(&self) {
+        self.redis_hits.clear();
+    }
+
+    pub fn remove(&self, key: &str) {
+        self.entries.lock().unwrap().pop(key);
+        self.redis_hits.remove(key);
+    }
+
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+        self.redis_hits.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+}
+
+/// Exact-match hit counts per key for `GET /admin/cache/hot`. Bounded: once
+/// full, the least recently hit key is dropped to make room
+pub struct HotKeyTracker {
+    hits: Mutex<LruCache<String, u64>>
+}
+
+impl HotKeyTracker {
+
+    pub fn new(capacity: NonZeroUsize) -> Self {
+        HotKeyTracker { hits: Mutex::new(LruCache::new(capacity)) }
+    }
+
+    pub fn record(&self, key: &str) {
+
+        let mut hits = self.hits.lock().unwrap();
+        match hits.get_mut(key) {
+            Some(count) => *count += 1,
+            None => {
+                hits.put(key.to_string(), 1);
+            }
+        }
+
+    }
+
+    /// Up to `limit` keys, most hit first
+    pub fn top(&self, limit: usize) -> Vec<(String, u64)> {
+
+        let mut top: Vec<(String, u64)> = self.hits.lock().unwrap().iter()
+            .map(|(key, count)| (key.clone(), *count))
+            .collect();
+        top.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        top.truncate(limit);
+        top
+
+    }
+
+    pub fn remove(&self, key: &str) {
+        self.hits.lock().unwrap().pop(key);
+    }
+
+    /// Hits recorded for `key`, 0 once it has been evicted or was never hit
+    pub fn hits(&self, key: &str) -> u64 {
+        self.hits.lock().unwrap().peek(key).copied().unwrap_or(0)
+    }
+
+    pub fn clear(&self) {
+        self.hits.lock().unwrap().clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.hits.lock().unwrap().len()
+    }
+
+}
+
+/// Size of the exact-match tier. INFO-derived fields are `None` when the
+/// Redis user isn't allowed to run INFO
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RedisInfo {
+    // keys in the current exact-match namespace only, not the whole database
+    pub key_count: u64,
+    pub used_memory_bytes: Option<u64>,
+    pub used_memory_human: Option<String>,
+    // 0 means no limit
+    pub maxmemory_bytes: Option<u64>,
+    pub evicted_keys: Option<u64>
+}
+
+fn payload_str(payload: &HashMap<String, qdrant_client::qdrant::Value>, key: &str) -> Option<String> {
+    match payload.get(key).and_then(|v| v.kind.as_ref()) {
+        Some(Kind::StringValue(s)) => Some(s.clone()),
+        _ => None
+    }
+}
+
+// reads "name:value" out of an INFO section
+fn info_field<'a>(info: &'a str, name: &str) -> Option<&'a str> {
+    info.lines()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value.trim())
+}
+
+pub const EMBEDDING_DIM: usize = 384;
+
+// stands in for EMBEDDING_URL with EMBEDDING_PROVIDER=local, so logs and
+// embedding cache keys name the in-process model
+pub const LOCAL_EMBEDDING_URL: &str = "local://all-MiniLM-L6-v2";
+
+// points sampled to estimate the average payload size
+const PAYLOAD_SAMPLE_SIZE: u32 = 64;
+
+/// Size of the semantic tier
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct QdrantUsage {
+    pub points_count: u64,
+    // raw f32 vector data, excluding index overhead
+    pub vector_bytes: u64,
+    // extrapolated from a sample of points
+    pub estimated_payload_bytes: u64
+}
+
+// how long collection_stats reuses a collection_info answer, so /health polling stays cheap
+const COLLECTION_STATS_TTL: Duration = Duration::from_secs(30);
+
+/// Counts from Qdrant's collection info for the active collection
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct CollectionStats {
+    // one unnamed vector per point, so this equals points_count
+    pub vectors_count: u64,
+    pub indexed_vectors_count: u64,
+    pub points_count: u64,
+    pub segments_count: u64
+}
+
+// points read per scroll request when collecting one payload field from every point
+const PAYLOAD_SCROLL_PAGE: u32 = 1000;
+
+/// Spread of the upstream latencies recorded with semantic cache entries
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct LatencyStats {
+    pub entries: u64,
+    pub min_ms: u64,
+    pub max_ms: u64,
+    pub mean_ms: f64,
+    pub p95_ms: u64
+}
+
+impl LatencyStats {
+
+    /// None when there are no samples
+    pub fn from_samples(mut samples: Vec<u64>) -> Option<Self> {
+
+        if samples.is_empty() {
+            return None;
+        }
+        samples.sort_unstable();
+
+        let entries = samples.len() as u64;
+        // nearest-rank percentile
+        let p95_rank = (samples.len() * 95).div_ceil(100).max(1);
+
+        Some(LatencyStats {
+            entries,
+            min_ms: samples[0],
+            max_ms: samples[samples.len() - 1],
+            mean_ms: samples.iter().sum::<u64>() as f64 / entries as f64,
+            p95_ms: samples[p95_rank - 1]
+        })
+
+    }
+
+}
+
+#[derive(Debug)]
+pub enum CacheError {
+    Qdrant(qdrant_client::QdrantError),
+    DimensionMismatch { expected: usize, actual: usize }
+}
+
+impl std::fmt::Display for CacheError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CacheError::Qdrant(e) => write!(f, "Qdrant error: {}", e),
+            CacheError::DimensionMismatch { expected, actual } => write!(
+                f, "Qdrant collection has {}-dim vectors but embeddings are {}-dim", actual, expected
+            )
+        }
+    }
+}
+
+impl std::error::Error for CacheError {}
+
+impl From<qdrant_client::QdrantError> for CacheError {
+    fn from(e: qdrant_client::QdrantError) -> Self {
+        CacheError::Qdrant(e)
+    }
+}
+
+/// A semantic cache match along with its cosine similarity score
+#[derive(Debug, Clone)]
+pub struct SemanticHit {
+    pub point_id: String,
+    pub cache_key: String,
+    pub response: String,
+    pub score: f32,
+    // entries stored before temperature tracking won't have it
+    pub temperature: Option<f32>,
+    // likewise for model and max_tokens, which were added later still
+    pub model: Option<String>,
+    pub max_tokens: Option<u32>,
+    // None for an untenanted entry
+    pub tenant: Option<String>,
+    // likewise for entries stored before latency tracking
+    pub original_latency_ms: Option<u64>
+}
+
+/// A stored point with its full payload, read back to be copied elsewhere
+#[derive(Debug, Clone)]
+pub struct StoredPoint {
+    pub id: String,
+    pub payload: serde_json::Map<String, serde_json::Value>
+}
+
+impl StoredPoint {
+
+    pub fn prompt(&self) -> Option<&str> {
+        self.payload.get("prompt").and_then(|prompt| prompt.as_str())
+    }
+
+}
+
+// point ids are UUIDs, except in collections written by other tools
+fn point_id(id: &str) -> PointId {
+    match id.parse::<u64>() {
+        Ok(num) => num.into(),
+        Err(_) => id.into()
+    }
+}
+
+fn point_id_string(id: Option<PointId>) -> Option<String> {
+    match id?.point_id_options? {
+        PointIdOptions::Uuid(uuid) => Some(uuid),
+        PointIdOptions::Num(num) => Some(num.to_string())
+    }
+}
+
+/// Unix time `ttl` seconds from now, stored as a point's `expires_at`
+pub fn expires_at(ttl: u64) -> i64 {
+    chrono::Utc::now().timestamp().saturating_add(ttl.try_into().unwrap_or(i64::MAX))
+}
+
+// points past their expiry; a point without `expires_at` never matches, so it's kept
+fn expired() -> Condition {
+    Condition::range("expires_at", Range { lte: Some(chrono::Utc::now().timestamp() as f64), ..Default::default() })
+}
+
+/// Whether a response cached at `stored` temperature may be served for a
+/// request at `requested`. Entries stored before temperature tracking
+/// don't have the field and are treated as compatible
+pub fn temperature_compatible(stored: Option<f32>, requested: f32) -> bool {
+    stored.is_none_or(|stored| (stored - requested).abs() <= 0.05)
+}
+
+/// The request parameters a response was cached with, kept in its semantic
+/// point. A match is only served to a request from the same tenant for the
+/// same model and max_tokens at a compatible temperature
+#[derive(Debug, Clone, PartialEq)]
+pub struct EntryParams {
+    pub model: String,
+    pub temperature: f32,
+    pub max_tokens: Option<u32>,
+    pub tenant: Option<String>
+}
+
+impl EntryParams {
+
+    /// The parameters of an untenanted request; see `AppState::entry_params`
+    pub fn of(request: &LLMRequest) -> Self {
+        EntryParams {
+            model: request.model.clone(),
+            temperature: request.temperature.unwrap_or(0.0),
+            max_tokens: request.max_tokens,
+            tenant: None
+        }
+    }
+
+    /// Whether `hit` may be served for these parameters. Points stored
+    /// without a model can't be told apart, so they never match
+    pub fn matches(&self, hit: &SemanticHit) -> bool {
+        hit.model.as_deref() == Some(self.model.as_str())
+            && hit.max_tokens == self.max_tokens
+            && hit.tenant == self.tenant
+            && temperature_compatible(hit.temperature, self.temperature)
+    }
+
+    // the same check as `matches`, run by Qdrant so the best match isn't crowded out
+    fn filter(&self) -> Vec<Condition> {
+
+        let temperature = self.temperature as f64;
+        vec![
+            Condition::matches("model", self.model.clone()),
+            match self.max_tokens {
+                Some(max_tokens) => Condition::matches("max_tokens", max_tokens as i64),
+                None => Condition::is_empty("max_tokens")
+            },
+            match &self.tenant {
+                Some(tenant) => Condition::matches("tenant", tenant.clone()),
+                None => Condition::is_empty("tenant")
+            },
+            Condition::range("temperature", Range { gte: Some(temperature - 0.05), lte: Some(temperature + 0.05), ..Default::default() })
+        ]
+
+    }
+
+}
+
+/// Outcome of checking the Qdrant collection against the embedding dimension
+#[derive(Debug, Clone, PartialEq)]
+pub enum CollectionValidation {
+    Valid,
+    Created,
+    Recreated { previous_dim: usize }
+}
+
+impl std::fmt::Display for CollectionValidation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CollectionValidation::Valid => write!(f, "valid"),
+            CollectionValidation::Created => write!(f, "created"),
+            CollectionValidation::Recreated { previous_dim } => write!(f, "recreated (was {}-dim)", previous_dim)
+        }
+    }
+}
+
+pub const DEFAULT_QDRANT_MAX_CONNECTIONS: usize = 4;
+
+/// Qdrant connection pool size and how many requests are using it right now
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct QdrantPoolStats {
+    pub pool_size: usize,
+    pub in_flight_requests: usize
+}
+
+/// The Qdrant client, counted as one in-flight request until dropped.
+/// Used as a temporary, so the count covers exactly one call
+struct InFlight<'a> {
+    client: &'a Qdrant,
+    counter: &'a AtomicUsize
+}
+
+impl Deref for InFlight<'_> {
+    type Target = Qdrant;
+    fn deref(&self) -> &Qdrant {
+        self.client
+    }
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.counter.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[derive(Clone)]
+pub struct QdrantCache {
+    client: Qdrant,
+    // swapped when a re-embed migration switches collections; shared by every clone
+    collection_name: Arc<ArcSwap<String>>,
+    embedding_dim: Arc<AtomicUsize>,
+    validation: CollectionValidation,
+    // gRPC channels the client round-robins over (QDRANT_MAX_CONNECTIONS)
+    pool_size: usize,
+    in_flight: Arc<AtomicUsize>,
+    // last collection_stats answer, shared by every clone
+    stats: Arc<Mutex<Option<(Instant, CollectionStats)>>>
+}
+
+impl QdrantCache {
+
+    #[cfg(test)]
+    #[tracing::instrument(level = "debug", skip_all)]
+    pub async fn new(qdrant_url: &str) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+
+        Self::with_collection(qdrant_url, "llm_cache", EMBEDDING_DIM, DEFAULT_QDRANT_MAX_CONNECTIONS).await
+
+    }
+
+    #[tracing::instrument(level = "debug", skip_all, fields(collection = %collection_name, max_connections))]
+    pub async fn with_collection(
+        qdrant_url: &str,
+        collection_name: &str,
+        embedding_dim: usize,
+        max_connections: usize
+    ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+
+        // connect to qdrant. The client opens up to `max_connections` gRPC
+        // channels and spreads requests across them round-robin
+        let pool_size = max_connections.max(1);
+        let mut config = Qdrant::from_url(qdrant_url);
+        config.set_pool_size(pool_size);
+        let client = config.build()?;
+
+        let mut cache = QdrantCache {
+            client,
+            collection_name: Arc::new(ArcSwap::from_pointee(collection_name.to_string())),
+            embedding_dim: Arc::new(AtomicUsize::new(embedding_dim)),
+            validation: CollectionValidation::Valid,
+            pool_size,
+            in_flight: Arc::new(AtomicUsize::new(0)),
+            stats: Arc::new(Mutex::new(None))
+        };
+
+        // create the collection if it doesn't exist and check its vector size
+        cache.validation = cache.validate_or_recreate(embedding_dim).await?;
+        tracing::info!("Qdrant collection '{}': {}", cache.collection_name(), cache.validation);
+
+        Ok(cache)
+
+    }
+
+    /// Checks the collection exists with `expected_dim` vectors. A missing collection
+    /// is created; a mismatched one is recreated, or rejected when
+    /// `STRICT_COLLECTION_VALIDATION=true`
+    #[tracing::instrument(level = "debug", skip_all, fields(collection = %self.collection_name(), expected_dim))]
+    pub async fn validate_or_recreate(&self, expected_dim: usize) -> Result<CollectionValidation, CacheError> {
+
+        if !self.client().collection_exists(self.collection_name().as_str()).await? {
+            self.create_collection(expected_dim).await?;
+            return Ok(CollectionValidation::Created);
+        }
+
+        let actual_dim = self.collection_dim(self.collection_name().as_str()).await?;
+
+        match actual_dim {
+            Some(dim) if dim == expected_dim => Ok(CollectionValidation::Valid),
+            actual => {
+                let actual = actual.unwrap_or(0);
+                tracing::warn!(
+                    "Qdrant collection '{}' has {}-dim vectors, expected {}",
+                    self.collection_name(), actual, expected_dim
+                );
+
+                let strict = std::env::var("STRICT_COLLECTION_VALIDATION")
+                    .map(|v| v.to_lowercase() == "true")
+                    .unwrap_or(false);
+
+                if strict {
+                    return Err(CacheError::DimensionMismatch { expected: expected_dim, actual });
+                }
+
+                tracing::warn!("recreating Qdrant collection '{}' - cached vectors are lost", self.collection_name());
+                self.client().delete_collection(self.collection_name().as_str()).await?;
+                self.create_collection(expected_dim).await?;
+                Ok(CollectionValidation::Recreated { previous_dim: actual })
+            }
+        }
+
+    }
+
+    async fn create_collection(&self, dim: usize) -> Result<(), CacheError> {
+
+        self.create_named_collection(self.collection_name().as_str(), dim).await
+
+    }
+
+    async fn create_named_collection(&self, collection: &str, dim: usize) -> Result<(), CacheError> {
+
+        self.client().create_collection(CreateCollectionBuilder::new(collection)
+            .vectors_config(VectorParamsBuilder::new(dim as u64, Distance::Cosine)))
+            .await?;
+        Ok(())
+
+    }
+
+    // vector size of a single-vector collection; None for named-vector collections
+    async fn collection_dim(&self, collection: &str) -> Result<Option<usize>, CacheError> {
+
+        let info = self.client().collection_info(collection).await?;
+
+        Ok(info.result
+            .and_then(|i| i.config)
+            .and_then(|c| c.params)
+            .and_then(|p| p.vectors_config)
+            .and_then(|v| v.config)
+            .and_then(|c| match c {
+                vectors_config::Config::Params(params) => Some(params.size as usize),
+                vectors_config::Config::ParamsMap(_) => None
+            }))
+
+    }
+
+    fn client(&self) -> InFlight<'_> {
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        InFlight { client: &self.client, counter: &self.in_flight }
+    }
+
+}
+
+#[async_trait]
+impl SemanticStore for QdrantCache {
+
+    fn name(&self) -> &'static str {
+        "qdrant"
+    }
+
+    /// The collection currently being read and written
+    fn collection_name(&self) -> Arc<String> {
+        self.collection_name.load_full()
+    }
+
+    /// Points this cache and every clone of it at another, existing collection
+    /// holding `embedding_dim`-sized vectors
+    fn switch_collection(&self, collection: &str, embedding_dim: usize) {
+        tracing::info!("Qdrant collection switched to '{}' ({}-dim)", collection, embedding_dim);
+        self.embedding_dim.store(embedding_dim, Ordering::Relaxed);
+        self.collection_name.store(Arc::new(collection.to_string()));
+        self.stats.lock().unwrap().take();
+    }
+
+    /// Point, vector and segment counts for the active collection, cached
+    /// for `COLLECTION_STATS_TTL`
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn collection_stats(&self) -> Result<CollectionStats, CacheError> {
+
+        if let Some((fetched_at, stats)) = *self.stats.lock().unwrap()
+            && fetched_at.elapsed() < COLLECTION_STATS_TTL {
+            return Ok(stats);
+        }
+
+        let info = self.client().collection_info(self.collection_name().as_str()).await?;
+        let stats = info.result
+            .map(|info| {
+                let points_count = info.points_count.unwrap_or(0);
+                CollectionStats {
+                    vectors_count: points_count,
+                    indexed_vectors_count: info.indexed_vectors_count.unwrap_or(0),
+                    points_count,
+                    segments_count: info.segments_count
+                }
+            })
+            .unwrap_or_default();
+
+        *self.stats.lock().unwrap() = Some((Instant::now(), stats));
+        Ok(stats)
+
+    }
+
+    /// The last counts `collection_stats` fetched, however old, without a round trip
+    fn cached_collection_stats(&self) -> Option<CollectionStats> {
+        self.stats.lock().unwrap().map(|(_, stats)| stats)
+    }
+
+    /// Creates `collection` with `dim`-sized vectors unless it already exists.
+    /// An existing collection of a different size is an error
+    #[tracing::instrument(level = "debug", skip_all, fields(collection = %collection, dim))]
+    async fn ensure_collection(&self, collection: &str, dim: usize) -> Result<(), CacheError> {
+
+        if !self.client().collection_exists(collection).await? {
+            return self.create_named_collection(collection, dim).await;
+        }
+
+        match self.collection_dim(collection).await? {
+            Some(actual) if actual == dim => Ok(()),
+            actual => Err(CacheError::DimensionMismatch { expected: dim, actual: actual.unwrap_or(0) })
+        }
+
+    }
+
+    #[tracing::instrument(level = "debug", skip_all, fields(collection = %collection))]
+    async fn collection_points(&self, collection: &str) -> Result<u64, CacheError> {
+
+        let info = self.client().collection_info(collection).await?;
+        Ok(info.result.and_then(|r| r.points_count).unwrap_or(0))
+
+    }
+
+    /// One page of points from `collection` starting at `offset`, along with
+    /// the offset of the next page (None once the end is reached)
+    #[tracing::instrument(level = "debug", skip_all, fields(collection = %collection, limit))]
+    async fn scroll_points(
+        &self,
+        collection: &str,
+        offset: Option<&str>,
+        limit: u32
+    ) -> Result<(Vec<StoredPoint>, Option<String>), CacheError> {
+
+        let mut scroll = ScrollPointsBuilder::new(collection)
+            .limit(limit)
+            .with_payload(true)
+            .with_vectors(false);
+        if let Some(offset) = offset {
+            scroll = scroll.offset(point_id(offset));
+        }
+
+        let response = self.client().scroll(scroll).await?;
+
+        let points = response.result.into_iter()
+            .filter_map(|point| Some(StoredPoint {
+                id: point_id_string(point.id)?,
+                payload: point.payload.into_iter().map(|(key, value)| (key, value.into_json())).collect()
+            }))
+            .collect();
+
+        Ok((points, point_id_string(response.next_page_offset)))
+
+    }
+
+    /// The `original_latency_ms` of every entry in the active collection.
+    /// Entries stored before latency tracking are left out
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn original_latencies(&self) -> Result<Vec<u64>, CacheError> {
+
+        let collection = self.collection_name();
+        let mut latencies = Vec::new();
+        let mut offset = None;
+
+        loop {
+            let mut scroll = ScrollPointsBuilder::new(collection.as_str())
+                .limit(PAYLOAD_SCROLL_PAGE)
+                .with_payload(PayloadIncludeSelector::new(vec!["original_latency_ms".to_string()]))
+                .with_vectors(false);
+            if let Some(offset) = offset.take() {
+                scroll = scroll.offset(offset);
+            }
+
+            let response = self.client().scroll(scroll).await?;
+            latencies.extend(response.result.iter().filter_map(|point| {
+                match point.payload.get("original_latency_ms")?.kind.as_ref()? {
+                    Kind::IntegerValue(ms) => u64::try_from(*ms).ok(),
+                    _ => None
+                }
+            }));
+
+            match response.next_page_offset {
+                Some(next) => offset = Some(next),
+                None => break
+            }
+        }
+
+        Ok(latencies)
+
+    }
+
+    /// Writes points into `collection` under their existing ids, so writing
+    /// the same point twice overwrites it rather than duplicating it
+    #[tracing::instrument(level = "debug", skip_all, fields(collection = %collection, points = points.len()))]
+    async fn upsert_points(&self, collection: &str, points: Vec<(StoredPoint, Vec<f32>)>) -> Result<(), CacheError> {
+
+        if points.is_empty() {
+            return Ok(());
+        }
+
+        let points: Vec<PointStruct> = points.into_iter()
+            .map(|(point, embedding)| PointStruct::new(point_id(&point.id), embedding, Payload::from(point.payload)))
+            .collect();
+
+        self.client().upsert_points(UpsertPointsBuilder::new(collection, points)).await?;
+        Ok(())
+
+    }
+
+    fn validation(&self) -> &CollectionValidation {
+        &self.validation
+    }
+
+    fn pool_stats(&self) -> QdrantPoolStats {
+        QdrantPoolStats {
+            pool_size: self.pool_size,
+            in_flight_requests: self.in_flight.load(Ordering::Relaxed)
+        }
+    }
+
+    #[tracing::instrument(level = "debug", skip_all, fields(cache_key = %cache_key, temperature))]
+    async fn store(
+        &self,
+        cache_key: &str,
+        prompt: &str,
+        embedding: Vec<f32>,
+        cached_response: &str,
+        params: &EntryParams,
+        original_latency_ms: u64,
+        ttl: u64,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+
+        let mut payload = Payload::from([
+            ("cache_key", cache_key.into()),
+            ("prompt", prompt.into()),
+            ("response", cached_response.into()),
+            ("model", params.model.clone().into()),
+            ("temperature", (params.temperature as f64).into()),
+            // how long the upstream call took, reported on semantic hits
+            ("original_latency_ms", (original_latency_ms as i64).into()),
+            ("updated_at", chrono::Utc::now().to_rfc3339().into()),
+            ("expires_at", expires_at(ttl).into()),
+        ]);
+        // left out rather than null, so an unlimited request can filter on is_empty
+        if let Some(max_tokens) = params.max_tokens {
+            payload.insert("max_tokens", max_tokens as i64);
+        }
+        if let Some(tenant) = &params.tenant {
+            payload.insert("tenant", tenant.clone());
+        }
+        let point = PointStruct::new(Uuid::new_v4().to_string(), embedding, payload);
+
+        self.client()
+            .upsert_points(
+                UpsertPointsBuilder::new(self.collection_name().as_str(), vec![point])
+            )
+            .await?;
+
+        Ok(())
+
+    }
+
+    /// Overwrites a stored point's response in place, keeping its prompt and
+    /// vector. Used instead of `store` when a near-identical prompt is already cached
+    #[tracing::instrument(level = "debug", skip_all, fields(point_id = %point_id))]
+    async fn refresh_point(
+        &self,
+        point_id: &str,
+        cache_key: &str,
+        cached_response: &str,
+        params: &EntryParams,
+        original_latency_ms: u64,
+        ttl: u64,
+    ) -> Result<(), CacheError> {
+
+        // a duplicate only ever matched with the same model and max_tokens, so those stay as they are
+        let payload = Payload::from([
+            ("cache_key", cache_key.into()),
+            ("response", cached_response.into()),
+            ("temperature", (params.temperature as f64).into()),
+            ("original_latency_ms", (original_latency_ms as i64).into()),
+            ("updated_at", chrono::Utc::now().to_rfc3339().into()),
+            ("expires_at", expires_at(ttl).into())
+        ]);
+
+        self.client().set_payload(
+            SetPayloadPointsBuilder::new(self.collection_name().as_str(), payload)
+                .points_selector(vec![crate::cache::point_id(point_id)])
+        ).await?;
+
+        Ok(())
+
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn health_check(&self) -> bool {
+        self.client().list_collections().await.is_ok()
+    }
+
+    /// Deletes every point stored under `cache_key`
+    #[tracing::instrument(level = "debug", skip_all, fields(cache_key = %cache_key))]
+    async fn delete_by_cache_key(&self, cache_key: &str) -> Result<(), CacheError> {
+
+        self.client().delete_points(
+            DeletePointsBuilder::new(self.collection_name().as_str())
+                .points(Filter::must([Condition::matches("cache_key", cache_key.to_string())]))
+        ).await?;
+
+        Ok(())
+
+    }
+
+    /// Drops the active collection and creates it again empty, with the same vector size
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn recreate_collection(&self) -> Result<(), CacheError> {
 
-    // return formatted cache key
-    format!("cache:exact:{}:{}", hash_hex, model)
+        self.client().delete_collection(self.collection_name().as_str()).await?;
+        self.create_collection(self.embedding_dim.load(Ordering::Relaxed)).await
 
-}
+    }
 
-#[derive(Clone)]
-pub struct RedisCache {
-    conn_manager: ConnectionManager
-}
+    /// Deletes the points with these ids
+    #[tracing::instrument(level = "debug", skip_all, fields(points = point_ids.len()))]
+    async fn delete_points(&self, point_ids: &[String]) -> Result<(), CacheError> {
 
-impl RedisCache {
+        if point_ids.is_empty() {
+            return Ok(());
+        }
 
-    pub async fn new(redis_url: &str) -> Result<Self, redis::RedisError> {
+        let ids: Vec<PointId> = point_ids.iter().map(|id| point_id(id)).collect();
+        self.client().delete_points(
+            DeletePointsBuilder::new(self.collection_name().as_str()).points(ids)
+        ).await?;
 
-        let client = redis::Client::open(redis_url)?;
-        let conn_manager = ConnectionManager::new(client).await?;
-        Ok(RedisCache { conn_manager })
+        Ok(())
 
     }
 
-    pub async fn get(&self, key: &str) -> Result<Option<String>, redis::RedisError> {
-        
-        let mut connection = self.conn_manager.clone();
-        connection.get(key).await
+    /// Deletes every point cached for `model`
+    #[tracing::instrument(level = "debug", skip_all, fields(model = %model))]
+    async fn delete_by_model(&self, model: &str) -> Result<(), CacheError> {
+
+        self.client().delete_points(
+            DeletePointsBuilder::new(self.collection_name().as_str())
+                .points(Filter::must([Condition::matches("model", model.to_string())]))
+        ).await?;
+
+        Ok(())
 
     }
 
-    pub async fn set(&self, key: &str, value: &str) -> Result<(), redis::RedisError> {
+    /// Flags the points stored under `cache_key` so searches skip them,
+    /// keeping the vectors and payload for later analysis
+    #[tracing::instrument(level = "debug", skip_all, fields(cache_key = %cache_key))]
+    async fn quarantine_by_cache_key(&self, cache_key: &str, reason: Option<&str>) -> Result<(), CacheError> {
 
-        let mut connection = self.conn_manager.clone();
-        connection.set_ex(key, value, CACHE_TTL_SECONDS).await
+        let payload = Payload::from([
+            ("quarantined", true.into()),
+            ("quarantine_reason", reason.unwrap_or_default().into()),
+            ("quarantined_at", chrono::Utc::now().to_rfc3339().into())
+        ]);
+
+        self.client().set_payload(
+            SetPayloadPointsBuilder::new(self.collection_name().as_str(), payload)
+                .points_selector(Filter::must([Condition::matches("cache_key", cache_key.to_string())]))
+        ).await?;
+
+        Ok(())
 
     }
 
-    pub async fn set_with_ttl(&self, key: &str, value: &str, ttl: u64) -> Result<(), redis::RedisError> {
+    /// Up to `limit` quarantined points with their prompt, response and reason
+    #[tracing::instrument(level = "debug", skip_all, fields(limit))]
+    async fn quarantined(&self, limit: u32) -> Result<Vec<QuarantinedEntry>, CacheError> {
 
-        let mut connection = self.conn_manager.clone();
-        connection.set_ex(key, value, ttl).await
+        let result = self.client().scroll(
+            ScrollPointsBuilder::new(self.collection_name().as_str())
+                .filter(Filter::must([Condition::matches("quarantined", true)]))
+                .limit(limit)
+                .with_payload(true)
+                .with_vectors(false)
+        ).await?;
 
-    }
+        Ok(result.result.into_iter()
+            .map(|point| QuarantinedEntry {
+                cache_key: payload_str(&point.payload, "cache_key").unwrap_or_default(),
+                prompt: payload_str(&point.payload, "prompt"),
+                response: payload_str(&point.payload, "response").unwrap_or_default(),
+                reason: payload_str(&point.payload, "quarantine_reason").filter(|r| !r.is_empty()),
+                quarantined_at: payload_str(&point.payload, "quarantined_at").unwrap_or_default()
+            })
+            .collect())
 
-    pub async fn health_check(&self) -> bool {
-        let mut connection = self.conn_manager.clone();
-        redis::cmd("PING")
-            .query_async::<String>(&mut connection)
-            .await
-            .is_ok()
     }
 
-    pub async fn flush_all(&self) -> Result<(), redis::RedisError> {
-        let mut connection = self.conn_manager.clone();
-        redis::cmd("FLUSHDB")
-            .query_async::<redis::Value>(&mut connection)
-            .await
-            .map(|_| ())
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn purge_quarantined(&self) -> Result<(), CacheError> {
+
+        self.client().delete_points(
+            DeletePointsBuilder::new(self.collection_name().as_str())
+                .points(Filter::must([Condition::matches("quarantined", true)]))
+        ).await?;
+
+        Ok(())
+
     }
 
-}
+    /// Deletes every point whose `expires_at` has passed
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn delete_expired(&self) -> Result<(), CacheError> {
 
-#[derive(Clone)]
-pub struct QdrantCache {
-    client: Qdrant,
-    collection_name: String
-}
+        self.client().delete_points(
+            DeletePointsBuilder::new(self.collection_name().as_str())
+                .points(Filter::must([expired()]))
+        ).await?;
 
-impl QdrantCache {
+        Ok(())
 
-    pub async fn new(qdrant_url: &str) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+    }
 
-        // connect to qdrant
-        let client = Qdrant::from_url(qdrant_url).build()?;
-        let collection_name = "llm_cache".to_string();
+    /// Deletes the points closest to expiring until at most `max_points`
+    /// remain, returning how many were deleted
+    #[tracing::instrument(level = "debug", skip_all, fields(max_points))]
+    async fn trim_to(&self, max_points: u64) -> Result<u64, CacheError> {
 
-        // create collection if it doesn't exist
-        match client.create_collection(CreateCollectionBuilder::new(&collection_name)
-            .vectors_config(VectorParamsBuilder::new(384, Distance::Cosine)))
-            .await
-        {
-            Ok(_) => {}
-            Err(e) if e.to_string().contains("already exists") => {}
-            Err(e) => eprintln!("Warning: Qdrant collection creation failed: {}", e),
+        let collection = self.collection_name();
+        let points = self.collection_points(&collection).await?;
+        if points <= max_points {
+            return Ok(0);
         }
 
-        Ok(QdrantCache {
-            client,
-            collection_name
-        })
+        let mut expiries = Vec::new();
+        let mut offset = None;
 
-    }
+        loop {
+            let mut scroll = ScrollPointsBuilder::new(collection.as_str())
+                .limit(PAYLOAD_SCROLL_PAGE)
+                .with_payload(PayloadIncludeSelector::new(vec!["expires_at".to_string()]))
+                .with_vectors(false);
+            if let Some(offset) = offset.take() {
+                scroll = scroll.offset(offset);
+            }
 
-    pub async fn store(
-        &self,
-        cache_key: &str,
-        embedding: Vec<f32>,
-        cached_response: &str,
-        temperature: f32,
-    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+            let response = self.client().scroll(scroll).await?;
+            expiries.extend(response.result.into_iter().filter_map(|point| {
+                let expires_at = match point.payload.get("expires_at").and_then(|v| v.kind.as_ref()) {
+                    Some(Kind::IntegerValue(at)) => *at,
+                    // points from before expiry was tracked are the oldest
+                    _ => i64::MIN
+                };
+                Some((expires_at, point.id?))
+            }));
+
+            match response.next_page_offset {
+                Some(next) => offset = Some(next),
+                None => break
+            }
+        }
 
-        let point = PointStruct::new(
-            Uuid::new_v4().to_string(),
-            embedding,
-            [
-                ("cache_key", cache_key.into()),
-                ("response", cached_response.into()),
-                ("temperature", (temperature as f64).into()),
-            ]
-        );
+        expiries.sort_by_key(|(expires_at, _)| *expires_at);
+        let excess: Vec<PointId> = expiries.into_iter()
+            .take(points.saturating_sub(max_points) as usize)
+            .map(|(_, id)| id)
+            .collect();
+        let deleted = excess.len() as u64;
 
-        self.client
-            .upsert_points(
-                UpsertPointsBuilder::new(&self.collection_name, vec![point])
-            )
-            .await?;
+        self.client().delete_points(
+            DeletePointsBuilder::new(collection.as_str()).points(excess)
+        ).await?;
 
-        Ok(())
+        Ok(deleted)
 
     }
 
-    pub async fn health_check(&self) -> bool {
-        self.client.list_collections().await.is_ok()
+    /// Point count plus vector and payload size estimates for the collection
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn usage(&self) -> Result<QdrantUsage, CacheError> {
+
+        let info = self.client().collection_info(self.collection_name().as_str()).await?;
+        let points_count = info.result.and_then(|r| r.points_count).unwrap_or(0);
+
+        if points_count == 0 {
+            return Ok(QdrantUsage::default());
+        }
+
+        let sample = self.client().scroll(
+            ScrollPointsBuilder::new(self.collection_name().as_str())
+                .limit(PAYLOAD_SAMPLE_SIZE)
+                .with_payload(true)
+                .with_vectors(false)
+        ).await?;
+
+        let sampled = sample.result.len() as u64;
+        let sample_bytes: u64 = sample.result.iter()
+            .flat_map(|point| point.payload.iter())
+            .map(|(key, value)| key.len() as u64 + match &value.kind {
+                Some(Kind::StringValue(s)) => s.len() as u64,
+                _ => 8
+            })
+            .sum();
+
+        Ok(QdrantUsage {
+            points_count,
+            vector_bytes: points_count * (self.embedding_dim.load(Ordering::Relaxed) as u64) * 4,
+            estimated_payload_bytes: (sample_bytes * points_count).checked_div(sampled).unwrap_or(0)
+        })
+
     }
 
-    pub async fn search_similar(
+    /// Returns up to `limit` matches above the threshold, skipping the first
+    /// `offset` results so large collections can be walked page by page
+    #[tracing::instrument(level = "debug", skip_all, fields(similarity_threshold, limit, offset = ?offset))]
+    async fn search_paginated(
         &self,
         embedding: Vec<f32>,
         similarity_threshold: f32,
-        temperature: f32,
-    ) -> Result<Option<String>, Box<dyn std::error::Error + Send + Sync>> {
-
-        let search_result = self.client.search_points(
-            SearchPointsBuilder::new(&self.collection_name, embedding, 1)
+        limit: usize,
+        offset: Option<u64>,
+        params: Option<&EntryParams>,
+    ) -> Result<Vec<SemanticHit>, CacheError> {
+
+        let filter = Filter {
+            must: params.map(EntryParams::filter).unwrap_or_default(),
+            // quarantined entries are kept for analysis but never served, and
+            // expired ones may linger until the janitor's next pass
+            must_not: vec![Condition::matches("quarantined", true), expired()],
+            ..Default::default()
+        };
+        let mut search = SearchPointsBuilder::new(self.collection_name().as_str(), embedding, limit as u64)
             .with_payload(true)
             .score_threshold(similarity_threshold)
-        ).await?;
+            .filter(filter);
+
+        if let Some(offset) = offset {
+            search = search.offset(offset);
+        }
 
-        if let Some(point) = search_result.result.first() {
-            // Check temperature compatibility — don't return a cached response
-            // if it was generated with a significantly different temperature.
-            // Entries stored before this fix won't have the field; treat as compatible.
-            let stored_temp = point.payload.get("temperature")
+        let search_result = self.client().search_points(search).await?;
+
+        let mut hits = Vec::with_capacity(search_result.result.len());
+
+        for point in search_result.result {
+            let Some(point_id) = point_id_string(point.id) else {
+                continue;
+            };
+
+            let response = match point.payload.get("response").and_then(|v| v.kind.as_ref()) {
+                Some(Kind::StringValue(s)) => s.clone(),
+                // points without a response can't be served; skip them
+                _ => continue
+            };
+
+            let cache_key = match point.payload.get("cache_key").and_then(|v| v.kind.as_ref()) {
+                Some(Kind::StringValue(s)) => s.clone(),
+                _ => String::new()
+            };
+
+            let temperature = point.payload.get("temperature")
                 .and_then(|v| v.kind.as_ref())
                 .and_then(|k| if let Kind::DoubleValue(f) = k { Some(*f as f32) } else { None });
 
-            if let Some(stored) = stored_temp {
-                if (stored - temperature).abs() > 0.05 {
-                    return Ok(None);
-                }
-            }
+            let original_latency_ms = point.payload.get("original_latency_ms")
+                .and_then(|v| v.kind.as_ref())
+                .and_then(|k| if let Kind::IntegerValue(ms) = k { u64::try_from(*ms).ok() } else { None });
 
-            if let Some(response_value) = point.payload.get("response") {
-                if let Some(kind) = &response_value.kind {
-                    if let Kind::StringValue(s) = kind {
-                        return Ok(Some(s.clone()));
-                    }
-                }
-            }
+            let model = match point.payload.get("model").and_then(|v| v.kind.as_ref()) {
+                Some(Kind::StringValue(s)) => Some(s.clone()),
+                _ => None
+            };
+
+            let max_tokens = point.payload.get("max_tokens")
+                .and_then(|v| v.kind.as_ref())
+                .and_then(|k| if let Kind::IntegerValue(n) = k { u32::try_from(*n).ok() } else { None });
+
+            let tenant = payload_str(&point.payload, "tenant");
+
+            hits.push(SemanticHit {
+                point_id,
+                cache_key,
+                response,
+                score: point.score,
+                temperature,
+                model,
+                max_tokens,
+                tenant,
+                original_latency_ms
+            });
         }
 
-        Ok(None) // no match found
+        Ok(hits)
 
-    }
+    }}
+
+#[cfg(feature = "mock")]
+#[tracing::instrument(level = "debug", skip_all, fields(text_len = text.len()))]
+pub async fn get_embedding(
+    _http_client: &Client,
+    _endpoint: &EmbeddingEndpoint,
+    text: &str
+) -> Result<Vec<f32>, Box<dyn std::error::Error + Send + Sync>> {
+
+    Ok(crate::mock::fake_embedding(text))
 
 }
 
+#[cfg(not(feature = "mock"))]
+#[tracing::instrument(level = "debug", skip_all, fields(text_len = text.len()))]
 pub async fn get_embedding(
     http_client: &Client,
-    embedding_url: &str,
+    endpoint: &EmbeddingEndpoint,
     text: &str
 ) -> Result<Vec<f32>, Box<dyn std::error::Error + Send + Sync>> {
 
-    let response = http_client
-        .post(embedding_url)
-        .json(&json!({"text": text}))
-        .send()
-        .await?;
+    let request = match endpoint.provider {
+        #[cfg(feature = "local-embeddings")]
+        EmbeddingProvider::Local => return crate::local_embedding::embed(text).await,
+        EmbeddingProvider::OpenAi => http_client
+            .post(&endpoint.url)
+            .json(&json!({"model": endpoint.model, "input": text})),
+        _ => http_client
+            .post(&endpoint.url)
+            .json(&json!({"text": text}))
+    };
+    let request = match &endpoint.api_key {
+        Some(api_key) => request.bearer_auth(api_key),
+        None => request
+    };
 
+    let response = request.send().await?;
+    let status = response.status();
     let result: Value = response.json().await?;
+    if !status.is_success() {
+        let message = result["error"]["message"].as_str().unwrap_or("no error message");
+        return Err(format!("Embedding request failed with {}: {}", status, message).into());
+    }
 
-    let embedding: Vec<f32> = result["embedding"]
+    // OpenAI: {"data": [{"embedding": [...]}]}, the embedding service: {"embedding": [...]}
+    let embedding = match endpoint.provider {
+        EmbeddingProvider::OpenAi => &result["data"][0]["embedding"],
+        _ => &result["embedding"]
+    };
+    let embedding: Vec<f32> = embedding
         .as_array()
         .ok_or("No embedding in response")?
         .iter()
@@ -246,14 +1927,55 @@ pub async fn get_embedding(
 
 }
 
-pub async fn check_embedding_service(http_client: &Client, embedding_url: &str) -> bool {
-    let health_url = match reqwest::Url::parse(embedding_url) {
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+
+    if a.len() != b.len() || a.is_empty() {
+        return 0.0;
+    }
+
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm_a: f32 = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norm_b: f32 = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return 0.0;
+    }
+
+    dot / (norm_a * norm_b)
+
+}
+
+#[cfg(feature = "mock")]
+#[tracing::instrument(level = "debug", skip_all)]
+pub async fn check_embedding_service(_http_client: &Client, _endpoint: &EmbeddingEndpoint) -> bool {
+    true
+}
+
+#[cfg(not(feature = "mock"))]
+#[tracing::instrument(level = "debug", skip_all)]
+pub async fn check_embedding_service(http_client: &Client, endpoint: &EmbeddingEndpoint) -> bool {
+    #[cfg(feature = "local-embeddings")]
+    if endpoint.provider == EmbeddingProvider::Local {
+        return crate::local_embedding::health().await;
+    }
+
+    // OpenAI-compatible APIs have no health route; listing models checks the key too
+    let health_url = match reqwest::Url::parse(&endpoint.url) {
+        Ok(mut url) if endpoint.provider == EmbeddingProvider::OpenAi => {
+            let path = url.path().trim_end_matches('/').trim_end_matches("/embeddings").to_string();
+            url.set_path(&format!("{}/models", path));
+            url
+        }
         Ok(mut url) => { url.set_path("/health"); url }
         Err(_) => return false,
     };
+    let request = http_client.get(health_url);
+    let request = match &endpoint.api_key {
+        Some(api_key) => request.bearer_auth(api_key),
+        None => request
+    };
 
-    http_client
-        .get(health_url)
+    request
         .timeout(std::time::Duration::from_secs(3))
         .send()
         .await
@@ -266,45 +1988,325 @@ mod tests {
 
     use super::*;
     use crate::models::{LLMRequest, Message};
+    use crate::test_helpers::{test_embedding, test_llm_request, test_llm_response, test_params, user_message};
 
     #[test]
     fn test_same_prompts_same_key() {
 
-        let req1 = LLMRequest {
-            messages: vec![
-                    Message {
-                        role: "user".to_string(),
-                        content: "What is Rust?".to_string()
-                    }
-            ],
-            model: "gpt-4".to_string(),
-            temperature: Some(0.7),
-            max_tokens: None
-        };
-
+        let req1 = test_llm_request();
         let req2 = LLMRequest {
-            messages: vec![
-                Message {
-                    role: "user".to_string(),
-                    content: "   what is Rust?     ".to_string()
-                }
-            ],
-            model: "gpt-4".to_string(),
-            temperature: Some(0.7),
-            max_tokens: None
+            messages: vec![user_message("   what is Rust?     ")],
+            ..test_llm_request()
         };
 
-        let key1 = generate_cache_key(&req1);
-        let key2 = generate_cache_key(&req2);
+        let key1 = generate_cache_key(&req1, &KeyNormalization::default(), EXACT_KEY_PREFIX);
+        let key2 = generate_cache_key(&req2, &KeyNormalization::default(), EXACT_KEY_PREFIX);
 
         assert_eq!(key1, key2, "Normalized prompts should generate same key");
 
     }
 
+    #[test]
+    fn test_tier0_promotes_on_third_redis_hit() {
+
+        let tier0 = InMemoryCache::new(NonZeroUsize::new(2).unwrap(), Duration::from_secs(60));
+        let response = test_llm_response();
+
+        assert!(!tier0.record_redis_hit("a", &response));
+        assert!(!tier0.record_redis_hit("a", &response));
+        assert!(tier0.get("a").is_none());
+        assert!(tier0.record_redis_hit("a", &response), "Third Redis hit should promote");
+        assert_eq!(tier0.get("a").map(|r| r.id), Some(response.id.clone()));
+
+        // counters reset, so two more hits on another key aren't enough
+        tier0.record_redis_hit("b", &response);
+        tier0.clear_hit_counts();
+        tier0.record_redis_hit("b", &response);
+        assert!(!tier0.record_redis_hit("b", &response));
+
+        tier0.remove("a");
+        assert!(tier0.get("a").is_none());
+
+    }
+
+    #[test]
+    fn test_tier0_evicts_expired_entries() {
+
+        let tier0 = InMemoryCache::new(NonZeroUsize::new(10).unwrap(), Duration::ZERO);
+        let response = test_llm_response();
+
+        for _ in 0..3 {
+            tier0.record_redis_hit("a", &response);
+        }
+
+        assert!(tier0.get("a").is_none(), "Entries older than the TTL should not be served");
+        assert_eq!(tier0.len(), 0, "Expired entries should be evicted on lookup");
+
+    }
+
+    #[test]
+    fn test_hot_key_tracker_ranks_and_evicts() {
+
+        let tracker = HotKeyTracker::new(NonZeroUsize::new(2).unwrap());
+
+        for key in ["a", "b", "a", "a", "b"] {
+            tracker.record(key);
+        }
+        assert_eq!(tracker.top(10), vec![("a".to_string(), 3), ("b".to_string(), 2)]);
+        assert_eq!(tracker.top(1), vec![("a".to_string(), 3)]);
+
+        // "a" was hit less recently than "b", so it makes room for "c"
+        tracker.record("c");
+        assert_eq!(tracker.len(), 2);
+        assert_eq!(tracker.top(10), vec![("b".to_string(), 2), ("c".to_string(), 1)]);
+
+    }
+
+    #[test]
+    fn test_qdrant_in_flight_counter() {
+
+        // building the client doesn't connect, so no Qdrant is needed
+        let client = Qdrant::from_url("http://127.0.0.1:6334").skip_compatibility_check().build().unwrap();
+        let cache = QdrantCache {
+            client,
+            collection_name: Arc::new(ArcSwap::from_pointee("test".to_string())),
+            embedding_dim: Arc::new(AtomicUsize::new(EMBEDDING_DIM)),
+            validation: CollectionValidation::Valid,
+            pool_size: 3,
+            in_flight: Arc::new(AtomicUsize::new(0)),
+            stats: Arc::new(Mutex::new(None))
+        };
+
+        let first = cache.client();
+        let second = cache.client();
+        assert_eq!(cache.pool_stats().in_flight_requests, 2);
+
+        drop(first);
+        drop(second);
+        assert_eq!(cache.pool_stats().in_flight_requests, 0);
+        assert_eq!(cache.pool_stats().pool_size, 3);
+
+    }
+
+    #[test]
+    fn test_cosine_similarity() {
+
+        let a = vec![1.0, 0.0, 0.0];
+        let b = vec![1.0, 0.0, 0.0];
+        let c = vec![0.0, 1.0, 0.0];
+
+        assert!((cosine_similarity(&a, &b) - 1.0).abs() < 1e-6, "Identical vectors should score 1.0");
+        assert!(cosine_similarity(&a, &c).abs() < 1e-6, "Orthogonal vectors should score 0.0");
+        assert_eq!(cosine_similarity(&a, &[]), 0.0, "Mismatched lengths should score 0.0");
+
+        let embedding = test_embedding(EMBEDDING_DIM);
+        assert!((cosine_similarity(&embedding, &embedding) - 1.0).abs() < 1e-6);
+
+    }
+
+    #[test]
+    fn test_latency_stats() {
+
+        assert_eq!(LatencyStats::from_samples(vec![]), None);
+
+        let stats = LatencyStats::from_samples((1..=20).rev().map(|ms| ms * 100).collect()).unwrap();
+        assert_eq!(stats, LatencyStats { entries: 20, min_ms: 100, max_ms: 2000, mean_ms: 1050.0, p95_ms: 1900 });
+
+        let single = LatencyStats::from_samples(vec![42]).unwrap();
+        assert_eq!((single.min_ms, single.max_ms, single.p95_ms), (42, 42, 42));
+
+    }
+
+    #[test]
+    fn test_info_field() {
+
+        let info = "# Memory\r\nused_memory:1048576\r\nused_memory_human:1.00M\r\nmaxmemory:0\r\n";
+
+        assert_eq!(info_field(info, "used_memory"), Some("1048576"));
+        assert_eq!(info_field(info, "used_memory_human"), Some("1.00M"));
+        assert_eq!(info_field(info, "evicted_keys"), None);
+
+    }
+
+    #[test]
+    fn test_temperature_formatting_is_stable() {
+
+        let key_for = |temperature: Option<f32>| generate_cache_key(
+            &LLMRequest { temperature, ..test_llm_request() },
+            &KeyNormalization::default(),
+            EXACT_KEY_PREFIX
+        );
+
+        // 0.699999988 as sent by clients that serialize an f32 via f64
+        assert_eq!(key_for(Some(0.7)), key_for(Some(0.699_999_99_f64 as f32)));
+        assert_eq!(key_for(Some(0.7)), key_for(Some(0.1 + 0.6)));
+        assert_eq!(key_for(Some(0.0)), key_for(Some(-0.0)));
+        assert_ne!(key_for(Some(0.7)), key_for(Some(0.71)));
+        assert_ne!(key_for(None), key_for(Some(0.0)), "A missing temperature is not the same as 0");
+
+    }
+
+    fn prompt_key(content: &str, normalization: &KeyNormalization) -> String {
+        let request = LLMRequest {
+            messages: vec![user_message(content)],
+            ..test_llm_request()
+        };
+        generate_cache_key(&request, normalization, EXACT_KEY_PREFIX)
+    }
+
+    #[test]
+    fn test_sampling_params_split_the_key() {
+
+        let key = |request: &LLMRequest| generate_cache_key(request, &KeyNormalization::default(), EXACT_KEY_PREFIX);
+        let base = key(&test_llm_request());
+
+        let variants = [
+            LLMRequest { top_p: Some(0.5), ..test_llm_request() },
+            LLMRequest { stop: Some(serde_json::json!(["\n"])), ..test_llm_request() },
+            LLMRequest { seed: Some(7), ..test_llm_request() },
+            LLMRequest { frequency_penalty: Some(0.5), ..test_llm_request() },
+            LLMRequest { presence_penalty: Some(0.5), ..test_llm_request() },
+            LLMRequest { n: Some(2), ..test_llm_request() },
+            LLMRequest { response_format: Some(serde_json::json!({"type": "json_object"})), ..test_llm_request() },
+            LLMRequest { extra: Some(serde_json::json!({"logit_bias": {"50256": -100}}).as_object().cloned().unwrap()), ..test_llm_request() }
+        ];
+        let keys: std::collections::HashSet<String> = variants.iter().map(key).collect();
+        assert_eq!(keys.len(), variants.len());
+        assert!(!keys.contains(&base));
+
+        // who is asking doesn't change the answer
+        let with_user = LLMRequest { extra: Some(serde_json::json!({"user": "user-42"}).as_object().cloned().unwrap()), ..test_llm_request() };
+        assert_eq!(key(&with_user), base);
+        assert_eq!(key(&LLMRequest { top_p: Some(0.5), ..test_llm_request() }), key(&LLMRequest { top_p: Some(0.500001), ..test_llm_request() }));
+
+    }
+
+    #[test]
+    fn test_tools_split_the_key() {
+
+        let key = |request: &LLMRequest| generate_cache_key(request, &KeyNormalization::default(), EXACT_KEY_PREFIX);
+        let weather = serde_json::json!([{"type": "function", "function": {"name": "get_weather", "parameters": {"type": "object"}}}]);
+        let time = serde_json::json!([{"type": "function", "function": {"name": "get_time", "parameters": {"type": "object"}}}]);
+
+        let plain = key(&test_llm_request());
+        let with_weather = key(&LLMRequest { tools: Some(weather.clone()), ..test_llm_request() });
+        let with_time = key(&LLMRequest { tools: Some(time), ..test_llm_request() });
+        let forced = key(&LLMRequest { tools: Some(weather), tool_choice: Some(serde_json::json!("required")), ..test_llm_request() });
+        let keys: std::collections::HashSet<&String> = [&plain, &with_weather, &with_time, &forced].into_iter().collect();
+        assert_eq!(keys.len(), 4);
+
+        // a tool result answering a different call is a different conversation
+        let result = |id: &str| {
+            let mut request = test_llm_request();
+            request.messages.push(Message { role: "tool".to_string(), content: "18C".into(), tool_call_id: Some(id.to_string()), ..Default::default() });
+            key(&request)
+        };
+        assert_ne!(result("call_1"), result("call_2"));
+
+    }
+
+    #[test]
+    fn test_images_split_the_key() {
+
+        let key = |content: serde_json::Value| {
+            let mut request = test_llm_request();
+            request.messages = vec![Message { role: "user".to_string(), content: serde_json::from_value(content).unwrap(), ..Default::default() }];
+            generate_cache_key(&request, &KeyNormalization::default(), EXACT_KEY_PREFIX)
+        };
+        let with_image = |url: &str| key(serde_json::json!([
+            {"type": "text", "text": "What is this?"},
+            {"type": "image_url", "image_url": {"url": url}}
+        ]));
+
+        let text_only = key(serde_json::json!("What is this?"));
+        let keys: std::collections::HashSet<String> = [
+            text_only.clone(),
+            with_image("https://example.com/cat.png"),
+            with_image("https://example.com/dog.png"),
+            with_image("data:image/png;base64,AAAA"),
+            with_image("data:image/png;base64,BBBB")
+        ].into_iter().collect();
+        assert_eq!(keys.len(), 5);
+
+        // text parts alone hash like the same text sent as a string
+        assert_eq!(key(serde_json::json!([{"type": "text", "text": "What is this?"}])), text_only);
+
+    }
+
+    #[test]
+    fn test_whitespace_variants_same_key() {
+
+        let policy = KeyNormalization::default();
+        let expected = prompt_key("What is Rust?", &policy);
+
+        for variant in [
+            "What is  Rust?",          // double space
+            "What\tis Rust?",          // tab instead of space
+            "What is Rust?\n",         // trailing newline
+            "What\nis\r\nRust?",       // internal newlines
+            "What\u{00A0}is Rust?",    // non-breaking space
+            "What is\u{200B} Rust?"    // zero-width space
+        ] {
+            assert_eq!(prompt_key(variant, &policy), expected, "{:?} should normalize to the same key", variant);
+        }
+
+    }
+
+    #[test]
+    fn test_normalization_can_be_disabled() {
+
+        let strict = KeyNormalization { case_sensitive: true, collapse_whitespace: false };
+
+        assert_ne!(prompt_key("What is  Rust?", &strict), prompt_key("What is Rust?", &strict));
+        assert_ne!(prompt_key("what is rust?", &strict), prompt_key("What is Rust?", &strict));
+        assert_eq!(prompt_key("What is Rust?\n", &strict), prompt_key("What is Rust?", &strict), "Trimming still applies");
+
+    }
+
+    #[cfg(not(feature = "mock"))]
+    #[tokio::test]
+    async fn test_openai_embeddings() {
+
+        use axum::{Json, http::HeaderMap, routing::{get, post}};
+
+        // an OpenAI-compatible API that checks the key and echoes the model into the vector size
+        let authorized = |headers: &HeaderMap| headers.get("authorization").is_some_and(|v| v == "Bearer sk-embed");
+        let api = axum::Router::new()
+            .route("/v1/embeddings", post(move |headers: HeaderMap, Json(body): Json<Value>| async move {
+                assert!(authorized(&headers));
+                assert_eq!(body["input"], "What is Rust?");
+                let dim = if body["model"] == "text-embedding-3-large" { 3072 } else { 1536 };
+                Json(json!({"object": "list", "data": [{"object": "embedding", "index": 0, "embedding": vec![0.5; dim]}]}))
+            }))
+            .route("/v1/models", get(move |headers: HeaderMap| async move {
+                if authorized(&headers) { axum::http::StatusCode::OK } else { axum::http::StatusCode::UNAUTHORIZED }
+            }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/v1/embeddings", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, api).await });
+
+        let client = Client::new();
+        let endpoint = EmbeddingEndpoint {
+            provider: EmbeddingProvider::OpenAi,
+            url,
+            model: Some("text-embedding-3-large".to_string()),
+            api_key: Some("sk-embed".to_string())
+        };
+        assert_eq!(get_embedding(&client, &endpoint, "What is Rust?").await.unwrap().len(), 3072);
+        assert!(check_embedding_service(&client, &endpoint).await);
+
+        let other_model = EmbeddingEndpoint { model: Some("text-embedding-3-small".to_string()), ..endpoint.clone() };
+        assert_ne!(embedding_cache_key(&endpoint, "What is Rust?"), embedding_cache_key(&other_model, "What is Rust?"));
+
+        let no_key = EmbeddingEndpoint { api_key: None, ..endpoint };
+        assert!(!check_embedding_service(&client, &no_key).await);
+
+    }
+
     #[tokio::test]
     async fn test_get_embedding() {
         let client = Client::new();
-        let embedding = get_embedding(&client, "http://127.0.0.1:8001/embed", "What is Rust?")
+        let embedding = get_embedding(&client, &EmbeddingEndpoint::http("http://127.0.0.1:8001/embed"), "What is Rust?")
             .await
             .expect("Failed to get embedding");
 
@@ -320,27 +2322,99 @@ mod tests {
         let client = Client::new();
         
         // Get embedding for "What is Rust?"
-        let embedding1 = get_embedding(&client, "http://127.0.0.1:8001/embed", "What is Rust?")
+        let embedding1 = get_embedding(&client, &EmbeddingEndpoint::http("http://127.0.0.1:8001/embed"), "What is Rust?")
             .await
             .expect("Failed to get embedding");
         
         // Store it with a fake response
         qdrant.store(
             "test_key_1",
+            "What is Rust?",
             embedding1.clone(),
             "Rust is a programming language",
-            0.0,
+            &test_params(0.0),
+            120,
+            3600,
         ).await.expect("Failed to store");
 
         // Search with same embedding (should find exact match)
-        let result = qdrant.search_similar(embedding1, 0.99, 0.0)
+        let result = qdrant.search_similar(embedding1, 0.99, &test_params(0.0))
             .await
             .expect("Search failed");
         
         assert!(result.is_some(), "Should find the stored embedding");
-        assert_eq!(result.unwrap(), "Rust is a programming language");
+        assert_eq!(result.unwrap().response, "Rust is a programming language");
         
         println!("✅ Qdrant store and search working!");
     }
 
+    #[tokio::test]
+    async fn test_qdrant_search_paginated() {
+        let collection = format!("test_pagination_{}", Uuid::new_v4());
+        let qdrant = QdrantCache::with_collection("http://127.0.0.1:6334", &collection, EMBEDDING_DIM, 2).await
+            .expect("Failed to connect to Qdrant");
+
+        let client = Client::new();
+        let embedding = get_embedding(&client, &EmbeddingEndpoint::http("http://127.0.0.1:8001/embed"), "What is Rust?")
+            .await
+            .expect("Failed to get embedding");
+
+        // store 5 points with the same vector
+        for i in 0..5 {
+            qdrant.store(
+                &format!("page_key_{}", i),
+                "What is Rust?",
+                embedding.clone(),
+                &format!("response {}", i),
+                &test_params(0.0),
+                120,
+                3600,
+            ).await.expect("Failed to store");
+        }
+
+        let first_page = qdrant.search_paginated(embedding.clone(), 0.5, 2, None, None)
+            .await
+            .expect("Search failed");
+        let second_page = qdrant.search_paginated(embedding.clone(), 0.5, 2, Some(2), None)
+            .await
+            .expect("Search failed");
+        let last_page = qdrant.search_paginated(embedding, 0.5, 2, Some(4), None)
+            .await
+            .expect("Search failed");
+
+        let _ = qdrant.client.delete_collection(&collection).await;
+
+        assert_eq!(first_page.len(), 2);
+        assert_eq!(second_page.len(), 2);
+        assert_eq!(last_page.len(), 1);
+        assert!(
+            second_page.iter().all(|hit| first_page.iter().all(|p| p.cache_key != hit.cache_key)),
+            "Page 2 should not repeat page 1 results"
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis on 127.0.0.1:6379"]
+    async fn test_redis_atomic_increment_or_init_under_concurrency() {
+
+        let redis = Arc::new(RedisCache::new("redis://127.0.0.1:6379").await.expect("Failed to connect to Redis"));
+        let key = format!("{}test:{}", RATE_LIMIT_PREFIX, Uuid::new_v4());
+
+        let mut tasks = tokio::task::JoinSet::new();
+        for _ in 0..100 {
+            let redis = redis.clone();
+            let key = key.clone();
+            tasks.spawn(async move { redis.atomic_increment_or_init(&key, 60).await.unwrap() });
+        }
+        let mut counts = tasks.join_all().await;
+        counts.sort();
+
+        let ttl = redis.get_with_ttl(&key).await.unwrap().map(|(_, ttl)| ttl);
+        let _ = redis.delete(&key).await;
+
+        assert_eq!(counts, (1..=100).collect::<Vec<u32>>(), "Every caller should see a distinct count");
+        assert!(ttl.is_some_and(|ttl| (59..=60).contains(&ttl)), "The window starts on the first increment: {:?}", ttl);
+
+    }
+
 }
\ No newline at end of file
@@ -1,25 +1,804 @@
-use reqwest::Client;
-use crate::models::{LLMRequest, LLMResponse};
+use std::collections::VecDeque;
+use std::time::Duration;
+use axum::body::Bytes;
+use serde_json::Value;
+use reqwest::header::HeaderMap;
+use crate::models::{ApiError, LLMRequest, LLMResponse};
+use crate::AppState;
+use crate::metrics::ErrorCategory;
 
+// env vars checked for an upstream API key, in priority order
+pub const SUPPORTED_API_KEY_VARS: [&str; 2] = ["GROQ_API_KEY", "OPENAI_API_KEY"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Provider {
+    Groq,
+    OpenAi,
+    Anthropic,
+    Ollama
+}
+
+impl Provider {
+
+    pub const ALL: [Provider; 4] = [Provider::Groq, Provider::OpenAi, Provider::Anthropic, Provider::Ollama];
+
+    /// Also the model prefix that routes to this provider, e.g. `openai/gpt-4o`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Provider::Groq => "groq",
+            Provider::OpenAi => "openai",
+            Provider::Anthropic => "anthropic",
+            Provider::Ollama => "ollama"
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Provider> {
+        Provider::ALL.into_iter().find(|provider| provider.name() == name)
+    }
+
+    pub fn default_base_url(&self) -> &'static str {
+        match self {
+            Provider::Groq => "https://api.groq.com/openai/v1",
+            Provider::OpenAi => "https://api.openai.com/v1",
+            Provider::Anthropic => "https://api.anthropic.com/v1",
+            // Ollama's OpenAI-compatible API
+            Provider::Ollama => "http://localhost:11434/v1"
+        }
+    }
+
+    /// The env var overriding `default_base_url`. Groq is the default
+    /// upstream, whose base URL is UPSTREAM_BASE_URL
+    pub fn base_url_var(&self) -> &'static str {
+        match self {
+            Provider::Groq => "UPSTREAM_BASE_URL",
+            Provider::OpenAi => "OPENAI_BASE_URL",
+            Provider::Anthropic => "ANTHROPIC_BASE_URL",
+            Provider::Ollama => "OLLAMA_BASE_URL"
+        }
+    }
+
+    /// The env var holding this provider's API key; a local Ollama needs none
+    pub fn api_key_var(&self) -> Option<&'static str> {
+        match self {
+            Provider::Groq => Some("GROQ_API_KEY"),
+            Provider::OpenAi => Some("OPENAI_API_KEY"),
+            Provider::Anthropic => Some("ANTHROPIC_API_KEY"),
+            Provider::Ollama => None
+        }
+    }
+
+}
+
+/// Where one provider's requests go
+#[derive(Debug, Clone, PartialEq)]
+pub struct Upstream {
+    pub provider: Provider,
+    pub base_url: String,
+    pub api_key: Option<String>
+}
+
+/// Resolves the upstream API key from `GROQ_API_KEY`, falling back to
+/// `OPENAI_API_KEY` so OpenAI client setups work without env var changes
+pub fn resolve_api_key(lookup: impl Fn(&str) -> Option<String>) -> Result<(Provider, String), String> {
+
+    let non_empty = |name: &str| lookup(name).filter(|v| !v.trim().is_empty());
+
+    if let Some(key) = non_empty("GROQ_API_KEY") {
+        return Ok((Provider::Groq, key));
+    }
+
+    if let Some(key) = non_empty("OPENAI_API_KEY") {
+        tracing::warn!("GROQ_API_KEY is not set, using OPENAI_API_KEY instead. \
+            This fallback is deprecated; set GROQ_API_KEY to silence this warning.");
+        return Ok((Provider::Groq, key));
+    }
+
+    Err(format!(
+        "No upstream API key found. Set one of: {}",
+        SUPPORTED_API_KEY_VARS.join(", ")
+    ))
+
+}
+
+/// Validates an OpenAI-compatible base URL (e.g. `https://my-gateway.internal/v1`)
+/// and strips trailing slashes so paths can be appended safely
+pub fn normalize_base_url(raw: &str) -> Result<String, String> {
+
+    let trimmed = raw.trim().trim_end_matches('/');
+
+    let url = reqwest::Url::parse(trimmed)
+        .map_err(|e| format!("Invalid upstream base URL '{}': {}", raw, e))?;
+
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return Err(format!("Upstream base URL '{}' must use http or https", raw));
+    }
+
+    if url.host_str().is_none() {
+        return Err(format!("Upstream base URL '{}' has no host", raw));
+    }
+
+    Ok(trimmed.to_string())
+
+}
+
+pub fn upstream_url(base_url: &str, path: &str) -> String {
+    format!("{}/{}", base_url.trim_end_matches('/'), path.trim_start_matches('/'))
+}
+
+/// Maps a path the proxy doesn't handle onto the upstream. A leading `/v1`
+/// is dropped when the base URL already ends with it, so `/v1/completions`
+/// goes to `{base}/completions` rather than `{base}/v1/completions`
+pub fn passthrough_url(base_url: &str, path: &str, query: Option<&str>) -> String {
+
+    let base = base_url.trim_end_matches('/');
+    let path = match path.strip_prefix("/v1/") {
+        Some(rest) if base.ends_with("/v1") => rest,
+        _ => path
+    };
+
+    let url = upstream_url(base, path);
+    match query {
+        Some(query) => format!("{}?{}", url, query),
+        None => url
+    }
+
+}
+
+/// A failed upstream call. Non-2xx responses keep the status and the error
+/// message from the body (OpenAI format) instead of collapsing into a `reqwest::Error`.
+/// `body` is the upstream's error object when it sent one, relayed to the client as-is
+#[derive(Debug)]
+pub enum LLMError {
+    // 429 - `retry_after` comes from the Retry-After header, in seconds
+    RateLimited { retry_after: Option<u64>, message: String, body: Option<Box<ApiError>> },
+    // 401 - the configured API key was rejected
+    Unauthorized { message: String, body: Option<Box<ApiError>> },
+    // 5xx - worth retrying
+    Transient { status: u16, message: String, body: Option<Box<ApiError>> },
+    // any other non-2xx response
+    Rejected { status: u16, message: String, body: Option<Box<ApiError>> },
+    // a 2xx body that isn't a completion we understand; `path` names the offending field
+    InvalidResponse { path: String, message: String },
+    // not sent: the circuit breaker is open, with seconds until it lets a probe through
+    CircuitOpen { retry_after: u64 },
+    // the request never produced an HTTP response, or the body couldn't be read
+    Request(reqwest::Error)
+}
+
+impl LLMError {
+
+    pub async fn from_response(response: reqwest::Response) -> Self {
+
+        let status = response.status().as_u16();
+        let retry_after = response.headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.trim().parse::<u64>().ok());
+
+        match response.text().await {
+            Ok(body) => Self::from_parts(status, retry_after, &body),
+            Err(e) => LLMError::Request(e)
+        }
+
+    }
+
+    pub fn from_parts(status: u16, retry_after: Option<u64>, body: &str) -> Self {
+
+        let parsed: Option<Value> = serde_json::from_str(body).ok();
+
+        // OpenAI format: {"error": {"message": "...", "type": "...", "code": "..."}}
+        let message = parsed.as_ref()
+            .and_then(|v| v["error"]["message"].as_str())
+            .map(|m| m.to_string())
+            .unwrap_or_else(|| body.chars().take(500).collect());
+
+        let body = parsed.and_then(|v| serde_json::from_value(v).ok()).map(Box::new);
+
+        match status {
+            429 => LLMError::RateLimited { retry_after, message, body },
+            401 => LLMError::Unauthorized { message, body },
+            500..=599 => LLMError::Transient { status, message, body },
+            _ => LLMError::Rejected { status, message, body }
+        }
+
+    }
+
+    /// Worth trying again: rate limiting, a gateway or availability error, or
+    /// a connection that was never made
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            LLMError::RateLimited { .. } => true,
+            LLMError::Transient { status, .. } => matches!(status, 502..=504),
+            LLMError::Request(e) => e.is_connect(),
+            _ => false
+        }
+    }
+
+    /// The upstream is down rather than refusing this request: counted by the circuit breaker
+    pub fn is_outage(&self) -> bool {
+        matches!(self, LLMError::Transient { .. } | LLMError::Request(_))
+    }
+
+    /// The status of the upstream's error response, if it sent one
+    pub fn upstream_status(&self) -> Option<u16> {
+        match self {
+            LLMError::RateLimited { .. } => Some(429),
+            LLMError::Unauthorized { .. } => Some(401),
+            LLMError::Transient { status, .. } | LLMError::Rejected { status, .. } => Some(*status),
+            LLMError::InvalidResponse { .. } | LLMError::CircuitOpen { .. } | LLMError::Request(_) => None
+        }
+    }
+
+    /// The upstream's OpenAI-format error object, if its error response had one
+    pub fn upstream_body(&self) -> Option<&ApiError> {
+        match self {
+            LLMError::RateLimited { body, .. }
+            | LLMError::Unauthorized { body, .. }
+            | LLMError::Transient { body, .. }
+            | LLMError::Rejected { body, .. } => body.as_deref(),
+            LLMError::InvalidResponse { .. } | LLMError::CircuitOpen { .. } | LLMError::Request(_) => None
+        }
+    }
+
+}
+
+impl std::fmt::Display for LLMError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LLMError::RateLimited { retry_after: Some(secs), message, .. } =>
+                write!(f, "rate limited by upstream (retry after {}s): {}", secs, message),
+            LLMError::RateLimited { retry_after: None, message, .. } =>
+                write!(f, "rate limited by upstream: {}", message),
+            LLMError::Unauthorized { message, .. } =>
+                write!(f, "upstream rejected the API key - check GROQ_API_KEY: {}", message),
+            LLMError::Transient { status, message, .. } =>
+                write!(f, "upstream unavailable ({}): {}", status, message),
+            LLMError::Rejected { status, message, .. } =>
+                write!(f, "upstream rejected the request ({}): {}", status, message),
+            LLMError::InvalidResponse { path, message } =>
+                write!(f, "upstream returned an unparseable response at `{}`: {}", path, message),
+            LLMError::CircuitOpen { retry_after } =>
+                write!(f, "upstream circuit breaker is open after repeated failures - next attempt in {}s", retry_after),
+            LLMError::Request(e) => write!(f, "{}", e)
+        }
+    }
+}
+
+impl std::error::Error for LLMError {}
+
+impl From<reqwest::Error> for LLMError {
+    fn from(e: reqwest::Error) -> Self {
+        LLMError::Request(e)
+    }
+}
+
+/// Maps a failed upstream call to the error category used in metrics
+pub fn classify_upstream_error(e: &LLMError) -> ErrorCategory {
+
+    match e {
+        LLMError::RateLimited { .. } | LLMError::Unauthorized { .. } | LLMError::Rejected { .. } =>
+            ErrorCategory::Upstream4xx,
+        LLMError::Transient { .. } | LLMError::CircuitOpen { .. } => ErrorCategory::Upstream5xx,
+        LLMError::InvalidResponse { .. } => ErrorCategory::SerializationError,
+        LLMError::Request(e) if e.is_timeout() => ErrorCategory::UpstreamTimeout,
+        LLMError::Request(e) if e.is_decode() => ErrorCategory::SerializationError,
+        // connection failures mean the upstream is unavailable
+        LLMError::Request(_) => ErrorCategory::Upstream5xx
+    }
+
+}
+
+/// How failed upstream calls are retried: up to `max_attempts` calls in all,
+/// waiting between half and all of `backoff_base_ms * 2^n` (capped at
+/// `max_backoff_ms`) before each retry, or the upstream's Retry-After
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    // 1 turns retries off
+    pub max_attempts: u32,
+    pub backoff_base_ms: u64,
+    // the longest single wait; a Retry-After beyond it isn't waited for
+    pub max_backoff_ms: u64
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy { max_attempts: 3, backoff_base_ms: 200, max_backoff_ms: 10_000 }
+    }
+}
+
+impl RetryPolicy {
+
+    /// How long to wait after attempt number `attempt` (from 1) failed with
+    /// `error`, or `None` if it shouldn't be retried
+    pub fn delay(&self, attempt: u32, error: &LLMError) -> Option<Duration> {
+
+        if attempt >= self.max_attempts || !error.is_retryable() {
+            return None;
+        }
+        if let LLMError::RateLimited { retry_after: Some(secs), .. } = error {
+            let wait = Duration::from_secs(*secs);
+            return (wait <= Duration::from_millis(self.max_backoff_ms)).then_some(wait);
+        }
+
+        let ceiling = self.backoff_base_ms
+            .saturating_mul(1 << (attempt - 1).min(20))
+            .min(self.max_backoff_ms);
+        // a random half on top of a fixed half keeps retries from many clients apart
+        let jitter = (uuid::Uuid::new_v4().as_u128() % (ceiling / 2 + 1) as u128) as u64;
+        Some(Duration::from_millis(ceiling - ceiling / 2 + jitter))
+
+    }
+
+}
+
+// sends the request `build` makes, retrying per the configured RetryPolicy.
+// A non-2xx response that isn't retried comes back as its LLMError. Every
+// attempt asks the circuit breaker first and reports back to it
+#[cfg(not(feature = "mock"))]
+async fn send_with_retry(state: &AppState, build: impl Fn() -> reqwest::RequestBuilder) -> Result<reqwest::Response, LLMError> {
+
+    let policy = state.config.retry;
+    let mut attempt = 1;
+    loop {
+        state.circuit_breaker.allow()
+            .map_err(|wait| LLMError::CircuitOpen { retry_after: wait.as_secs().max(1) })?;
+        let error = match build().send().await {
+            Ok(response) if response.status().is_success() => {
+                state.circuit_breaker.record_success();
+                return Ok(response);
+            }
+            Ok(response) => LLMError::from_response(response).await,
+            Err(e) => LLMError::from(e)
+        };
+        // a refusal still shows the upstream is up
+        if error.is_outage() {
+            state.circuit_breaker.record_failure();
+        } else {
+            state.circuit_breaker.record_success();
+        }
+        let Some(delay) = policy.delay(attempt, &error) else {
+            if attempt > 1 {
+                state.metrics.record_upstream_retries_exhausted();
+            }
+            return Err(error);
+        };
+
+        tracing::warn!(%error, delay_ms = delay.as_millis() as u64, attempt = attempt + 1, max_attempts = policy.max_attempts, "upstream call failed - retrying");
+        state.metrics.record_upstream_retry();
+        tokio::time::sleep(delay).await;
+        attempt += 1;
+    }
+
+}
+
+// prefix of the upstream rate-limit headers (Groq and OpenAI share the names)
+const RATE_LIMIT_HEADER_PREFIX: &str = "x-ratelimit-";
+
+/// Response metadata from the upstream that isn't part of the JSON body
+#[derive(Debug, Clone, Default)]
+pub struct UpstreamMeta {
+    // every x-ratelimit-* header, forwarded to the client on a miss
+    pub rate_limit_headers: HeaderMap
+}
+
+impl UpstreamMeta {
+
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+
+        let rate_limit_headers = headers.iter()
+            .filter(|(name, _)| name.as_str().starts_with(RATE_LIMIT_HEADER_PREFIX))
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+
+        UpstreamMeta { rate_limit_headers }
+
+    }
+
+    fn rate_limit_value(&self, name: &str) -> Option<u64> {
+        self.rate_limit_headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.trim().parse().ok())
+    }
+
+    pub fn remaining_tokens(&self) -> Option<u64> {
+        self.rate_limit_value("x-ratelimit-remaining-tokens")
+    }
+
+    pub fn remaining_requests(&self) -> Option<u64> {
+        self.rate_limit_value("x-ratelimit-remaining-requests")
+    }
+
+}
+
+// characters of an unparseable upstream body kept in the debug log
+const INVALID_BODY_LOG_CHARS: usize = 512;
+
+/// Deserializes an upstream completion. On failure the error names the
+/// path of the offending field, e.g. `choices[0]` for a missing `message`
+pub fn parse_llm_response(body: &[u8]) -> Result<LLMResponse, LLMError> {
+
+    let deserializer = &mut serde_json::Deserializer::from_slice(body);
+    serde_path_to_error::deserialize(deserializer).map_err(|e| LLMError::InvalidResponse {
+        path: e.path().to_string(),
+        message: e.inner().to_string()
+    })
+
+}
+
+/// The start of an upstream body for logging, with every JSON string
+/// replaced by its length so no prompt or completion text is written out
+pub fn redacted_body(body: &[u8]) -> String {
+
+    let text = match serde_json::from_slice::<Value>(body) {
+        Ok(mut value) => {
+            redact_strings(&mut value);
+            value.to_string()
+        }
+        Err(_) => String::from_utf8_lossy(body).into_owned()
+    };
+    text.chars().take(INVALID_BODY_LOG_CHARS).collect()
+
+}
+
+fn redact_strings(value: &mut Value) {
+    match value {
+        Value::String(s) => *s = format!("<{} chars>", s.chars().count()),
+        Value::Array(items) => items.iter_mut().for_each(redact_strings),
+        Value::Object(fields) => fields.values_mut().for_each(redact_strings),
+        _ => {}
+    }
+}
+
+#[cfg(feature = "mock")]
+#[tracing::instrument(level = "debug", skip_all, fields(model = %request.model))]
+pub async fn call_llm(
+    _state: &AppState,
+    request: LLMRequest
+) -> Result<(LLMResponse, UpstreamMeta), LLMError> {
+
+    Ok((crate::mock::mock_llm_response(&request), UpstreamMeta::default()))
+
+}
+
+// a POST to `path` on the upstream, with that provider's auth headers.
+// A BYOK client's own key replaces the configured one
+#[cfg(not(feature = "mock"))]
+fn upstream_post(state: &AppState, upstream: &Upstream, path: &str) -> reqwest::RequestBuilder {
+
+    let builder = state.http_client.post(upstream_url(&upstream.base_url, path));
+    match (upstream.provider, state.upstream_key.as_ref().or(upstream.api_key.as_ref())) {
+        (Provider::Anthropic, Some(key)) => builder
+            .header("x-api-key", key)
+            .header("anthropic-version", crate::anthropic::ANTHROPIC_VERSION),
+        (_, Some(key)) => builder.header("Authorization", format!("Bearer {}", key)),
+        (_, None) => builder
+    }
+
+}
+
+/// Sends the request to the upstream its model routes to, with the provider
+/// prefix stripped from the model name. Anthropic's Messages API is
+/// translated both ways, so every provider answers in the OpenAI format
+#[cfg(not(feature = "mock"))]
+#[tracing::instrument(level = "debug", skip_all, fields(model = %request.model))]
 pub async fn call_llm(
-    client: &Client, 
-    api_key: &str,
+    state: &AppState,
+    mut request: LLMRequest
+) -> Result<(LLMResponse, UpstreamMeta), LLMError> {
+
+    let (upstream, model) = state.config.route_model(&request.model);
+    request.model = model.to_string();
+    // only valid alongside "stream": true
+    if let Some(extra) = &mut request.extra {
+        extra.remove("stream_options");
+    }
+
+    let response = send_with_retry(state, || match upstream.provider {
+        Provider::Anthropic => upstream_post(state, upstream, "messages").json(&crate::anthropic::messages_request(&request)),
+        _ => upstream_post(state, upstream, "chat/completions").json(&request)
+    }).await?;
+
+    let meta = UpstreamMeta::from_headers(response.headers());
+    state.metrics.record_rate_limit(meta.remaining_tokens(), meta.remaining_requests());
+
+    let body = response.bytes().await?;
+    let parsed = match upstream.provider {
+        Provider::Anthropic => crate::anthropic::parse_messages_response(&body),
+        _ => parse_llm_response(&body)
+    };
+    let llm_response = parsed.inspect_err(|e| {
+        state.metrics.record_upstream_parse_error();
+        tracing::debug!(error = %e, body = %redacted_body(&body), "unparseable upstream response");
+    })?;
+
+    Ok((llm_response, meta))
+
+}
+
+/// The body of a streaming upstream response, read one network chunk at a time
+pub struct UpstreamStream {
+    #[cfg(not(feature = "mock"))]
+    response: Option<reqwest::Response>,
+    // chunks already in hand: the mock's, or a full completion replayed for
+    // a provider whose event format isn't relayed (Anthropic)
+    buffered: VecDeque<Bytes>
+}
+
+impl UpstreamStream {
+
+    fn replayed(response: &LLMResponse) -> Self {
+
+        let buffered = crate::stream::replay(response).iter().map(|event| event.encode(None)).collect();
+        UpstreamStream {
+            #[cfg(not(feature = "mock"))]
+            response: None,
+            buffered
+        }
+
+    }
+
+    pub async fn chunk(&mut self) -> Result<Option<Bytes>, LLMError> {
+
+        if let Some(chunk) = self.buffered.pop_front() {
+            return Ok(Some(chunk));
+        }
+        #[cfg(not(feature = "mock"))]
+        if let Some(response) = &mut self.response {
+            return Ok(response.chunk().await?);
+        }
+        Ok(None)
+
+    }
+
+}
+
+// the request body sent upstream for a streamed completion
+#[cfg(not(feature = "mock"))]
+#[derive(serde::Serialize)]
+struct StreamingRequest<'a> {
+    #[serde(flatten)]
+    request: &'a LLMRequest,
+    // OpenAI only reports usage in a stream when asked; Groq sends it either way
+    stream_options: Value
+}
+
+#[cfg(feature = "mock")]
+#[tracing::instrument(level = "debug", skip_all, fields(model = %request.model))]
+pub async fn call_llm_stream(
+    _state: &AppState,
     request: LLMRequest
-) -> Result<LLMResponse, reqwest::Error> {
+) -> Result<(UpstreamStream, UpstreamMeta), LLMError> {
+
+    Ok((UpstreamStream::replayed(&crate::mock::mock_llm_response(&request)), UpstreamMeta::default()))
+
+}
+
+/// Sends the request with `"stream": true`. Only the status is checked here;
+/// the caller reads the events off the returned stream. Anthropic models are
+/// called without streaming and the completion replayed as chunks
+#[cfg(not(feature = "mock"))]
+#[tracing::instrument(level = "debug", skip_all, fields(model = %request.model))]
+pub async fn call_llm_stream(
+    state: &AppState,
+    mut request: LLMRequest
+) -> Result<(UpstreamStream, UpstreamMeta), LLMError> {
+
+    let (upstream, model) = state.config.route_model(&request.model);
+    if upstream.provider == Provider::Anthropic {
+        let (response, meta) = call_llm(state, request).await?;
+        return Ok((UpstreamStream::replayed(&response), meta));
+    }
+    request.model = model.to_string();
+    // replaced by the proxy's own below
+    if let Some(extra) = &mut request.extra {
+        extra.remove("stream_options");
+    }
+
+    request.stream = Some(true);
+    let body = StreamingRequest { request: &request, stream_options: serde_json::json!({"include_usage": true}) };
+
+    let response = send_with_retry(state, || upstream_post(state, upstream, "chat/completions").json(&body)).await?;
+
+    let meta = UpstreamMeta::from_headers(response.headers());
+    state.metrics.record_rate_limit(meta.remaining_tokens(), meta.remaining_requests());
+
+    Ok((UpstreamStream { response: Some(response), buffered: VecDeque::new() }, meta))
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_groq_key_takes_priority() {
+
+        let (provider, key) = resolve_api_key(|name| match name {
+            "GROQ_API_KEY" => Some("groq-key".to_string()),
+            "OPENAI_API_KEY" => Some("openai-key".to_string()),
+            _ => None
+        }).unwrap();
+
+        assert_eq!(provider, Provider::Groq);
+        assert_eq!(key, "groq-key");
+
+    }
+
+    #[test]
+    fn test_openai_key_fallback() {
+
+        let (_, key) = resolve_api_key(|name| match name {
+            "GROQ_API_KEY" => Some("  ".to_string()),
+            "OPENAI_API_KEY" => Some("openai-key".to_string()),
+            _ => None
+        }).unwrap();
+
+        assert_eq!(key, "openai-key", "Blank GROQ_API_KEY should fall back to OPENAI_API_KEY");
+
+    }
+
+    #[test]
+    fn test_base_url_trailing_slash() {
+
+        let base = normalize_base_url("https://my-gateway.internal/v1/").unwrap();
+
+        assert_eq!(base, "https://my-gateway.internal/v1");
+        assert_eq!(upstream_url(&base, "chat/completions"), "https://my-gateway.internal/v1/chat/completions");
+
+    }
+
+    #[test]
+    fn test_upstream_meta_keeps_rate_limit_headers() {
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-ratelimit-remaining-tokens", "5800".parse().unwrap());
+        headers.insert("x-ratelimit-remaining-requests", "14370".parse().unwrap());
+        headers.insert("x-ratelimit-reset-tokens", "2.1s".parse().unwrap());
+        headers.insert("content-type", "application/json".parse().unwrap());
+
+        let meta = UpstreamMeta::from_headers(&headers);
+
+        assert_eq!(meta.rate_limit_headers.len(), 3, "Only x-ratelimit-* headers should be kept");
+        assert_eq!(meta.remaining_tokens(), Some(5800));
+        assert_eq!(meta.remaining_requests(), Some(14370));
+
+    }
+
+    #[test]
+    fn test_passthrough_url() {
+
+        let base = "https://api.groq.com/openai/v1";
+        assert_eq!(passthrough_url(base, "/v1/completions", None), "https://api.groq.com/openai/v1/completions");
+        assert_eq!(passthrough_url(base, "/v1/models", Some("limit=5")), "https://api.groq.com/openai/v1/models?limit=5");
+        assert_eq!(passthrough_url("http://localhost:4000", "/v1/models", None), "http://localhost:4000/v1/models");
+
+    }
+
+    #[test]
+    fn test_base_url_validation() {
+
+        assert!(normalize_base_url("not a url").is_err());
+        assert!(normalize_base_url("ftp://gateway/v1").is_err());
+        assert!(normalize_base_url("http://localhost:4000").is_ok());
+
+    }
+
+    #[test]
+    fn test_rate_limit_error() {
+
+        let body = r#"{"error": {"message": "Rate limit reached", "type": "tokens", "code": "rate_limit_exceeded"}}"#;
+        let err = LLMError::from_parts(429, Some(7), body);
+
+        assert!(matches!(err, LLMError::RateLimited { retry_after: Some(7), .. }));
+        assert_eq!(classify_upstream_error(&err), ErrorCategory::Upstream4xx);
+        assert!(err.to_string().contains("Rate limit reached"));
+
+    }
+
+    #[test]
+    fn test_retry_policy_delays() {
+
+        let policy = RetryPolicy { max_attempts: 4, backoff_base_ms: 100, max_backoff_ms: 300 };
+        let unavailable = LLMError::from_parts(503, None, "Service Unavailable");
+
+        // half fixed, half jitter, doubling up to the cap
+        for (attempt, ceiling) in [(1, 100), (2, 200), (3, 300)] {
+            let delay = policy.delay(attempt, &unavailable).unwrap().as_millis() as u64;
+            assert!((ceiling / 2..=ceiling).contains(&delay), "attempt {}: {}ms", attempt, delay);
+        }
+        assert_eq!(policy.delay(4, &unavailable), None, "Out of attempts");
+
+        // Retry-After is honoured, unless it's longer than the cap
+        let slow_down = LLMError::from_parts(429, Some(0), "{}");
+        assert_eq!(policy.delay(1, &slow_down), Some(Duration::ZERO));
+        assert_eq!(policy.delay(1, &LLMError::from_parts(429, Some(60), "{}")), None);
+
+        for status in [400, 401, 500] {
+            assert_eq!(policy.delay(1, &LLMError::from_parts(status, None, "{}")), None, "{} isn't retried", status);
+        }
+
+    }
+
+    #[test]
+    fn test_auth_and_server_errors() {
+
+        let unauthorized = LLMError::from_parts(401, None, r#"{"error": {"message": "Invalid API Key", "type": "invalid_request_error"}}"#);
+        let unavailable = LLMError::from_parts(503, None, "Service Unavailable");
+        let rejected = LLMError::from_parts(400, None, "{}");
+
+        assert!(matches!(unauthorized, LLMError::Unauthorized { .. }));
+        assert!(matches!(unavailable, LLMError::Transient { status: 503, .. }));
+        assert_eq!(unavailable.to_string(), "upstream unavailable (503): Service Unavailable");
+        assert!(matches!(rejected, LLMError::Rejected { status: 400, .. }));
+
+        // the upstream's error object is kept when it sent one
+        assert_eq!(unauthorized.upstream_status(), Some(401));
+        assert_eq!(unauthorized.upstream_body().unwrap().error.message, "Invalid API Key");
+        assert!(unavailable.upstream_body().is_none());
+        assert!(rejected.upstream_body().is_none());
+
+    }
+
+    #[test]
+    fn test_parse_tolerates_benign_variations() {
+
+        // no finish_reason, no usage, an unknown usage field and an unknown top-level field
+        let bodies = [
+            r#"{"id": "1", "object": "chat.completion", "created": 1, "model": "m", "choices": [{"message": {"role": "assistant", "content": "Hi"}, "index": 0}], "usage": {"prompt_tokens": 1, "completion_tokens": 1, "total_tokens": 2}}"#,
+            r#"{"id": "1", "model": "m", "choices": [{"message": {"role": "assistant", "content": "Hi"}, "finish_reason": "stop"}]}"#,
+            r#"{"id": "1", "model": "m", "choices": [{"message": {"role": "assistant", "content": "Hi"}}], "usage": {"total_tokens": 2, "queue_time": 0.01}, "x_groq": {"id": "req_1"}}"#
+        ];
+        for body in bodies {
+            let response = parse_llm_response(body.as_bytes()).unwrap();
+            assert_eq!(response.choices[0].message.content.as_text(), "Hi");
+        }
+
+    }
+
+    #[test]
+    fn test_parse_names_the_offending_field() {
+
+        let cases = [
+            // a streaming chunk where a completion was expected
+            (r#"{"id": "1", "choices": [{"delta": {"content": "Hi"}, "index": 0}]}"#, "choices[0]", "missing field `message`"),
+            (r#"{"id": "1", "created": "yesterday", "choices": []}"#, "created", "invalid type"),
+            (r#"{"id": "1", "choices": [{"message": {"role": "assistant", "content": "Hi"}}], "usage": {"total_tokens": -1}}"#, "usage.total_tokens", "invalid value"),
+            (r#"{"id": "1"}"#, ".", "missing field `choices`"),
+            ("<html>Bad Gateway</html>", ".", "expected value")
+        ];
+        for (body, expected_path, expected_message) in cases {
+            match parse_llm_response(body.as_bytes()) {
+                Err(LLMError::InvalidResponse { path, message }) => {
+                    assert_eq!(path, expected_path, "{}", body);
+                    assert!(message.contains(expected_message), "{}: {}", body, message);
+                }
+                other => panic!("Expected InvalidResponse for {}, got {:?}", body, other)
+            }
+        }
+
+    }
+
+    #[test]
+    fn test_redacted_body_hides_text() {
+
+        let body = br#"{"choices": [{"delta": {"content": "secret answer"}}], "created": 1}"#;
+        let redacted = redacted_body(body);
+
+        assert!(!redacted.contains("secret"));
+        assert!(redacted.contains("<13 chars>") && redacted.contains("\"created\":1"));
+        assert_eq!(redacted_body(&[b'x'; 2000]).len(), INVALID_BODY_LOG_CHARS);
+
+    }
+
+    #[test]
+    fn test_missing_key_lists_supported_vars() {
 
-    let response = client
-        .post("https://api.groq.com/openai/v1/chat/completions")
-        .timeout(std::time::Duration::from_secs(60))
-        .header("Authorization", format!("Bearer {}", api_key))
-        .json(&request)
-        .send()
-        .await?;
+        let err = resolve_api_key(|_| None).unwrap_err();
 
-    let llm_response: LLMResponse = response
-        .error_for_status()?
-        .json()
-        .await?;
+        assert!(err.contains("GROQ_API_KEY") && err.contains("OPENAI_API_KEY"));
 
-    Ok(llm_response) 
+    }
 
-}
\ No newline at end of file
+}
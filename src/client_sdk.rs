@@ -0,0 +1,250 @@
+// Typed client for services that call the proxy, enabled with the
+// `client-sdk` feature. Wraps the cache-control request headers and parses
+// the cache metadata the proxy sends back.
+
+use std::fmt;
+use reqwest::header::HeaderMap;
+use crate::models::{LLMRequest, LLMResponse};
+
+pub const HEADER_BYPASS_CACHE: &str = "x-bypass-cache";
+pub const HEADER_CACHE_TTL: &str = "x-cache-ttl";
+pub const HEADER_SEMANTIC_THRESHOLD: &str = "x-semantic-threshold";
+pub const HEADER_CACHE: &str = "x-cache";
+pub const HEADER_CACHE_KEY: &str = "x-cache-key";
+pub const HEADER_CACHE_AGE: &str = "x-cache-age";
+pub const HEADER_SIMILARITY: &str = "x-similarity-score";
+pub const HEADER_COST: &str = "x-cache-cost-usd";
+
+/// Which cache tier answered a request, from the `X-Cache` header
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CacheTier {
+    Exact,
+    Semantic,
+    Miss,
+    // an expired entry, served because the upstream call failed
+    Stale,
+    // a value this client version doesn't know about
+    Other(String)
+}
+
+impl CacheTier {
+
+    pub fn parse(value: &str) -> Self {
+        match value.trim().to_uppercase().as_str() {
+            "EXACT_HIT" => CacheTier::Exact,
+            "SEMANTIC_HIT" => CacheTier::Semantic,
+            "MISS" => CacheTier::Miss,
+            "STALE" => CacheTier::Stale,
+            other => CacheTier::Other(other.to_string())
+        }
+    }
+
+    pub fn is_hit(&self) -> bool {
+        matches!(self, CacheTier::Exact | CacheTier::Semantic)
+    }
+
+}
+
+/// Cache metadata parsed from the response headers. Every field is optional
+/// because older proxies (or a bypassed request) may not send the header
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CacheMetadata {
+    pub tier: Option<CacheTier>,
+    pub cache_key: Option<String>,
+    pub age_secs: Option<u64>,
+    pub similarity: Option<f32>,
+    pub cost_usd: Option<f64>
+}
+
+impl CacheMetadata {
+
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+
+        let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok());
+
+        CacheMetadata {
+            tier: header(HEADER_CACHE).map(CacheTier::parse),
+            cache_key: header(HEADER_CACHE_KEY).map(|v| v.to_string()),
+            age_secs: header(HEADER_CACHE_AGE).and_then(|v| v.trim().parse().ok()),
+            similarity: header(HEADER_SIMILARITY).and_then(|v| v.trim().parse().ok()),
+            cost_usd: header(HEADER_COST).and_then(|v| v.trim().trim_start_matches('$').parse().ok())
+        }
+
+    }
+
+}
+
+/// A completion plus the cache metadata that came with it
+#[derive(Debug)]
+pub struct ProxyResponse {
+    pub response: LLMResponse,
+    pub cache: CacheMetadata
+}
+
+#[derive(Debug)]
+pub enum ProxyError {
+    // the proxy answered with a non-2xx status
+    Status { status: u16, body: String },
+    Request(reqwest::Error)
+}
+
+impl fmt::Display for ProxyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProxyError::Status { status, body } => write!(f, "Proxy returned {}: {}", status, body),
+            ProxyError::Request(e) => write!(f, "Request to proxy failed: {}", e)
+        }
+    }
+}
+
+impl std::error::Error for ProxyError {}
+
+impl From<reqwest::Error> for ProxyError {
+    fn from(e: reqwest::Error) -> Self {
+        ProxyError::Request(e)
+    }
+}
+
+/// Client for the proxy's `/v1/chat/completions` endpoint.
+///
+/// ```no_run
+/// use llm_cache_proxy::client_sdk::ProxyClient;
+/// use llm_cache_proxy::models::{LLMRequest, Message};
+///
+/// # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+/// let client = ProxyClient::new("http://localhost:3000", "my-key");
+/// let request = LLMRequest {
+///     messages: vec![Message { role: "user".to_string(), content: "What is Rust?".into(), ..Default::default() }],
+///     model: "llama-3.3-70b-versatile".to_string(),
+///     temperature: Some(0.0),
+///     max_tokens: None,
+///     ..Default::default()
+/// };
+///
+/// // the second identical call is served from the exact-match cache
+/// client.chat(&request).await?;
+/// let hit = client.chat(&request).await?;
+/// assert!(hit.cache.tier.is_some_and(|tier| tier.is_hit()));
+///
+/// // skip the cache for one call, keeping the other settings
+/// let fresh = client.clone().bypass_cache(true).chat(&request).await?;
+/// println!("{}", fresh.response.choices[0].message.content.as_text());
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ProxyClient {
+    http: reqwest::Client,
+    base_url: String,
+    api_key: String,
+    bypass_cache: bool,
+    cache_ttl: Option<u64>,
+    semantic_threshold: Option<f32>
+}
+
+impl ProxyClient {
+
+    pub fn new(base_url: &str, api_key: &str) -> Self {
+
+        ProxyClient {
+            http: reqwest::Client::new(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            api_key: api_key.to_string(),
+            bypass_cache: false,
+            cache_ttl: None,
+            semantic_threshold: None
+        }
+
+    }
+
+    /// Reuses an existing `reqwest::Client` (connection pool, proxy settings)
+    pub fn with_http_client(mut self, http: reqwest::Client) -> Self {
+        self.http = http;
+        self
+    }
+
+    /// Sends `x-bypass-cache: true`, forcing a fresh upstream call
+    pub fn bypass_cache(mut self, bypass: bool) -> Self {
+        self.bypass_cache = bypass;
+        self
+    }
+
+    /// Sends `x-cache-ttl`, overriding how long the response is cached
+    pub fn cache_ttl(mut self, ttl_secs: u64) -> Self {
+        self.cache_ttl = Some(ttl_secs);
+        self
+    }
+
+    /// Sends `x-semantic-threshold`, overriding the similarity needed for a semantic hit
+    pub fn semantic_threshold(mut self, threshold: f32) -> Self {
+        self.semantic_threshold = Some(threshold);
+        self
+    }
+
+    pub async fn chat(&self, request: &LLMRequest) -> Result<ProxyResponse, ProxyError> {
+
+        let mut builder = self.http
+            .post(format!("{}/v1/chat/completions", self.base_url))
+            .bearer_auth(&self.api_key)
+            .json(request);
+
+        if self.bypass_cache {
+            builder = builder.header(HEADER_BYPASS_CACHE, "true");
+        }
+        if let Some(ttl) = self.cache_ttl {
+            builder = builder.header(HEADER_CACHE_TTL, ttl.to_string());
+        }
+        if let Some(threshold) = self.semantic_threshold {
+            builder = builder.header(HEADER_SEMANTIC_THRESHOLD, threshold.to_string());
+        }
+
+        let response = builder.send().await?;
+        let status = response.status();
+
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(ProxyError::Status { status: status.as_u16(), body });
+        }
+
+        let cache = CacheMetadata::from_headers(response.headers());
+        let response = response.json::<LLMResponse>().await?;
+
+        Ok(ProxyResponse { response, cache })
+
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_parse_cache_metadata() {
+
+        let mut headers = HeaderMap::new();
+        headers.insert(HEADER_CACHE, "SEMANTIC_HIT".parse().unwrap());
+        headers.insert(HEADER_CACHE_AGE, "42".parse().unwrap());
+        headers.insert(HEADER_SIMILARITY, "0.9731".parse().unwrap());
+        headers.insert(HEADER_COST, "$0.00012".parse().unwrap());
+
+        let metadata = CacheMetadata::from_headers(&headers);
+
+        assert_eq!(metadata.tier, Some(CacheTier::Semantic));
+        assert_eq!(metadata.age_secs, Some(42));
+        assert_eq!(metadata.similarity, Some(0.9731));
+        assert_eq!(metadata.cost_usd, Some(0.00012));
+        assert_eq!(metadata.cache_key, None);
+
+    }
+
+    #[test]
+    fn test_missing_headers_are_none() {
+
+        assert_eq!(CacheMetadata::from_headers(&HeaderMap::new()), CacheMetadata::default());
+        assert_eq!(CacheTier::parse("stale"), CacheTier::Other("STALE".to_string()));
+
+    }
+
+}
@@ -0,0 +1,139 @@
+// Single-flight for cache misses. While one request for a cache key is
+// upstream, identical requests that also missed wait for its answer rather
+// than making calls of their own; only that first request writes the caches.
+
+use std::sync::Arc;
+use axum::http::StatusCode;
+use dashmap::DashMap;
+use dashmap::mapref::entry::Entry;
+use tokio::sync::watch;
+use crate::models::{ApiError, LLMResponse};
+
+/// What the leading request got back: its response, or the error it answered with
+pub type Outcome = Result<LLMResponse, (StatusCode, ApiError)>;
+
+type Slot = watch::Receiver<Option<Arc<Outcome>>>;
+
+/// Upstream calls in progress, by cache key
+#[derive(Clone, Default)]
+pub struct InFlightRequests {
+    calls: Arc<DashMap<String, Slot>>
+}
+
+pub enum Flight {
+    /// No call for the key was in progress; this request makes it
+    Leader(Leader),
+    /// Another request is already making the call
+    Follower(Follower)
+}
+
+impl InFlightRequests {
+
+    pub fn join(&self, cache_key: &str) -> Flight {
+
+        match self.calls.entry(cache_key.to_string()) {
+            Entry::Occupied(entry) => Flight::Follower(Follower { slot: entry.get().clone() }),
+            Entry::Vacant(entry) => {
+                let (sender, slot) = watch::channel(None);
+                entry.insert(slot);
+                Flight::Leader(Leader { calls: self.calls.clone(), cache_key: cache_key.to_string(), sender })
+            }
+        }
+
+    }
+
+}
+
+/// Owns the call for a key until dropped. Dropping it without `finish`
+/// (the client went away mid-call) releases the waiting followers to make
+/// their own calls
+pub struct Leader {
+    calls: Arc<DashMap<String, Slot>>,
+    cache_key: String,
+    sender: watch::Sender<Option<Arc<Outcome>>>
+}
+
+impl Leader {
+
+    /// Hands the outcome to every follower
+    pub fn finish(self, outcome: Outcome) {
+        self.sender.send_replace(Some(Arc::new(outcome)));
+    }
+
+}
+
+impl Drop for Leader {
+    fn drop(&mut self) {
+        self.calls.remove(&self.cache_key);
+    }
+}
+
+pub struct Follower {
+    slot: Slot
+}
+
+impl Follower {
+
+    /// The leader's outcome, or `None` if it was dropped before finishing
+    pub async fn outcome(mut self) -> Option<Arc<Outcome>> {
+
+        match self.slot.wait_for(|outcome| outcome.is_some()).await {
+            Ok(outcome) => outcome.clone(),
+            Err(_) => None
+        }
+
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::test_helpers::test_llm_response;
+
+    #[tokio::test]
+    async fn test_followers_share_the_leaders_outcome() {
+
+        let in_flight = InFlightRequests::default();
+
+        let Flight::Leader(leader) = in_flight.join("key") else {
+            panic!("The first request should lead");
+        };
+        let followers: Vec<Follower> = (0..3)
+            .map(|_| match in_flight.join("key") {
+                Flight::Follower(follower) => follower,
+                Flight::Leader(_) => panic!("A second leader was elected while the first was in flight")
+            })
+            .collect();
+        let waiting: Vec<_> = followers.into_iter().map(|follower| tokio::spawn(follower.outcome())).collect();
+
+        leader.finish(Ok(test_llm_response()));
+        assert_eq!(in_flight.calls.len(), 0);
+
+        for waiter in waiting {
+            let outcome = waiter.await.unwrap().unwrap();
+            assert_eq!(outcome.as_ref().as_ref().unwrap().id, test_llm_response().id);
+        }
+        assert!(matches!(in_flight.join("key"), Flight::Leader(_)), "A finished call shouldn't be joined");
+
+    }
+
+    #[tokio::test]
+    async fn test_dropped_leader_releases_followers() {
+
+        let in_flight = InFlightRequests::default();
+
+        let Flight::Leader(leader) = in_flight.join("key") else {
+            panic!("The first request should lead");
+        };
+        let Flight::Follower(follower) = in_flight.join("key") else {
+            panic!("The second request should follow");
+        };
+
+        drop(leader);
+        assert!(follower.outcome().await.is_none());
+
+    }
+
+}
@@ -0,0 +1,1627 @@
+use std::collections::BTreeMap;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use serde::Serialize;
+use serde_json::{json, Value};
+use crate::ratelimit::RateLimits;
+use crate::quota::Quotas;
+use crate::budget::{BudgetConfig, BudgetLimits, BudgetPeriod};
+use crate::breaker::BreakerConfig;
+use crate::logger::{LogRotation, RotateEvery};
+use crate::backend::DEFAULT_MEMORY_CACHE_MAX_ENTRIES;
+use crate::cache::{DEFAULT_QDRANT_MAX_CONNECTIONS, EMBEDDING_DIM, LOCAL_EMBEDDING_URL, KeyNormalization, exact_key_prefix};
+use crate::client::{Provider, RetryPolicy, Upstream, resolve_api_key, normalize_base_url};
+use llm_cache_proxy::pricing::{ModelPrice, parse_model_pricing};
+
+/// Whether cached responses are returned to clients (`serve`) or only
+/// looked up and recorded for comparison against the upstream (`shadow`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheMode {
+    Serve,
+    Shadow
+}
+
+impl CacheMode {
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "serve" => Some(CacheMode::Serve),
+            "shadow" => Some(CacheMode::Shadow),
+            _ => None
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CacheMode::Serve => "serve",
+            CacheMode::Shadow => "shadow"
+        }
+    }
+
+}
+
+/// Where prompt embeddings come from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmbeddingProvider {
+    // the embedding service at EMBEDDING_URL
+    Http,
+    // all-MiniLM-L6-v2 in process (src/local_embedding.rs)
+    Local,
+    // an OpenAI-compatible /v1/embeddings API
+    OpenAi
+}
+
+impl EmbeddingProvider {
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "http" => Some(EmbeddingProvider::Http),
+            "local" => Some(EmbeddingProvider::Local),
+            "openai" => Some(EmbeddingProvider::OpenAi),
+            _ => None
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EmbeddingProvider::Http => "http",
+            EmbeddingProvider::Local => "local",
+            EmbeddingProvider::OpenAi => "openai"
+        }
+    }
+
+}
+
+/// Where `/metrics` counters are saved so they survive a restart
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MetricsPersist {
+    #[default]
+    Off,
+    // under METRICS_SNAPSHOT_PREFIX + METRICS_INSTANCE_ID in the exact-match Redis
+    Redis,
+    // as JSON at METRICS_PERSIST_PATH
+    File
+}
+
+impl MetricsPersist {
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "off" => Some(MetricsPersist::Off),
+            "redis" => Some(MetricsPersist::Redis),
+            "file" => Some(MetricsPersist::File),
+            _ => None
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MetricsPersist::Off => "off",
+            MetricsPersist::Redis => "redis",
+            MetricsPersist::File => "file"
+        }
+    }
+
+}
+
+/// How a request's tenant is told, giving each tenant its own cache partition
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TenantSource {
+    // every caller shares one cache
+    #[default]
+    Off,
+    // the x-tenant-id header; requests without one share the untenanted cache
+    Header,
+    // the name of the proxy API key the request authenticated with
+    ApiKey
+}
+
+impl TenantSource {
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "off" => Some(TenantSource::Off),
+            "header" => Some(TenantSource::Header),
+            "api_key" => Some(TenantSource::ApiKey),
+            _ => None
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TenantSource::Off => "off",
+            TenantSource::Header => "header",
+            TenantSource::ApiKey => "api_key"
+        }
+    }
+
+}
+
+/// Where get_embedding sends a prompt
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmbeddingEndpoint {
+    pub provider: EmbeddingProvider,
+    // LOCAL_EMBEDDING_URL with the local provider
+    pub url: String,
+    // sent with the OpenAI provider only
+    pub model: Option<String>,
+    pub api_key: Option<String>
+}
+
+impl EmbeddingEndpoint {
+
+    /// An embedding service taking `{"text": ...}`
+    pub fn http(url: &str) -> Self {
+        EmbeddingEndpoint { provider: EmbeddingProvider::Http, url: url.to_string(), model: None, api_key: None }
+    }
+
+}
+
+/// Storage for the exact-match tier (see src/backend.rs)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExactCacheBackend {
+    Redis,
+    // in process, for CI and edge deployments without Redis
+    Memory
+}
+
+impl ExactCacheBackend {
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "redis" => Some(ExactCacheBackend::Redis),
+            "memory" => Some(ExactCacheBackend::Memory),
+            _ => None
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ExactCacheBackend::Redis => "redis",
+            ExactCacheBackend::Memory => "memory"
+        }
+    }
+
+}
+
+/// Where a resolved configuration value came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConfigSource {
+    Default,
+    Env,
+    // the CONFIG_FILE, overridden by any env var
+    File,
+    // changed at runtime via PUT /admin/config or SIGHUP
+    Runtime
+}
+
+// settings that need a restart - a runtime patch touching these is rejected
+const IMMUTABLE_KEYS: &[&str] = &[
+    "api_key", "provider", "upstream_base_url", "upstreams", "redis_url", "qdrant_url",
+    "qdrant_collection", "embedding_provider", "embedding_url", "embedding_model", "embedding_api_key", "embedding_dim", "cache_mode", "key_normalization", "cache_namespace",
+    "request_timeout_secs", "reqwest_timeout_secs", "health_timeout_secs", "health_monitor_interval_secs",
+    "strict_collection_validation", "log_path", "log_rotation", "request_store_url", "audit_log_path", "redact_prompts_in_logs", "admin_token", "compression", "prefill_parallelism", "quarantine_ttl_secs", "bind_address", "trusted_proxies",
+    "exact_cache_enabled", "exact_cache_backend", "memory_cache_max_entries", "semantic_cache_enabled", "tier0_cache_size", "tier0_ttl_secs", "hot_key_tracker_size",
+    "qdrant_max_connections", "refresh", "models", "include_cost_in_response", "self_test_on_start", "request_coalescing",
+    "api_keys", "tenant_source", "tenant_quotas", "budget", "rate_limits", "byok", "config_file", "pricing", "retry", "circuit_breaker", "stale_grace_secs", "swr_window_secs", "embedding_cache_ttl_secs",
+    "janitor_interval_secs", "semantic_max_points", "metrics_persist", "startup_retries", "startup_retry_delay_secs"
+];
+
+/// Which encodings responses may be compressed with, and the smallest body worth compressing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompressionConfig {
+    pub gzip: bool,
+    pub br: bool,
+    pub min_bytes: u16
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        CompressionConfig { gzip: true, br: true, min_bytes: 1024 }
+    }
+}
+
+impl CompressionConfig {
+
+    /// Parses a comma-separated list such as `gzip,br`; `none` disables compression
+    pub fn from_algorithms(algorithms: &str, min_bytes: u16) -> Self {
+
+        let enabled: Vec<String> = algorithms.split(',').map(|a| a.trim().to_lowercase()).collect();
+        for unknown in enabled.iter().filter(|a| !matches!(a.as_str(), "gzip" | "br" | "none" | "")) {
+            tracing::warn!("Unknown compression algorithm '{}' ignored", unknown);
+        }
+
+        CompressionConfig {
+            gzip: enabled.iter().any(|a| a == "gzip"),
+            br: enabled.iter().any(|a| a == "br"),
+            min_bytes
+        }
+
+    }
+
+    pub fn algorithms(&self) -> Vec<&'static str> {
+        [("gzip", self.gzip), ("br", self.br)]
+            .into_iter()
+            .filter(|(_, enabled)| *enabled)
+            .map(|(name, _)| name)
+            .collect()
+    }
+
+}
+
+/// How client-sent model names are mapped to upstream model ids.
+/// Names are trimmed and lowercased before the alias lookup
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ModelConfig {
+    // lowercased alias -> upstream model id
+    pub aliases: BTreeMap<String, String>,
+    // when set, any other model is rejected before the cache is touched
+    pub allowlist: Option<Vec<String>>,
+    // echo the client's own model name back in responses
+    pub preserve_client_name: bool
+}
+
+impl ModelConfig {
+
+    /// Parses `MODEL_ALIASES` (a JSON object of alias -> model) and
+    /// `MODEL_ALLOWLIST` (comma-separated model ids)
+    pub fn from_parts(aliases: Option<&str>, allowlist: Option<&str>, preserve_client_name: bool) -> Result<Self, String> {
+
+        let aliases = match aliases {
+            Some(raw) => serde_json::from_str::<BTreeMap<String, String>>(raw)
+                .map_err(|e| format!("MODEL_ALIASES must be a JSON object of alias -> model: {}", e))?
+                .into_iter()
+                .map(|(alias, model)| (normalize_model_name(&alias), normalize_model_name(&model)))
+                .collect(),
+            None => BTreeMap::new()
+        };
+
+        let allowlist = allowlist.map(|raw| raw.split(',')
+            .map(normalize_model_name)
+            .filter(|m| !m.is_empty())
+            .collect());
+
+        Ok(ModelConfig { aliases, allowlist, preserve_client_name })
+
+    }
+
+    /// The upstream model id for a client-sent name, or an error if the
+    /// allowlist doesn't include it
+    pub fn resolve(&self, requested: &str) -> Result<String, String> {
+
+        let model = self.canonical(requested);
+
+        if let Some(allowlist) = &self.allowlist
+            && !allowlist.contains(&model) {
+            return Err(format!("Unknown model '{}'. Allowed models: {}", requested, allowlist.join(", ")));
+        }
+        Ok(model)
+
+    }
+
+    /// The name a model's entries are cached under, whether or not the
+    /// allowlist still includes it
+    pub fn canonical(&self, requested: &str) -> String {
+
+        let normalized = normalize_model_name(requested);
+        self.aliases.get(&normalized).cloned().unwrap_or(normalized)
+
+    }
+
+}
+
+/// Proxy API keys clients authenticate with, each under a name that stands
+/// in for it in logs and metrics. Empty when authentication is off
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ApiKeys {
+    // key -> name
+    names: BTreeMap<String, String>,
+    // PROXY_API_KEYS_FILE, kept to show in /admin/config
+    pub file: Option<String>
+}
+
+impl ApiKeys {
+
+    /// Parses `PROXY_API_KEYS` (a JSON object of name -> key) and the file
+    /// named by `PROXY_API_KEYS_FILE` (the same JSON), merged
+    pub fn from_parts(inline: Option<&str>, file: Option<&str>) -> Result<Self, String> {
+
+        let mut sources = Vec::new();
+        if let Some(raw) = inline {
+            sources.push(("PROXY_API_KEYS".to_string(), raw.to_string()));
+        }
+        if let Some(path) = file {
+            let raw = std::fs::read_to_string(path)
+                .map_err(|e| format!("PROXY_API_KEYS_FILE: cannot read {}: {}", path, e))?;
+            sources.push((format!("PROXY_API_KEYS_FILE ({})", path), raw));
+        }
+
+        let mut names = BTreeMap::new();
+        for (source, raw) in sources {
+            let keys = serde_json::from_str::<BTreeMap<String, String>>(&raw)
+                .map_err(|e| format!("{} must be a JSON object of name -> key: {}", source, e))?;
+            for (name, key) in keys {
+                let key = key.trim().to_string();
+                if key.is_empty() {
+                    return Err(format!("{}: key '{}' is empty", source, name));
+                }
+                if let Some(existing) = names.insert(key, name.clone()) {
+                    return Err(format!("{}: '{}' and '{}' have the same key", source, existing, name));
+                }
+            }
+        }
+
+        Ok(ApiKeys { names, file: file.map(|path| path.to_string()) })
+
+    }
+
+    pub fn enabled(&self) -> bool {
+        !self.names.is_empty()
+    }
+
+    /// The name of `key`, or `None` if it isn't one of the proxy's keys
+    pub fn identify(&self, key: &str) -> Option<&str> {
+        self.names.get(key).map(|name| name.as_str())
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.names.values().map(|name| name.as_str()).collect();
+        names.sort();
+        names
+    }
+
+}
+
+fn normalize_model_name(name: &str) -> String {
+    name.trim().to_lowercase()
+}
+
+/// Background refresh of popular exact-match entries before they expire.
+/// The count and cost limits apply to each pass
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct RefreshConfig {
+    pub enabled: bool,
+    pub interval_secs: u64,
+    // hits an entry needs to count as popular
+    pub min_hits: u64,
+    // only entries with less than this many seconds left are refreshed
+    pub ttl_below_secs: u64,
+    pub max_per_interval: usize,
+    // estimated from each entry's cached token usage
+    pub max_cost_usd: f64
+}
+
+impl Default for RefreshConfig {
+    fn default() -> Self {
+        RefreshConfig {
+            enabled: false,
+            interval_secs: 60,
+            min_hits: 10,
+            ttl_below_secs: 300,
+            max_per_interval: 10,
+            max_cost_usd: 0.10
+        }
+    }
+}
+
+const DEFAULT_CONFIG_FILE: &str = "config.toml";
+
+// settings holding a JSON object, written as a TOML table in the config file
+const JSON_VALUED_VARS: &[&str] = &["MODEL_ALIASES", "PROXY_API_KEYS", "MODEL_PRICING"];
+
+/// Settings from a TOML file: CONFIG_FILE, or ./config.toml when it exists.
+/// Each key is an env var name in lowercase, either written out in full or
+/// under a table named after its prefix, so `[rate_limit] requests_per_min = 60`
+/// sets RATE_LIMIT_REQUESTS_PER_MIN. An env var always wins over the file
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConfigFile {
+    pub path: String,
+    // env var name -> value, as it would be written in the environment
+    values: BTreeMap<String, String>
+}
+
+impl ConfigFile {
+
+    pub fn from_env() -> Result<Option<Self>, String> {
+
+        match std::env::var("CONFIG_FILE") {
+            Ok(path) if !path.trim().is_empty() => Self::load(path.trim()).map(Some),
+            _ if std::path::Path::new(DEFAULT_CONFIG_FILE).exists() => Self::load(DEFAULT_CONFIG_FILE).map(Some),
+            _ => Ok(None)
+        }
+
+    }
+
+    pub fn load(path: &str) -> Result<Self, String> {
+
+        let raw = std::fs::read_to_string(path)
+            .map_err(|e| format!("CONFIG_FILE: cannot read {}: {}", path, e))?;
+        Self::parse(path, &raw)
+
+    }
+
+    pub fn parse(path: &str, raw: &str) -> Result<Self, String> {
+
+        let table: toml::Table = raw.parse()
+            .map_err(|e| format!("{} is not valid TOML: {}", path, e))?;
+
+        let mut values = BTreeMap::new();
+        flatten_toml("", &table, &mut values).map_err(|e| format!("{}: {}", path, e))?;
+        Ok(ConfigFile { path: path.to_string(), values })
+
+    }
+
+    pub fn get(&self, name: &str) -> Option<String> {
+        self.values.get(name).cloned()
+    }
+
+}
+
+// [cache] ttl_secs = 60 -> CACHE_TTL_SECS = "60"
+fn flatten_toml(prefix: &str, table: &toml::Table, values: &mut BTreeMap<String, String>) -> Result<(), String> {
+
+    for (key, value) in table {
+        let key = key.to_uppercase().replace('-', "_");
+        let name = if prefix.is_empty() { key } else { format!("{}_{}", prefix, key) };
+
+        let flat = match value {
+            toml::Value::String(s) => s.clone(),
+            _ if JSON_VALUED_VARS.contains(&name.as_str()) => serde_json::to_string(value).map_err(|e| format!("{}: {}", name, e))?,
+            toml::Value::Table(inner) => {
+                flatten_toml(&name, inner, values)?;
+                continue;
+            }
+            // lists are comma-separated, like MODEL_ALLOWLIST
+            toml::Value::Array(items) => items.iter()
+                .map(|item| match item {
+                    toml::Value::String(s) => s.clone(),
+                    other => other.to_string()
+                })
+                .collect::<Vec<_>>()
+                .join(","),
+            other => other.to_string()
+        };
+        if values.insert(name.clone(), flat).is_some() {
+            return Err(format!("{} is set twice", name));
+        }
+    }
+    Ok(())
+
+}
+
+/// Bring-your-own-key: clients may send their own upstream key as
+/// `Authorization: Bearer`, used in place of the proxy's
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ByokConfig {
+    pub enabled: bool,
+    // let clients with different keys share cache entries
+    pub shared_cache: bool
+}
+
+/// The subset of configuration that can change while the proxy is running.
+/// Lives behind an `ArcSwap` in `AppState` so readers never take a lock
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RuntimeConfig {
+    pub semantic_threshold: f32,
+    // a miss whose prompt is at least this similar to a stored one updates
+    // that point instead of adding a neighbour; 0 turns dedup off
+    pub semantic_write_dedup_threshold: f32,
+    pub default_ttl_secs: u64,
+    pub creative_ttl_secs: u64,
+    pub creative_temperature: f32
+}
+
+/// One value changed by a runtime config update
+#[derive(Debug, Clone, Serialize)]
+pub struct ConfigChange {
+    pub key: &'static str,
+    pub before: Value,
+    pub after: Value
+}
+
+impl RuntimeConfig {
+
+    pub fn from_env() -> Result<Self, String> {
+
+        Self::from_lookup(|name| std::env::var(name).ok())
+
+    }
+
+    pub fn from_lookup(mut lookup: impl FnMut(&'static str) -> Option<String>) -> Result<Self, String> {
+
+        let runtime = RuntimeConfig {
+            semantic_threshold: parse_or(lookup("SEMANTIC_THRESHOLD"), 0.90),
+            semantic_write_dedup_threshold: parse_or(lookup("SEMANTIC_WRITE_DEDUP_THRESHOLD"), 0.98),
+            default_ttl_secs: parse_or(lookup("CACHE_TTL_SECS"), 86400),  // 24 hours for deterministic
+            creative_ttl_secs: parse_or(lookup("CREATIVE_CACHE_TTL_SECS"), 3600),  // 1 hour for creative
+            creative_temperature: parse_or(lookup("CREATIVE_TEMPERATURE"), 0.7)
+        };
+
+        runtime.validate()?;
+        Ok(runtime)
+
+    }
+
+    /// The same rules apply at startup, on PUT /admin/config and on SIGHUP
+    pub fn validate(&self) -> Result<(), String> {
+
+        if !(self.semantic_threshold > 0.0 && self.semantic_threshold <= 1.0) {
+            return Err(format!("semantic_threshold must be in (0, 1], got {}", self.semantic_threshold));
+        }
+        if !(0.0..=1.0).contains(&self.semantic_write_dedup_threshold) {
+            return Err(format!("semantic_write_dedup_threshold must be in [0, 1], got {}", self.semantic_write_dedup_threshold));
+        }
+        if self.default_ttl_secs == 0 || self.creative_ttl_secs == 0 {
+            return Err("TTL values must be greater than 0".to_string());
+        }
+        if !(0.0..=2.0).contains(&self.creative_temperature) {
+            return Err(format!("creative_temperature must be in [0, 2], got {}", self.creative_temperature));
+        }
+
+        Ok(())
+
+    }
+
+    /// Default TTL for a response generated at this temperature
+    pub fn ttl_for(&self, temperature: f32) -> u64 {
+        if temperature > self.creative_temperature {
+            self.creative_ttl_secs
+        } else {
+            self.default_ttl_secs
+        }
+    }
+
+    /// Applies a JSON object of `key: value` pairs, returning the new config.
+    /// Immutable or unknown keys and invalid values reject the whole patch
+    pub fn apply_patch(&self, patch: &Value) -> Result<Self, String> {
+
+        let Some(fields) = patch.as_object() else {
+            return Err("Config patch must be a JSON object".to_string());
+        };
+
+        let immutable: Vec<&str> = fields.keys()
+            .map(|k| k.as_str())
+            .filter(|k| IMMUTABLE_KEYS.contains(k))
+            .collect();
+        if !immutable.is_empty() {
+            return Err(format!("Cannot change {} at runtime - restart the proxy instead", immutable.join(", ")));
+        }
+
+        let mut updated = self.clone();
+
+        for (key, value) in fields {
+            let invalid = || format!("Invalid value for {}: {}", key, value);
+            match key.as_str() {
+                "semantic_threshold" => updated.semantic_threshold = value.as_f64().ok_or_else(invalid)? as f32,
+                "semantic_write_dedup_threshold" => updated.semantic_write_dedup_threshold = value.as_f64().ok_or_else(invalid)? as f32,
+                "default_ttl_secs" => updated.default_ttl_secs = value.as_u64().ok_or_else(invalid)?,
+                "creative_ttl_secs" => updated.creative_ttl_secs = value.as_u64().ok_or_else(invalid)?,
+                "creative_temperature" => updated.creative_temperature = value.as_f64().ok_or_else(invalid)? as f32,
+                _ => return Err(format!("Unknown config key: {}", key))
+            }
+        }
+
+        updated.validate()?;
+        Ok(updated)
+
+    }
+
+    /// Lists every value that differs between `self` and `other`
+    pub fn changes(&self, other: &RuntimeConfig) -> Vec<ConfigChange> {
+
+        let fields = [
+            ("semantic_threshold", json!(round_f32(self.semantic_threshold)), json!(round_f32(other.semantic_threshold))),
+            ("semantic_write_dedup_threshold", json!(round_f32(self.semantic_write_dedup_threshold)), json!(round_f32(other.semantic_write_dedup_threshold))),
+            ("default_ttl_secs", json!(self.default_ttl_secs), json!(other.default_ttl_secs)),
+            ("creative_ttl_secs", json!(self.creative_ttl_secs), json!(other.creative_ttl_secs)),
+            ("creative_temperature", json!(round_f32(self.creative_temperature)), json!(round_f32(other.creative_temperature)))
+        ];
+
+        fields.into_iter()
+            .filter(|(_, before, after)| before != after)
+            .map(|(key, before, after)| ConfigChange { key, before, after })
+            .collect()
+
+    }
+
+}
+
+// versions become part of Redis keys and SCAN patterns, so no ':' or glob characters
+fn validate_namespace_version(version: &str) -> Result<(), String> {
+
+    if version.is_empty() || !version.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')) {
+        return Err(format!("Invalid cache namespace version '{}': use letters, digits, '-', '_' or '.'", version));
+    }
+    Ok(())
+
+}
+
+/// The effective configuration, resolved once at startup. Building it
+/// never touches the network, so it can be tested without any backends
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    pub api_key: String,
+    pub provider: Provider,
+    pub upstream_base_url: String,
+    // providers reachable through a `<provider>/` model prefix, the default upstream first
+    pub upstreams: Vec<Upstream>,
+    pub redis_url: String,
+    pub qdrant_url: String,
+    pub qdrant_collection: String,
+    pub qdrant_max_connections: usize,
+    pub embedding: EmbeddingEndpoint,
+    pub embedding_dim: usize,
+    pub cache_mode: CacheMode,
+    // a disabled tier is never looked up or written, and its backend is never connected
+    pub exact_cache_enabled: bool,
+    pub exact_cache_backend: ExactCacheBackend,
+    // MemoryBackend capacity; unused with Redis
+    pub memory_cache_max_entries: usize,
+    // identical concurrent misses share one upstream call
+    pub request_coalescing: bool,
+    pub semantic_cache_enabled: bool,
+    // in-process LRU in front of Redis; a size of 0 disables it
+    pub tier0_cache_size: usize,
+    pub tier0_ttl_secs: u64,
+    // distinct keys counted for GET /admin/cache/hot
+    pub hot_key_tracker_size: usize,
+    // changing this changes every exact-match key, so it is fixed at startup
+    pub key_normalization: KeyNormalization,
+    // exact keys live under cache:<version>:exact: when set, cache:exact: otherwise
+    pub cache_namespace_version: Option<String>,
+    // copy entries from this namespace into the current one before serving
+    pub cache_previous_namespace_version: Option<String>,
+    pub cache_migrate_on_startup: bool,
+    // startup values; the live ones are in AppState::runtime
+    pub runtime: RuntimeConfig,
+    // deadline for every route except prefill
+    pub request_timeout_secs: u64,
+    // per-call limit on outbound HTTP (upstream, embeddings), kept below request_timeout_secs
+    // so a slow upstream surfaces as an upstream error rather than a dropped request
+    pub reqwest_timeout_secs: u64,
+    pub health_timeout_secs: u64,
+    pub health_monitor_interval_secs: u64,
+    pub strict_collection_validation: bool,
+    // run the --check probes before serving and exit if any fail
+    pub self_test_on_start: bool,
+    // connection attempts per backing service before giving up at startup
+    pub startup_retries: u32,
+    pub startup_retry_delay_secs: u64,
+    pub compression: CompressionConfig,
+    pub prefill_parallelism: usize,
+    pub quarantine_ttl_secs: u64,
+    pub refresh: RefreshConfig,
+    pub models: ModelConfig,
+    // add usage.cost_usd to chat completion responses
+    pub include_cost_in_response: bool,
+    pub log_path: String,
+    // when the request log at `log_path` is rotated and how many old files are kept
+    pub log_rotation: LogRotation,
+    // SQLite or Postgres database that request records are also written to, for /admin/requests
+    pub request_store_url: Option<String>,
+    pub audit_log_path: String,
+    // log a sanitized request (no message content) in place of the raw one
+    pub redact_prompts_in_logs: bool,
+    pub admin_token: Option<String>,
+    // keys clients must present on the completion and passthrough routes
+    pub api_keys: ApiKeys,
+    pub rate_limits: RateLimits,
+    pub byok: ByokConfig,
+    // TENANT_SOURCE: how requests are split into per-tenant cache partitions
+    pub tenant_source: TenantSource,
+    // daily usage each tenant may spend upstream
+    pub tenant_quotas: Quotas,
+    // spend caps on upstream calls, globally and per API key
+    pub budget: BudgetConfig,
+    // how failed upstream calls are retried
+    pub retry: RetryPolicy,
+    pub circuit_breaker: BreakerConfig,
+    // how long past its expiry an exact-match entry may still be served when
+    // the upstream fails; 0 turns stale fallback off
+    pub stale_grace_secs: u64,
+    // an exact hit with less than this many seconds left is served and re-run
+    // upstream in the background; 0 turns stale-while-revalidate off
+    pub swr_window_secs: u64,
+    // how long a prompt's embedding is kept in the exact-match backend; 0 turns the embedding cache off
+    pub embedding_cache_ttl_secs: u64,
+    // how often the janitor runs (src/janitor.rs); 0 turns it off
+    pub janitor_interval_secs: u64,
+    // the janitor trims the semantic collection to this many points; 0 is unlimited
+    pub semantic_max_points: u64,
+    // where metrics counters are saved every METRICS_PERSIST_INTERVAL_SECS and restored from on startup
+    pub metrics_persist: MetricsPersist,
+    pub metrics_persist_path: String,
+    pub metrics_persist_interval_secs: u64,
+    // tells apart the snapshots of proxies sharing one Redis
+    pub metrics_instance_id: String,
+    pub bind_address: SocketAddr,
+    // load balancers whose X-Forwarded-For is believed; empty ignores the header
+    pub trusted_proxies: Vec<IpAddr>,
+    // MODEL_PRICING, consulted before the built-in price table
+    pub pricing: BTreeMap<String, ModelPrice>,
+    // the TOML file settings were read from, if any
+    pub config_file: Option<String>,
+    // config key -> where its value came from
+    pub sources: BTreeMap<&'static str, ConfigSource>
+}
+
+impl Config {
+
+    pub fn from_env() -> Result<Self, String> {
+
+        Self::from_sources(|name| std::env::var(name).ok(), ConfigFile::from_env()?)
+
+    }
+
+    pub fn from_lookup(lookup: impl Fn(&str) -> Option<String>) -> Result<Self, String> {
+
+        Self::from_sources(lookup, None)
+
+    }
+
+    /// Reads every setting from `env`, falling back to `file`
+    pub fn from_sources(env: impl Fn(&str) -> Option<String>, file: Option<ConfigFile>) -> Result<Self, String> {
+
+        let mut sources = BTreeMap::new();
+
+        let from_env = |name: &str| env(name).filter(|v| !v.trim().is_empty());
+        let from_file = |name: &str| file.as_ref().and_then(|file| file.get(name)).filter(|v| !v.trim().is_empty());
+        let lookup = |name: &str| from_env(name).or_else(|| from_file(name));
+
+        let (provider, api_key) = match resolve_api_key(lookup) {
+            Ok(resolved) => {
+                let in_env = from_env("GROQ_API_KEY").or_else(|| from_env("OPENAI_API_KEY")).is_some();
+                sources.insert("API_KEY", if in_env { ConfigSource::Env } else { ConfigSource::File });
+                resolved
+            }
+            Err(_) if cfg!(feature = "mock") => {
+                sources.insert("API_KEY", ConfigSource::Default);
+                (Provider::Groq, "mock".to_string())
+            }
+            Err(e) => return Err(e)
+        };
+
+        // returns the env or file value if set, recording where it came from
+        let mut read = |name: &'static str| -> Option<String> {
+            let (value, source) = match (from_env(name), from_file(name)) {
+                (Some(value), _) => (Some(value), ConfigSource::Env),
+                (None, Some(value)) => (Some(value), ConfigSource::File),
+                (None, None) => (None, ConfigSource::Default)
+            };
+            sources.insert(name, source);
+            value
+        };
+
+        let upstream_base_url = normalize_base_url(
+            &read("UPSTREAM_BASE_URL").unwrap_or_else(|| provider.default_base_url().to_string())
+        )?;
+
+        // the other providers are reachable once they have a key (Ollama needs none).
+        // Without GROQ_API_KEY, OPENAI_API_KEY is still the deprecated Groq fallback
+        let groq_key_set = lookup("GROQ_API_KEY").is_some_and(|key| !key.trim().is_empty());
+        let mut upstreams = vec![Upstream { provider, base_url: upstream_base_url.clone(), api_key: Some(api_key.clone()) }];
+        for other in Provider::ALL.into_iter().filter(|other| *other != provider) {
+            let api_key = match other.api_key_var() {
+                Some("OPENAI_API_KEY") if !groq_key_set => continue,
+                Some(var) => match read(var) {
+                    Some(key) => Some(key),
+                    None => continue
+                },
+                None => None
+            };
+            let base_url = normalize_base_url(&read(other.base_url_var()).unwrap_or_else(|| other.default_base_url().to_string()))?;
+            upstreams.push(Upstream { provider: other, base_url, api_key });
+        }
+
+        let cache_mode = match read("CACHE_MODE") {
+            Some(value) => CacheMode::parse(&value).unwrap_or_else(|| {
+                tracing::warn!("Unknown CACHE_MODE '{}', defaulting to serve", value);
+                CacheMode::Serve
+            }),
+            None => CacheMode::Serve
+        };
+
+        let embedding_provider = match read("EMBEDDING_PROVIDER") {
+            Some(value) => EmbeddingProvider::parse(&value)
+                .ok_or_else(|| format!("Unknown EMBEDDING_PROVIDER '{}': use http, local or openai", value))?,
+            None => EmbeddingProvider::Http
+        };
+        if embedding_provider == EmbeddingProvider::Local && !cfg!(feature = "local-embeddings") {
+            return Err("EMBEDDING_PROVIDER=local needs a build with --features local-embeddings".to_string());
+        }
+
+        // falling back to Redis would leave a Redis-less deployment retrying at startup
+        let exact_cache_backend = match read("EXACT_CACHE_BACKEND") {
+            Some(value) => ExactCacheBackend::parse(&value)
+                .ok_or_else(|| format!("Unknown EXACT_CACHE_BACKEND '{}': use redis or memory", value))?,
+            None => ExactCacheBackend::Redis
+        };
+
+        let defaults = KeyNormalization::default();
+        let key_normalization = KeyNormalization {
+            case_sensitive: parse_or(read("KEY_CASE_SENSITIVE"), defaults.case_sensitive),
+            collapse_whitespace: parse_or(read("KEY_COLLAPSE_WHITESPACE"), defaults.collapse_whitespace)
+        };
+
+        let cache_namespace_version = read("CACHE_NAMESPACE_VERSION").map(|v| v.trim().to_string());
+        let cache_previous_namespace_version = read("CACHE_PREVIOUS_NAMESPACE_VERSION").map(|v| v.trim().to_string());
+        for version in [&cache_namespace_version, &cache_previous_namespace_version].into_iter().flatten() {
+            validate_namespace_version(version)?;
+        }
+        let cache_migrate_on_startup = parse_or(read("CACHE_MIGRATE_ON_STARTUP"), false);
+        if cache_migrate_on_startup && cache_namespace_version == cache_previous_namespace_version {
+            return Err("CACHE_MIGRATE_ON_STARTUP needs CACHE_PREVIOUS_NAMESPACE_VERSION to differ from CACHE_NAMESPACE_VERSION".to_string());
+        }
+
+        let compression = CompressionConfig::from_algorithms(
+            &read("COMPRESSION_ALGORITHMS").unwrap_or_else(|| "gzip,br".to_string()),
+            parse_or(read("COMPRESS_MIN_BYTES"), CompressionConfig::default().min_bytes)
+        );
+
+        let refresh_defaults = RefreshConfig::default();
+        let refresh = RefreshConfig {
+            enabled: parse_or(read("CACHE_REFRESH_ENABLED"), refresh_defaults.enabled),
+            interval_secs: parse_or(read("CACHE_REFRESH_INTERVAL_SECS"), refresh_defaults.interval_secs).max(1),
+            min_hits: parse_or(read("CACHE_REFRESH_MIN_HITS"), refresh_defaults.min_hits),
+            ttl_below_secs: parse_or(read("CACHE_REFRESH_TTL_BELOW_SECS"), refresh_defaults.ttl_below_secs),
+            max_per_interval: parse_or(read("CACHE_REFRESH_MAX_PER_INTERVAL"), refresh_defaults.max_per_interval),
+            max_cost_usd: parse_or(read("CACHE_REFRESH_MAX_COST_USD"), refresh_defaults.max_cost_usd)
+        };
+
+        let models = ModelConfig::from_parts(
+            read("MODEL_ALIASES").as_deref(),
+            read("MODEL_ALLOWLIST").as_deref(),
+            parse_or(read("PRESERVE_CLIENT_MODEL_NAME"), false)
+        )?;
+
+        let api_keys = ApiKeys::from_parts(read("PROXY_API_KEYS").as_deref(), read("PROXY_API_KEYS_FILE").as_deref())?;
+
+        let tenant_source = match read("TENANT_SOURCE") {
+            Some(value) => TenantSource::parse(&value)
+                .ok_or_else(|| format!("Unknown TENANT_SOURCE '{}': use off, header or api_key", value))?,
+            None => TenantSource::Off
+        };
+        if tenant_source == TenantSource::ApiKey && !api_keys.enabled() {
+            return Err("TENANT_SOURCE=api_key needs PROXY_API_KEYS or PROXY_API_KEYS_FILE".to_string());
+        }
+
+        // 0 or unset leaves a quota off
+        let tenant_quotas = Quotas {
+            daily_tokens: Some(parse_or(read("TENANT_DAILY_TOKEN_QUOTA"), 0)).filter(|n| *n > 0),
+            daily_cost_usd: Some(parse_or(read("TENANT_DAILY_COST_QUOTA_USD"), 0.0)).filter(|usd| *usd > 0.0)
+        };
+        if tenant_quotas != Quotas::default() && tenant_source == TenantSource::Off {
+            return Err("TENANT_DAILY_TOKEN_QUOTA and TENANT_DAILY_COST_QUOTA_USD need TENANT_SOURCE".to_string());
+        }
+
+        let bind_address = match read("BIND_ADDRESS") {
+            Some(raw) => raw.trim().parse()
+                .map_err(|e| format!("BIND_ADDRESS must be host:port, like 0.0.0.0:3000: {}", e))?,
+            None => SocketAddr::from(([0, 0, 0, 0], 3000))
+        };
+
+        let trusted_proxies = read("TRUSTED_PROXIES").unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|ip| !ip.is_empty())
+            .map(|ip| ip.parse().map_err(|e| format!("TRUSTED_PROXIES must be comma-separated IP addresses, got {}: {}", ip, e)))
+            .collect::<Result<Vec<IpAddr>, String>>()?;
+
+        let pricing = match read("MODEL_PRICING") {
+            Some(raw) => parse_model_pricing(&raw)?,
+            None => BTreeMap::new()
+        };
+
+        let byok = ByokConfig {
+            enabled: parse_or(read("BYOK_ENABLED"), false),
+            shared_cache: parse_or(read("BYOK_SHARED_CACHE"), false)
+        };
+        // both would arrive in the same Authorization header
+        if byok.enabled && api_keys.enabled() {
+            return Err("BYOK_ENABLED can't be combined with PROXY_API_KEYS: both use the Authorization header".to_string());
+        }
+
+        // 0 or unset leaves a limit off
+        let mut limit = |name: &'static str| Some(parse_or(read(name), 0.0)).filter(|usd: &f64| *usd > 0.0);
+        let budget = BudgetConfig {
+            global: BudgetLimits { soft_usd: limit("BUDGET_SOFT_LIMIT_USD"), hard_usd: limit("BUDGET_HARD_LIMIT_USD") },
+            per_key: BudgetLimits { soft_usd: limit("BUDGET_KEY_SOFT_LIMIT_USD"), hard_usd: limit("BUDGET_KEY_HARD_LIMIT_USD") },
+            period: match read("BUDGET_PERIOD") {
+                Some(value) => BudgetPeriod::parse(&value)
+                    .ok_or_else(|| format!("Unknown BUDGET_PERIOD '{}': use daily, weekly or monthly", value))?,
+                None => BudgetPeriod::default()
+            },
+            reject_status: match parse_or(read("BUDGET_REJECT_STATUS"), 402) {
+                status @ (402 | 429) => status,
+                status => return Err(format!("BUDGET_REJECT_STATUS must be 402 or 429, not {}", status))
+            }
+        };
+        if budget.per_key.enabled() && !api_keys.enabled() {
+            return Err("BUDGET_KEY_SOFT_LIMIT_USD and BUDGET_KEY_HARD_LIMIT_USD need PROXY_API_KEYS".to_string());
+        }
+
+        let metrics_persist = match read("METRICS_PERSIST") {
+            Some(value) => MetricsPersist::parse(&value)
+                .ok_or_else(|| format!("Unknown METRICS_PERSIST '{}': use off, redis or file", value))?,
+            None => MetricsPersist::Off
+        };
+        let exact_cache_enabled = parse_or(read("EXACT_CACHE_ENABLED"), true);
+        if metrics_persist == MetricsPersist::Redis && (!exact_cache_enabled || exact_cache_backend != ExactCacheBackend::Redis) {
+            return Err("METRICS_PERSIST=redis needs the exact-match tier on Redis; use METRICS_PERSIST=file instead".to_string());
+        }
+        let rate_limit_shared = parse_or(read("RATE_LIMIT_SHARED"), false);
+        if rate_limit_shared && (!exact_cache_enabled || exact_cache_backend != ExactCacheBackend::Redis) {
+            return Err("RATE_LIMIT_SHARED needs the exact-match tier on Redis".to_string());
+        }
+
+        let log_rotation = LogRotation {
+            max_bytes: parse_or(read("LOG_ROTATE_MAX_BYTES"), LogRotation::default().max_bytes),
+            every: match read("LOG_ROTATE_INTERVAL") {
+                Some(value) => RotateEvery::parse(&value)
+                    .ok_or_else(|| format!("Unknown LOG_ROTATE_INTERVAL '{}': use never, hourly or daily", value))?,
+                None => RotateEvery::default()
+            },
+            retain_files: parse_or(read("LOG_RETAIN_FILES"), LogRotation::default().retain_files)
+        };
+
+        let request_store_url = read("REQUEST_STORE_URL");
+        if let Some(url) = &request_store_url
+            && !["sqlite:", "postgres:", "postgresql:"].iter().any(|scheme| url.starts_with(scheme)) {
+            return Err(format!("REQUEST_STORE_URL must be a sqlite: or postgres: URL, got '{}'", url));
+        }
+
+        let runtime = RuntimeConfig::from_lookup(&mut read)?;
+
+        let config = Config {
+            api_key,
+            provider,
+            upstream_base_url,
+            upstreams,
+            redis_url: read("REDIS_URL").unwrap_or_else(|| "redis://127.0.0.1:6379".to_string()),
+            qdrant_url: read("QDRANT_URL").unwrap_or_else(|| "http://127.0.0.1:6334".to_string()),
+            qdrant_collection: read("QDRANT_COLLECTION").unwrap_or_else(|| "llm_cache".to_string()),
+            qdrant_max_connections: parse_or(read("QDRANT_MAX_CONNECTIONS"), DEFAULT_QDRANT_MAX_CONNECTIONS).max(1),
+            embedding: match embedding_provider {
+                EmbeddingProvider::Http => EmbeddingEndpoint::http(
+                    &read("EMBEDDING_URL").unwrap_or_else(|| "http://127.0.0.1:8001/embed".to_string())
+                ),
+                EmbeddingProvider::Local => EmbeddingEndpoint {
+                    provider: embedding_provider,
+                    ..EmbeddingEndpoint::http(LOCAL_EMBEDDING_URL)
+                },
+                EmbeddingProvider::OpenAi => EmbeddingEndpoint {
+                    url: read("EMBEDDING_URL").unwrap_or_else(|| "https://api.openai.com/v1/embeddings".to_string()),
+                    model: Some(read("EMBEDDING_MODEL").unwrap_or_else(|| "text-embedding-3-small".to_string())),
+                    api_key: read("EMBEDDING_API_KEY").or_else(|| read("OPENAI_API_KEY")),
+                    provider: embedding_provider
+                }
+            },
+            embedding_dim: parse_or(read("EMBEDDING_DIM"), EMBEDDING_DIM).max(1),
+            cache_mode,
+            exact_cache_enabled,
+            exact_cache_backend,
+            memory_cache_max_entries: parse_or(read("MEMORY_CACHE_MAX_ENTRIES"), DEFAULT_MEMORY_CACHE_MAX_ENTRIES).max(1),
+            semantic_cache_enabled: parse_or(read("SEMANTIC_CACHE_ENABLED"), true),
+            request_coalescing: parse_or(read("REQUEST_COALESCING_ENABLED"), true),
+            tier0_cache_size: parse_or(read("TIER0_CACHE_SIZE"), 100),
+            tier0_ttl_secs: parse_or(read("TIER0_TTL_SECS"), 60).max(1),
+            hot_key_tracker_size: parse_or(read("HOT_KEY_TRACKER_SIZE"), 1000).max(1),
+            key_normalization,
+            cache_namespace_version,
+            cache_previous_namespace_version,
+            cache_migrate_on_startup,
+            runtime,
+            request_timeout_secs: parse_or(read("REQUEST_TIMEOUT_SECS"), 120).max(1),
+            reqwest_timeout_secs: parse_or(read("REQWEST_TIMEOUT_SECS"), 90).max(1),
+            health_timeout_secs: parse_or(read("HEALTH_TIMEOUT_SECS"), 5),
+            health_monitor_interval_secs: parse_or(read("HEALTH_MONITOR_INTERVAL_SECS"), 30).max(1),
+            strict_collection_validation: read("STRICT_COLLECTION_VALIDATION")
+                .map(|v| v.to_lowercase() == "true")
+                .unwrap_or(false),
+            self_test_on_start: read("SELF_TEST_ON_START")
+                .map(|v| v.to_lowercase() == "true")
+                .unwrap_or(false),
+            startup_retries: parse_or(read("STARTUP_RETRIES"), 10).max(1),
+            startup_retry_delay_secs: parse_or(read("STARTUP_RETRY_DELAY_SECS"), 3),
+            compression,
+            prefill_parallelism: parse_or(read("PREFILL_PARALLELISM"), 5).max(1),
+            quarantine_ttl_secs: parse_or(read("QUARANTINE_TTL_SECS"), 86400).max(1),
+            refresh,
+            models,
+            include_cost_in_response: read("INCLUDE_COST_IN_RESPONSE")
+                .map(|v| v.to_lowercase() == "true")
+                .unwrap_or(false),
+            log_path: read("LOG_PATH").unwrap_or_else(|| "./requests.log".to_string()),
+            log_rotation,
+            request_store_url,
+            audit_log_path: read("AUDIT_LOG_PATH").unwrap_or_else(|| "./audit.log".to_string()),
+            redact_prompts_in_logs: read("REDACT_PROMPTS_IN_LOGS")
+                .map(|v| v.to_lowercase() == "true")
+                .unwrap_or(false),
+            admin_token: read("ADMIN_TOKEN"),
+            api_keys,
+            // 0 or unset leaves a limit off
+            rate_limits: RateLimits {
+                requests_per_min: Some(parse_or(read("RATE_LIMIT_REQUESTS_PER_MIN"), 0)).filter(|n| *n > 0),
+                tokens_per_min: Some(parse_or(read("RATE_LIMIT_TOKENS_PER_MIN"), 0)).filter(|n| *n > 0),
+                shared: rate_limit_shared
+            },
+            byok,
+            tenant_source,
+            tenant_quotas,
+            budget,
+            retry: RetryPolicy {
+                max_attempts: parse_or(read("UPSTREAM_RETRY_MAX_ATTEMPTS"), RetryPolicy::default().max_attempts).max(1),
+                backoff_base_ms: parse_or(read("UPSTREAM_RETRY_BACKOFF_MS"), RetryPolicy::default().backoff_base_ms),
+                max_backoff_ms: parse_or(read("UPSTREAM_RETRY_MAX_BACKOFF_MS"), RetryPolicy::default().max_backoff_ms)
+            },
+            circuit_breaker: BreakerConfig {
+                failure_threshold: parse_or(read("CIRCUIT_BREAKER_FAILURE_THRESHOLD"), BreakerConfig::default().failure_threshold),
+                open_secs: parse_or(read("CIRCUIT_BREAKER_OPEN_SECS"), BreakerConfig::default().open_secs).max(1)
+            },
+            stale_grace_secs: parse_or(read("STALE_GRACE_SECS"), 0),
+            swr_window_secs: parse_or(read("SWR_WINDOW_SECS"), 0),
+            embedding_cache_ttl_secs: parse_or(read("EMBEDDING_CACHE_TTL_SECS"), 604800),
+            janitor_interval_secs: parse_or(read("JANITOR_INTERVAL_SECS"), 300),
+            semantic_max_points: parse_or(read("SEMANTIC_MAX_POINTS"), 0),
+            metrics_persist,
+            metrics_persist_path: read("METRICS_PERSIST_PATH").unwrap_or_else(|| "./metrics.json".to_string()),
+            metrics_persist_interval_secs: parse_or(read("METRICS_PERSIST_INTERVAL_SECS"), 60).max(1),
+            metrics_instance_id: read("METRICS_INSTANCE_ID").unwrap_or_else(|| "default".to_string()),
+            bind_address,
+            trusted_proxies,
+            pricing,
+            config_file: file.as_ref().map(|file| file.path.clone()),
+            sources
+        };
+
+        Ok(config)
+
+    }
+
+    /// The upstream for `model` and the model name to send it. A prefix
+    /// naming a configured provider (`openai/gpt-4o`) picks that provider;
+    /// anything else, including Groq's own `openai/gpt-oss-20b` while OpenAI
+    /// isn't configured, goes to the default upstream unchanged
+    pub fn route_model<'a>(&self, model: &'a str) -> (&Upstream, &'a str) {
+
+        if let Some((prefix, name)) = model.split_once('/')
+            && let Some(provider) = Provider::from_name(prefix)
+            && let Some(upstream) = self.upstreams.iter().find(|upstream| upstream.provider == provider) {
+            return (upstream, name);
+        }
+        (&self.upstreams[0], model)
+
+    }
+
+    /// Prefix of every exact-match key in the current namespace
+    pub fn exact_key_prefix(&self) -> String {
+        exact_key_prefix(self.cache_namespace_version.as_deref())
+    }
+
+    pub fn request_timeout(&self) -> Duration {
+        Duration::from_secs(self.request_timeout_secs)
+    }
+
+    pub fn reqwest_timeout(&self) -> Duration {
+        Duration::from_secs(self.reqwest_timeout_secs)
+    }
+
+    pub fn startup_retry_delay(&self) -> Duration {
+        Duration::from_secs(self.startup_retry_delay_secs)
+    }
+
+    pub fn health_timeout(&self) -> Duration {
+        Duration::from_secs(self.health_timeout_secs)
+    }
+
+    fn source(&self, env_var: &str) -> ConfigSource {
+        self.sources.get(env_var).copied().unwrap_or(ConfigSource::Default)
+    }
+
+    /// OpenAI embedding models differ in size, so without EMBEDDING_DIM the
+    /// dimension is taken from a probe embedding at startup
+    pub fn detects_embedding_dim(&self) -> bool {
+        self.embedding.provider == EmbeddingProvider::OpenAi && self.source("EMBEDDING_DIM") == ConfigSource::Default
+    }
+
+    /// Serializes the configuration with secrets masked to their last 4
+    /// characters, annotating each value with where it came from.
+    /// `runtime` is the live runtime config, which may differ from startup
+    pub fn redacted(&self, runtime: &RuntimeConfig) -> Value {
+
+        let entry = |value: Value, env_var: Option<&str>| json!({
+            "value": value,
+            "source": env_var.map(|v| self.source(v)).unwrap_or(ConfigSource::Default)
+        });
+
+        let changed: Vec<&str> = self.runtime.changes(runtime).iter().map(|c| c.key).collect();
+        let runtime_entry = |key: &str, value: Value, env_var: &str| json!({
+            "value": value,
+            "source": if changed.contains(&key) { ConfigSource::Runtime } else { self.source(env_var) }
+        });
+
+        json!({
+            "cache": {
+                "mode": entry(json!(self.cache_mode.as_str()), Some("CACHE_MODE")),
+                "exact_enabled": entry(json!(self.exact_cache_enabled), Some("EXACT_CACHE_ENABLED")),
+                "semantic_enabled": entry(json!(self.semantic_cache_enabled), Some("SEMANTIC_CACHE_ENABLED")),
+                "tier0": {
+                    "size": entry(json!(self.tier0_cache_size), Some("TIER0_CACHE_SIZE")),
+                    "ttl_secs": entry(json!(self.tier0_ttl_secs), Some("TIER0_TTL_SECS"))
+                },
+                "hot_key_tracker_size": entry(json!(self.hot_key_tracker_size), Some("HOT_KEY_TRACKER_SIZE")),
+                "request_coalescing": entry(json!(self.request_coalescing), Some("REQUEST_COALESCING_ENABLED")),
+                "namespace": {
+                    "version": entry(json!(self.cache_namespace_version), Some("CACHE_NAMESPACE_VERSION")),
+                    "previous_version": entry(json!(self.cache_previous_namespace_version), Some("CACHE_PREVIOUS_NAMESPACE_VERSION")),
+                    "migrate_on_startup": entry(json!(self.cache_migrate_on_startup), Some("CACHE_MIGRATE_ON_STARTUP"))
+                },
+                "key_normalization": {
+                    "case_sensitive": entry(json!(self.key_normalization.case_sensitive), Some("KEY_CASE_SENSITIVE")),
+                    "collapse_whitespace": entry(json!(self.key_normalization.collapse_whitespace), Some("KEY_COLLAPSE_WHITESPACE"))
+                },
+                "semantic_threshold": runtime_entry("semantic_threshold", json!(round_f32(runtime.semantic_threshold)), "SEMANTIC_THRESHOLD"),
+                "semantic_write_dedup_threshold": runtime_entry("semantic_write_dedup_threshold", json!(round_f32(runtime.semantic_write_dedup_threshold)), "SEMANTIC_WRITE_DEDUP_THRESHOLD"),
+                "quarantine_ttl_secs": entry(json!(self.quarantine_ttl_secs), Some("QUARANTINE_TTL_SECS")),
+                "ttl_policy": {
+                    "default_ttl_secs": runtime_entry("default_ttl_secs", json!(runtime.default_ttl_secs), "CACHE_TTL_SECS"),
+                    "creative_ttl_secs": runtime_entry("creative_ttl_secs", json!(runtime.creative_ttl_secs), "CREATIVE_CACHE_TTL_SECS"),
+                    "creative_temperature_above": runtime_entry("creative_temperature", json!(round_f32(runtime.creative_temperature)), "CREATIVE_TEMPERATURE")
+                }
+            },
+            "backends": {
+                "exact": entry(json!(self.exact_cache_backend.as_str()), Some("EXACT_CACHE_BACKEND")),
+                "memory_cache_max_entries": entry(json!(self.memory_cache_max_entries), Some("MEMORY_CACHE_MAX_ENTRIES")),
+                "semantic": "qdrant",
+                "redis_url": entry(json!(self.redis_url), Some("REDIS_URL")),
+                "qdrant_url": entry(json!(self.qdrant_url), Some("QDRANT_URL")),
+                "qdrant_collection": entry(json!(self.qdrant_collection), Some("QDRANT_COLLECTION")),
+                "qdrant_max_connections": entry(json!(self.qdrant_max_connections), Some("QDRANT_MAX_CONNECTIONS")),
+                "strict_collection_validation": entry(json!(self.strict_collection_validation), Some("STRICT_COLLECTION_VALIDATION")),
+                "self_test_on_start": entry(json!(self.self_test_on_start), Some("SELF_TEST_ON_START")),
+                "startup_retries": entry(json!(self.startup_retries), Some("STARTUP_RETRIES")),
+                "startup_retry_delay_secs": entry(json!(self.startup_retry_delay_secs), Some("STARTUP_RETRY_DELAY_SECS"))
+            },
+            "upstream": {
+                "provider": entry(json!(self.provider.name()), None),
+                "base_url": entry(json!(self.upstream_base_url), Some("UPSTREAM_BASE_URL")),
+                "api_key": entry(json!(mask_secret(&self.api_key)), Some("API_KEY")),
+                "providers": self.upstreams.iter().skip(1).map(|upstream| json!({
+                    "provider": upstream.provider.name(),
+                    "base_url": entry(json!(upstream.base_url), Some(upstream.provider.base_url_var())),
+                    "api_key": upstream.api_key.as_deref().map(mask_secret)
+                })).collect::<Vec<_>>()
+            },
+            "embedding": {
+                "backend": entry(json!(self.embedding.provider.as_str()), Some("EMBEDDING_PROVIDER")),
+                "url": entry(json!(self.embedding.url), Some("EMBEDDING_URL")),
+                "model": entry(json!(self.embedding.model), Some("EMBEDDING_MODEL")),
+                "api_key": self.embedding.api_key.as_deref().map(mask_secret),
+                "dimension": entry(json!(self.embedding_dim), Some("EMBEDDING_DIM"))
+            },
+            "timeouts": {
+                "request_timeout_secs": entry(json!(self.request_timeout_secs), Some("REQUEST_TIMEOUT_SECS")),
+                "reqwest_timeout_secs": entry(json!(self.reqwest_timeout_secs), Some("REQWEST_TIMEOUT_SECS")),
+                "health_timeout_secs": entry(json!(self.health_timeout_secs), Some("HEALTH_TIMEOUT_SECS")),
+                "health_monitor_interval_secs": entry(json!(self.health_monitor_interval_secs), Some("HEALTH_MONITOR_INTERVAL_SECS"))
+            },
+            "compression": {
+                "algorithms": entry(json!(self.compression.algorithms()), Some("COMPRESSION_ALGORITHMS")),
+                "min_bytes": entry(json!(self.compression.min_bytes), Some("COMPRESS_MIN_BYTES"))
+            },
+            "refresh": {
+                "enabled": entry(json!(self.refresh.enabled), Some("CACHE_REFRESH_ENABLED")),
+                "interval_secs": entry(json!(self.refresh.interval_secs), Some("CACHE_REFRESH_INTERVAL_SECS")),
+                "min_hits": entry(json!(self.refresh.min_hits), Some("CACHE_REFRESH_MIN_HITS")),
+                "ttl_below_secs": entry(json!(self.refresh.ttl_below_secs), Some("CACHE_REFRESH_TTL_BELOW_SECS")),
+                "max_per_interval": entry(json!(self.refresh.max_per_interval), Some("CACHE_REFRESH_MAX_PER_INTERVAL")),
+                "max_cost_usd": entry(json!(self.refresh.max_cost_usd), Some("CACHE_REFRESH_MAX_COST_USD"))
+            },
+            "models": {
+                "aliases": entry(json!(self.models.aliases), Some("MODEL_ALIASES")),
+                "allowlist": entry(json!(self.models.allowlist), Some("MODEL_ALLOWLIST")),
+                "preserve_client_name": entry(json!(self.models.preserve_client_name), Some("PRESERVE_CLIENT_MODEL_NAME"))
+            },
+            "responses": {
+                "include_cost": entry(json!(self.include_cost_in_response), Some("INCLUDE_COST_IN_RESPONSE"))
+            },
+            "prefill": {
+                "parallelism": entry(json!(self.prefill_parallelism), Some("PREFILL_PARALLELISM"))
+            },
+            "logging": {
+                "log_path": entry(json!(self.log_path), Some("LOG_PATH")),
+                "log_rotation": {
+                    "max_bytes": entry(json!(self.log_rotation.max_bytes), Some("LOG_ROTATE_MAX_BYTES")),
+                    "interval": entry(json!(self.log_rotation.every.as_str()), Some("LOG_ROTATE_INTERVAL")),
+                    "retain_files": entry(json!(self.log_rotation.retain_files), Some("LOG_RETAIN_FILES"))
+                },
+                "request_store_url": entry(json!(self.request_store_url), Some("REQUEST_STORE_URL")),
+                "audit_log_path": entry(json!(self.audit_log_path), Some("AUDIT_LOG_PATH")),
+                "redact_prompts_in_logs": entry(json!(self.redact_prompts_in_logs), Some("REDACT_PROMPTS_IN_LOGS"))
+            },
+            "admin": {
+                "token": entry(json!(self.admin_token.as_deref().map(mask_secret)), Some("ADMIN_TOKEN"))
+            },
+            "rate_limit": {
+                "requests_per_min": entry(json!(self.rate_limits.requests_per_min), Some("RATE_LIMIT_REQUESTS_PER_MIN")),
+                "tokens_per_min": entry(json!(self.rate_limits.tokens_per_min), Some("RATE_LIMIT_TOKENS_PER_MIN")),
+                "shared": entry(json!(self.rate_limits.shared), Some("RATE_LIMIT_SHARED"))
+            },
+            // key names only; the keys themselves are never shown
+            "auth": {
+                "enabled": self.api_keys.enabled(),
+                "api_keys": entry(json!(self.api_keys.names()), Some("PROXY_API_KEYS")),
+                "api_keys_file": entry(json!(self.api_keys.file), Some("PROXY_API_KEYS_FILE"))
+            },
+            "server": {
+                "bind_address": entry(json!(self.bind_address.to_string()), Some("BIND_ADDRESS")),
+                "trusted_proxies": entry(json!(self.trusted_proxies), Some("TRUSTED_PROXIES")),
+                "config_file": self.config_file
+            },
+            "pricing": entry(json!(self.pricing), Some("MODEL_PRICING")),
+            "tenant_source": entry(json!(self.tenant_source.as_str()), Some("TENANT_SOURCE")),
+            "tenant_quotas": {
+                "daily_tokens": entry(json!(self.tenant_quotas.daily_tokens), Some("TENANT_DAILY_TOKEN_QUOTA")),
+                "daily_cost_usd": entry(json!(self.tenant_quotas.daily_cost_usd), Some("TENANT_DAILY_COST_QUOTA_USD"))
+            },
+            "budget": {
+                "soft_limit_usd": entry(json!(self.budget.global.soft_usd), Some("BUDGET_SOFT_LIMIT_USD")),
+                "hard_limit_usd": entry(json!(self.budget.global.hard_usd), Some("BUDGET_HARD_LIMIT_USD")),
+                "key_soft_limit_usd": entry(json!(self.budget.per_key.soft_usd), Some("BUDGET_KEY_SOFT_LIMIT_USD")),
+                "key_hard_limit_usd": entry(json!(self.budget.per_key.hard_usd), Some("BUDGET_KEY_HARD_LIMIT_USD")),
+                "period": entry(json!(self.budget.period.as_str()), Some("BUDGET_PERIOD")),
+                "reject_status": entry(json!(self.budget.reject_status), Some("BUDGET_REJECT_STATUS"))
+            },
+            "byok": {
+                "enabled": entry(json!(self.byok.enabled), Some("BYOK_ENABLED")),
+                "shared_cache": entry(json!(self.byok.shared_cache), Some("BYOK_SHARED_CACHE"))
+            },
+            "retry": {
+                "max_attempts": entry(json!(self.retry.max_attempts), Some("UPSTREAM_RETRY_MAX_ATTEMPTS")),
+                "backoff_base_ms": entry(json!(self.retry.backoff_base_ms), Some("UPSTREAM_RETRY_BACKOFF_MS")),
+                "max_backoff_ms": entry(json!(self.retry.max_backoff_ms), Some("UPSTREAM_RETRY_MAX_BACKOFF_MS"))
+            },
+            "stale_grace_secs": entry(json!(self.stale_grace_secs), Some("STALE_GRACE_SECS")),
+            "swr_window_secs": entry(json!(self.swr_window_secs), Some("SWR_WINDOW_SECS")),
+            "embedding_cache_ttl_secs": entry(json!(self.embedding_cache_ttl_secs), Some("EMBEDDING_CACHE_TTL_SECS")),
+            "janitor_interval_secs": entry(json!(self.janitor_interval_secs), Some("JANITOR_INTERVAL_SECS")),
+            "semantic_max_points": entry(json!(self.semantic_max_points), Some("SEMANTIC_MAX_POINTS")),
+            "metrics_persist": {
+                "store": entry(json!(self.metrics_persist.as_str()), Some("METRICS_PERSIST")),
+                "path": entry(json!(self.metrics_persist_path), Some("METRICS_PERSIST_PATH")),
+                "interval_secs": entry(json!(self.metrics_persist_interval_secs), Some("METRICS_PERSIST_INTERVAL_SECS")),
+                "instance_id": entry(json!(self.metrics_instance_id), Some("METRICS_INSTANCE_ID"))
+            },
+            "circuit_breaker": {
+                "failure_threshold": entry(json!(self.circuit_breaker.failure_threshold), Some("CIRCUIT_BREAKER_FAILURE_THRESHOLD")),
+                "open_secs": entry(json!(self.circuit_breaker.open_secs), Some("CIRCUIT_BREAKER_OPEN_SECS"))
+            },
+            "features": {
+                "mock": cfg!(feature = "mock")
+            }
+        })
+
+    }
+
+}
+
+// f32 -> f64 without exposing float noise like 0.8999999761581421
+fn round_f32(value: f32) -> f64 {
+    (value as f64 * 10_000.0).round() / 10_000.0
+}
+
+fn parse_or<T: std::str::FromStr>(value: Option<String>, default: T) -> T {
+    value.and_then(|v| v.trim().parse().ok()).unwrap_or(default)
+}
+
+/// Masks all but the last 4 characters of a secret
+pub fn mask_secret(secret: &str) -> String {
+    mask_secret_keeping(secret, 4)
+}
+
+/// Masks all but the last `visible` characters. A secret no longer than
+/// that is masked entirely
+pub fn mask_secret_keeping(secret: &str, visible: usize) -> String {
+
+    let chars: Vec<char> = secret.chars().collect();
+    if chars.len() <= visible {
+        return "****".to_string();
+    }
+    let tail: String = chars[chars.len() - visible..].iter().collect();
+    format!("****{}", tail)
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn lookup(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: Vec<(String, String)> = vars.iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        move |name| vars.iter().find(|(k, _)| k == name).map(|(_, v)| v.clone())
+    }
+
+    #[test]
+    fn test_defaults_and_sources() {
+
+        let config = Config::from_lookup(lookup(&[
+            ("GROQ_API_KEY", "gsk_secret_key_1234"),
+            ("REDIS_URL", "redis://cache:6379")
+        ])).unwrap();
+
+        assert_eq!(config.redis_url, "redis://cache:6379");
+        assert_eq!(config.qdrant_url, "http://127.0.0.1:6334");
+        assert_eq!(config.source("REDIS_URL"), ConfigSource::Env);
+        assert_eq!(config.source("QDRANT_URL"), ConfigSource::Default);
+
+    }
+
+    #[test]
+    fn test_cache_namespace_validation() {
+
+        let lookup = |previous: &'static str| move |name: &str| match name {
+            "GROQ_API_KEY" => Some("test-key".to_string()),
+            "CACHE_NAMESPACE_VERSION" => Some("v2".to_string()),
+            "CACHE_PREVIOUS_NAMESPACE_VERSION" => Some(previous.to_string()),
+            "CACHE_MIGRATE_ON_STARTUP" => Some("true".to_string()),
+            _ => None
+        };
+
+        assert!(Config::from_lookup(lookup("v2")).is_err());
+        assert!(Config::from_lookup(lookup("v1:*")).is_err(), "Glob characters must be rejected");
+        let config = Config::from_lookup(lookup("v1")).unwrap();
+        assert_eq!(config.exact_key_prefix(), "cache:v2:exact:");
+
+    }
+
+    #[test]
+    fn test_embedding_provider() {
+
+        let key = ("GROQ_API_KEY", "test-key");
+        let config = Config::from_lookup(lookup(&[key])).unwrap();
+        assert_eq!(config.embedding, EmbeddingEndpoint::http("http://127.0.0.1:8001/embed"));
+        assert!(Config::from_lookup(lookup(&[key, ("EMBEDDING_PROVIDER", "onnx")])).is_err());
+
+        let openai = Config::from_lookup(lookup(&[key, ("EMBEDDING_PROVIDER", "openai"), ("OPENAI_API_KEY", "sk-embed")])).unwrap();
+        assert_eq!(openai.embedding.url, "https://api.openai.com/v1/embeddings");
+        assert_eq!(openai.embedding.model.as_deref(), Some("text-embedding-3-small"));
+        assert_eq!(openai.embedding.api_key.as_deref(), Some("sk-embed"));
+        assert!(openai.detects_embedding_dim());
+        let sized = Config::from_lookup(lookup(&[key, ("EMBEDDING_PROVIDER", "openai"), ("EMBEDDING_DIM", "1536")])).unwrap();
+        assert!(!sized.detects_embedding_dim());
+        assert!(!config.detects_embedding_dim());
+
+        // EMBEDDING_URL is ignored in favour of the in-process model
+        let local = Config::from_lookup(lookup(&[key, ("EMBEDDING_PROVIDER", "local"), ("EMBEDDING_URL", "http://embed:8000/embed")]));
+        if cfg!(feature = "local-embeddings") {
+            assert_eq!(local.unwrap().embedding.url, LOCAL_EMBEDDING_URL);
+        } else {
+            assert!(local.unwrap_err().contains("--features local-embeddings"));
+        }
+
+    }
+
+    #[test]
+    fn test_trusted_proxies() {
+
+        let key = ("GROQ_API_KEY", "test-key");
+        assert!(Config::from_lookup(lookup(&[key])).unwrap().trusted_proxies.is_empty());
+
+        let config = Config::from_lookup(lookup(&[key, ("TRUSTED_PROXIES", "10.0.0.1, ::1")])).unwrap();
+        assert_eq!(config.trusted_proxies, vec!["10.0.0.1".parse::<IpAddr>().unwrap(), "::1".parse().unwrap()]);
+        assert!(Config::from_lookup(lookup(&[key, ("TRUSTED_PROXIES", "10.0.0.0/8")])).unwrap_err().contains("TRUSTED_PROXIES"));
+
+    }
+
+    #[test]
+    fn test_same_environment_gives_equal_config() {
+
+        let vars = [("GROQ_API_KEY", "gsk_secret_key_1234"), ("EMBEDDING_URL", "http://embed:8000/embed")];
+        let config = Config::from_lookup(lookup(&vars)).unwrap();
+
+        assert_eq!(config, Config::from_lookup(lookup(&vars)).unwrap());
+        assert_ne!(config, Config::from_lookup(lookup(&vars[..1])).unwrap());
+
+    }
+
+    #[test]
+    fn test_redacted_masks_secrets() {
+
+        let config = Config::from_lookup(lookup(&[
+            ("GROQ_API_KEY", "gsk_secret_key_1234"),
+            ("ADMIN_TOKEN", "admin-token-abcd")
+        ])).unwrap();
+
+        let redacted = config.redacted(&config.runtime);
+        let serialized = redacted.to_string();
+
+        assert_eq!(redacted["upstream"]["api_key"]["value"], "****1234");
+        assert_eq!(redacted["admin"]["token"]["value"], "****abcd");
+        assert_eq!(redacted["upstream"]["api_key"]["source"], "env");
+        assert!(!serialized.contains("gsk_secret_key"), "API key must not appear unmasked");
+
+    }
+
+    #[test]
+    fn test_model_aliases_and_allowlist() {
+
+        let models = ModelConfig::from_parts(
+            Some(r#"{"Fast": "llama-3.1-8b-instant"}"#),
+            Some("llama-3.1-8b-instant, llama-3.3-70b-versatile"),
+            false
+        ).unwrap();
+
+        assert_eq!(models.resolve("Llama-3.3-70B-Versatile").unwrap(), "llama-3.3-70b-versatile");
+        assert_eq!(models.resolve(" fast ").unwrap(), "llama-3.1-8b-instant");
+        assert!(models.resolve("gpt-4").unwrap_err().contains("Unknown model 'gpt-4'"));
+
+        // without an allowlist every name passes through, normalized
+        assert_eq!(ModelConfig::default().resolve("GPT-4").unwrap(), "gpt-4");
+
+        let err = Config::from_lookup(lookup(&[
+            ("GROQ_API_KEY", "gsk_secret_key_1234"),
+            ("MODEL_ALIASES", "fast=llama-3.1-8b-instant")
+        ])).unwrap_err();
+        assert!(err.contains("MODEL_ALIASES"));
+
+    }
+
+    #[test]
+    fn test_config_file_with_env_overrides() {
+
+        let file = ConfigFile::parse("config.toml", r#"
+            groq_api_key = "file-key"
+            bind_address = "127.0.0.1:8080"
+            redis_url = "redis://file:6379"
+            model_allowlist = ["llama-3.1-8b-instant", "fast"]
+
+            [semantic]
+            threshold = 0.85
+
+            [rate_limit]
+            requests_per_min = 60
+
+            [model_aliases]
+            fast = "llama-3.1-8b-instant"
+
+            [model_pricing."llama-3.1-8b-instant"]
+            input = 0.1
+            output = 0.2
+        "#).unwrap();
+        assert_eq!(file.get("SEMANTIC_THRESHOLD").as_deref(), Some("0.85"));
+
+        let config = Config::from_sources(|name| match name {
+            "REDIS_URL" => Some("redis://env:6379".to_string()),
+            _ => None
+        }, Some(file)).unwrap();
+
+        assert_eq!(config.api_key, "file-key");
+        assert_eq!(config.bind_address, "127.0.0.1:8080".parse().unwrap());
+        assert_eq!(config.redis_url, "redis://env:6379", "The env var overrides the file");
+        assert_eq!(config.source("REDIS_URL"), ConfigSource::Env);
+        assert_eq!(config.source("SEMANTIC_THRESHOLD"), ConfigSource::File);
+        assert_eq!(config.source("QDRANT_URL"), ConfigSource::Default);
+        assert_eq!(config.runtime.semantic_threshold, 0.85);
+        assert_eq!(config.rate_limits.requests_per_min, Some(60));
+        assert_eq!(config.models.resolve("fast").unwrap(), "llama-3.1-8b-instant");
+        assert!(config.models.resolve("gpt-4").is_err());
+        assert_eq!(config.pricing["llama-3.1-8b-instant"], ModelPrice { input: 0.1, output: 0.2 });
+        assert_eq!(config.config_file.as_deref(), Some("config.toml"));
+
+        let err = ConfigFile::parse("config.toml", "rate_limit_requests_per_min = 1\n[rate_limit]\nrequests_per_min = 2").unwrap_err();
+        assert!(err.contains("RATE_LIMIT_REQUESTS_PER_MIN is set twice"), "{}", err);
+
+    }
+
+    #[test]
+    fn test_api_keys_from_env_and_file() {
+
+        let path = std::env::temp_dir().join(format!("proxy_api_keys_{}.json", uuid::Uuid::new_v4()));
+        std::fs::write(&path, r#"{"batch-jobs": "sk-proxy-batch"}"#).unwrap();
+        let path = path.to_str().unwrap().to_string();
+
+        let config = Config::from_lookup(lookup(&[
+            ("GROQ_API_KEY", "gsk_secret_key_1234"),
+            ("PROXY_API_KEYS", r#"{"web-app": "sk-proxy-web"}"#),
+            ("PROXY_API_KEYS_FILE", path.as_str())
+        ])).unwrap();
+
+        assert!(config.api_keys.enabled());
+        assert_eq!(config.api_keys.identify("sk-proxy-web"), Some("web-app"));
+        assert_eq!(config.api_keys.identify("sk-proxy-batch"), Some("batch-jobs"));
+        assert_eq!(config.api_keys.identify("sk-unknown"), None);
+
+        let redacted = config.redacted(&config.runtime).to_string();
+        assert!(redacted.contains("web-app") && !redacted.contains("sk-proxy-web"), "Only key names may be shown");
+
+        let err = ApiKeys::from_parts(Some(r#"{"a": "same", "b": "same"}"#), None).unwrap_err();
+        assert!(err.contains("same key"), "{}", err);
+        assert!(!ApiKeys::from_parts(None, None).unwrap().enabled());
+
+        std::fs::remove_file(&path).unwrap();
+
+    }
+
+    #[test]
+    fn test_model_prefix_routes_to_configured_providers() {
+
+        let config = Config::from_lookup(lookup(&[
+            ("GROQ_API_KEY", "gsk_secret_key_1234"),
+            ("ANTHROPIC_API_KEY", "sk-ant-key"),
+            ("OLLAMA_BASE_URL", "http://gpu-box:11434/v1/")
+        ])).unwrap();
+
+        let (upstream, model) = config.route_model("anthropic/claude-3-5-sonnet");
+        assert_eq!((upstream.provider, model), (Provider::Anthropic, "claude-3-5-sonnet"));
+        assert_eq!(upstream.api_key.as_deref(), Some("sk-ant-key"));
+
+        let (upstream, model) = config.route_model("ollama/llama3");
+        assert_eq!((upstream.base_url.as_str(), model), ("http://gpu-box:11434/v1", "llama3"));
+        assert_eq!(upstream.api_key, None);
+
+        // OpenAI has no key here, so this is Groq's model of that name
+        let (upstream, model) = config.route_model("openai/gpt-oss-20b");
+        assert_eq!((upstream.provider, model), (Provider::Groq, "openai/gpt-oss-20b"));
+        let (upstream, model) = config.route_model("groq/llama-3.1-8b-instant");
+        assert_eq!((upstream.provider, model), (Provider::Groq, "llama-3.1-8b-instant"));
+
+        // on its own OPENAI_API_KEY is still the Groq fallback, not a second provider
+        let config = Config::from_lookup(lookup(&[("OPENAI_API_KEY", "sk-openai-key")])).unwrap();
+        assert_eq!(config.route_model("openai/gpt-4o").0.provider, Provider::Groq);
+
+    }
+
+    #[test]
+    fn test_mask_short_secret() {
+
+        assert_eq!(mask_secret("abc"), "****");
+        assert_eq!(mask_secret("abcdef"), "****cdef");
+
+    }
+
+    #[test]
+    fn test_runtime_patch_applies_and_validates() {
+
+        let runtime = RuntimeConfig::from_lookup(|_| None).unwrap();
+
+        let patched = runtime.apply_patch(&json!({"semantic_threshold": 0.85, "default_ttl_secs": 7200})).unwrap();
+        assert_eq!(patched.default_ttl_secs, 7200);
+        assert_eq!(runtime.changes(&patched).len(), 2);
+
+        assert!(runtime.apply_patch(&json!({"semantic_threshold": 1.5})).is_err());
+        assert!(runtime.apply_patch(&json!({"semantic_write_dedup_threshold": -0.1})).is_err());
+        assert_eq!(runtime.apply_patch(&json!({"semantic_write_dedup_threshold": 0})).unwrap().semantic_write_dedup_threshold, 0.0);
+        assert!(runtime.apply_patch(&json!({"default_ttl_secs": "soon"})).is_err());
+        assert!(runtime.apply_patch(&json!({"no_such_key": 1})).is_err());
+
+    }
+
+    #[test]
+    fn test_runtime_patch_rejects_immutable_keys() {
+
+        let runtime = RuntimeConfig::from_lookup(|_| None).unwrap();
+
+        let err = runtime.apply_patch(&json!({"semantic_threshold": 0.8, "redis_url": "redis://other"})).unwrap_err();
+        assert!(err.contains("redis_url"), "Error should name the immutable key: {}", err);
+
+    }
+
+    #[test]
+    fn test_redacted_marks_runtime_changes() {
+
+        let config = Config::from_lookup(lookup(&[("GROQ_API_KEY", "gsk_secret_key_1234")])).unwrap();
+        let live = config.runtime.apply_patch(&json!({"creative_ttl_secs": 60})).unwrap();
+
+        let redacted = config.redacted(&live);
+        assert_eq!(redacted["cache"]["ttl_policy"]["creative_ttl_secs"]["value"], 60);
+        assert_eq!(redacted["cache"]["ttl_policy"]["creative_ttl_secs"]["source"], "runtime");
+        assert_eq!(redacted["cache"]["ttl_policy"]["default_ttl_secs"]["source"], "default");
+
+    }
+
+}
@@ -1,67 +1,101 @@
-use axum::{Json, extract::State, http::HeaderMap, response::{Html, IntoResponse}};
-use axum::http::StatusCode;
+use axum::{Extension, Json, extract::{Path, Query, State}, http::HeaderMap, response::{Html, IntoResponse, Response}};
+use axum::http::{StatusCode, Method, Uri, header};
+use axum::body::{Body, Bytes};
+use axum::BoxError;
+use std::convert::Infallible;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tokio_stream::wrappers::ReceiverStream;
 use chrono::Utc;
-use crate::models::{LLMRequest, LLMResponse};
-use crate::client::call_llm;
-use crate::cache::{generate_cache_key, get_embedding};
+use crate::models::{ApiError, LLMRequest, LLMResponse};
+use llm_cache_proxy::pricing::{calculate_cost, get_groq_model_pricing};
+use crate::client::{LLMError, UpstreamStream, call_llm, call_llm_stream, classify_upstream_error, passthrough_url};
+use crate::metrics::{EndpointMetrics, ErrorCategory, Metrics, Stage};
+use crate::history;
+use crate::request_store::{GroupBy, RequestFilter};
+use crate::middleware::{ApiKeyIdentity, RateLimitCaller, TENANT_HEADER, Tenant, request_id, valid_tenant};
+use crate::cache::{
+    CacheError, EntryParams, LatencyStats, EMBEDDING_PREFIX, QUARANTINE_PREFIX, REFRESH_REQUEST_PREFIX,
+    check_embedding_service, embedding_cache_key, generate_cache_key, get_embedding, cosine_similarity
+};
 use crate::AppState;
+use crate::backend::BackendError;
+use crate::config::{CacheMode, MetricsPersist, RuntimeConfig, TenantSource, mask_secret};
 use serde_json::json;
-use crate::logger::log_request;
+use uuid::Uuid;
+use crate::logger::RequestLogEntry;
+use crate::migrate;
+use crate::stream;
+use crate::coalesce::{Flight, Outcome};
+use crate::reembed::{self, ReembedRequest};
+use serde::Deserialize;
 
-/// Returns (input_cost_per_1m_tokens, output_cost_per_1m_tokens) for Groq models
-fn get_groq_model_pricing(model: &str) -> (f64, f64) {
-    match model {
-        // Llama models
-        "llama-3.3-70b-versatile" => (0.59, 0.79),
-        "llama-3.1-8b-instant" => (0.05, 0.08),
-        "llama-4-scout" => (0.11, 0.34),
-        "llama-4-maverick" => (0.20, 0.60),
-        
-        // Qwen models
-        "qwen3-32b" => (0.29, 0.59),
-        
-        // Kimi models
-        "kimi-k2-0905-1t" => (1.00, 3.00),
-        
-        // GPT OSS models
-        "gpt-oss-20b" => (0.075, 0.30),
-        "gpt-oss-safeguard-20b" => (0.075, 0.30),
-        "gpt-oss-120b" => (0.15, 0.60),
-        
-        // Default to Llama 3.3 70B pricing (most common)
-        _ => {
-            eprintln!("Warning: Unknown model '{}', using Llama 3.3 70B pricing", model);
-            (0.59, 0.79)
+/// (redis, qdrant, embeddings) health. A service only used by a disabled
+/// cache tier is `None` instead of being probed
+pub type ServiceStatus = (Option<bool>, Option<bool>, Option<bool>);
+
+pub async fn check_services(state: &AppState) -> ServiceStatus {
+
+    let redis = async {
+        match &state.exact_cache {
+            Some(redis) => Some(redis.health_check().await),
+            None => None
         }
-    }
-}
+    };
+    let qdrant = async {
+        match &state.semantic_cache {
+            Some(qdrant) => Some(qdrant.health_check().await),
+            None => None
+        }
+    };
+    let embeddings = async {
+        match &state.semantic_cache {
+            Some(_) => Some(check_embedding_service(&state.http_client, &state.config.embedding).await),
+            None => None
+        }
+    };
 
-fn calculate_cost(model: &str, tokens: u64) -> f64 {
+    tokio::join!(redis, qdrant, embeddings)
 
-    let (input_price, output_price) = get_groq_model_pricing(model);
-    let avg_price = (input_price + output_price) / 2.0;
-    (tokens as f64 / 1_000_000.0) * avg_price
+}
 
+/// "up", "down", or "disabled" for one entry of `ServiceStatus`
+pub fn service_label(status: Option<bool>) -> &'static str {
+    match status {
+        Some(true) => "up",
+        Some(false) => "down",
+        None => "disabled"
+    }
 }
 
+#[tracing::instrument(level = "debug", skip_all)]
 pub async fn health_check(State(state): State<AppState>) -> impl IntoResponse {
-    use crate::cache::check_embedding_service;
 
-    let (redis_up, qdrant_up, embeddings_up) = tokio::join!(
-        state.redis_cache.health_check(),
-        state.qdrant_cache.health_check(),
-        check_embedding_service(&state.http_client, &state.embedding_url)
-    );
+    let (redis_up, qdrant_up, embeddings_up) = check_services(&state).await;
+
+    // a failed lookup just leaves the stats out; qdrant's status already says why
+    let qdrant_stats = match &state.semantic_cache {
+        Some(qdrant) if qdrant_up == Some(true) => qdrant.collection_stats().await.ok(),
+        _ => None
+    };
 
-    let all_healthy = redis_up && qdrant_up && embeddings_up;
+    // disabled services don't count against health
+    let all_healthy = [redis_up, qdrant_up, embeddings_up].iter().all(|up| *up != Some(false));
     let status = if all_healthy { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
 
     let body = json!({
         "status": if all_healthy { "healthy" } else { "unhealthy" },
         "services": {
-            "redis":      { "status": if redis_up      { "up" } else { "down" } },
-            "qdrant":     { "status": if qdrant_up     { "up" } else { "down" } },
-            "embeddings": { "status": if embeddings_up { "up" } else { "down" } }
+            "redis":      { "status": service_label(redis_up) },
+            "qdrant":     {
+                "status": service_label(qdrant_up),
+                "collection": state.semantic_cache.as_ref().map(|qdrant| qdrant.validation().to_string()),
+                "stats": qdrant_stats
+            },
+            "embeddings": { "status": service_label(embeddings_up) }
         },
         "timestamp": Utc::now().to_rfc3339()
     });
@@ -69,20 +103,259 @@ pub async fn health_check(State(state): State<AppState>) -> impl IntoResponse {
     (status, Json(body))
 }
 
+/// Converts a tower timeout into a 504 with an OpenAI-style error body,
+/// `code` telling the upstream deadline apart from the health/metrics one
+pub async fn handle_timeout_error(err: BoxError, timeout: Duration, code: &'static str) -> (StatusCode, Json<serde_json::Value>) {
+
+    if err.is::<tower::timeout::error::Elapsed>() {
+        tracing::warn!(timeout_secs = timeout.as_secs(), "request timed out");
+        (StatusCode::GATEWAY_TIMEOUT, Json(json!({
+            "error": {
+                "message": format!("Request timed out after {}s", timeout.as_secs()),
+                "type": "timeout_error",
+                "code": code
+            },
+            "timeout_secs": timeout.as_secs()
+        })))
+    } else {
+        (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({
+            "error": {
+                "message": format!("Unhandled internal error: {}", err),
+                "type": "internal_error",
+                "code": null
+            }
+        })))
+    }
+
+}
+
 pub async fn dashboard() -> Html<&'static str> {
     Html(include_str!("../dashboard.html"))
 }
 
+// set on every cache hit; rate-limit headers are only present when the upstream was called
+const SERVED_FROM_CACHE_HEADER: &str = "x-served-from-cache";
+// on semantic hits, how long the upstream took to produce the cached answer
+const ORIGINAL_LATENCY_HEADER: &str = "x-original-latency-ms";
+// set when the answer came from an identical request's upstream call
+const COALESCED_HEADER: &str = "x-coalesced";
+// overrides SEMANTIC_THRESHOLD for one request; the threshold used is echoed back under the same name
+const SEMANTIC_THRESHOLD_HEADER: &str = "x-semantic-threshold";
+
+// relayed events waiting for a slow client before the upstream read pauses
+const STREAM_BUFFER: usize = 64;
+
+// EXACT_HIT, SEMANTIC_HIT, STALE, MISS or BYPASS, along with the exact-match key
+const X_CACHE_HEADER: &str = "x-cache";
+const X_CACHE_KEY_HEADER: &str = "x-cache-key";
+// on hits, seconds since the upstream created the cached response
+const X_CACHE_AGE_HEADER: &str = "x-cache-age";
+// on semantic hits, how similar the cached prompt was
+const X_SIMILARITY_SCORE_HEADER: &str = "x-similarity-score";
+
+fn cache_headers(mut headers: HeaderMap, status: &'static str, cache_key: &str) -> HeaderMap {
+
+    headers.insert(X_CACHE_HEADER, header::HeaderValue::from_static(status));
+    if let Ok(value) = header::HeaderValue::from_str(cache_key) {
+        headers.insert(X_CACHE_KEY_HEADER, value);
+    }
+    headers
+
+}
+
+// a tier 0 and Redis hit are both EXACT_HIT
+fn served_from_cache(status: &'static str, cache_key: &str, response: &LLMResponse) -> HeaderMap {
+
+    let mut headers = cache_headers(HeaderMap::new(), status, cache_key);
+    headers.insert(SERVED_FROM_CACHE_HEADER, header::HeaderValue::from_static("true"));
+    headers.insert(X_CACHE_AGE_HEADER, header::HeaderValue::from((Utc::now().timestamp() - response.created).max(0)));
+    headers
+
+}
+
+/// POST /v1/chat/completions. With INCLUDE_COST_IN_RESPONSE set the body is
+/// sent with `usage.cost_usd`; what gets cached is unchanged either way.
+/// `"stream": true` answers with server-sent events: relayed from the upstream
+/// on a miss, replayed from the cached completion on a hit
+pub async fn chat_completions(
+    State(mut state): State<AppState>,
+    endpoint: Option<Extension<EndpointMetrics>>,
+    api_key: Option<Extension<ApiKeyIdentity>>,
+    caller: Option<Extension<RateLimitCaller>>,
+    tenant: Option<Extension<Tenant>>,
+    headers: HeaderMap,
+    Json(mut request): Json<LLMRequest>
+) -> Result<Response, (StatusCode, Json<ApiError>)> {
+
+    state.endpoint_metrics = endpoint.map(|Extension(EndpointMetrics(metrics))| metrics);
+    use_api_key(&mut state, api_key);
+    use_upstream_key(&mut state, &headers);
+    use_tenant(&mut state, tenant);
+    state.rate_limit_caller = caller.map(|Extension(RateLimitCaller(caller))| caller);
+    let config = state.config.clone();
+    let streaming = request.stream.take() == Some(true);
+
+    if streaming {
+        let (headers, body) = match serve_completion(state, headers, request, true).await? {
+            (headers, Completion::Stream(body)) => (headers, body),
+            // a cache hit is replayed as the chunks an upstream stream would have sent
+            (headers, Completion::Full(response)) => {
+                let events = stream::replay(&response).into_iter()
+                    .map(|event| Ok::<_, Infallible>(event.encode(None)));
+                (headers, Body::from_stream(tokio_stream::iter(events)))
+            }
+        };
+        return Ok(event_stream(headers, body));
+    }
+
+    let (headers, Json(response)) = proxy_handler(State(state), headers, Json(request)).await?;
+
+    if !config.include_cost_in_response {
+        return Ok((headers, Json(response)).into_response());
+    }
+
+    // price the canonical model even when a client alias is echoed back
+    let model = config.models.resolve(&response.model).unwrap_or_else(|_| response.model.clone());
+    Ok((headers, Json(response.with_cost(&model))).into_response())
+
+}
+
+// points `state` at the metrics of the API key the request authenticated
+// with, if any, and logs which key it was
+fn use_api_key(state: &mut AppState, api_key: Option<Extension<ApiKeyIdentity>>) {
+
+    if let Some(Extension(identity)) = api_key {
+        tracing::debug!(api_key = %identity.name, "authenticated");
+        state.key_metrics = Some(identity.metrics);
+        state.api_key_name = Some(identity.name);
+    }
+
+}
+
+// points `state` at the request's tenant partition and its metrics entry
+fn use_tenant(state: &mut AppState, tenant: Option<Extension<Tenant>>) {
+
+    if let Some(Extension(tenant)) = tenant {
+        state.tenant = Some(tenant.id);
+        state.tenant_metrics = Some(tenant.metrics);
+    }
+
+}
+
+// with BYOK_ENABLED, the upstream key the client sent in place of the proxy's
+fn use_upstream_key(state: &mut AppState, headers: &HeaderMap) {
+
+    if state.config.byok.enabled {
+        state.upstream_key = headers.get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .map(str::trim)
+            .filter(|key| !key.is_empty())
+            .map(str::to_string);
+    }
+
+}
+
+fn event_stream(mut headers: HeaderMap, body: Body) -> Response {
+
+    headers.insert(header::CONTENT_TYPE, header::HeaderValue::from_static("text/event-stream"));
+    headers.insert(header::CACHE_CONTROL, header::HeaderValue::from_static("no-cache"));
+    (headers, body).into_response()
+
+}
+
+/// Answers a chat completion with a single JSON body; `"stream"` is ignored
 pub async fn proxy_handler(
     State(state): State<AppState>,
     headers: HeaderMap,
-    Json(request): Json<LLMRequest>
-) -> Result<Json<LLMResponse>, (StatusCode, String)> {
+    Json(mut request): Json<LLMRequest>
+) -> Result<(HeaderMap, Json<LLMResponse>), (StatusCode, Json<ApiError>)> {
+
+    request.stream = None;
+    let (headers, Completion::Full(response)) = serve_completion(state, headers, request, false).await? else {
+        unreachable!("only streaming requests are answered with a stream");
+    };
+    Ok((headers, Json(response)))
+
+}
+
+enum Completion {
+    Full(LLMResponse),
+    // the upstream's events as they arrive, on a streaming miss
+    Stream(Body)
+}
+
+// the x-semantic-threshold header if it holds a number from 0 to 1, else SEMANTIC_THRESHOLD
+fn semantic_threshold(headers: &HeaderMap, runtime: &RuntimeConfig) -> Result<f32, ApiError> {
+
+    let Some(value) = headers.get(SEMANTIC_THRESHOLD_HEADER) else {
+        return Ok(runtime.semantic_threshold);
+    };
+    value.to_str().ok()
+        .and_then(|v| v.trim().parse::<f32>().ok())
+        .filter(|threshold| (0.0..=1.0).contains(threshold))
+        .ok_or_else(|| ApiError::new("invalid_request_error", format!("{} must be a number from 0.0 to 1.0", SEMANTIC_THRESHOLD_HEADER))
+            .with_code("invalid_semantic_threshold")
+            .with_param(SEMANTIC_THRESHOLD_HEADER))
+
+}
+
+async fn serve_completion(
+    state: AppState,
+    headers: HeaderMap,
+    request: LLMRequest,
+    streaming: bool
+) -> Result<(HeaderMap, Completion), (StatusCode, Json<ApiError>)> {
+
+    let threshold = semantic_threshold(&headers, &state.runtime.load())
+        .map_err(|e| (StatusCode::BAD_REQUEST, Json(e)))?;
+
+    let (mut response_headers, completion) = lookup_or_fetch(state, headers, request, streaming, threshold).await?;
+    if let Ok(value) = header::HeaderValue::from_str(&threshold.to_string()) {
+        response_headers.insert(SEMANTIC_THRESHOLD_HEADER, value);
+    }
+    Ok((response_headers, completion))
+
+}
+
+#[tracing::instrument(level = "debug", skip_all, fields(model = %request.model))]
+async fn lookup_or_fetch(
+    state: AppState,
+    headers: HeaderMap,
+    mut request: LLMRequest,
+    streaming: bool,
+    semantic_threshold: f32
+) -> Result<(HeaderMap, Completion), (StatusCode, Json<ApiError>)> {
+
+    // the id `request_span` gave the request, so errors can be matched to its log lines
+    let request_id = request_id(&headers);
+    let started = Instant::now();
 
     let temperature = request.temperature.unwrap_or(0.0);
 
+    // the canonical name drives the cache key, pricing and the upstream call
+    let client_model = std::mem::take(&mut request.model);
+    request.model = state.config.models.resolve(&client_model)
+        .map_err(|e| (StatusCode::BAD_REQUEST, Json(ApiError::new("invalid_request_error", e).with_code("model_not_found").with_param("model"))))?;
+    let client_model = state.config.models.preserve_client_name.then_some(client_model);
+
+    // rejected before hashing so they never become cache entries
+    request.validate_messages().map_err(|e| {
+        let error = ApiError::new("invalid_request_error", e.to_string())
+            .with_code(e.code())
+            .with_param(format!("messages[{}]", e.message_index()));
+        (StatusCode::BAD_REQUEST, Json(error))
+    })?;
+
+    log_request_body(&state, &request);
+
     let model = request.model.clone();
 
+    // in shadow mode lookups still happen but every request is sent upstream
+    let shadow_mode = state.config.cache_mode == CacheMode::Shadow;
+    // one consistent snapshot of the hot-reloadable settings for this request
+    let runtime = state.runtime.load_full();
+
     let bypass_cache = headers
         .get("x-bypass-cache")
         .and_then(|v| v.to_str().ok())
@@ -96,237 +369,4294 @@ pub async fn proxy_handler(
         .and_then(|v| v.parse::<u64>().ok());
 
     if bypass_cache {
-        println!("Cache bypass requested - skipping cache");
+        tracing::info!(cache = "bypass", "cache bypass requested - skipping cache");
     }
 
     // generate cache key
-    let cache_key = generate_cache_key(&request);
-    println!("Cache key: {}", cache_key);
+    let cache_key = generate_cache_key(&request, &state.config.key_normalization, &state.cache_key_prefix());
+    tracing::debug!(cache_key = %cache_key, "cache key");
 
-    // Tier 1: Exact match cache (Redis)
-    if !bypass_cache {
-        match state.redis_cache.get(&cache_key).await {
-            Ok(Some(cache_response)) => {
-                println!("Exact Cache Hit");
+    // the cached response shadow mode would have served, kept to compare
+    // against the fresh upstream answer
+    let mut shadow_hit = false;
+    let mut shadow_candidate: Option<LLMResponse> = None;
+
+    // Tier 0: in-process LRU for the hottest exact-match entries.
+    // Shadow mode never fills it, as nothing is served from cache there
+    if !bypass_cache && !shadow_mode
+        && let Some(tier0) = &state.tier0_cache
+        && let Some(response) = tier0.get(&cache_key) {
+        tracing::info!(cache = "hit", tier = "tier0", "cache hit");
+
+        let tokens = response.usage.total_tokens as u64;
+        state.record(|metrics| metrics.record_tier0_hit(tokens));
+        count_hit(&state, &cache_key);
 
-                state.metrics.record_exact_hit();
+        let cost = calculate_cost(&model, tokens);
+        log_request(&state, "TIER0_HIT", &model, tokens, cost, Some(&request_id), Some(started.elapsed().as_millis() as u64));
 
-                log_request("EXACT_HIT", &model, 0, 0.0);
+        let headers = served_from_cache("EXACT_HIT", &cache_key, &response);
+        return Ok((headers, Completion::Full(with_client_model(response, &client_model))));
+    }
+
+    // Tier 1: Exact match cache (Redis)
+    if !bypass_cache && let Some(redis_cache) = &state.exact_cache {
+        match timed(&state, Stage::RedisLookup, redis_cache.get_with_ttl(&cache_key)).await {
+            Ok(Some((cache_response, _))) if shadow_mode => {
+                tracing::info!(cache = "shadow_hit", tier = "exact", "shadow: cache hit not served");
+
+                state.metrics.record_shadow_exact_hit();
+                shadow_hit = true;
+                shadow_candidate = serde_json::from_str(&cache_response).ok();
+            }
+            Ok(Some((cache_response, ttl_remaining))) => {
+                tracing::info!(cache = "hit", tier = "exact", "cache hit");
 
                 // deserialize the cache JSON string back to LLMResponse
-                let response = serde_json::from_str(&cache_response)
-                    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Cache deserialization error: {}", e)))?;
-                
-                return Ok(Json(response));
+                let response: LLMResponse = serde_json::from_str(&cache_response)
+                    .map_err(|e| {
+                        state.metrics.record_error(ErrorCategory::SerializationError, format!("Cache deserialization error: {}", e), Some(&request_id));
+                        (StatusCode::INTERNAL_SERVER_ERROR, Json(ApiError::new("cache_error", format!("Cache deserialization error: {}", e))))
+                    })?;
+
+                let tokens = response.usage.total_tokens as u64;
+                state.record(|metrics| metrics.record_exact_hit(tokens));
+                count_hit(&state, &cache_key);
+
+                if let Some(tier0) = &state.tier0_cache
+                    && tier0.record_redis_hit(&cache_key, &response) {
+                    tracing::debug!("promoted to tier 0");
+                }
+
+                let cost = calculate_cost(&model, tokens);
+                log_request(&state, "EXACT_HIT", &model, tokens, cost, Some(&request_id), Some(started.elapsed().as_millis() as u64));
+
+                // close to expiry: served as is, and re-run upstream for the next caller
+                let window = state.config.swr_window_secs as i64;
+                if (0..window).contains(&ttl_remaining) {
+                    revalidate_in_background(&state, &cache_key, &request);
+                }
+
+                let headers = served_from_cache("EXACT_HIT", &cache_key, &response);
+                return Ok((headers, Completion::Full(with_client_model(response, &client_model))));
             }
             Ok(None) => {
-                println!("Exact Cache Miss");
+                tracing::debug!(tier = "exact", "cache miss");
             }
             Err(e) => {
-                println!("Redis Error: {} - continuing", e);
+                tracing::warn!(error = %e, "redis get failed - continuing");
+                state.metrics.record_error(ErrorCategory::RedisError, format!("Redis get failed: {}", e), Some(&request_id));
             }
         }
     }
     // Tier 2: Semantic cache (Qdrant)
     // extract prompt text for embedding
     
-    let prompt_text: String = request.messages.iter()
-        .map(|m| format!("{}: {}", m.role, m.content))
-        .collect::<Vec<_>>()
-        .join("\n");
+    let prompt_text = prompt_text(&request);
 
-    // get embedding — stored so it can be reused for Qdrant storage on a cache miss
-    let maybe_embedding = get_embedding(&state.http_client, &state.embedding_url, &prompt_text).await;
+    // get embedding — stored so it can be reused for Qdrant storage on a cache miss.
+    // None when the semantic tier is disabled or the request uses tools or images
+    let maybe_embedding = match &state.semantic_cache {
+        Some(_) if request.semantic_eligible() => Some(embed(&state, &prompt_text).await),
+        _ => None
+    };
     
-    if !bypass_cache {
-        match &maybe_embedding {
+    if !bypass_cache && !shadow_hit
+        && let Some(semantic_cache) = &state.semantic_cache
+        && let Some(maybe_embedding) = &maybe_embedding {
+        match maybe_embedding {
             Ok(embedding) => {
                 // Search for similar cached responses
-                match state.qdrant_cache.search_similar(embedding.clone(), 0.90, temperature).await {
-                    Ok(Some(cached_response)) => {
-                        println!("Semantic Cache Hit");
+                let found = timed(&state, Stage::QdrantSearch, semantic_cache.search_similar(embedding.clone(), semantic_threshold, &state.entry_params(&request))).await
+                    .map(|hit| hit.filter(|hit| state.in_cache_partition(&hit.cache_key)));
+                match found {
+                    Ok(Some(hit)) if shadow_mode => {
+                        tracing::info!(cache = "shadow_hit", tier = "semantic", similarity = hit.score, "shadow: cache hit not served");
+
+                        state.metrics.record_shadow_semantic_hit(hit.score);
+                        shadow_hit = true;
+                        shadow_candidate = serde_json::from_str(&hit.response).ok();
+                    }
+                    Ok(Some(hit)) => {
+                        tracing::info!(cache = "hit", tier = "semantic", similarity = hit.score, "cache hit");
 
-                        let cached_llm_response: LLMResponse = serde_json::from_str(&cached_response)
-                            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Cache deserialization error: {}", e)))?;
+                        let cached_llm_response: LLMResponse = serde_json::from_str(&hit.response)
+                            .map_err(|e| {
+                                state.metrics.record_error(ErrorCategory::SerializationError, format!("Cache deserialization error: {}", e), Some(&request_id));
+                                (StatusCode::INTERNAL_SERVER_ERROR, Json(ApiError::new("cache_error", format!("Cache deserialization error: {}", e))))
+                            })?;
                         
                         let tokens = cached_llm_response.usage.total_tokens as u64;
-                        state.metrics.record_semantic_hit(tokens);
+                        state.record(|metrics| metrics.record_semantic_hit(tokens));
 
                         let cost = calculate_cost(&model, tokens); 
-                        log_request("SEMANTIC_HIT", &model, 0, cost); 
+                        log_request(&state, "SEMANTIC_HIT", &model, tokens, cost, Some(&request_id), Some(started.elapsed().as_millis() as u64));
                         
                         // Store in Redis for faster future lookups
-                        let _ = state.redis_cache.set(&cache_key, &cached_response).await;
+                        if let Some(redis_cache) = &state.exact_cache
+                            && let Err(e) = redis_cache.set(&cache_key, &hit.response).await {
+                            state.metrics.record_error(ErrorCategory::RedisError, format!("Redis promotion failed: {}", e), Some(&request_id));
+                        }
                         
-                        return Ok(Json(cached_llm_response));
+                        // the key of the entry that matched, which invalidating would remove
+                        let mut headers = served_from_cache("SEMANTIC_HIT", &hit.cache_key, &cached_llm_response);
+                        if let Ok(score) = header::HeaderValue::from_str(&format!("{:.4}", hit.score)) {
+                            headers.insert(X_SIMILARITY_SCORE_HEADER, score);
+                        }
+                        if let Some(ms) = hit.original_latency_ms {
+                            headers.insert(ORIGINAL_LATENCY_HEADER, header::HeaderValue::from(ms));
+                        }
+                        return Ok((headers, Completion::Full(with_client_model(cached_llm_response, &client_model))));
                     }
                     Ok(None) => {
-                        println!("Semantic cache miss");
+                        tracing::debug!(tier = "semantic", "cache miss");
                     }
                     Err(e) => {
-                        println!("Qdrant search error: {} - continuing", e);
+                        tracing::warn!(error = %e, "qdrant search failed - continuing");
+                        state.metrics.record_error(ErrorCategory::QdrantError, format!("Qdrant search failed: {}", e), Some(&request_id));
                     }
                 }
             }
             Err(e) => {
-                println!("Embedding error: {} - skipping semantic cache", e);
+                tracing::warn!(error = %e, "embedding failed - skipping semantic cache");
+                state.metrics.record_error(ErrorCategory::EmbeddingError, format!("Embedding failed: {}", e), Some(&request_id));
             }
         }
     }
 
+    if shadow_mode && !bypass_cache && !shadow_hit {
+        state.metrics.record_shadow_miss();
+    }
+
     // Tier 3: Cache miss - call LLM
-    println!("Cache Miss - calling LLM"); 
+    tracing::info!(cache = "miss", "cache miss - calling upstream");
+
+    let pending = PendingEntry {
+        refresh_request: refresh_request_json(&state, &request),
+        // a fresh answer was explicitly asked for, or nothing is served from cache
+        stale_fallback: !bypass_cache && !shadow_mode,
+        // store in redis with custom TTL if given
+        ttl: custom_ttl.unwrap_or(runtime.ttl_for(temperature)),
+        custom_ttl: custom_ttl.is_some(),
+        // reuse embedding from semantic search, avoid a second HTTP call
+        embedding: maybe_embedding.and_then(|embedding| embedding.ok()),
+        request_id,
+        model,
+        cache_key,
+        prompt_text,
+        params: state.entry_params(&request),
+        shadow_candidate,
+        started: Instant::now()
+    };
+
+    let miss_status = if bypass_cache { "BYPASS" } else { "MISS" };
+
+    if streaming {
+        let (upstream, upstream_meta) = match call_llm_stream(&state, request).await {
+            Ok(upstream) => upstream,
+            Err(e) => {
+                let error = upstream_error(&state, &e, &pending.request_id);
+                return serve_stale(&state, &pending, error, &client_model).await;
+            }
+        };
+        let headers = cache_headers(upstream_meta.rate_limit_headers, miss_status, &pending.cache_key);
+        let body = relay_stream(state, upstream, pending, client_model);
+        return Ok((headers, Completion::Stream(body)));
+    }
+
+    // identical misses wait for the first one's upstream call. Bypass and
+    // shadow requests always make their own, as do streams
+    let flight = (state.config.request_coalescing && !bypass_cache && !shadow_mode)
+        .then(|| state.in_flight.join(&pending.cache_key));
+    let leader = match flight {
+        Some(Flight::Follower(follower)) => match follower.outcome().await {
+            Some(outcome) => return match serve_coalesced(&state, &pending, &outcome, &client_model) {
+                Err(error) => serve_stale(&state, &pending, error, &client_model).await,
+                served => served
+            },
+            None => {
+                tracing::info!("coalesced call abandoned - calling upstream");
+                None
+            }
+        },
+        Some(Flight::Leader(leader)) => Some(leader),
+        None => None
+    };
+
+    let result = fetch_and_cache(&state, &pending, request).await;
+    if let Some(leader) = leader {
+        leader.finish(result.as_ref()
+            .map(|(response, _)| response.clone())
+            .map_err(|(status, Json(error))| (*status, error.clone())));
+    }
+    let (response, rate_limit_headers) = match result {
+        Ok(fetched) => fetched,
+        Err(error) => return serve_stale(&state, &pending, error, &client_model).await
+    };
+
+    // pass the upstream's rate-limit headers through so clients can pace themselves
+    let headers = cache_headers(rate_limit_headers, miss_status, &pending.cache_key);
+    Ok((headers, Completion::Full(with_client_model(response, &client_model))))
+
+}
 
-    let response = call_llm(&state.http_client, &state.groq_api_key, request)
+// calls the upstream for a non-streaming miss and caches the answer, returning
+// it with the upstream's rate-limit headers
+async fn fetch_and_cache(
+    state: &AppState,
+    pending: &PendingEntry,
+    request: LLMRequest
+) -> Result<(LLMResponse, HeaderMap), (StatusCode, Json<ApiError>)> {
+
+    let (response, upstream_meta) = timed(state, Stage::Upstream, call_llm(state, request))
+        .await
+        .map_err(|e| upstream_error(state, &e, &pending.request_id))?;
+    let latency_ms = pending.started.elapsed().as_millis() as u64;
+    let (response, duplicate) = dedup_by_id(state, &pending.cache_key, response);
+
+    cache_fresh_response(state, pending, &response, latency_ms, if duplicate { "DEDUP_BY_ID" } else { "MISS" })
         .await
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("LLM API error: {}", e)))?;
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, Json(ApiError::new("internal_error", format!("Serialization error: {}", e)))))?;
+
+    Ok((response, upstream_meta.rate_limit_headers))
+
+}
+
+// answers a request that waited on an identical one with that request's
+// outcome. The leader already cached the response and counted any error
+fn serve_coalesced(
+    state: &AppState,
+    pending: &PendingEntry,
+    outcome: &Outcome,
+    client_model: &Option<String>
+) -> Result<(HeaderMap, Completion), (StatusCode, Json<ApiError>)> {
+
+    let response = match outcome {
+        Ok(response) => response.clone(),
+        Err((status, error)) => return Err((*status, Json(error.clone())))
+    };
 
     let tokens = response.usage.total_tokens as u64;
-    state.metrics.record_miss(tokens);
+    state.record(|metrics| metrics.record_coalesced(tokens));
 
-    let cost = calculate_cost(&model, tokens); 
-    log_request("MISS", &model, tokens, cost); 
+    let cost = calculate_cost(&pending.model, tokens);
+    log_request(state, "COALESCED", &pending.model, tokens, cost, Some(&pending.request_id), Some(pending.started.elapsed().as_millis() as u64));
 
-    // store in both caches
-    let response_json = serde_json::to_string(&response)
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Serialization error: {}", e)))?;
-    
-    // store in redis with custom TTL if given
-    let ttl = custom_ttl.unwrap_or_else(|| {
-        if temperature > 0.7 {
-            3600  // 1 hour for creative
-        } else {
-            86400  // 24 hours for deterministic
-        }
-    });
-    
-    if let Err(e) = state.redis_cache.set_with_ttl(&cache_key, &response_json, ttl).await {
-        println!("Warning: Failed to cache in Redis: {}", e);
-    } else {
-        if custom_ttl.is_some() {
-            println!("Stored in Redis (TTL: {}s)", ttl);
-        }
-        else {
-            println!("Stored in Redis");
-        }
+    let mut headers = cache_headers(HeaderMap::new(), "MISS", &pending.cache_key);
+    headers.insert(COALESCED_HEADER, header::HeaderValue::from_static("true"));
+    Ok((headers, Completion::Full(with_client_model(response, client_model))))
+
+}
+
+// counts a failed upstream call and turns it into the error sent to the client.
+// An upstream error response keeps its status and, when it has one, its
+// OpenAI-format error object, so clients see the upstream's 400/401/429
+fn upstream_error(state: &AppState, e: &LLMError, request_id: &str) -> (StatusCode, Json<ApiError>) {
+
+    state.metrics.record_error(classify_upstream_error(e), format!("LLM API error: {}", e), Some(request_id));
+    match e {
+        LLMError::InvalidResponse { path, message } => return (
+            StatusCode::BAD_GATEWAY,
+            Json(ApiError::new("upstream_error", message.clone()).with_code("upstream_invalid_response").with_param(path.clone()))
+        ),
+        LLMError::CircuitOpen { .. } => return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ApiError::new("upstream_error", e.to_string()).with_code("upstream_circuit_open"))
+        ),
+        _ => {}
     }
 
-    // store in Qdrant — reuse embedding from semantic search, avoid a second HTTP call
-    if let Ok(embedding) = maybe_embedding {
-        if let Err(e) = state.qdrant_cache.store(&cache_key, embedding, &response_json, temperature).await {
-            println!("Failed to cache in Qdrant: {}", e);
-        } else {
-            println!("Stored in Qdrant");
+    let status = e.upstream_status()
+        .and_then(|status| StatusCode::from_u16(status).ok())
+        .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+    let error = e.upstream_body()
+        .cloned()
+        .unwrap_or_else(|| ApiError::new("upstream_error", format!("LLM API error: {}", e)));
+    (status, Json(error))
+
+}
+
+// answers a miss whose upstream call failed with the entry's stale copy, kept
+// STALE_GRACE_SECS past its expiry, or with `error` when there is none
+async fn serve_stale(
+    state: &AppState,
+    pending: &PendingEntry,
+    error: (StatusCode, Json<ApiError>),
+    client_model: &Option<String>
+) -> Result<(HeaderMap, Completion), (StatusCode, Json<ApiError>)> {
+
+    let Some(redis_cache) = state.exact_cache.as_ref().filter(|_| pending.stale_fallback && state.config.stale_grace_secs > 0) else {
+        return Err(error);
+    };
+    let response = match redis_cache.stale(&pending.cache_key).await {
+        Ok(Some(stale)) => serde_json::from_str::<LLMResponse>(&stale).ok(),
+        Ok(None) => None,
+        Err(e) => {
+            state.metrics.record_error(ErrorCategory::RedisError, format!("Redis stale get failed: {}", e), Some(&pending.request_id));
+            None
         }
+    };
+    let Some(response) = response else {
+        return Err(error);
+    };
+    tracing::warn!(cache = "stale", "upstream failed - serving stale entry");
+
+    let tokens = response.usage.total_tokens as u64;
+    state.record(|metrics| metrics.record_stale_hit(tokens));
+
+    let cost = calculate_cost(&pending.model, tokens);
+    log_request(state, "STALE_HIT", &pending.model, tokens, cost, Some(&pending.request_id), Some(pending.started.elapsed().as_millis() as u64));
+
+    let mut headers = served_from_cache("STALE", &pending.cache_key, &response);
+    headers.insert(header::WARNING, header::HeaderValue::from_static("111 - \"Revalidation Failed\""));
+    Ok((headers, Completion::Full(with_client_model(response, client_model))))
+
+}
+
+/// What a miss needs to cache the upstream's answer once it has arrived,
+/// which for a stream is after the last event was relayed
+struct PendingEntry {
+    request_id: String,
+    model: String,
+    cache_key: String,
+    prompt_text: String,
+    embedding: Option<Vec<f32>>,
+    params: EntryParams,
+    ttl: u64,
+    custom_ttl: bool,
+    refresh_request: Option<String>,
+    // the stale copy may stand in for a failed upstream call
+    stale_fallback: bool,
+    shadow_candidate: Option<LLMResponse>,
+    started: Instant
+}
+
+/// Records a miss (logged as `label`) and writes `response` to both caches.
+/// Only serializing the response can fail
+async fn cache_fresh_response(
+    state: &AppState,
+    pending: &PendingEntry,
+    response: &LLMResponse,
+    latency_ms: u64,
+    label: &str
+) -> Result<(), serde_json::Error> {
+
+    // a duplicate still cost an upstream call, so it counts as a miss too
+    let tokens = response.usage.total_tokens as u64;
+    state.record(|metrics| metrics.record_miss(tokens));
+    if let Some(caller) = &state.rate_limit_caller {
+        state.rate_limiter.record_tokens(caller, tokens);
+    }
+
+    let cost = calculate_cost(&pending.model, tokens);
+    log_request(state, label, &pending.model, tokens, cost, Some(&pending.request_id), Some(latency_ms));
+    if let Some(tenant) = &state.tenant {
+        state.quotas.record(tenant, tokens, cost);
+    }
+    state.budget.record(state.api_key_name.as_deref(), cost);
+
+    // compare the would-be cached answer with the fresh one off the request path.
+    // The comparison needs embeddings, so it only runs with the semantic tier on
+    if let Some(cached) = &pending.shadow_candidate
+        && state.semantic_cache.is_some() {
+        let cached_text = response_text(cached);
+        let fresh_text = response_text(response);
+        let state = state.clone();
+        tokio::spawn(async move {
+            compare_shadow_answers(&state, &cached_text, &fresh_text).await;
+        });
+    }
+
+    // store in both caches
+    let response_json = serde_json::to_string(response)
+        .inspect_err(|e| {
+            state.metrics.record_error(ErrorCategory::SerializationError, format!("Serialization error: {}", e), Some(&pending.request_id));
+        })?;
+
+    let semantic = pending.embedding.clone()
+        .map(|embedding| (pending.prompt_text.as_str(), embedding));
+    store_in_caches(state, &pending.cache_key, &response_json, semantic, &pending.params, pending.ttl, latency_ms, &pending.request_id).await;
+    if let Some(request_json) = &pending.refresh_request {
+        remember_request(state, &pending.cache_key, request_json, pending.ttl).await;
+    }
+    if pending.custom_ttl {
+        tracing::debug!(ttl_secs = pending.ttl, "requested ttl");
     }
 
-    Ok(Json(response))
+    Ok(())
 
 }
 
-pub async fn metrics(State(state): State<AppState>) -> Json<serde_json::Value> {
-    let snapshot = state.metrics.snapshot();
-    
-    let hit_rate = snapshot.cache_hit_rate();
-    let total_hits = snapshot.exact_hits + snapshot.semantic_hits;
-    
-    // Use default model for cost calculation
-    // In production, you'd want to track which model was actually used
-    let default_model = "llama-3.3-70b-versatile";
-    let (input_price, output_price) = get_groq_model_pricing(default_model);
-    
-    let input_cost_per_token = input_price / 1_000_000.0;
-    let output_cost_per_token = output_price / 1_000_000.0;
-    
-    // Rough estimate: assume 50/50 input/output split
-    let avg_cost_per_token = (input_cost_per_token + output_cost_per_token) / 2.0;
-    
-    let cost_saved = snapshot.tokens_saved as f64 * avg_cost_per_token;
-    let cost_spent = snapshot.tokens_used as f64 * avg_cost_per_token;
-    let total_cost_without_cache = (snapshot.tokens_saved + snapshot.tokens_used) as f64 * avg_cost_per_token;
-    
-    Json(json!({
-        "cache_performance": {
-            "exact_hits": snapshot.exact_hits,
-            "semantic_hits": snapshot.semantic_hits,
-            "total_hits": total_hits,
-            "misses": snapshot.misses,
-            "total_requests": snapshot.total_requests,
-            "hit_rate_percent": format!("{:.2}%", hit_rate)
-        },
-        "token_usage": {
-            "tokens_saved": snapshot.tokens_saved,
-            "tokens_used": snapshot.tokens_used,
-            "total_tokens_without_cache": snapshot.tokens_saved + snapshot.tokens_used
-        },
-        "cost_analysis": {
-            "cost_saved_usd": format!("${:.4}", cost_saved),
-            "cost_spent_usd": format!("${:.4}", cost_spent),
-            "total_cost_without_cache_usd": format!("${:.4}", total_cost_without_cache),
-            "savings_percent": if total_cost_without_cache > 0.0 {
-                format!("{:.2}%", (cost_saved / total_cost_without_cache) * 100.0)
-            } else {
-                "0.00%".to_string()
-            },
-            "note": format!("Costs calculated using {} pricing. Actual costs may vary if different models were used.", default_model)
-        },
-        "pricing": {
-            "model_assumed": default_model,
-            "input_per_1m_tokens": format!("${:.2}", input_price),
-            "output_per_1m_tokens": format!("${:.2}", output_price),
-            "supported_models": [
-                "llama-3.3-70b-versatile", "llama-3.1-8b-instant",
-                "llama-4-scout", "llama-4-maverick",
-                "qwen3-32b", "kimi-k2-0905-1t",
-                "gpt-oss-20b", "gpt-oss-safeguard-20b", "gpt-oss-120b"
-            ]
+/// Relays an upstream event stream to the client while rebuilding the
+/// completion, which is cached once the stream ends with `[DONE]`. The
+/// upstream is read to the end even if the client goes away, so the answer
+/// that was paid for still lands in the cache
+fn relay_stream(state: AppState, mut upstream: UpstreamStream, pending: PendingEntry, client_model: Option<String>) -> Body {
+
+    let (tx, rx) = tokio::sync::mpsc::channel::<Result<Bytes, std::io::Error>>(STREAM_BUFFER);
+
+    tokio::spawn(async move {
+        let mut parser = stream::SseParser::default();
+        let mut assembler = stream::StreamAssembler::default();
+        let mut client_connected = true;
+
+        loop {
+            let bytes = match upstream.chunk().await {
+                Ok(Some(bytes)) => bytes,
+                Ok(None) => break,
+                Err(e) => {
+                    tracing::warn!(error = %e, "upstream stream failed - not cached");
+                    let _ = upstream_error(&state, &e, &pending.request_id);
+                    let _ = tx.send(Err(std::io::Error::other(e.to_string()))).await;
+                    return;
+                }
+            };
+            for event in parser.feed(&bytes) {
+                assembler.apply(&event);
+                if client_connected && tx.send(Ok(event.encode(client_model.as_deref()))).await.is_err() {
+                    tracing::info!("client disconnected mid-stream - still reading the upstream to cache it");
+                    client_connected = false;
+                }
+            }
         }
-    }))
+        drop(tx);
+
+        let latency_ms = pending.started.elapsed().as_millis() as u64;
+        match assembler.finish() {
+            Some(response) => {
+                // the stream has already gone out, so a failure here only goes to the metrics
+                let _ = cache_fresh_response(&state, &pending, &response, latency_ms, "STREAM_MISS").await;
+            }
+            None => {
+                tracing::warn!("upstream stream ended early - not cached");
+                state.metrics.record_error(ErrorCategory::Upstream5xx, "Upstream stream ended before [DONE]", Some(&pending.request_id));
+            }
+        }
+    });
+
+    Body::from_stream(ReceiverStream::new(rx))
+
 }
 
-pub async fn admin_clear_cache(
-    State(state): State<AppState>,
-) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
-    state.redis_cache.flush_all()
-        .await
-        .map_err(|e| (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(json!({"error": format!("Failed to flush Redis: {}", e)}))
-        ))?;
+// the cache always holds the upstream's model name; PRESERVE_CLIENT_MODEL_NAME
+// swaps the name the client sent back in on the way out
+fn with_client_model(mut response: LLMResponse, client_model: &Option<String>) -> LLMResponse {
 
-    println!("Admin: Redis cache cleared");
+    if let Some(client_model) = client_model {
+        response.model = client_model.clone();
+    }
+    response
 
-    Ok(Json(json!({
-        "status": "success",
-        "message": "Redis cache cleared"
-    })))
 }
 
-pub async fn admin_stats(
-    State(state): State<AppState>,
-) -> Json<serde_json::Value> {
-    use crate::cache::check_embedding_service;
+// headers that describe a single connection and must not be forwarded
+const HOP_BY_HOP_HEADERS: [header::HeaderName; 5] = [
+    header::HOST,
+    header::CONNECTION,
+    header::CONTENT_LENGTH,
+    header::TRANSFER_ENCODING,
+    header::UPGRADE
+];
 
-    let (redis_up, qdrant_up, embeddings_up) = tokio::join!(
-        state.redis_cache.health_check(),
-        state.qdrant_cache.health_check(),
-        check_embedding_service(&state.http_client, &state.embedding_url)
-    );
+/// Forwards any route the proxy doesn't handle to the upstream unchanged.
+/// Nothing here is cached; the client's Authorization header is kept and
+/// the proxy's own key is only added when the client sent none. With
+/// PROXY_API_KEYS set the client's header was the proxy key and has already
+/// been removed, so the upstream key is always sent
+#[tracing::instrument(level = "debug", skip_all, fields(%method, path = %uri.path()))]
+pub async fn passthrough_handler(
+    State(mut state): State<AppState>,
+    endpoint: Option<Extension<EndpointMetrics>>,
+    api_key: Option<Extension<ApiKeyIdentity>>,
+    method: Method,
+    uri: Uri,
+    mut headers: HeaderMap,
+    body: Bytes,
+) -> Response {
 
-    let snapshot = state.metrics.snapshot();
+    state.endpoint_metrics = endpoint.map(|Extension(EndpointMetrics(metrics))| metrics);
+    use_api_key(&mut state, api_key);
+    state.record(|metrics| metrics.record_passthrough());
 
-    Json(json!({
-        "cache_stats": {
-            "exact_hits": snapshot.exact_hits,
-            "semantic_hits": snapshot.semantic_hits,
-            "misses": snapshot.misses,
-            "total_requests": snapshot.total_requests,
-            "hit_rate": snapshot.cache_hit_rate()
-        },
-        "services": {
-            "redis":      if redis_up      { "up" } else { "down" },
-            "qdrant":     if qdrant_up     { "up" } else { "down" },
-            "embeddings": if embeddings_up { "up" } else { "down" }
+    let url = passthrough_url(&state.config.upstream_base_url, uri.path(), uri.query());
+    tracing::info!(cache = "passthrough", %url, "forwarding uncached");
+
+    for name in &HOP_BY_HOP_HEADERS {
+        headers.remove(name);
+    }
+    if !headers.contains_key(header::AUTHORIZATION) {
+        let bearer = format!("Bearer {}", state.config.api_key);
+        if let Ok(value) = bearer.parse() {
+            headers.insert(header::AUTHORIZATION, value);
         }
-    }))
-}
\ No newline at end of file
+    }
+
+    let upstream = state.http_client
+        .request(method, &url)
+        .headers(headers)
+        .body(body)
+        .send()
+        .await;
+
+    let result = match upstream {
+        Ok(upstream) => {
+            let status = upstream.status();
+            let mut response_headers = upstream.headers().clone();
+            for name in &HOP_BY_HOP_HEADERS {
+                response_headers.remove(name);
+            }
+            upstream.bytes().await.map(|bytes| (status, response_headers, bytes))
+        }
+        Err(e) => Err(e)
+    };
+
+    match result {
+        Ok((status, response_headers, bytes)) => {
+            let mut response = Response::new(Body::from(bytes));
+            *response.status_mut() = status;
+            *response.headers_mut() = response_headers;
+            response
+        }
+        Err(e) => {
+            let e = LLMError::from(e);
+            state.metrics.record_error(classify_upstream_error(&e), e.to_string(), None);
+            tracing::error!(%url, error = %e, "passthrough failed");
+            (
+                StatusCode::BAD_GATEWAY,
+                Json(json!({"error": {"message": format!("Upstream request failed: {}", e), "type": "upstream_error"}}))
+            ).into_response()
+        }
+    }
+
+}
+
+/// The text embedded for the semantic cache: one "role: content" line per
+/// message, with only the text parts of multimodal content
+fn prompt_text(request: &LLMRequest) -> String {
+    request.messages.iter()
+        .map(|m| format!("{}: {}", m.role, m.content.as_text()))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// how long an upstream response id is remembered, and how many ids at most
+pub const ID_DEDUP_WINDOW: Duration = Duration::from_secs(30);
+const ID_DEDUP_CAPACITY: usize = 1000;
+
+/// (cache partition, upstream response id) -> the first response seen with it,
+/// its cache key and when it was seen
+pub type IdDedup = dashmap::DashMap<(String, String), (LLMResponse, String, Instant)>;
+
+/// Groq has been seen to send the same response `id` twice under load. Returns
+/// the response first seen with this id in the last `ID_DEDUP_WINDOW` (and
+/// `true`), or remembers this one and returns it unchanged. Ids are remembered
+/// per cache partition, so a repeat never hands one tenant another's answer,
+/// and only count as a repeat for the same `cache_key`, so a reused id never
+/// answers one prompt with another's response
+fn dedup_by_id(state: &AppState, cache_key: &str, response: LLMResponse) -> (LLMResponse, bool) {
+
+    // an upstream that sends no id can't be deduplicated
+    if response.id.is_empty() {
+        return (response, false);
+    }
+
+    let key = (state.cache_partition().unwrap_or_default(), response.id.clone());
+    if let Some(seen) = state.id_dedup.get(&key)
+        && seen.2.elapsed() < ID_DEDUP_WINDOW {
+        if seen.1 != cache_key {
+            tracing::warn!(response_id = %response.id, "upstream reused a response id for another prompt - keeping the fresh response");
+            return (response, false);
+        }
+        tracing::info!(response_id = %response.id, "upstream repeated a response id - returning the first response seen with it");
+        state.metrics.record_id_dedup_hit();
+        return (seen.0.clone(), true);
+    }
+
+    if state.id_dedup.len() >= ID_DEDUP_CAPACITY {
+        let oldest = state.id_dedup.iter()
+            .min_by_key(|entry| entry.value().2)
+            .map(|entry| entry.key().clone());
+        if let Some(oldest) = oldest {
+            state.id_dedup.remove(&oldest);
+        }
+    }
+    state.id_dedup.insert(key, (response.clone(), cache_key.to_string(), Instant::now()));
+    (response, false)
+
+}
+
+/// Writes a fresh response to Redis and, when the prompt was embedded, to Qdrant.
+/// `semantic` is the prompt text with its embedding and `latency_ms` how long
+/// the upstream call took. Failures are logged and counted but never fail the request
+#[allow(clippy::too_many_arguments)]
+async fn store_in_caches(
+    state: &AppState,
+    cache_key: &str,
+    response_json: &str,
+    semantic: Option<(&str, Vec<f32>)>,
+    params: &EntryParams,
+    ttl: u64,
+    latency_ms: u64,
+    request_id: &str
+) {
+
+    if let Some(redis_cache) = &state.exact_cache {
+        if let Err(e) = redis_cache.set_with_ttl(cache_key, response_json, ttl).await {
+            tracing::warn!(error = %e, "failed to cache in redis");
+            state.metrics.record_error(ErrorCategory::RedisError, format!("Redis set failed: {}", e), Some(request_id));
+        } else {
+            tracing::debug!("stored in redis");
+            keep_stale_copy(state, cache_key, response_json, ttl).await;
+        }
+    }
+
+    if let Some(semantic_cache) = &state.semantic_cache
+        && let Some((prompt, embedding)) = semantic {
+        // a near-identical prompt already has a point: refresh it rather than add a neighbour
+        let dedup_threshold = state.runtime.load().semantic_write_dedup_threshold;
+        let duplicate = if dedup_threshold > 0.0 {
+            semantic_cache.search_similar(embedding.clone(), dedup_threshold, params).await
+                .unwrap_or_else(|e| {
+                    state.metrics.record_error(ErrorCategory::QdrantError, format!("Qdrant dedup search failed: {}", e), Some(request_id));
+                    None
+                })
+                // another key's point is left alone
+                .filter(|hit| state.in_cache_partition(&hit.cache_key))
+        } else {
+            None
+        };
+
+        let stored = match &duplicate {
+            Some(hit) => semantic_cache.refresh_point(&hit.point_id, cache_key, response_json, params, latency_ms, ttl).await
+                .map_err(|e| e.to_string()),
+            None => semantic_cache.store(cache_key, prompt, embedding, response_json, params, latency_ms, ttl).await
+                .map_err(|e| e.to_string())
+        };
+        match stored {
+            Ok(()) if duplicate.is_some() => tracing::debug!("refreshed near-duplicate in qdrant"),
+            Ok(()) => tracing::debug!("stored in qdrant"),
+            Err(e) => {
+                tracing::warn!(error = %e, "failed to cache in qdrant");
+                state.metrics.record_error(ErrorCategory::QdrantError, format!("Qdrant store failed: {}", e), Some(request_id));
+            }
+        }
+    }
+
+}
+
+// counts an exact-match hit for /admin/cache/hot and, when the background
+// refresher is on, in the Redis hit counts it reads
+fn count_hit(state: &AppState, cache_key: &str) {
+
+    state.hot_keys.record(cache_key);
+
+    if !state.config.refresh.enabled {
+        return;
+    }
+    if let Some(redis_cache) = &state.exact_cache {
+        let redis_cache = redis_cache.clone();
+        let metrics = state.metrics.clone();
+        let cache_key = cache_key.to_string();
+        tokio::spawn(async move {
+            if let Err(e) = redis_cache.record_hit(&cache_key).await {
+                metrics.record_error(ErrorCategory::RedisError, format!("Redis hit count failed: {}", e), None);
+            }
+        });
+    }
+
+}
+
+// awaits `future`, adding how long it took to the `stage` histogram
+async fn timed<T>(state: &AppState, stage: Stage, future: impl std::future::Future<Output = T>) -> T {
+
+    let started = Instant::now();
+    let output = future.await;
+    state.latencies.record(stage, started.elapsed());
+    output
+
+}
+
+// get_embedding through the embedding cache, kept in the exact-match backend
+// for EMBEDDING_CACHE_TTL_SECS. A cache error falls back to the embedding service
+async fn embed(state: &AppState, text: &str) -> Result<Vec<f32>, Box<dyn std::error::Error + Send + Sync>> {
+
+    let ttl = state.config.embedding_cache_ttl_secs;
+    let Some(redis_cache) = state.exact_cache.as_ref().filter(|_| ttl > 0) else {
+        return timed(state, Stage::Embedding, get_embedding(&state.http_client, &state.config.embedding, text)).await;
+    };
+
+    let key = embedding_cache_key(&state.config.embedding, text);
+    match redis_cache.get(&key).await {
+        Ok(Some(cached)) => if let Ok(embedding) = serde_json::from_str::<Vec<f32>>(&cached) {
+            state.metrics.record_embedding_lookup(true);
+            return Ok(embedding);
+        },
+        Ok(None) => {}
+        Err(e) => state.metrics.record_error(ErrorCategory::RedisError, format!("Redis embedding get failed: {}", e), None)
+    }
+    state.metrics.record_embedding_lookup(false);
+
+    let embedding = timed(state, Stage::Embedding, get_embedding(&state.http_client, &state.config.embedding, text)).await?;
+    if let Ok(json) = serde_json::to_string(&embedding)
+        && let Err(e) = redis_cache.set_with_ttl(&key, &json, ttl).await {
+        state.metrics.record_error(ErrorCategory::RedisError, format!("Redis embedding set failed: {}", e), None);
+    }
+    Ok(embedding)
+
+}
+
+/// Writes a line to the request log. `request_id` and `latency_ms` are `None`
+/// for work no client request is waiting on
+fn log_request(state: &AppState, cache_status: &str, model: &str, tokens: u64, cost: f64, request_id: Option<&str>, latency_ms: Option<u64>) {
+
+    let entry = RequestLogEntry {
+        timestamp: chrono::Utc::now().to_rfc3339(),
+        request_id: request_id.map(str::to_string),
+        tenant: state.tenant.clone(),
+        api_key: state.api_key_name.clone(),
+        cache_status: cache_status.to_string(),
+        model: model.to_string(),
+        latency_ms,
+        tokens,
+        cost_usd: cost
+    };
+    state.request_log.write(&entry);
+
+    // off the request path; a lost record only leaves /admin/requests short
+    if let Some(store) = state.request_store.clone() {
+        tokio::spawn(async move {
+            if let Err(e) = store.insert(&entry).await {
+                tracing::warn!(error = %e, "failed to store request record");
+            }
+        });
+    }
+
+}
+
+// debug level only; with REDACT_PROMPTS_IN_LOGS the message content never reaches the log
+fn log_request_body(state: &AppState, request: &LLMRequest) {
+
+    if !tracing::enabled!(tracing::Level::DEBUG) {
+        return;
+    }
+    let body = if state.config.redact_prompts_in_logs {
+        serde_json::to_string(&request.strip_sensitive_fields())
+    } else {
+        serde_json::to_string(request)
+    };
+    if let Ok(body) = body {
+        tracing::debug!(request = %body, "Chat completion request");
+    }
+
+}
+
+// stale-while-revalidate: re-runs a hit's request upstream and replaces the
+// entry, at most once at a time per key. Failures leave the entry to expire
+fn revalidate_in_background(state: &AppState, cache_key: &str, request: &LLMRequest) {
+
+    if state.revalidating.insert(cache_key.to_string(), ()).is_some() {
+        return;
+    }
+
+    let state = state.clone();
+    let cache_key = cache_key.to_string();
+    let request = request.clone();
+    tokio::spawn(async move {
+        tracing::debug!("revalidating entry close to expiry");
+        revalidate(&state, &cache_key, request).await;
+        state.revalidating.remove(&cache_key);
+    });
+
+}
+
+async fn revalidate(state: &AppState, cache_key: &str, request: LLMRequest) {
+
+    let Some(redis_cache) = &state.exact_cache else {
+        return;
+    };
+    let model = request.model.clone();
+    let temperature = request.temperature.unwrap_or(0.0);
+    let refresh_request = refresh_request_json(state, &request);
+
+    let response = match call_llm(state, request).await {
+        Ok((response, _)) => response,
+        Err(e) => {
+            state.metrics.record_error(classify_upstream_error(&e), format!("Revalidation LLM error: {}", e), None);
+            return;
+        }
+    };
+    let Ok(response_json) = serde_json::to_string(&response) else {
+        return;
+    };
+
+    let ttl = state.runtime.load().ttl_for(temperature);
+    if let Err(e) = redis_cache.set_with_ttl(cache_key, &response_json, ttl).await {
+        state.metrics.record_error(ErrorCategory::RedisError, format!("Redis set failed: {}", e), None);
+        return;
+    }
+    if let Some(request_json) = refresh_request {
+        remember_request(state, cache_key, &request_json, ttl).await;
+    }
+    keep_stale_copy(state, cache_key, &response_json, ttl).await;
+    // tier 0 would otherwise keep serving the old answer
+    if let Some(tier0) = &state.tier0_cache {
+        tier0.remove(cache_key);
+    }
+
+    let tokens = response.usage.total_tokens as u64;
+    state.metrics.record_revalidation(tokens);
+    let cost = calculate_cost(&model, tokens);
+    log_request(state, "REVALIDATE", &model, tokens, cost, None, None);
+    state.budget.record(None, cost);
+
+}
+
+// the request is only kept around when the background refresher may replay it
+fn refresh_request_json(state: &AppState, request: &LLMRequest) -> Option<String> {
+
+    // a client's own upstream key is never stored, so its entries can't be replayed
+    if !state.config.refresh.enabled || state.exact_cache.is_none() || state.upstream_key.is_some() {
+        return None;
+    }
+    serde_json::to_string(request).ok()
+
+}
+
+// with STALE_GRACE_SECS set, a copy of the entry that outlives it by the grace period
+async fn keep_stale_copy(state: &AppState, cache_key: &str, response_json: &str, ttl: u64) {
+
+    let grace = state.config.stale_grace_secs;
+    if grace > 0
+        && let Some(redis_cache) = &state.exact_cache
+        && let Err(e) = redis_cache.set_stale(cache_key, response_json, ttl.saturating_add(grace)).await {
+        state.metrics.record_error(ErrorCategory::RedisError, format!("Redis stale set failed: {}", e), None);
+    }
+
+}
+
+async fn remember_request(state: &AppState, cache_key: &str, request_json: &str, ttl: u64) {
+
+    if let Some(redis_cache) = &state.exact_cache
+        && let Err(e) = redis_cache.set_refresh_request(cache_key, request_json, ttl).await {
+        state.metrics.record_error(ErrorCategory::RedisError, format!("Redis set failed: {}", e), None);
+    }
+
+}
+
+/// Re-runs popular exact-match entries that are about to expire, within the
+/// configured count and cost budget. Returns how many entries were refreshed
+pub async fn refresh_popular_entries(state: &AppState) -> u64 {
+
+    let settings = state.config.refresh;
+    let Some(redis_cache) = &state.exact_cache else {
+        return 0;
+    };
+
+    let keys = match redis_cache.popular_expiring(settings.min_hits, settings.ttl_below_secs, settings.max_per_interval).await {
+        Ok(keys) => keys,
+        Err(e) => {
+            state.metrics.record_error(ErrorCategory::RedisError, format!("Refresh candidate scan failed: {}", e), None);
+            return 0;
+        }
+    };
+
+    let mut refreshed = 0;
+    let mut spent_usd = 0.0;
+
+    for cache_key in keys {
+        let (Ok(Some(cached)), Ok(Some(request_json))) = (redis_cache.get(&cache_key).await, redis_cache.refresh_request(&cache_key).await) else {
+            continue;
+        };
+        let (Ok(cached), Ok(request)) = (serde_json::from_str::<LLMResponse>(&cached), serde_json::from_str::<LLMRequest>(&request_json)) else {
+            continue;
+        };
+
+        // the cached usage is the best guess at what the refresh will cost
+        let model = request.model.clone();
+        let estimate = calculate_cost(&model, cached.usage.total_tokens as u64);
+        if spent_usd + estimate > settings.max_cost_usd {
+            tracing::info!(max_cost_usd = settings.max_cost_usd, "refresh budget reached");
+            break;
+        }
+
+        let temperature = request.temperature.unwrap_or(0.0);
+        let response = match call_llm(state, request).await {
+            Ok((response, _)) => response,
+            Err(e) => {
+                state.metrics.record_error(classify_upstream_error(&e), format!("Refresh LLM error: {}", e), None);
+                continue;
+            }
+        };
+        let Ok(response_json) = serde_json::to_string(&response) else {
+            continue;
+        };
+
+        let tokens = response.usage.total_tokens as u64;
+        let cost = calculate_cost(&model, tokens);
+        spent_usd += cost;
+        state.budget.record(None, cost);
+
+        let ttl = state.runtime.load().ttl_for(temperature);
+        if let Err(e) = redis_cache.set_with_ttl(&cache_key, &response_json, ttl).await {
+            state.metrics.record_error(ErrorCategory::RedisError, format!("Redis set failed: {}", e), None);
+            continue;
+        }
+        remember_request(state, &cache_key, &request_json, ttl).await;
+        keep_stale_copy(state, &cache_key, &response_json, ttl).await;
+        if let Err(e) = redis_cache.clear_hits(&cache_key).await {
+            state.metrics.record_error(ErrorCategory::RedisError, format!("Redis hit reset failed: {}", e), None);
+        }
+        // a stale copy in tier 0 would otherwise outlive the refresh
+        if let Some(tier0) = &state.tier0_cache {
+            tier0.remove(&cache_key);
+        }
+
+        state.metrics.record_refresh(tokens);
+        log_request(state, "REFRESH", &model, tokens, cost, None, None);
+        refreshed += 1;
+    }
+
+    if refreshed > 0 {
+        tracing::info!(refreshed, spent_usd, "refreshed popular cache entries");
+    }
+    refreshed
+
+}
+
+/// Parses a prefill body, either `{"prompts": [...]}` or JSONL with one
+/// request per line. Errors name the prompt (or line) and the field at fault
+fn parse_prefill_body(body: &str) -> Result<Vec<LLMRequest>, String> {
+
+    if let Ok(serde_json::Value::Object(mut object)) = serde_json::from_str::<serde_json::Value>(body)
+        && let Some(prompts) = object.remove("prompts") {
+        let serde_json::Value::Array(prompts) = prompts else {
+            return Err("field `prompts`: expected an array".to_string());
+        };
+        return prompts.into_iter().enumerate()
+            .map(|(i, prompt)| {
+                let request = LLMRequest::try_from(prompt).map_err(|e| format!("prompt {}: {}", i, e))?;
+                request.validate_messages().map_err(|e| format!("prompt {}: {}", i, e))?;
+                Ok(request)
+            })
+            .collect();
+    }
+
+    body.lines().enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            let value: serde_json::Value = serde_json::from_str(line)
+                .map_err(|e| format!("line {}: invalid JSON: {}", i + 1, e))?;
+            let request = LLMRequest::try_from(value).map_err(|e| format!("line {}: {}", i + 1, e))?;
+            request.validate_messages().map_err(|e| format!("line {}: {}", i + 1, e))?;
+            Ok(request)
+        })
+        .collect()
+
+}
+
+enum PrefillOutcome {
+    Cached { cost: f64 },
+    Skipped,
+    Failed
+}
+
+/// Generates and caches responses for prompts expected to be common.
+/// Prompts already in the exact cache are skipped; upstream calls run
+/// at most `PREFILL_PARALLELISM` at a time. A malformed prompt rejects
+/// the whole batch before anything is sent upstream
+#[tracing::instrument(level = "debug", skip_all, fields(prompts = tracing::field::Empty))]
+pub async fn prefill_cache(
+    State(mut state): State<AppState>,
+    api_key: Option<Extension<ApiKeyIdentity>>,
+    tenant: Option<Extension<Tenant>>,
+    headers: HeaderMap,
+    body: String,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+
+    use_api_key(&mut state, api_key);
+    use_upstream_key(&mut state, &headers);
+    use_tenant(&mut state, tenant);
+
+    let prompts = parse_prefill_body(&body).map_err(|e| {
+        (StatusCode::BAD_REQUEST, Json(json!({ "error": e })))
+    })?;
+    tracing::Span::current().record("prompts", prompts.len());
+
+    let permits = Arc::new(Semaphore::new(state.config.prefill_parallelism));
+    let mut tasks = JoinSet::new();
+
+    tracing::info!(prompts = prompts.len(), "prefill started");
+
+    for request in prompts {
+        let state = state.clone();
+        let permits = permits.clone();
+        tasks.spawn(async move {
+            let _permit = permits.acquire_owned().await;
+            prefill_one(&state, request).await
+        });
+    }
+
+    let (mut cached, mut skipped, mut failed, mut cost_usd) = (0u64, 0u64, 0u64, 0.0f64);
+    while let Some(outcome) = tasks.join_next().await {
+        match outcome {
+            Ok(PrefillOutcome::Cached { cost }) => {
+                cached += 1;
+                cost_usd += cost;
+            }
+            Ok(PrefillOutcome::Skipped) => skipped += 1,
+            // a panicked task counts as a failure
+            Ok(PrefillOutcome::Failed) | Err(_) => failed += 1
+        }
+    }
+
+    tracing::info!(cached, skipped, failed, "prefill done");
+
+    Ok(Json(json!({
+        "cached": cached,
+        "skipped": skipped,
+        "failed": failed,
+        "cost_usd": cost_usd
+    })))
+
+}
+
+async fn prefill_one(state: &AppState, mut request: LLMRequest) -> PrefillOutcome {
+
+    let request_id = Uuid::new_v4().to_string();
+    request.model = match state.config.models.resolve(&request.model) {
+        Ok(model) => model,
+        Err(e) => {
+            tracing::info!(error = %e, "prefill skipped prompt");
+            return PrefillOutcome::Failed;
+        }
+    };
+    let cache_key = generate_cache_key(&request, &state.config.key_normalization, &state.cache_key_prefix());
+
+    if let Some(redis_cache) = &state.exact_cache {
+        match redis_cache.get(&cache_key).await {
+            Ok(Some(_)) => return PrefillOutcome::Skipped,
+            Ok(None) => {}
+            Err(e) => state.metrics.record_error(ErrorCategory::RedisError, format!("Redis get failed: {}", e), Some(&request_id))
+        }
+    }
+
+    let temperature = request.temperature.unwrap_or(0.0);
+    let model = request.model.clone();
+    let params = state.entry_params(&request);
+    let prompt = prompt_text(&request);
+    let embedding = match &state.semantic_cache {
+        Some(_) if request.semantic_eligible() => embed(state, &prompt).await.ok(),
+        _ => None
+    };
+
+    let refresh_request = refresh_request_json(state, &request);
+
+    let started = Instant::now();
+    let response = match call_llm(state, request).await {
+        Ok((response, _)) => response,
+        Err(e) => {
+            state.metrics.record_error(classify_upstream_error(&e), format!("Prefill LLM error: {}", e), Some(&request_id));
+            return PrefillOutcome::Failed;
+        }
+    };
+    let latency_ms = started.elapsed().as_millis() as u64;
+
+    let Ok(response_json) = serde_json::to_string(&response) else {
+        return PrefillOutcome::Failed;
+    };
+
+    let tokens = response.usage.total_tokens as u64;
+    let cost = calculate_cost(&model, tokens);
+    log_request(state, "PREFILL", &model, tokens, cost, Some(&request_id), Some(latency_ms));
+    if let Some(tenant) = &state.tenant {
+        state.quotas.record(tenant, tokens, cost);
+    }
+    state.budget.record(state.api_key_name.as_deref(), cost);
+
+    let ttl = state.runtime.load().ttl_for(temperature);
+
+    let semantic = embedding.map(|embedding| (prompt.as_str(), embedding));
+    store_in_caches(state, &cache_key, &response_json, semantic, &params, ttl, latency_ms, &request_id).await;
+    if let Some(request_json) = refresh_request {
+        remember_request(state, &cache_key, &request_json, ttl).await;
+    }
+
+    PrefillOutcome::Cached { cost }
+
+}
+
+// lookups never reach the upstream, so a batch can run wider than a prefill
+const LOOKUP_PARALLELISM: usize = 16;
+
+/// Reports whether a request would be served from cache, without ever
+/// calling the upstream or writing to a cache. Accepts one request, which
+/// gets a 404 on a miss, or an array of requests answered item by item
+#[tracing::instrument(level = "debug", skip_all)]
+pub async fn cache_lookup(
+    State(mut state): State<AppState>,
+    tenant: Option<Extension<Tenant>>,
+    headers: HeaderMap,
+    Json(body): Json<serde_json::Value>,
+) -> (StatusCode, Json<serde_json::Value>) {
+
+    use_upstream_key(&mut state, &headers);
+    use_tenant(&mut state, tenant);
+
+    let serde_json::Value::Array(items) = body else {
+        return match LLMRequest::try_from(body) {
+            Ok(request) => {
+                let (status, result) = lookup_one(&state, request).await;
+                (status, Json(result))
+            }
+            Err(e) => (StatusCode::BAD_REQUEST, Json(json!({"error": e.to_string()})))
+        };
+    };
+
+    let permits = Arc::new(Semaphore::new(LOOKUP_PARALLELISM));
+    let mut tasks = JoinSet::new();
+    let mut results = vec![serde_json::Value::Null; items.len()];
+
+    for (i, item) in items.into_iter().enumerate() {
+        let request = match LLMRequest::try_from(item) {
+            Ok(request) => request,
+            Err(e) => {
+                results[i] = json!({"error": e.to_string()});
+                continue;
+            }
+        };
+        let state = state.clone();
+        let permits = permits.clone();
+        tasks.spawn(async move {
+            let _permit = permits.acquire_owned().await;
+            (i, lookup_one(&state, request).await.1)
+        });
+    }
+
+    while let Some(outcome) = tasks.join_next().await {
+        if let Ok((i, result)) = outcome {
+            results[i] = result;
+        }
+    }
+
+    let hits = results.iter().filter(|r| r["cached"] == true).count();
+    (StatusCode::OK, Json(json!({
+        "count": results.len(),
+        "hits": hits,
+        "results": results
+    })))
+
+}
+
+async fn lookup_one(state: &AppState, mut request: LLMRequest) -> (StatusCode, serde_json::Value) {
+
+    request.model = match state.config.models.resolve(&request.model) {
+        Ok(model) => model,
+        Err(e) => return (StatusCode::BAD_REQUEST, json!({"error": e}))
+    };
+    let params = state.entry_params(&request);
+    let cache_key = generate_cache_key(&request, &state.config.key_normalization, &state.cache_key_prefix());
+
+    let hit = |tier: &str, similarity: Option<f32>, response: LLMResponse| {
+        state.metrics.record_lookup(true);
+        let age_secs = (Utc::now().timestamp() - response.created).max(0);
+        (StatusCode::OK, json!({
+            "cached": true,
+            "tier": tier,
+            "similarity": similarity,
+            "age_secs": age_secs,
+            "cache_key": cache_key,
+            "response": response
+        }))
+    };
+
+    if let Some(tier0) = &state.tier0_cache
+        && let Some(response) = tier0.get(&cache_key) {
+        return hit("tier0", None, response);
+    }
+
+    if let Some(redis_cache) = &state.exact_cache {
+        match redis_cache.get(&cache_key).await {
+            Ok(Some(cached)) => {
+                if let Ok(response) = serde_json::from_str(&cached) {
+                    return hit("exact", None, response);
+                }
+            }
+            Ok(None) => {}
+            Err(e) => state.metrics.record_error(ErrorCategory::RedisError, format!("Lookup Redis get failed: {}", e), None)
+        }
+    }
+
+    // the closest entry is reported even below the threshold, to help tune it
+    let mut best_semantic_score = None;
+    if let Some(semantic_cache) = &state.semantic_cache
+        && request.semantic_eligible() {
+        match embed(state, &prompt_text(&request)).await {
+            Ok(embedding) => match semantic_cache.search_paginated(embedding, 0.0, 1, None, Some(&params)).await {
+                Ok(hits) => if let Some(best) = hits.into_iter().next().filter(|hit| state.in_cache_partition(&hit.cache_key)) {
+                    if best.score >= state.runtime.load().semantic_threshold
+                        && params.matches(&best)
+                        && let Ok(response) = serde_json::from_str(&best.response) {
+                        return hit("semantic", Some(best.score), response);
+                    }
+                    best_semantic_score = Some(best.score);
+                },
+                Err(e) => state.metrics.record_error(ErrorCategory::QdrantError, format!("Lookup Qdrant search failed: {}", e), None)
+            },
+            Err(e) => state.metrics.record_error(ErrorCategory::EmbeddingError, format!("Lookup embedding failed: {}", e), None)
+        }
+    }
+
+    state.metrics.record_lookup(false);
+    (StatusCode::NOT_FOUND, json!({
+        "cached": false,
+        "cache_key": cache_key,
+        "best_semantic_score": best_semantic_score
+    }))
+
+}
+
+// embedding dimensions echoed back by /v1/chat/completions/explain
+const EXPLAIN_EMBEDDING_PREVIEW: usize = 10;
+const EXPLAIN_NEIGHBORS: usize = 3;
+
+/// Walks a request through the same key, TTL and tier decisions as
+/// `proxy_handler` and reports each one, without calling the upstream or
+/// writing to any cache. Admin only, since it exposes cached entries.
+/// It's explained as the tenant in `x-tenant-id` (the key's name under
+/// TENANT_SOURCE=api_key) and, under BYOK, the upstream key sent in
+/// `Authorization` alongside an `x-admin-token`
+#[tracing::instrument(level = "debug", skip_all)]
+pub async fn explain_handler(
+    State(mut state): State<AppState>,
+    headers: HeaderMap,
+    Json(mut request): Json<LLMRequest>
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+
+    require_admin(&state, &headers)?;
+
+    if state.config.tenant_source != TenantSource::Off
+        && let Some(tenant) = headers.get(TENANT_HEADER) {
+        let tenant = tenant.to_str().ok().map(str::trim).filter(|id| valid_tenant(id))
+            .ok_or_else(|| (StatusCode::BAD_REQUEST, Json(json!({"error": format!("Invalid {} header", TENANT_HEADER)}))))?;
+        state.tenant = Some(tenant.to_string());
+    }
+    if headers.contains_key("x-admin-token") {
+        use_upstream_key(&mut state, &headers);
+    }
+
+    request.model = state.config.models.resolve(&request.model)
+        .map_err(|e| (StatusCode::BAD_REQUEST, Json(json!({"error": e}))))?;
+    let temperature = request.temperature.unwrap_or(0.0);
+    let runtime = state.runtime.load_full();
+    let shadow_mode = state.config.cache_mode == CacheMode::Shadow;
+
+    // read the same way proxy_handler reads them
+    let bypass_cache = headers.get("x-bypass-cache")
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.to_lowercase() == "true");
+    let custom_ttl = headers.get("x-cache-ttl")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok());
+    let threshold = semantic_threshold(&headers, &runtime)
+        .map_err(|e| (StatusCode::BAD_REQUEST, Json(json!({"error": e.error.message}))))?;
+
+    let cache_key = generate_cache_key(&request, &state.config.key_normalization, &state.cache_key_prefix());
+
+    let ttl_secs = custom_ttl.unwrap_or(runtime.ttl_for(temperature));
+    let ttl_source = if custom_ttl.is_some() { "x-cache-ttl" } else { "temperature" };
+
+    let tier0_present = state.tier0_cache.as_ref().map(|tier0| tier0.contains(&cache_key));
+
+    let exact = match &state.exact_cache {
+        Some(redis_cache) => match redis_cache.get_with_ttl(&cache_key).await {
+            Ok(entry) => json!({
+                "enabled": true,
+                "present": entry.is_some(),
+                "ttl_secs": entry.map(|(_, ttl)| ttl)
+            }),
+            Err(e) => json!({"enabled": true, "error": e.to_string()})
+        },
+        None => json!({"enabled": false})
+    };
+
+    let mut semantic_match = false;
+    let semantic = match &state.semantic_cache {
+        Some(_) if !request.semantic_eligible() => json!({
+            "enabled": true,
+            "eligible": false,
+            "reason": if request.uses_tools() { "the request uses tools" } else { "the request has images" }
+        }),
+        Some(semantic_cache) => match get_embedding(&state.http_client, &state.config.embedding, &prompt_text(&request)).await {
+            Ok(embedding) => {
+                let norm = embedding.iter().map(|v| v * v).sum::<f32>().sqrt();
+                let preview: Vec<f32> = embedding.iter().take(EXPLAIN_EMBEDDING_PREVIEW).copied().collect();
+                let dimensions = embedding.len();
+
+                // the nearest entries this request could be served from, flagging those under the threshold
+                let params = state.entry_params(&request);
+                let neighbors = match semantic_cache.search_paginated(embedding, 0.0, EXPLAIN_NEIGHBORS, None, Some(&params)).await {
+                    Ok(hits) => hits.iter().filter(|hit| state.in_cache_partition(&hit.cache_key)).map(|hit| {
+                        let servable = hit.score >= threshold && params.matches(hit);
+                        semantic_match |= servable;
+                        json!({
+                            "cache_key": hit.cache_key,
+                            "score": hit.score,
+                            "model": hit.model,
+                            "temperature": hit.temperature,
+                            "max_tokens": hit.max_tokens,
+                            "servable": servable
+                        })
+                    }).collect::<Vec<_>>().into(),
+                    Err(e) => json!({"error": e.to_string()})
+                };
+
+                json!({
+                    "enabled": true,
+                    "eligible": true,
+                    "threshold": threshold,
+                    "embedding": {"dimensions": dimensions, "first_values": preview, "norm": norm},
+                    "neighbors": neighbors
+                })
+            }
+            Err(e) => json!({"enabled": true, "error": format!("Embedding failed: {}", e)})
+        },
+        None => json!({"enabled": false})
+    };
+
+    let would_serve_from = if bypass_cache || shadow_mode {
+        None
+    } else if tier0_present == Some(true) {
+        Some("tier0")
+    } else if exact["present"] == true {
+        Some("exact")
+    } else if semantic_match {
+        Some("semantic")
+    } else {
+        None
+    };
+
+    Ok(Json(json!({
+        "model": request.model,
+        "cache_key": cache_key,
+        "cache_mode": state.config.cache_mode.as_str(),
+        "headers": {
+            "x-bypass-cache": bypass_cache,
+            "x-cache-ttl": custom_ttl,
+            "x-semantic-threshold": headers.contains_key(SEMANTIC_THRESHOLD_HEADER).then_some(threshold)
+        },
+        "ttl": {"secs": ttl_secs, "source": ttl_source},
+        "tiers": {
+            "tier0": {"enabled": tier0_present.is_some(), "present": tier0_present},
+            "exact": exact,
+            "semantic": semantic
+        },
+        // null means the request would go upstream
+        "would_serve_from": would_serve_from
+    })))
+
+}
+
+/// Concatenates the message content of every choice in a response
+fn response_text(response: &LLMResponse) -> String {
+    response.choices.iter()
+        .map(|c| c.message.content.as_text())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+async fn compare_shadow_answers(state: &AppState, cached_text: &str, fresh_text: &str) {
+
+    let (cached_embedding, fresh_embedding) = tokio::join!(
+        get_embedding(&state.http_client, &state.config.embedding, cached_text),
+        get_embedding(&state.http_client, &state.config.embedding, fresh_text)
+    );
+
+    match (cached_embedding, fresh_embedding) {
+        (Ok(cached), Ok(fresh)) => {
+            let similarity = cosine_similarity(&cached, &fresh);
+            let matched = similarity >= state.runtime.load().semantic_threshold;
+            tracing::info!(similarity, matched, "shadow: cached vs fresh answer compared");
+            state.metrics.record_shadow_comparison(matched);
+        }
+        (Err(e), _) | (_, Err(e)) => {
+            tracing::warn!(error = %e, "shadow: answer comparison skipped");
+        }
+    }
+
+}
+
+// Use default model for cost calculation
+// In production, you'd want to track which model was actually used
+const METRICS_PRICING_MODEL: &str = "llama-3.3-70b-versatile";
+
+// Rough estimate: assume 50/50 input/output split
+fn estimated_cost_per_token() -> f64 {
+
+    let (input_price, output_price) = get_groq_model_pricing(METRICS_PRICING_MODEL);
+    (input_price + output_price) / 2.0 / 1_000_000.0
+
+}
+
+/// GET /metrics/tenants. Cache results and upstream usage by tenant
+/// (TENANT_SOURCE), with each tenant's usage today against its quotas.
+/// Today's cost is priced per model, like `usage.cost_usd`; the all-time
+/// figures use the /metrics estimate. Tenant ids and their figures need the
+/// admin token; without it only the count and the quotas are shown
+pub async fn tenant_metrics(State(state): State<AppState>, headers: HeaderMap) -> Json<serde_json::Value> {
+
+    let quotas = state.quotas.quotas();
+    let mut body = json!({
+        "tenant_source": state.config.tenant_source.as_str(),
+        "tenant_count": state.per_tenant.len(),
+        "quotas": {
+            "daily_tokens": quotas.daily_tokens,
+            "daily_cost_usd": quotas.daily_cost_usd,
+            "resets_at": state.quotas.resets_at().to_rfc3339()
+        }
+    });
+    if require_admin(&state, &headers).is_err() {
+        return Json(body);
+    }
+
+    let avg_cost_per_token = estimated_cost_per_token();
+
+    let tenants: std::collections::BTreeMap<String, serde_json::Value> = state.per_tenant.iter()
+        .map(|entry| {
+            let metrics = entry.value().snapshot();
+            let today = state.quotas.usage(entry.key());
+            (entry.key().clone(), json!({
+                "requests": metrics.total_requests,
+                "exact_hits": metrics.tier0_hits + metrics.exact_hits,
+                "semantic_hits": metrics.semantic_hits,
+                "misses": metrics.misses,
+                "hit_rate_percent": format!("{:.2}%", metrics.cache_hit_rate()),
+                "tokens_used": metrics.tokens_used,
+                "tokens_saved": metrics.tokens_saved,
+                "cost_spent_usd": format!("${:.4}", metrics.tokens_used as f64 * avg_cost_per_token),
+                "cost_saved_usd": format!("${:.4}", metrics.tokens_saved as f64 * avg_cost_per_token),
+                "today": {
+                    "tokens": today.tokens,
+                    "cost_usd": format!("${:.4}", today.cost_usd),
+                    "tokens_remaining": quotas.daily_tokens.map(|limit| limit.saturating_sub(today.tokens)),
+                    "cost_remaining_usd": quotas.daily_cost_usd.map(|limit| format!("${:.4}", (limit - today.cost_usd).max(0.0)))
+                }
+            }))
+        })
+        .collect();
+
+    body["tenants"] = json!(tenants);
+    Json(body)
+
+}
+
+#[derive(Deserialize)]
+pub struct HistoryQuery {
+    // how far back, e.g. "24h" (default); at most 24 hours
+    window: Option<String>,
+    // how wide each bucket is, e.g. "5m" (default); at least a minute
+    bucket: Option<String>
+}
+
+/// GET /metrics/history. Requests, hit rate and estimated cost in time
+/// buckets, oldest first, from the per-minute samples in `history`
+pub async fn metrics_history(
+    State(state): State<AppState>,
+    Query(query): Query<HistoryQuery>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+
+    let span = |value: Option<&str>, default: &str, name: &str| {
+        let value = value.unwrap_or(default);
+        history::parse_span(value)
+            .ok_or_else(|| (StatusCode::BAD_REQUEST, Json(json!({"error": format!("{} must be a span like 30m, 6h or 1d, got '{}'", name, value)}))))
+    };
+    let window = span(query.window.as_deref(), "24h", "window")?;
+    let bucket = span(query.bucket.as_deref(), "5m", "bucket")?;
+
+    if window > history::max_window() {
+        return Err((StatusCode::BAD_REQUEST, Json(json!({"error": "window can be at most 24h"}))));
+    }
+    if bucket < history::HISTORY_RESOLUTION || bucket > window {
+        return Err((StatusCode::BAD_REQUEST, Json(json!({"error": "bucket must be between 1m and the window"}))));
+    }
+
+    let buckets = state.history.buckets(window, bucket, &state.metrics.snapshot(), estimated_cost_per_token());
+
+    Ok(Json(json!({
+        "window_secs": window.as_secs(),
+        "bucket_secs": bucket.as_secs(),
+        "buckets": buckets
+    })))
+
+}
+
+#[derive(Deserialize, Default)]
+pub struct MetricsQuery {
+    // "all" (default) includes what METRICS_PERSIST restored; "process" is this process alone
+    scope: Option<String>
+}
+
+pub async fn metrics(State(state): State<AppState>, Query(query): Query<MetricsQuery>) -> Json<serde_json::Value> {
+    let restored = state.restored_metrics.lock().unwrap().clone();
+    let process_scope = query.scope.as_deref() == Some("process");
+    let snapshot = match &restored {
+        Some(restored) if process_scope => state.metrics.snapshot().since(restored),
+        _ => state.metrics.snapshot()
+    };
+    
+    let hit_rate = snapshot.cache_hit_rate();
+    let total_hits = snapshot.total_hits();
+    
+    let default_model = METRICS_PRICING_MODEL;
+    let (input_price, output_price) = get_groq_model_pricing(default_model);
+    let avg_cost_per_token = estimated_cost_per_token();
+    
+    let cost_saved = snapshot.tokens_saved as f64 * avg_cost_per_token;
+    let cost_spent = snapshot.tokens_used as f64 * avg_cost_per_token;
+    let total_cost_without_cache = (snapshot.tokens_saved + snapshot.tokens_used) as f64 * avg_cost_per_token;
+    
+    let shadow_hit_rate = snapshot.shadow_hit_rate();
+
+    // a disabled tier reports null rather than a misleading zero
+    let exact_enabled = state.exact_cache.is_some();
+    let semantic_enabled = state.semantic_cache.is_some();
+    let any_enabled = exact_enabled || semantic_enabled;
+
+    let error_counts: serde_json::Map<String, serde_json::Value> = state.metrics.error_counts()
+        .into_iter()
+        .map(|(category, count)| (category.to_string(), json!(count)))
+        .collect();
+
+    // passthrough requests never touch the cache, so they aren't part of the hit rate
+    let breakdown = |entries: &dashmap::DashMap<String, Arc<Metrics>>| -> std::collections::BTreeMap<String, serde_json::Value> {
+        entries.iter()
+            .map(|entry| {
+                let metrics = entry.value().snapshot();
+                (entry.key().clone(), json!({
+                    "requests": metrics.total_requests + metrics.passthrough_requests,
+                    "hits": metrics.total_hits(),
+                    "misses": metrics.misses,
+                    "passthrough": metrics.passthrough_requests,
+                    "hit_rate_percent": format!("{:.2}%", metrics.cache_hit_rate()),
+                    "tokens_used": metrics.tokens_used,
+                    "tokens_saved": metrics.tokens_saved,
+                    "cost_spent_usd": format!("${:.4}", metrics.tokens_used as f64 * avg_cost_per_token),
+                    "cost_saved_usd": format!("${:.4}", metrics.tokens_saved as f64 * avg_cost_per_token)
+                }))
+            })
+            .collect()
+    };
+    let endpoints = breakdown(&state.per_endpoint);
+
+    let budget = state.budget.config();
+    let (period_start, resets_at) = state.budget.period();
+    let (global_spent, key_spent) = state.budget.spent();
+
+    Json(json!({
+        "scope": if process_scope { "process" } else { "all" },
+        // counters carried over from earlier runs by METRICS_PERSIST
+        "restored": restored.is_some(),
+        "cache_mode": state.config.cache_mode.as_str(),
+        "passthrough_requests": snapshot.passthrough_requests,
+        "cache_tiers": {
+            "exact": if exact_enabled { "enabled" } else { "disabled" },
+            "semantic": if semantic_enabled { "enabled" } else { "disabled" }
+        },
+        "cache_performance": {
+            "tier0_hits": state.tier0_cache.is_some().then_some(snapshot.tier0_hits),
+            "tier0_entries": state.tier0_cache.as_ref().map(|tier0| tier0.len()),
+            "exact_hits": exact_enabled.then_some(snapshot.exact_hits),
+            "semantic_hits": semantic_enabled.then_some(snapshot.semantic_hits),
+            "total_hits": any_enabled.then_some(total_hits),
+            // expired entries served in place of a failed upstream call; not counted as hits
+            "stale_hits": (state.config.stale_grace_secs > 0).then_some(snapshot.stale_hits),
+            "misses": snapshot.misses,
+            "total_requests": snapshot.total_requests,
+            "hit_rate_percent": any_enabled.then(|| format!("{:.2}%", hit_rate))
+        },
+        "token_usage": {
+            "tokens_saved": snapshot.tokens_saved,
+            "exact_tokens_saved": exact_enabled.then_some(snapshot.exact_tokens_saved),
+            "semantic_tokens_saved": semantic_enabled.then_some(snapshot.semantic_tokens_saved),
+            "tokens_used": snapshot.tokens_used,
+            "total_tokens_without_cache": snapshot.tokens_saved + snapshot.tokens_used
+        },
+        "cost_analysis": {
+            "cost_saved_usd": format!("${:.4}", cost_saved),
+            "cost_spent_usd": format!("${:.4}", cost_spent),
+            "total_cost_without_cache_usd": format!("${:.4}", total_cost_without_cache),
+            "savings_percent": if total_cost_without_cache > 0.0 {
+                format!("{:.2}%", (cost_saved / total_cost_without_cache) * 100.0)
+            } else {
+                "0.00%".to_string()
+            },
+            "note": format!("Costs calculated using {} pricing. Actual costs may vary if different models were used.", default_model)
+        },
+        "pricing": {
+            "model_assumed": default_model,
+            "input_per_1m_tokens": format!("${:.2}", input_price),
+            "output_per_1m_tokens": format!("${:.2}", output_price),
+            "supported_models": [
+                "llama-3.3-70b-versatile", "llama-3.1-8b-instant",
+                "llama-4-scout", "llama-4-maverick",
+                "qwen3-32b", "kimi-k2-0905-1t",
+                "gpt-oss-20b", "gpt-oss-safeguard-20b", "gpt-oss-120b"
+            ]
+        },
+        "endpoints": endpoints,
+        // by proxy API key name; empty unless PROXY_API_KEYS is set
+        "api_keys": breakdown(&state.per_api_key),
+        "errors": error_counts,
+        // 2xx upstream bodies that couldn't be parsed, also counted under serialization_error
+        "upstream_parse_errors": snapshot.upstream_parse_errors,
+        // upstream responses that repeated a recently seen id and were answered with the first copy
+        "id_dedup_hits": snapshot.id_dedup_hits,
+        // misses answered by an identical request's upstream call instead of their own
+        "coalesced_requests": snapshot.coalesced_requests,
+        // turned away with 429 by RATE_LIMIT_REQUESTS_PER_MIN / RATE_LIMIT_TOKENS_PER_MIN
+        "rate_limited_requests": snapshot.rate_limited_requests,
+        // p50/p95/p99 of each step of serving a request, in ms
+        "latency": state.latencies.snapshot(),
+        // estimated upstream spend this BUDGET_PERIOD against the BUDGET_* limits
+        "budget": {
+            "period": budget.period.as_str(),
+            "period_start": period_start.to_rfc3339(),
+            "resets_at": resets_at.to_rfc3339(),
+            "spent_usd": global_spent,
+            "soft_limit_usd": budget.global.soft_usd,
+            "hard_limit_usd": budget.global.hard_usd,
+            "api_keys": key_spent.into_iter().map(|(key, spent)| (key, json!({
+                "spent_usd": spent,
+                "soft_limit_usd": budget.per_key.soft_usd,
+                "hard_limit_usd": budget.per_key.hard_usd
+            }))).collect::<serde_json::Map<_, _>>()
+        },
+        // upstream calls repeated after a 429/502/503/504 or connection failure, per UPSTREAM_RETRY_*
+        "upstream_retries": {
+            "retries": snapshot.upstream_retries,
+            "exhausted": snapshot.upstream_retries_exhausted
+        },
+        // CIRCUIT_BREAKER_*: closed, open (failing fast) or half_open (probing)
+        "circuit_breaker": state.circuit_breaker.snapshot(),
+        // from the latest upstream response; null until one has been seen
+        "upstream_rate_limit": {
+            "remaining_tokens": snapshot.rate_limit_remaining_tokens,
+            "remaining_requests": snapshot.rate_limit_remaining_requests
+        },
+        "lookups": {
+            "total": snapshot.lookups,
+            "hits": snapshot.lookup_hits
+        },
+        "background_refresh": {
+            "enabled": state.config.refresh.enabled,
+            "refreshes": snapshot.refreshes,
+            "tokens_used": snapshot.refresh_tokens_used
+        },
+        "embedding_cache": {
+            "enabled": state.exact_cache.is_some() && state.config.embedding_cache_ttl_secs > 0,
+            "hits": snapshot.embedding_cache_hits,
+            "misses": snapshot.embedding_cache_misses
+        },
+        // the janitor's last pass; null until it has run (JANITOR_INTERVAL_SECS)
+        "maintenance": state.maintenance.lock().unwrap().clone(),
+        "stale_while_revalidate": {
+            "enabled": state.config.swr_window_secs > 0,
+            "window_secs": state.config.swr_window_secs,
+            "revalidations": snapshot.revalidations,
+            "tokens_used": snapshot.revalidation_tokens_used
+        },
+        "shadow_mode": {
+            "enabled": state.config.cache_mode == CacheMode::Shadow,
+            "would_be_exact_hits": snapshot.shadow_exact_hits,
+            "would_be_semantic_hits": snapshot.shadow_semantic_hits,
+            "would_be_misses": snapshot.shadow_misses,
+            "total_requests": snapshot.shadow_requests(),
+            "would_be_hit_rate_percent": format!("{:.2}%", shadow_hit_rate),
+            "avg_semantic_similarity": format!("{:.4}", snapshot.shadow_avg_similarity()),
+            "answer_comparison": {
+                "matched": snapshot.shadow_answer_matches,
+                "mismatched": snapshot.shadow_answer_mismatches,
+                "match_rate_percent": format!("{:.2}%", snapshot.shadow_answer_match_rate())
+            },
+            "note": "Would-be figures describe what the cache could have served. In shadow mode nothing is served from cache, so cache_performance reports every request as a miss."
+        }
+    }))
+}
+
+// one exact-match entry as returned by the inspect endpoints
+fn inspect_entry(key: &str, entry: Option<(String, i64)>) -> serde_json::Value {
+
+    match entry {
+        Some((value, ttl)) => json!({
+            "key": key,
+            "found": true,
+            // -1 means the key never expires
+            "ttl_secs": ttl,
+            "response": serde_json::from_str::<serde_json::Value>(&value).unwrap_or(json!(value))
+        }),
+        None => json!({"key": key, "found": false})
+    }
+
+}
+
+fn redis_error_response(state: &AppState, e: BackendError) -> (StatusCode, Json<serde_json::Value>) {
+
+    state.metrics.record_error(ErrorCategory::RedisError, e.to_string(), None);
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(json!({"error": format!("Redis error: {}", e)}))
+    )
+
+}
+
+fn qdrant_error_response(state: &AppState, e: CacheError) -> (StatusCode, Json<serde_json::Value>) {
+
+    state.metrics.record_error(ErrorCategory::QdrantError, e.to_string(), None);
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(json!({"error": format!("Qdrant error: {}", e)}))
+    )
+
+}
+
+// returned by endpoints that only make sense for a tier that is switched off
+fn tier_disabled(tier: &str, env_var: &str) -> (StatusCode, Json<serde_json::Value>) {
+
+    (
+        StatusCode::CONFLICT,
+        Json(json!({"error": format!("The {} cache tier is disabled ({}=false)", tier, env_var)}))
+    )
+
+}
+
+/// Runs `op` against a tier's backend, or returns `Ok(None)` when the tier is disabled
+async fn if_enabled<'a, B, T, E, Fut>(backend: &'a Option<B>, op: impl FnOnce(&'a B) -> Fut) -> Result<Option<T>, E>
+where
+    Fut: Future<Output = Result<T, E>>
+{
+
+    match backend {
+        Some(backend) => op(backend).await.map(Some),
+        None => Ok(None)
+    }
+
+}
+
+/// Counts the proxy's Redis keys by kind. Unlike DBSIZE this ignores
+/// anything else sharing the Redis instance
+pub async fn admin_cache_size(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+
+    require_admin(&state, &headers)?;
+    let redis_cache = state.exact_cache.as_ref().ok_or_else(|| tier_disabled("exact", "EXACT_CACHE_ENABLED"))?;
+
+    let exact_prefix = state.config.exact_key_prefix();
+    let [exact, refresh_requests, quarantined, embeddings] = [exact_prefix.as_str(), REFRESH_REQUEST_PREFIX, QUARANTINE_PREFIX, EMBEDDING_PREFIX]
+        .map(|prefix| format!("{}*", prefix));
+    let (exact, refresh_requests, quarantined, embeddings) = tokio::try_join!(
+        redis_cache.count_keys_matching(&exact),
+        redis_cache.count_keys_matching(&refresh_requests),
+        redis_cache.count_keys_matching(&quarantined),
+        redis_cache.count_keys_matching(&embeddings)
+    ).map_err(|e| redis_error_response(&state, e))?;
+
+    Ok(Json(json!({
+        "keys": {
+            "exact": exact,
+            "refresh_requests": refresh_requests,
+            "quarantined": quarantined,
+            "embeddings": embeddings
+        },
+        "total": exact + refresh_requests + quarantined + embeddings
+    })))
+
+}
+
+/// Starts re-embedding the semantic cache into a new collection, or resumes
+/// a paused or failed run over the same collections
+pub async fn admin_start_reembed(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(request): Json<ReembedRequest>,
+) -> Result<(StatusCode, Json<serde_json::Value>), (StatusCode, Json<serde_json::Value>)> {
+
+    require_admin(&state, &headers)?;
+    if state.semantic_cache.is_none() {
+        return Err(tier_disabled("semantic", "SEMANTIC_CACHE_ENABLED"));
+    }
+
+    let status = reembed::start(&state, request)
+        .await
+        .map_err(|(status, e)| (status, Json(json!({"error": e}))))?;
+
+    Ok((StatusCode::ACCEPTED, Json(json!(status))))
+
+}
+
+pub async fn admin_reembed_status(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+
+    require_admin(&state, &headers)?;
+    let status = state.reembed.lock().unwrap().clone();
+    Ok(Json(json!(status)))
+
+}
+
+/// Min/max/mean/p95 of the upstream latency recorded with each semantic
+/// cache entry - what a hit on that entry saved the client
+pub async fn admin_cache_latency_stats(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+
+    require_admin(&state, &headers)?;
+    let Some(semantic_cache) = &state.semantic_cache else {
+        return Err(tier_disabled("semantic", "SEMANTIC_CACHE_ENABLED"));
+    };
+
+    let latencies = semantic_cache.original_latencies().await.map_err(|e| {
+        state.metrics.record_error(ErrorCategory::QdrantError, format!("Qdrant scroll failed: {}", e), None);
+        (StatusCode::BAD_GATEWAY, Json(json!({"error": format!("Qdrant error: {}", e)})))
+    })?;
+
+    Ok(Json(match LatencyStats::from_samples(latencies) {
+        Some(stats) => json!(stats),
+        None => json!({"entries": 0})
+    }))
+
+}
+
+// exported lines buffered ahead of the client, about one SCAN page
+const EXPORT_BUFFER_LINES: usize = 100;
+
+/// One exported exact-tier entry. `response` is written through as the raw
+/// cached JSON rather than parsed into a tree and re-serialized
+#[derive(serde::Serialize)]
+struct ExportLine<'a> {
+    key: &'a str,
+    ttl_secs: i64,
+    response: &'a serde_json::value::RawValue
+}
+
+/// Streams every exact-tier entry as JSONL. Entries are read a SCAN page at
+/// a time and each line is sent as soon as it's written, so memory stays
+/// bounded by the page size however large the cache is
+pub async fn admin_export_cache(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, Json<serde_json::Value>)> {
+
+    require_admin(&state, &headers)?;
+    let redis_cache = state.exact_cache.clone().ok_or_else(|| tier_disabled("exact", "EXACT_CACHE_ENABLED"))?;
+
+    let (tx, rx) = tokio::sync::mpsc::channel::<Result<Bytes, std::io::Error>>(EXPORT_BUFFER_LINES);
+    let metrics = state.metrics.clone();
+    let pattern = format!("{}*", state.config.exact_key_prefix());
+
+    tokio::spawn(async move {
+        let mut cursor = 0;
+        loop {
+            let page = match redis_cache.scan_page(&pattern, cursor).await {
+                Ok((next, keys)) => {
+                    let key_refs: Vec<&str> = keys.iter().map(String::as_str).collect();
+                    redis_cache.get_many_with_ttl(&key_refs).await.map(|entries| (next, keys, entries))
+                }
+                Err(e) => Err(e)
+            };
+            let (next, keys, entries) = match page {
+                Ok(page) => page,
+                Err(e) => {
+                    metrics.record_error(ErrorCategory::RedisError, format!("Export failed: {}", e), None);
+                    // ends the body early so the client sees a truncated export, not a complete one
+                    let _ = tx.send(Err(std::io::Error::other(e))).await;
+                    return;
+                }
+            };
+
+            // keys that expired between SCAN and MGET are skipped
+            for (key, (value, ttl_secs)) in keys.iter().zip(entries).filter_map(|(key, entry)| Some((key, entry?))) {
+                let Ok(response) = serde_json::from_str::<&serde_json::value::RawValue>(&value) else {
+                    tracing::warn!(%key, "export: skipping entry - cached value isn't JSON");
+                    continue;
+                };
+                let mut line = Vec::with_capacity(key.len() + value.len() + 48);
+                if serde_json::to_writer(&mut line, &ExportLine { key, ttl_secs, response }).is_err() {
+                    continue;
+                }
+                line.push(b'\n');
+                // the client went away
+                if tx.send(Ok(Bytes::from(line))).await.is_err() {
+                    return;
+                }
+            }
+
+            cursor = next;
+            if cursor == 0 {
+                break;
+            }
+        }
+    });
+
+    Ok((
+        [(header::CONTENT_TYPE, "application/x-ndjson")],
+        Body::from_stream(ReceiverStream::new(rx))
+    ).into_response())
+
+}
+
+/// Looks up one exact-match entry with its remaining TTL
+pub async fn admin_inspect_cache_key(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(key): Path<String>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+
+    require_admin(&state, &headers)?;
+
+    let redis_cache = state.exact_cache.as_ref().ok_or_else(|| tier_disabled("exact", "EXACT_CACHE_ENABLED"))?;
+    let entry = redis_cache.get_with_ttl(&key)
+        .await
+        .map_err(|e| redis_error_response(&state, e))?;
+
+    if entry.is_none() {
+        return Err((StatusCode::NOT_FOUND, Json(json!({"error": "Key not found", "key": key}))));
+    }
+
+    Ok(Json(inspect_entry(&key, entry)))
+
+}
+
+#[derive(Deserialize)]
+pub struct InspectQuery {
+    // comma-separated cache keys
+    keys: String
+}
+
+/// Looks up several exact-match entries in a single Redis round trip
+pub async fn admin_inspect_cache(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<InspectQuery>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+
+    require_admin(&state, &headers)?;
+    let redis_cache = state.exact_cache.as_ref().ok_or_else(|| tier_disabled("exact", "EXACT_CACHE_ENABLED"))?;
+
+    let keys: Vec<&str> = query.keys
+        .split(',')
+        .map(str::trim)
+        .filter(|key| !key.is_empty())
+        .collect();
+
+    let entries = redis_cache.get_many_with_ttl(&keys)
+        .await
+        .map_err(|e| redis_error_response(&state, e))?;
+
+    let entries: Vec<serde_json::Value> = keys.iter()
+        .zip(entries)
+        .map(|(key, entry)| inspect_entry(key, entry))
+        .collect();
+
+    Ok(Json(json!({
+        "count": entries.len(),
+        "entries": entries
+    })))
+
+}
+
+// what the listing shows for one exact-match entry, without the response itself
+fn entry_summary(state: &AppState, key: &str, value: &str, ttl_secs: i64) -> serde_json::Value {
+
+    // the upstream's `created`, which is when the answer was generated and cached
+    let response = serde_json::from_str::<LLMResponse>(value).ok();
+    let created_at = response.as_ref()
+        .filter(|response| response.created > 0)
+        .and_then(|response| chrono::DateTime::from_timestamp(response.created, 0))
+        .map(|created| created.to_rfc3339());
+
+    json!({
+        "key": key,
+        // -1 means the key never expires
+        "ttl_secs": ttl_secs,
+        "size_bytes": value.len(),
+        // since startup, while the key is among the HOT_KEY_TRACKER_SIZE tracked
+        "hits": state.hot_keys.hits(key),
+        "created_at": created_at,
+        "model": response.map(|response| response.model)
+    })
+
+}
+
+#[derive(Deserialize)]
+pub struct EntriesQuery {
+    // from the previous page's next_cursor; omitted for the first page
+    cursor: Option<u64>
+}
+
+/// Lists exact-match entries a page (about 100 keys) at a time, with each
+/// entry's TTL, size, hit count and creation time
+pub async fn admin_list_entries(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<EntriesQuery>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+
+    require_admin(&state, &headers)?;
+    let redis_cache = state.exact_cache.as_ref().ok_or_else(|| tier_disabled("exact", "EXACT_CACHE_ENABLED"))?;
+
+    let pattern = format!("{}*", state.config.exact_key_prefix());
+    let (next, keys) = redis_cache.scan_page(&pattern, query.cursor.unwrap_or(0))
+        .await
+        .map_err(|e| redis_error_response(&state, e))?;
+
+    let key_refs: Vec<&str> = keys.iter().map(String::as_str).collect();
+    let values = redis_cache.get_many_with_ttl(&key_refs)
+        .await
+        .map_err(|e| redis_error_response(&state, e))?;
+
+    // keys that expired since the scan are left out
+    let entries: Vec<serde_json::Value> = keys.iter()
+        .zip(values)
+        .filter_map(|(key, entry)| entry.map(|(value, ttl)| entry_summary(&state, key, &value, ttl)))
+        .collect();
+
+    Ok(Json(json!({
+        "count": entries.len(),
+        "entries": entries,
+        // null once every key has been listed
+        "next_cursor": (next != 0).then_some(next)
+    })))
+
+}
+
+/// One exact-match entry: the listing's fields plus the stored response
+pub async fn admin_get_entry(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(key): Path<String>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+
+    require_admin(&state, &headers)?;
+    let redis_cache = state.exact_cache.as_ref().ok_or_else(|| tier_disabled("exact", "EXACT_CACHE_ENABLED"))?;
+
+    let Some((value, ttl)) = redis_cache.get_with_ttl(&key)
+        .await
+        .map_err(|e| redis_error_response(&state, e))? else {
+        return Err((StatusCode::NOT_FOUND, Json(json!({"error": "Key not found", "key": key}))));
+    };
+
+    let mut entry = entry_summary(&state, &key, &value, ttl);
+    entry["response"] = serde_json::from_str::<serde_json::Value>(&value).unwrap_or(json!(value));
+    Ok(Json(entry))
+
+}
+
+// characters of the cached answer shown per hot key
+const HOT_KEY_PREVIEW_CHARS: usize = 100;
+
+#[derive(Deserialize)]
+pub struct HotKeysQuery {
+    limit: Option<usize>
+}
+
+/// The most hit exact-match keys, with the TTL left on each and the start of its answer
+pub async fn admin_hot_keys(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<HotKeysQuery>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+
+    require_admin(&state, &headers)?;
+    let redis_cache = state.exact_cache.as_ref().ok_or_else(|| tier_disabled("exact", "EXACT_CACHE_ENABLED"))?;
+
+    let top = state.hot_keys.top(query.limit.unwrap_or(20).min(1000));
+    let keys: Vec<&str> = top.iter().map(|(key, _)| key.as_str()).collect();
+    let entries = redis_cache.get_many_with_ttl(&keys)
+        .await
+        .map_err(|e| redis_error_response(&state, e))?;
+
+    // a key that has since expired is still listed, with null TTL and preview
+    let hot: Vec<serde_json::Value> = top.iter()
+        .zip(entries)
+        .map(|((key, hits), entry)| {
+            let (ttl_secs, preview) = match entry {
+                Some((value, ttl)) => (Some(ttl), Some(response_preview(&value))),
+                None => (None, None)
+            };
+            json!({"key": key, "hits": hits, "ttl_secs": ttl_secs, "response_preview": preview})
+        })
+        .collect();
+
+    Ok(Json(json!({
+        "tracked": state.hot_keys.len(),
+        "count": hot.len(),
+        "keys": hot
+    })))
+
+}
+
+fn response_preview(cached: &str) -> String {
+
+    let text = serde_json::from_str::<LLMResponse>(cached)
+        .map(|response| response_text(&response))
+        .unwrap_or_else(|_| cached.to_string());
+    text.chars().take(HOT_KEY_PREVIEW_CHARS).collect()
+
+}
+
+#[derive(Deserialize)]
+pub struct InvalidateQuery {
+    // "delete" (default) or "quarantine"
+    mode: Option<String>,
+    reason: Option<String>
+}
+
+/// Invalidates one entry in every enabled tier. Hard delete by default; with
+/// `?mode=quarantine` the entry is kept for analysis but never served
+pub async fn admin_invalidate_cache_key(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(key): Path<String>,
+    Query(query): Query<InvalidateQuery>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+
+    require_admin(&state, &headers)?;
+    invalidate_entry(&state, key, &query).await
+
+}
+
+/// Like `admin_invalidate_cache_key`, with the key derived from the original
+/// request body the way the completion route derives it
+pub async fn admin_invalidate_by_request(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<InvalidateQuery>,
+    Json(mut request): Json<LLMRequest>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+
+    require_admin(&state, &headers)?;
+
+    // keyed under the canonical model name, like the completion route
+    request.model = state.config.models.resolve(&request.model)
+        .map_err(|e| (StatusCode::BAD_REQUEST, Json(json!({"error": e}))))?;
+    let key = generate_cache_key(&request, &state.config.key_normalization, &state.cache_key_prefix());
+    invalidate_entry(&state, key, &query).await
+
+}
+
+async fn invalidate_entry(
+    state: &AppState,
+    key: String,
+    query: &InvalidateQuery
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+
+    let quarantine = match query.mode.as_deref() {
+        None | Some("delete") => false,
+        Some("quarantine") => true,
+        Some(other) => return Err((
+            StatusCode::BAD_REQUEST,
+            Json(json!({"error": format!("Unknown mode '{}', expected delete or quarantine", other)}))
+        ))
+    };
+    let reason = query.reason.as_deref();
+
+    let (redis_result, qdrant_result) = if quarantine {
+        tokio::join!(
+            if_enabled(&state.exact_cache, |redis| redis.quarantine(&key, reason, state.config.quarantine_ttl_secs)),
+            if_enabled(&state.semantic_cache, |qdrant| qdrant.quarantine_by_cache_key(&key, reason))
+        )
+    } else {
+        tokio::join!(
+            if_enabled(&state.exact_cache, |redis| redis.delete(&key)),
+            if_enabled(&state.semantic_cache, |qdrant| qdrant.delete_by_cache_key(&key))
+        )
+    };
+
+    let found = redis_result.map_err(|e| redis_error_response(state, e))?;
+    if let Some(tier0) = &state.tier0_cache {
+        tier0.remove(&key);
+    }
+    state.hot_keys.remove(&key);
+    qdrant_result.map_err(|e| qdrant_error_response(state, e))?;
+
+    let mode = if quarantine { "quarantine" } else { "delete" };
+    tracing::info!("Admin: invalidated {} ({}{})", key, mode, reason.map(|r| format!(", reason: {}", r)).unwrap_or_default());
+
+    Ok(Json(json!({
+        "status": "success",
+        "key": key,
+        "mode": mode,
+        "found_in_redis": found
+    })))
+
+}
+
+/// Lists quarantined entries from every enabled tier
+pub async fn admin_list_quarantine(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+
+    require_admin(&state, &headers)?;
+
+    let (redis, qdrant) = tokio::join!(
+        if_enabled(&state.exact_cache, |redis| redis.quarantined()),
+        if_enabled(&state.semantic_cache, |qdrant| qdrant.quarantined(100))
+    );
+
+    let redis = redis.map_err(|e| redis_error_response(&state, e))?;
+    let qdrant = qdrant.map_err(|e| qdrant_error_response(&state, e))?;
+
+    Ok(Json(json!({
+        "redis": redis,
+        "qdrant": qdrant
+    })))
+
+}
+
+/// Hard-deletes everything in quarantine
+pub async fn admin_purge_quarantine(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+
+    require_admin(&state, &headers)?;
+
+    let redis_deleted = if_enabled(&state.exact_cache, |redis| redis.purge_quarantined())
+        .await
+        .map_err(|e| redis_error_response(&state, e))?;
+
+    if_enabled(&state.semantic_cache, |qdrant| qdrant.purge_quarantined())
+        .await
+        .map_err(|e| qdrant_error_response(&state, e))?;
+
+    tracing::info!("Admin: purged quarantine ({} Redis entries)", redis_deleted.unwrap_or(0));
+
+    Ok(Json(json!({
+        "status": "success",
+        "redis_deleted": redis_deleted
+    })))
+
+}
+
+#[derive(Deserialize)]
+pub struct InvalidateModelRequest {
+    model: String
+}
+
+/// Deletes every entry cached for a model from both tiers, for when a model
+/// is upgraded and its old answers no longer hold
+pub async fn admin_invalidate_model(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(request): Json<InvalidateModelRequest>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+
+    require_admin(&state, &headers)?;
+
+    // aliases resolve as on the completion route; a model since dropped from the allowlist still matches
+    let model = state.config.models.canonical(&request.model);
+    if model.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, Json(json!({"error": "model is required"}))));
+    }
+
+    let redis_deleted = match &state.exact_cache {
+        Some(redis_cache) => {
+            // exact-match keys end with :<model>, in every partition. Collected
+            // first, as deleting during the walk would move the cursor
+            let pattern = format!("{}*", state.config.exact_key_prefix());
+            let suffix = format!(":{}", model);
+            let mut keys = Vec::new();
+            let mut cursor = 0;
+            loop {
+                let (next, page) = redis_cache.scan_page(&pattern, cursor).await
+                    .map_err(|e| redis_error_response(&state, e))?;
+                keys.extend(page.into_iter().filter(|key| key.ends_with(&suffix)));
+                cursor = next;
+                if cursor == 0 {
+                    break;
+                }
+            }
+
+            let mut deleted = 0;
+            for key in &keys {
+                if redis_cache.delete(key).await.map_err(|e| redis_error_response(&state, e))? {
+                    deleted += 1;
+                }
+                if let Some(tier0) = &state.tier0_cache {
+                    tier0.remove(key);
+                }
+                state.hot_keys.remove(key);
+            }
+            Some(deleted)
+        }
+        None => None
+    };
+
+    if_enabled(&state.semantic_cache, |qdrant| qdrant.delete_by_model(&model))
+        .await
+        .map_err(|e| qdrant_error_response(&state, e))?;
+
+    tracing::info!("Admin: invalidated model {} ({} Redis entries)", model, redis_deleted.unwrap_or(0));
+
+    Ok(Json(json!({
+        "status": "success",
+        "model": model,
+        "redis_deleted": redis_deleted
+    })))
+
+}
+
+// most points one semantic purge deletes
+const PURGE_MAX_MATCHES: usize = 1000;
+
+#[derive(Deserialize)]
+pub struct SemanticPurgeRequest {
+    query: String,
+    // SEMANTIC_THRESHOLD when not given
+    threshold: Option<f32>,
+    // list what would be deleted without deleting it
+    #[serde(default)]
+    dry_run: bool
+}
+
+/// Deletes every semantic point at least `threshold` similar to a
+/// natural-language query, along with the exact-match entries they were
+/// stored with. For purging answers about a topic that has changed
+pub async fn admin_purge_similar(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(request): Json<SemanticPurgeRequest>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+
+    require_admin(&state, &headers)?;
+    let semantic_cache = state.semantic_cache.as_ref().ok_or_else(|| tier_disabled("semantic", "SEMANTIC_CACHE_ENABLED"))?;
+
+    let threshold = request.threshold.unwrap_or(state.runtime.load().semantic_threshold);
+    if !(0.0..=1.0).contains(&threshold) {
+        return Err((StatusCode::BAD_REQUEST, Json(json!({"error": "threshold must be a number from 0.0 to 1.0"}))));
+    }
+    if request.query.trim().is_empty() {
+        return Err((StatusCode::BAD_REQUEST, Json(json!({"error": "query is required"}))));
+    }
+
+    // embedded the way `prompt_text` renders a single user message, so it lands near those points
+    let embedding = get_embedding(&state.http_client, &state.config.embedding, &format!("user: {}", request.query)).await
+        .map_err(|e| {
+            state.metrics.record_error(ErrorCategory::EmbeddingError, format!("Purge embedding failed: {}", e), None);
+            (StatusCode::BAD_GATEWAY, Json(json!({"error": format!("Embedding failed: {}", e)})))
+        })?;
+
+    // every model's points, quarantined ones aside
+    let hits = semantic_cache.search_paginated(embedding, threshold, PURGE_MAX_MATCHES, None, None).await
+        .map_err(|e| qdrant_error_response(&state, e))?;
+
+    let matches: Vec<serde_json::Value> = hits.iter()
+        .map(|hit| json!({
+            "cache_key": hit.cache_key,
+            "score": hit.score,
+            "model": hit.model,
+            "response_preview": response_preview(&hit.response)
+        }))
+        .collect();
+
+    if !request.dry_run {
+        let point_ids: Vec<String> = hits.iter().map(|hit| hit.point_id.clone()).collect();
+        semantic_cache.delete_points(&point_ids).await
+            .map_err(|e| qdrant_error_response(&state, e))?;
+
+        let mut keys: Vec<&str> = hits.iter().map(|hit| hit.cache_key.as_str()).filter(|key| !key.is_empty()).collect();
+        keys.sort_unstable();
+        keys.dedup();
+        for key in keys {
+            if let Some(redis_cache) = &state.exact_cache {
+                redis_cache.delete(key).await.map_err(|e| redis_error_response(&state, e))?;
+            }
+            if let Some(tier0) = &state.tier0_cache {
+                tier0.remove(key);
+            }
+            state.hot_keys.remove(key);
+        }
+
+        tracing::info!("Admin: purged {} semantic points similar to '{}' (threshold {})", hits.len(), request.query, threshold);
+    }
+
+    Ok(Json(json!({
+        "status": "success",
+        "dry_run": request.dry_run,
+        "threshold": threshold,
+        "matched": matches.len(),
+        // the limit was hit; run the purge again for the rest
+        "truncated": matches.len() == PURGE_MAX_MATCHES,
+        "matches": matches
+    })))
+
+}
+
+#[derive(Deserialize)]
+pub struct CopyKeyRequest {
+    source_key: String,
+    dest_key: String,
+    // give the copy a fresh DEFAULT_TTL_SECS instead of the source's remaining TTL
+    #[serde(default)]
+    reset_ttl: bool,
+    // overwrite an existing dest_key
+    #[serde(default)]
+    replace: bool
+}
+
+/// Copies one Redis entry to a new key, e.g. into the next namespace of a blue-green deploy
+pub async fn admin_copy_key(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(request): Json<CopyKeyRequest>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+
+    require_admin(&state, &headers)?;
+    let redis_cache = state.exact_cache.as_ref().ok_or_else(|| tier_disabled("exact", "EXACT_CACHE_ENABLED"))?;
+
+    if request.source_key == request.dest_key {
+        return Err((StatusCode::BAD_REQUEST, Json(json!({"error": "source_key and dest_key must differ"}))));
+    }
+
+    let reset_ttl = request.reset_ttl.then(|| state.runtime.load().default_ttl_secs);
+    let outcome = redis_cache.copy_key(&request.source_key, &request.dest_key, reset_ttl, request.replace)
+        .await
+        .map_err(|e| redis_error_response(&state, e))?;
+
+    tracing::info!("Admin: copied {} to {}: {}", request.source_key, request.dest_key, outcome.copied);
+    Ok(Json(json!(outcome)))
+
+}
+
+#[derive(Deserialize)]
+pub struct BulkCopyRequest {
+    // both end in a single `*`: "cache:v1:exact:*" -> "cache:v2:exact:*"
+    source_pattern: String,
+    dest_pattern: String,
+    #[serde(default)]
+    reset_ttl: bool,
+    #[serde(default)]
+    replace: bool
+}
+
+// the part of a pattern before its only wildcard, which must be the last character
+fn pattern_prefix(pattern: &str) -> Option<&str> {
+    pattern.strip_suffix('*').filter(|prefix| !prefix.contains(['*', '?', '[']))
+}
+
+/// Copies every key under one prefix to the same key under another. SCAN can
+/// return a key twice, so `scanned` may count some keys more than once
+pub async fn admin_copy_keys_bulk(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(request): Json<BulkCopyRequest>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+
+    require_admin(&state, &headers)?;
+    let redis_cache = state.exact_cache.as_ref().ok_or_else(|| tier_disabled("exact", "EXACT_CACHE_ENABLED"))?;
+
+    let (Some(source_prefix), Some(dest_prefix)) = (pattern_prefix(&request.source_pattern), pattern_prefix(&request.dest_pattern)) else {
+        return Err((StatusCode::BAD_REQUEST, Json(json!({"error": "Patterns must end in a single trailing *, e.g. cache:v1:exact:*"}))));
+    };
+    // copies landing inside the source namespace would be picked up by the scan again
+    if dest_prefix.starts_with(source_prefix) {
+        return Err((StatusCode::BAD_REQUEST, Json(json!({"error": "dest_pattern must not fall inside source_pattern"}))));
+    }
+
+    let reset_ttl = request.reset_ttl.then(|| state.runtime.load().default_ttl_secs);
+    let report = migrate::copy_prefix(redis_cache, source_prefix, dest_prefix, reset_ttl, request.replace)
+        .await
+        .map_err(|e| redis_error_response(&state, e))?;
+
+    tracing::info!("Admin: bulk copy {} -> {}: {} of {} copied", request.source_pattern, request.dest_pattern, report.copied, report.scanned);
+    Ok(Json(json!({
+        "scanned": report.scanned,
+        "copied": report.copied,
+        "skipped": report.scanned - report.copied
+    })))
+
+}
+
+#[derive(Deserialize)]
+pub struct ClearQuery {
+    // "redis", "qdrant" or "all" (default)
+    tier: Option<String>
+}
+
+/// Flushes Redis and drops and recreates the Qdrant collection. `?tier=`
+/// limits it to one; with `all`, a disabled tier is skipped
+pub async fn admin_clear_cache(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<ClearQuery>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+
+    require_admin(&state, &headers)?;
+
+    let (redis, qdrant) = match query.tier.as_deref() {
+        None | Some("all") => (state.exact_cache.as_ref(), state.semantic_cache.as_ref()),
+        Some("redis") => (Some(state.exact_cache.as_ref().ok_or_else(|| tier_disabled("exact", "EXACT_CACHE_ENABLED"))?), None),
+        Some("qdrant") => (None, Some(state.semantic_cache.as_ref().ok_or_else(|| tier_disabled("semantic", "SEMANTIC_CACHE_ENABLED"))?)),
+        Some(other) => return Err((
+            StatusCode::BAD_REQUEST,
+            Json(json!({"error": format!("Unknown tier '{}', expected redis, qdrant or all", other)}))
+        ))
+    };
+    if redis.is_none() && qdrant.is_none() {
+        return Err(tier_disabled("exact", "EXACT_CACHE_ENABLED"));
+    }
+
+    let mut cleared = Vec::new();
+
+    if let Some(redis_cache) = redis {
+        redis_cache.flush_all()
+            .await
+            .map_err(|e| (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({"error": format!("Failed to flush Redis: {}", e)}))
+            ))?;
+
+        if let Some(tier0) = &state.tier0_cache {
+            tier0.clear();
+        }
+        state.hot_keys.clear();
+        // FLUSHDB took the saved metrics snapshot with it
+        if state.config.metrics_persist == MetricsPersist::Redis {
+            crate::metrics_store::save_or_record(&state).await;
+        }
+        cleared.push("redis");
+    }
+
+    if let Some(semantic_cache) = qdrant {
+        semantic_cache.recreate_collection()
+            .await
+            .map_err(|e| (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({"error": format!("Failed to recreate the Qdrant collection: {}", e), "cleared": cleared}))
+            ))?;
+        cleared.push("qdrant");
+    }
+
+    tracing::info!("Admin: cache cleared ({})", cleared.join(", "));
+
+    Ok(Json(json!({
+        "status": "success",
+        "cleared": cleared,
+        "message": format!("Cleared {}", cleared.join(" and "))
+    })))
+
+}
+
+/// The admin token sent as `x-admin-token` or `Authorization: Bearer <token>`, if any
+pub fn provided_admin_token(headers: &HeaderMap) -> Option<&str> {
+
+    headers
+        .get("x-admin-token")
+        .and_then(|v| v.to_str().ok())
+        .or_else(|| headers
+            .get("authorization")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer ")))
+
+}
+
+/// Checks the admin token sent as `Authorization: Bearer <token>` or `x-admin-token`,
+/// returning its masked form to identify the caller in logs
+pub fn require_admin(state: &AppState, headers: &HeaderMap) -> Result<String, (StatusCode, Json<serde_json::Value>)> {
+
+    let Some(expected) = state.config.admin_token.as_deref() else {
+        return Err((
+            StatusCode::FORBIDDEN,
+            Json(json!({"error": "Admin token not configured. Set ADMIN_TOKEN to enable this endpoint"}))
+        ));
+    };
+
+    if provided_admin_token(headers) != Some(expected) {
+        return Err((
+            StatusCode::UNAUTHORIZED,
+            Json(json!({"error": "Invalid or missing admin token"}))
+        ));
+    }
+
+    Ok(format!("admin token {}", mask_secret(expected)))
+
+}
+
+pub async fn admin_config(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+
+    require_admin(&state, &headers)?;
+
+    Ok(Json(state.config.redacted(&state.runtime.load())))
+
+}
+
+/// Applies a JSON patch to the runtime-mutable settings
+pub async fn admin_update_config(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(patch): Json<serde_json::Value>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+
+    let actor = require_admin(&state, &headers)?;
+
+    let updated = state.runtime.load()
+        .apply_patch(&patch)
+        .map_err(|e| (StatusCode::BAD_REQUEST, Json(json!({"error": e}))))?;
+
+    let changes = state.update_runtime(updated, &actor);
+
+    Ok(Json(json!({
+        "status": "success",
+        "changes": changes,
+        "cache": state.config.redacted(&state.runtime.load())["cache"]
+    })))
+
+}
+
+#[derive(Deserialize)]
+pub struct AuditQuery {
+    limit: Option<usize>
+}
+
+pub async fn admin_audit(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<AuditQuery>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+
+    require_admin(&state, &headers)?;
+
+    let limit = query.limit.unwrap_or(100).min(1000);
+    let entries = state.audit_log.recent(limit).await;
+
+    Ok(Json(json!({
+        "count": entries.len(),
+        "entries": entries
+    })))
+
+}
+
+/// Any `/admin/*` path without a route. Answered here rather than by the
+/// passthrough route, which would forward it upstream with the proxy's key
+pub async fn admin_not_found(uri: Uri) -> (StatusCode, Json<serde_json::Value>) {
+    (StatusCode::NOT_FOUND, Json(json!({"error": "Unknown admin endpoint", "path": uri.path()})))
+}
+
+#[derive(Deserialize)]
+pub struct RequestsQuery {
+    model: Option<String>,
+    cache_status: Option<String>,
+    tenant: Option<String>,
+    api_key: Option<String>,
+    // an RFC 3339 time, or a span back from now like "7d"
+    from: Option<String>,
+    to: Option<String>,
+    // tenant, model, cache_status, api_key or day
+    group_by: Option<String>,
+    limit: Option<usize>
+}
+
+/// Requests from the request store (REQUEST_STORE_URL) matching the filters,
+/// with their totals. With `group_by`, totals per group replace the list
+pub async fn admin_requests(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<RequestsQuery>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+
+    require_admin(&state, &headers)?;
+    let store = state.request_store.as_ref().ok_or_else(|| (
+        StatusCode::CONFLICT,
+        Json(json!({"error": "The request store is disabled (REQUEST_STORE_URL is unset)"}))
+    ))?;
+
+    let bad_request = |message: String| (StatusCode::BAD_REQUEST, Json(json!({"error": message})));
+    let time = |name: &str, value: Option<&str>| match value {
+        None => Ok(None),
+        Some(value) => chrono::DateTime::parse_from_rfc3339(value)
+            .map(|at| at.with_timezone(&chrono::Utc))
+            .ok()
+            .or_else(|| history::parse_span(value).and_then(|span| chrono::Duration::from_std(span).ok()).map(|span| chrono::Utc::now() - span))
+            .map(Some)
+            .ok_or_else(|| bad_request(format!("{} must be an RFC 3339 time or a span like 7d, got '{}'", name, value)))
+    };
+    let group_by = match query.group_by.as_deref() {
+        Some(value) => Some(GroupBy::parse(value)
+            .ok_or_else(|| bad_request(format!("Unknown group_by '{}': use tenant, model, cache_status, api_key or day", value)))?),
+        None => None
+    };
+
+    let filter = RequestFilter {
+        from: time("from", query.from.as_deref())?,
+        to: time("to", query.to.as_deref())?,
+        model: query.model,
+        cache_status: query.cache_status.map(|status| status.to_uppercase()),
+        tenant: query.tenant,
+        api_key: query.api_key
+    };
+    let store_error = |e: sqlx::Error| (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(json!({"error": format!("Request store query failed: {}", e)}))
+    );
+
+    let totals = store.totals(&filter, None).await.map_err(store_error)?.pop().unwrap_or_default();
+    let mut body = json!({
+        "from": filter.from.map(|at| at.to_rfc3339()),
+        "to": filter.to.map(|at| at.to_rfc3339()),
+        "totals": totals
+    });
+    match group_by {
+        Some(group_by) => {
+            body["group_by"] = json!(group_by.as_str());
+            body["groups"] = json!(store.totals(&filter, Some(group_by)).await.map_err(store_error)?);
+        }
+        None => {
+            let requests = store.list(&filter, query.limit.unwrap_or(100)).await.map_err(store_error)?;
+            body["count"] = json!(requests.len());
+            body["requests"] = json!(requests);
+        }
+    }
+    Ok(Json(body))
+
+}
+
+pub async fn admin_errors(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+
+    require_admin(&state, &headers)?;
+
+    let error_counts = state.metrics.error_counts();
+    let total: u64 = error_counts.iter().map(|(_, count)| count).sum();
+
+    let counts: serde_json::Map<String, serde_json::Value> = error_counts
+        .into_iter()
+        .map(|(category, count)| (category.to_string(), json!(count)))
+        .collect();
+
+    Ok(Json(json!({
+        "total": total,
+        "counts": counts,
+        "recent": state.metrics.recent_errors()
+    })))
+
+}
+
+// how long /admin/stats reuses the Redis and Qdrant size figures
+const STORAGE_STATS_TTL: Duration = Duration::from_secs(10);
+
+/// Redis and Qdrant size figures, cached briefly so the admin page doesn't
+/// run SCAN and INFO on every refresh
+async fn storage_stats(state: &AppState) -> serde_json::Value {
+
+    if let Some((measured_at, stats)) = state.storage_stats.lock().unwrap().as_ref()
+        && measured_at.elapsed() < STORAGE_STATS_TTL {
+        return stats.clone();
+    }
+
+    let exact_prefix = state.config.exact_key_prefix();
+    let (redis, qdrant) = tokio::join!(
+        if_enabled(&state.exact_cache, |redis| redis.info(&exact_prefix)),
+        if_enabled(&state.semantic_cache, |qdrant| qdrant.usage())
+    );
+
+    let stats = json!({
+        "measured_at": Utc::now().to_rfc3339(),
+        "redis": match redis {
+            Ok(Some(info)) => json!(info),
+            Ok(None) => json!("disabled"),
+            Err(e) => json!({"error": e.to_string()})
+        },
+        "qdrant": match qdrant {
+            Ok(Some(usage)) => json!(usage),
+            Ok(None) => json!("disabled"),
+            Err(e) => json!({"error": e.to_string()})
+        }
+    });
+
+    *state.storage_stats.lock().unwrap() = Some((std::time::Instant::now(), stats.clone()));
+    stats
+
+}
+
+pub async fn admin_stats(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+
+    require_admin(&state, &headers)?;
+
+    let (redis_up, qdrant_up, embeddings_up) = check_services(&state).await;
+
+    let snapshot = state.metrics.snapshot();
+    let storage = storage_stats(&state).await;
+
+    let upstream_host = reqwest::Url::parse(&state.config.upstream_base_url)
+        .ok()
+        .and_then(|url| url.host_str().map(|h| h.to_string()));
+
+    Ok(Json(json!({
+        "upstream": {
+            "provider": state.config.provider.name(),
+            "host": upstream_host
+        },
+        "cache_stats": {
+            "tier0_hits": state.tier0_cache.is_some().then_some(snapshot.tier0_hits),
+            "exact_hits": state.exact_cache.is_some().then_some(snapshot.exact_hits),
+            "semantic_hits": state.semantic_cache.is_some().then_some(snapshot.semantic_hits),
+            "misses": snapshot.misses,
+            "total_requests": snapshot.total_requests,
+            "hit_rate": (state.exact_cache.is_some() || state.semantic_cache.is_some()).then(|| snapshot.cache_hit_rate())
+        },
+        "storage": storage,
+        "qdrant_pool": state.semantic_cache.as_ref().map(|qdrant| qdrant.pool_stats()),
+        "services": {
+            "redis":      service_label(redis_up),
+            "qdrant":     service_label(qdrant_up),
+            "embeddings": service_label(embeddings_up)
+        }
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::test_helpers::test_llm_response;
+
+    #[test]
+    fn test_response_text() {
+
+        assert_eq!(response_text(&test_llm_response()), "Rust is a systems programming language.");
+
+    }
+
+    #[test]
+    fn test_calculate_cost_uses_model_pricing() {
+
+        let response = test_llm_response();
+        let cost = calculate_cost(&response.model, response.usage.total_tokens as u64);
+
+        assert!(cost > 0.0, "Unknown models should fall back to default pricing");
+
+    }
+
+    #[test]
+    fn test_parse_prefill_body_reports_line_and_field() {
+
+        let wrapped = r#"{"prompts": [{"model": "m", "messages": []}]}"#;
+        assert_eq!(parse_prefill_body(wrapped).unwrap().len(), 1);
+
+        let jsonl = "{\"model\": \"m\", \"messages\": []}\n\n{\"model\": \"m\", \"messages\": [], \"temperature\": \"hot\"}\n";
+        let err = parse_prefill_body(jsonl).unwrap_err();
+        assert_eq!(err, "line 3: field `temperature`: expected a number, got a string");
+
+        let err = parse_prefill_body(r#"{"prompts": [{"messages": []}]}"#).unwrap_err();
+        assert_eq!(err, "prompt 0: field `model`: missing");
+
+        let err = parse_prefill_body(r#"{"prompts": [{"model": "m", "messages": [{"role": "user", "content": " "}]}]}"#).unwrap_err();
+        assert_eq!(err, "prompt 0: messages[0]: content is empty");
+
+    }
+
+    #[cfg(feature = "mock")]
+    #[tokio::test]
+    async fn test_proxy_handler_rejects_invalid_messages() {
+
+        use crate::config::Config;
+        use crate::models::Message;
+        use crate::test_helpers::{test_llm_request, user_message};
+
+        let state = AppState::new(Config::from_lookup(|_| None).unwrap()).await;
+        let assistant = Message { role: "assistant".to_string(), content: "Rust is a language".into(), ..Default::default() };
+
+        let cases = [
+            (vec![user_message("What is Rust?"), user_message("")], "empty_message_content"),
+            (vec![user_message("What is Rust?"), assistant], "trailing_assistant_message")
+        ];
+        for (messages, expected_code) in cases {
+            let request = LLMRequest { messages, ..test_llm_request() };
+            let (status, Json(body)) = proxy_handler(State(state.clone()), HeaderMap::new(), Json(request)).await.unwrap_err();
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+            assert_eq!(body.error.r#type, "invalid_request_error");
+            assert_eq!(body.error.code.as_deref(), Some(expected_code));
+            assert_eq!(body.error.param.as_deref(), Some("messages[1]"));
+        }
+        assert_eq!(state.metrics.snapshot().misses, 0);
+
+    }
+
+    // a local stub stands in for the upstream; both cache tiers are off so nothing else is needed
+    #[cfg(not(feature = "mock"))]
+    #[tokio::test]
+    async fn test_unparseable_upstream_response_is_a_502() {
+
+        use crate::config::Config;
+        use crate::test_helpers::test_llm_request;
+
+        let bodies = [
+            (r#"{"id": "1", "choices": [{"delta": {"content": "Hi"}, "index": 0}]}"#, "choices[0]"),
+            (r#"{"id": "1", "created": "yesterday", "choices": []}"#, "created"),
+            ("<html>Bad Gateway</html>", ".")
+        ];
+
+        for (body, expected_path) in bodies {
+            let upstream = axum::Router::new().route("/chat/completions", axum::routing::post(move || async move {
+                ([(header::CONTENT_TYPE, "application/json")], body)
+            }));
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let base_url = format!("http://{}", listener.local_addr().unwrap());
+            tokio::spawn(async move { axum::serve(listener, upstream).await });
+
+            let config = Config::from_lookup(|name| match name {
+                "GROQ_API_KEY" => Some("test-key".to_string()),
+                "UPSTREAM_BASE_URL" => Some(base_url.clone()),
+                "EXACT_CACHE_ENABLED" | "SEMANTIC_CACHE_ENABLED" => Some("false".to_string()),
+                _ => None
+            }).unwrap();
+            let state = AppState::new(config).await;
+
+            let (status, Json(body)) = proxy_handler(State(state.clone()), HeaderMap::new(), Json(test_llm_request())).await.unwrap_err();
+            assert_eq!(status, StatusCode::BAD_GATEWAY);
+            assert_eq!(body.error.r#type, "upstream_error");
+            assert_eq!(body.error.code.as_deref(), Some("upstream_invalid_response"));
+            assert_eq!(body.error.param.as_deref(), Some(expected_path));
+            assert_eq!(state.metrics.snapshot().upstream_parse_errors, 1);
+        }
+
+    }
+
+    #[cfg(not(feature = "mock"))]
+    #[tokio::test]
+    async fn test_upstream_errors_keep_their_status_and_body() {
+
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use crate::config::Config;
+        use crate::test_helpers::test_llm_request;
+
+        let upstream_error = json!({"error": {
+            "message": "Rate limit reached for model llama-3.3-70b-versatile",
+            "type": "tokens",
+            "code": "rate_limit_exceeded"
+        }});
+        let calls = Arc::new(AtomicUsize::new(0));
+        let upstream = {
+            let (calls, upstream_error) = (calls.clone(), upstream_error.clone());
+            axum::Router::new().route("/chat/completions", axum::routing::post(move || async move {
+                calls.fetch_add(1, Ordering::SeqCst);
+                (StatusCode::TOO_MANY_REQUESTS, Json(upstream_error))
+            }))
+        };
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, upstream).await });
+
+        let config = Config::from_lookup(|name| match name {
+            "GROQ_API_KEY" => Some("test-key".to_string()),
+            "UPSTREAM_BASE_URL" => Some(base_url.clone()),
+            "SEMANTIC_CACHE_ENABLED" => Some("false".to_string()),
+            "EXACT_CACHE_ENABLED" => Some("false".to_string()),
+            "UPSTREAM_RETRY_MAX_ATTEMPTS" => Some("1".to_string()),
+            _ => None
+        }).unwrap();
+        let state = AppState::new(config).await;
+
+        for _ in 0..2 {
+            let (status, Json(body)) = proxy_handler(State(state.clone()), HeaderMap::new(), Json(test_llm_request())).await.unwrap_err();
+            assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+            assert_eq!(serde_json::to_value(&body).unwrap(), upstream_error);
+        }
+        // errors are never cached, so the repeat went upstream too
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+    }
+
+    #[cfg(not(feature = "mock"))]
+    #[tokio::test]
+    async fn test_transient_upstream_errors_are_retried() {
+
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use crate::config::Config;
+        use crate::test_helpers::{test_llm_request, test_llm_response};
+
+        // unavailable twice, then answers
+        let calls = Arc::new(AtomicUsize::new(0));
+        let upstream = {
+            let calls = calls.clone();
+            axum::Router::new().route("/chat/completions", axum::routing::post(move || async move {
+                match calls.fetch_add(1, Ordering::SeqCst) {
+                    0 => (StatusCode::SERVICE_UNAVAILABLE, "Service Unavailable").into_response(),
+                    1 => (StatusCode::BAD_GATEWAY, "Bad Gateway").into_response(),
+                    _ => Json(test_llm_response()).into_response()
+                }
+            }))
+        };
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, upstream).await });
+
+        let config = Config::from_lookup(|name| match name {
+            "GROQ_API_KEY" => Some("test-key".to_string()),
+            "UPSTREAM_BASE_URL" => Some(base_url.clone()),
+            "SEMANTIC_CACHE_ENABLED" => Some("false".to_string()),
+            "EXACT_CACHE_ENABLED" => Some("false".to_string()),
+            "UPSTREAM_RETRY_BACKOFF_MS" => Some("1".to_string()),
+            _ => None
+        }).unwrap();
+        let state = AppState::new(config).await;
+
+        let (_, Json(response)) = proxy_handler(State(state.clone()), HeaderMap::new(), Json(test_llm_request())).await.unwrap();
+        assert_eq!(response.id, test_llm_response().id);
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        let snapshot = state.metrics.snapshot();
+        assert_eq!((snapshot.upstream_retries, snapshot.upstream_retries_exhausted), (2, 0));
+
+        // the third failure in a row is the last attempt
+        calls.store(0, Ordering::SeqCst);
+        let second = LLMRequest { max_tokens: Some(5), ..test_llm_request() };
+        let mut config = (*state.config).clone();
+        config.retry.max_attempts = 2;
+        let state = AppState { config: Arc::new(config), ..state };
+        let (status, _) = proxy_handler(State(state.clone()), HeaderMap::new(), Json(second)).await.unwrap_err();
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+        assert_eq!(state.metrics.snapshot().upstream_retries_exhausted, 1);
+
+    }
+
+    #[cfg(not(feature = "mock"))]
+    #[tokio::test]
+    async fn test_open_circuit_fails_fast() {
+
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use crate::config::Config;
+        use crate::test_helpers::test_llm_request;
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let upstream = {
+            let calls = calls.clone();
+            axum::Router::new().route("/chat/completions", axum::routing::post(move || async move {
+                calls.fetch_add(1, Ordering::SeqCst);
+                (StatusCode::SERVICE_UNAVAILABLE, "Service Unavailable")
+            }))
+        };
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, upstream).await });
+
+        let config = Config::from_lookup(|name| match name {
+            "GROQ_API_KEY" => Some("test-key".to_string()),
+            "UPSTREAM_BASE_URL" => Some(base_url.clone()),
+            "SEMANTIC_CACHE_ENABLED" => Some("false".to_string()),
+            "EXACT_CACHE_ENABLED" => Some("false".to_string()),
+            "UPSTREAM_RETRY_MAX_ATTEMPTS" => Some("1".to_string()),
+            "CIRCUIT_BREAKER_FAILURE_THRESHOLD" => Some("2".to_string()),
+            _ => None
+        }).unwrap();
+        let state = AppState::new(config).await;
+
+        for _ in 0..2 {
+            let (status, _) = proxy_handler(State(state.clone()), HeaderMap::new(), Json(test_llm_request())).await.unwrap_err();
+            assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        }
+        assert_eq!(state.circuit_breaker.state(), crate::breaker::CircuitState::Open);
+
+        let (status, Json(body)) = proxy_handler(State(state.clone()), HeaderMap::new(), Json(test_llm_request())).await.unwrap_err();
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body.error.code.as_deref(), Some("upstream_circuit_open"));
+        assert_eq!(calls.load(Ordering::SeqCst), 2, "An open circuit doesn't call the upstream");
+
+    }
+
+    #[cfg(not(feature = "mock"))]
+    #[tokio::test]
+    async fn test_expired_entry_is_served_when_the_upstream_fails() {
+
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use crate::config::Config;
+        use crate::test_helpers::{test_llm_request, test_llm_response};
+
+        // answers once, then is down
+        let calls = Arc::new(AtomicUsize::new(0));
+        let upstream = {
+            let calls = calls.clone();
+            axum::Router::new().route("/chat/completions", axum::routing::post(move || async move {
+                match calls.fetch_add(1, Ordering::SeqCst) {
+                    0 => Json(test_llm_response()).into_response(),
+                    _ => (StatusCode::SERVICE_UNAVAILABLE, "Service Unavailable").into_response()
+                }
+            }))
+        };
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, upstream).await });
+
+        let config = Config::from_lookup(|name| match name {
+            "GROQ_API_KEY" => Some("test-key".to_string()),
+            "UPSTREAM_BASE_URL" => Some(base_url.clone()),
+            "SEMANTIC_CACHE_ENABLED" => Some("false".to_string()),
+            "EXACT_CACHE_BACKEND" => Some("memory".to_string()),
+            "TIER0_CACHE_SIZE" => Some("0".to_string()),
+            "CACHE_TTL_SECS" => Some("1".to_string()),
+            "STALE_GRACE_SECS" => Some("60".to_string()),
+            "UPSTREAM_RETRY_MAX_ATTEMPTS" => Some("1".to_string()),
+            _ => None
+        }).unwrap();
+        let state = AppState::new(config).await;
+
+        let (headers, _) = proxy_handler(State(state.clone()), HeaderMap::new(), Json(test_llm_request())).await.unwrap();
+        assert_eq!(headers[X_CACHE_HEADER], "MISS");
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+
+        let (headers, Json(response)) = proxy_handler(State(state.clone()), HeaderMap::new(), Json(test_llm_request())).await.unwrap();
+        assert_eq!(headers[X_CACHE_HEADER], "STALE");
+        assert_eq!(headers[header::WARNING], "111 - \"Revalidation Failed\"");
+        assert_eq!(response.choices[0].message.content.as_text(), test_llm_response().choices[0].message.content.as_text());
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        assert_eq!(state.metrics.snapshot().stale_hits, 1);
+
+        // a request that asks for a fresh answer gets the error
+        let mut bypass = HeaderMap::new();
+        bypass.insert("x-bypass-cache", header::HeaderValue::from_static("true"));
+        let (status, _) = proxy_handler(State(state.clone()), bypass, Json(test_llm_request())).await.unwrap_err();
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+
+    }
+
+    #[cfg(not(feature = "mock"))]
+    #[tokio::test]
+    async fn test_hits_near_expiry_are_revalidated_in_the_background() {
+
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use crate::config::Config;
+        use crate::test_helpers::{test_llm_request, test_llm_response};
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let upstream = {
+            let calls = calls.clone();
+            axum::Router::new().route("/chat/completions", axum::routing::post(move || async move {
+                calls.fetch_add(1, Ordering::SeqCst);
+                Json(test_llm_response())
+            }))
+        };
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, upstream).await });
+
+        // every entry is inside the window from the start
+        let config = Config::from_lookup(|name| match name {
+            "GROQ_API_KEY" => Some("test-key".to_string()),
+            "UPSTREAM_BASE_URL" => Some(base_url.clone()),
+            "SEMANTIC_CACHE_ENABLED" => Some("false".to_string()),
+            "EXACT_CACHE_BACKEND" => Some("memory".to_string()),
+            "TIER0_CACHE_SIZE" => Some("0".to_string()),
+            "CACHE_TTL_SECS" => Some("60".to_string()),
+            "SWR_WINDOW_SECS" => Some("120".to_string()),
+            _ => None
+        }).unwrap();
+        let state = AppState::new(config).await;
+
+        let (headers, _) = proxy_handler(State(state.clone()), HeaderMap::new(), Json(test_llm_request())).await.unwrap();
+        assert_eq!(headers[X_CACHE_HEADER], "MISS");
+        let (headers, _) = proxy_handler(State(state.clone()), HeaderMap::new(), Json(test_llm_request())).await.unwrap();
+        assert_eq!(headers[X_CACHE_HEADER], "EXACT_HIT");
+
+        for _ in 0..50 {
+            if state.metrics.snapshot().revalidations == 1 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(state.metrics.snapshot().revalidations, 1);
+        assert_eq!(calls.load(Ordering::SeqCst), 2, "The hit was re-run upstream once");
+        assert!(state.revalidating.is_empty());
+
+    }
+
+    // runs against the in-memory backends: cargo test --features mock
+    #[cfg(feature = "mock")]
+    #[tokio::test]
+    async fn test_proxy_handler_tier_combinations() {
+
+        use crate::config::Config;
+        use crate::test_helpers::test_llm_request;
+
+        // (exact, semantic) -> (exact hits, semantic hits, misses) after two identical requests
+        let cases = [
+            ((true, true), (1, 0, 1)),
+            ((true, false), (1, 0, 1)),
+            ((false, true), (0, 1, 1)),
+            ((false, false), (0, 0, 2))
+        ];
+
+        for ((exact, semantic), expected) in cases {
+            let config = Config::from_lookup(|name| match name {
+                "EXACT_CACHE_ENABLED" => Some(exact.to_string()),
+                "SEMANTIC_CACHE_ENABLED" => Some(semantic.to_string()),
+                _ => None
+            }).unwrap();
+            let state = AppState::new(config).await;
+
+            assert_eq!(state.exact_cache.is_some(), exact);
+            assert_eq!(state.semantic_cache.is_some(), semantic);
+
+            for _ in 0..2 {
+                let _ = proxy_handler(State(state.clone()), HeaderMap::new(), Json(test_llm_request()))
+                    .await
+                    .unwrap();
+            }
+
+            let snapshot = state.metrics.snapshot();
+            assert_eq!(
+                (snapshot.exact_hits, snapshot.semantic_hits, snapshot.misses), expected,
+                "exact={} semantic={}", exact, semantic
+            );
+
+            // disabled services are reported as such and don't make the proxy unhealthy
+            let response = health_check(State(state.clone())).await.into_response();
+            assert_eq!(response.status(), StatusCode::OK);
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(body["services"]["redis"]["status"], if exact { "up" } else { "disabled" });
+            assert_eq!(body["services"]["embeddings"]["status"], if semantic { "up" } else { "disabled" });
+            assert_eq!(body["services"]["qdrant"]["stats"]["points_count"], if semantic { json!(1) } else { json!(null) });
+
+            let metrics = metrics(State(state), Query(MetricsQuery::default())).await.0;
+            assert_eq!(metrics["cache_performance"]["exact_hits"].is_null(), !exact);
+            assert_eq!(metrics["cache_performance"]["semantic_hits"].is_null(), !semantic);
+        }
+
+    }
+
+    #[cfg(feature = "mock")]
+    #[tokio::test]
+    async fn test_embeddings_are_cached_by_prompt() {
+
+        use crate::config::Config;
+
+        let state = AppState::new(Config::from_lookup(|_| None).unwrap()).await;
+
+        let first = embed(&state, "What is Rust?").await.unwrap();
+        let again = embed(&state, "What is Rust?").await.unwrap();
+        assert_eq!(first, again);
+        let _ = embed(&state, "What is Go?").await.unwrap();
+
+        let snapshot = state.metrics.snapshot();
+        assert_eq!((snapshot.embedding_cache_hits, snapshot.embedding_cache_misses), (1, 2));
+        let key = embedding_cache_key(&state.config.embedding, "What is Rust?");
+        assert!(state.exact_cache.as_ref().unwrap().get(&key).await.unwrap().is_some());
+
+        // off with a TTL of 0
+        let state = AppState::new(Config::from_lookup(|name| match name {
+            "EMBEDDING_CACHE_TTL_SECS" => Some("0".to_string()),
+            _ => None
+        }).unwrap()).await;
+        let _ = embed(&state, "What is Rust?").await.unwrap();
+        let _ = embed(&state, "What is Rust?").await.unwrap();
+        assert_eq!(state.metrics.snapshot().embedding_cache_hits, 0);
+
+    }
+
+    #[cfg(feature = "mock")]
+    #[tokio::test]
+    async fn test_x_cache_headers_describe_each_outcome() {
+
+        use crate::config::Config;
+        use crate::test_helpers::{test_llm_request, user_message};
+
+        let state = AppState::new(Config::from_lookup(|_| None).unwrap()).await;
+        let header = |headers: &HeaderMap, name: &str| headers.get(name).map(|v| v.to_str().unwrap().to_string());
+
+        let (miss, _) = proxy_handler(State(state.clone()), HeaderMap::new(), Json(test_llm_request())).await.unwrap();
+        assert_eq!(header(&miss, X_CACHE_HEADER).as_deref(), Some("MISS"));
+        let cache_key = header(&miss, X_CACHE_KEY_HEADER).unwrap();
+        assert!(cache_key.starts_with(&state.cache_key_prefix()));
+        assert_eq!(header(&miss, X_CACHE_AGE_HEADER), None);
+
+        let (exact, _) = proxy_handler(State(state.clone()), HeaderMap::new(), Json(test_llm_request())).await.unwrap();
+        assert_eq!(header(&exact, X_CACHE_HEADER).as_deref(), Some("EXACT_HIT"));
+        assert_eq!(header(&exact, X_CACHE_KEY_HEADER), Some(cache_key.clone()));
+        assert!(header(&exact, X_CACHE_AGE_HEADER).unwrap().parse::<u64>().is_ok());
+        assert_eq!(header(&exact, X_SIMILARITY_SCORE_HEADER), None);
+
+        // the same words, so a different exact key but a perfect semantic match
+        let similar = LLMRequest { messages: vec![user_message("what is rust")], ..test_llm_request() };
+        let (semantic, _) = proxy_handler(State(state.clone()), HeaderMap::new(), Json(similar)).await.unwrap();
+        assert_eq!(header(&semantic, X_CACHE_HEADER).as_deref(), Some("SEMANTIC_HIT"));
+        assert_eq!(header(&semantic, X_CACHE_KEY_HEADER), Some(cache_key.clone()), "The matched entry's key is reported");
+        assert_eq!(header(&semantic, X_SIMILARITY_SCORE_HEADER).as_deref(), Some("1.0000"));
+
+        let mut bypass = HeaderMap::new();
+        bypass.insert("x-bypass-cache", "true".parse().unwrap());
+        let (bypassed, _) = proxy_handler(State(state.clone()), bypass, Json(test_llm_request())).await.unwrap();
+        assert_eq!(header(&bypassed, X_CACHE_HEADER).as_deref(), Some("BYPASS"));
+
+    }
+
+    #[cfg(feature = "mock")]
+    #[tokio::test]
+    async fn test_admin_cache_size_counts_by_prefix() {
+
+        use crate::config::Config;
+        use crate::test_helpers::test_llm_request;
+
+        let config = Config::from_lookup(|name| match name {
+            "SEMANTIC_CACHE_ENABLED" => Some("false".to_string()),
+            "ADMIN_TOKEN" => Some("admin-token".to_string()),
+            _ => None
+        }).unwrap();
+        let state = AppState::new(config).await;
+
+        let _ = proxy_handler(State(state.clone()), HeaderMap::new(), Json(test_llm_request())).await.unwrap();
+        let redis_cache = state.exact_cache.as_ref().unwrap();
+        redis_cache.set("unrelated:key", "x").await.unwrap();
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-admin-token", header::HeaderValue::from_static("admin-token"));
+        let Json(size) = admin_cache_size(State(state.clone()), headers).await.unwrap();
+        assert_eq!(size["keys"]["exact"], 1);
+        assert_eq!(size["keys"]["quarantined"], 0);
+        assert_eq!(size["total"], 1);
+
+    }
+
+    #[cfg(feature = "mock")]
+    #[tokio::test]
+    async fn test_invalidate_by_request_derives_the_key() {
+
+        use crate::config::Config;
+        use crate::mock::fake_embedding;
+        use crate::test_helpers::test_llm_request;
+
+        let state = AppState::new(Config::from_lookup(|name| match name {
+            "ADMIN_TOKEN" => Some("secret".to_string()),
+            _ => None
+        }).unwrap()).await;
+        let mut headers = HeaderMap::new();
+        headers.insert("x-admin-token", "secret".parse().unwrap());
+
+        let (response_headers, _) = proxy_handler(State(state.clone()), HeaderMap::new(), Json(test_llm_request())).await.unwrap();
+        let key = response_headers[X_CACHE_KEY_HEADER].to_str().unwrap().to_string();
+        let query = || Query(InvalidateQuery { mode: None, reason: None });
+
+        let Json(body) = admin_invalidate_by_request(State(state.clone()), headers.clone(), query(), Json(test_llm_request())).await.unwrap();
+        assert_eq!(body["key"], key.as_str());
+        assert_eq!(body["found_in_redis"], true);
+
+        let semantic_cache = state.semantic_cache.as_ref().unwrap();
+        let hits = semantic_cache.search_paginated(fake_embedding("user: What is Rust?"), 0.9, 10, None, None).await.unwrap();
+        assert!(hits.is_empty(), "The entry's semantic point should be deleted too");
+
+        let (response_headers, _) = proxy_handler(State(state.clone()), HeaderMap::new(), Json(test_llm_request())).await.unwrap();
+        assert_eq!(response_headers[X_CACHE_HEADER], "MISS");
+
+        let Json(body) = admin_invalidate_cache_key(State(state), headers, Path(key), query()).await.unwrap();
+        assert_eq!(body["found_in_redis"], true);
+
+    }
+
+    #[cfg(feature = "mock")]
+    #[tokio::test]
+    async fn test_invalidate_model_keeps_other_models() {
+
+        use crate::config::Config;
+        use crate::mock::fake_embedding;
+        use crate::test_helpers::{test_llm_request, test_params};
+
+        let state = AppState::new(Config::from_lookup(|name| match name {
+            "ADMIN_TOKEN" => Some("secret".to_string()),
+            _ => None
+        }).unwrap()).await;
+        let mut headers = HeaderMap::new();
+        headers.insert("x-admin-token", "secret".parse().unwrap());
+
+        let other = LLMRequest { model: "llama-3.3-70b-versatile".to_string(), ..test_llm_request() };
+        for request in [test_llm_request(), other.clone()] {
+            let _ = proxy_handler(State(state.clone()), HeaderMap::new(), Json(request)).await.unwrap();
+        }
+
+        let Json(body) = admin_invalidate_model(
+            State(state.clone()), headers, Json(InvalidateModelRequest { model: " GPT-4 ".to_string() })
+        ).await.unwrap();
+        assert_eq!(body["model"], "gpt-4");
+        assert_eq!(body["redis_deleted"], 1);
+
+        let semantic_cache = state.semantic_cache.as_ref().unwrap();
+        let gone = semantic_cache.search_similar(fake_embedding("user: What is Rust?"), 0.9, &test_params(0.7)).await.unwrap();
+        assert!(gone.is_none());
+
+        let (response_headers, _) = proxy_handler(State(state.clone()), HeaderMap::new(), Json(other)).await.unwrap();
+        assert_eq!(response_headers[X_CACHE_HEADER], "EXACT_HIT");
+
+    }
+
+    #[cfg(feature = "mock")]
+    #[tokio::test]
+    async fn test_purge_similar_dry_run_then_delete() {
+
+        use crate::config::Config;
+        use crate::test_helpers::{test_llm_request, user_message};
+
+        let state = AppState::new(Config::from_lookup(|name| match name {
+            "ADMIN_TOKEN" => Some("secret".to_string()),
+            _ => None
+        }).unwrap()).await;
+        let mut headers = HeaderMap::new();
+        headers.insert("x-admin-token", "secret".parse().unwrap());
+
+        let other = LLMRequest { messages: vec![user_message("Explain Python decorators")], ..test_llm_request() };
+        for request in [test_llm_request(), other.clone()] {
+            let _ = proxy_handler(State(state.clone()), HeaderMap::new(), Json(request)).await.unwrap();
+        }
+
+        let purge = |dry_run| SemanticPurgeRequest { query: "what is rust".to_string(), threshold: Some(0.9), dry_run };
+        let Json(body) = admin_purge_similar(State(state.clone()), headers.clone(), Json(purge(true))).await.unwrap();
+        assert_eq!(body["matched"], 1);
+        let (response_headers, _) = proxy_handler(State(state.clone()), HeaderMap::new(), Json(test_llm_request())).await.unwrap();
+        assert_eq!(response_headers[X_CACHE_HEADER], "EXACT_HIT", "A dry run shouldn't delete anything");
+
+        let Json(body) = admin_purge_similar(State(state.clone()), headers, Json(purge(false))).await.unwrap();
+        assert_eq!(body["matched"], 1);
+        let (response_headers, _) = proxy_handler(State(state.clone()), HeaderMap::new(), Json(test_llm_request())).await.unwrap();
+        assert_eq!(response_headers[X_CACHE_HEADER], "MISS");
+        let (response_headers, _) = proxy_handler(State(state.clone()), HeaderMap::new(), Json(other)).await.unwrap();
+        assert_eq!(response_headers[X_CACHE_HEADER], "EXACT_HIT");
+
+    }
+
+    #[cfg(feature = "mock")]
+    #[tokio::test]
+    async fn test_stage_latencies_are_recorded() {
+
+        use crate::config::Config;
+        use crate::test_helpers::test_llm_request;
+
+        let state = AppState::new(Config::from_lookup(|_| None).unwrap()).await;
+        for _ in 0..2 {
+            let _ = proxy_handler(State(state.clone()), HeaderMap::new(), Json(test_llm_request())).await.unwrap();
+        }
+
+        let Json(body) = metrics(State(state), Query(MetricsQuery::default())).await;
+        let latency = &body["latency"];
+        assert_eq!(latency["redis_lookup"]["count"], 2);
+        assert_eq!(latency["upstream"]["count"], 1, "The second request was an exact hit");
+        assert_eq!(latency["qdrant_search"]["count"], 1);
+        assert!(latency["embedding"]["count"].as_u64().unwrap() >= 1);
+        assert!(latency["upstream"]["p99_ms"].is_number());
+
+    }
+
+    #[cfg(feature = "mock")]
+    #[tokio::test]
+    async fn test_tenants_do_not_share_semantic_matches() {
+
+        use crate::config::Config;
+        use crate::mock::fake_embedding;
+        use crate::test_helpers::test_llm_request;
+
+        let state = AppState::new(Config::from_lookup(|_| None).unwrap()).await;
+        let tenant = |id: &str| AppState { tenant: Some(id.to_string()), ..state.clone() };
+        let semantic_cache = state.semantic_cache.as_ref().unwrap();
+        let embedding = || fake_embedding("user: What is Rust?");
+
+        let (response_headers, _) = proxy_handler(State(tenant("team-a")), HeaderMap::new(), Json(test_llm_request())).await.unwrap();
+        assert_eq!(response_headers[X_CACHE_HEADER], "MISS");
+
+        let params = |state: &AppState| state.entry_params(&test_llm_request());
+        assert!(semantic_cache.search_similar(embedding(), 0.9, &params(&tenant("team-a"))).await.unwrap().is_some());
+        assert!(semantic_cache.search_similar(embedding(), 0.9, &params(&tenant("team-b"))).await.unwrap().is_none());
+        assert!(semantic_cache.search_similar(embedding(), 0.9, &params(&state)).await.unwrap().is_none());
+
+        let (response_headers, _) = proxy_handler(State(tenant("team-b")), HeaderMap::new(), Json(test_llm_request())).await.unwrap();
+        assert_eq!(response_headers[X_CACHE_HEADER], "MISS");
+        let (response_headers, _) = proxy_handler(State(tenant("team-a")), HeaderMap::new(), Json(test_llm_request())).await.unwrap();
+        assert_eq!(response_headers[X_CACHE_HEADER], "EXACT_HIT");
+
+    }
+
+    #[cfg(feature = "mock")]
+    #[tokio::test]
+    async fn test_clear_flushes_the_requested_tiers() {
+
+        use crate::config::Config;
+        use crate::mock::fake_embedding;
+        use crate::test_helpers::test_llm_request;
+
+        let state = AppState::new(Config::from_lookup(|name| match name {
+            "ADMIN_TOKEN" => Some("secret".to_string()),
+            _ => None
+        }).unwrap()).await;
+        let mut headers = HeaderMap::new();
+        headers.insert("x-admin-token", "secret".parse().unwrap());
+        let clear = |tier: &str| Query(ClearQuery { tier: Some(tier.to_string()) });
+        let semantic_points = || async {
+            state.semantic_cache.as_ref().unwrap()
+                .search_paginated(fake_embedding("user: What is Rust?"), 0.9, 10, None, None).await.unwrap().len()
+        };
+
+        let _ = proxy_handler(State(state.clone()), HeaderMap::new(), Json(test_llm_request())).await.unwrap();
+        assert_eq!(semantic_points().await, 1);
+
+        let Json(body) = admin_clear_cache(State(state.clone()), headers.clone(), clear("qdrant")).await.unwrap();
+        assert_eq!(body["cleared"], json!(["qdrant"]));
+        assert_eq!(semantic_points().await, 0);
+        let (response_headers, _) = proxy_handler(State(state.clone()), HeaderMap::new(), Json(test_llm_request())).await.unwrap();
+        assert_eq!(response_headers[X_CACHE_HEADER], "EXACT_HIT");
+
+        let Json(body) = admin_clear_cache(State(state.clone()), headers.clone(), Query(ClearQuery { tier: None })).await.unwrap();
+        assert_eq!(body["cleared"], json!(["redis", "qdrant"]));
+        let (response_headers, _) = proxy_handler(State(state.clone()), HeaderMap::new(), Json(test_llm_request())).await.unwrap();
+        assert_eq!(response_headers[X_CACHE_HEADER], "MISS");
+
+        assert_eq!(admin_clear_cache(State(state), headers, clear("everything")).await.unwrap_err().0, StatusCode::BAD_REQUEST);
+
+    }
+
+    #[cfg(feature = "mock")]
+    #[tokio::test]
+    async fn test_cache_lookup_never_calls_upstream() {
+
+        use crate::config::Config;
+        use crate::test_helpers::{test_llm_request, user_message};
+
+        let config = Config::from_lookup(|_| None).unwrap();
+        let state = AppState::new(config).await;
+
+        let request = serde_json::to_value(test_llm_request()).unwrap();
+        let (status, Json(body)) = cache_lookup(State(state.clone()), None, HeaderMap::new(), Json(request.clone())).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["cached"], false);
+
+        let _ = proxy_handler(State(state.clone()), HeaderMap::new(), Json(test_llm_request())).await.unwrap();
+
+        let (status, Json(body)) = cache_lookup(State(state.clone()), None, HeaderMap::new(), Json(request.clone())).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["tier"], "exact");
+
+        let other = LLMRequest { messages: vec![user_message("Something unrelated entirely")], ..test_llm_request() };
+        let batch = json!([request, serde_json::to_value(other).unwrap(), {"messages": []}]);
+        let (status, Json(body)) = cache_lookup(State(state.clone()), None, HeaderMap::new(), Json(batch)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["hits"], 1);
+        assert_eq!(body["results"][1]["cached"], false);
+        assert!(body["results"][1]["best_semantic_score"].is_number());
+        assert_eq!(body["results"][2]["error"], "field `model`: missing");
+
+        // lookups are kept out of the hit and miss counters
+        let snapshot = state.metrics.snapshot();
+        assert_eq!((snapshot.exact_hits, snapshot.misses), (0, 1));
+        assert_eq!((snapshot.lookups, snapshot.lookup_hits), (4, 2));
+
+    }
+
+    #[cfg(feature = "mock")]
+    #[tokio::test]
+    async fn test_dedup_by_id_returns_the_first_copy() {
+
+        use crate::config::Config;
+        use crate::test_helpers::test_llm_response;
+
+        let state = AppState::new(Config::from_lookup(|_| None).unwrap()).await;
+
+        let first = LLMResponse { id: "chatcmpl-repeat".to_string(), ..test_llm_response() };
+        let (_, duplicate) = dedup_by_id(&state, "key", first.clone());
+        assert!(!duplicate);
+
+        let mut repeat = first.clone();
+        repeat.choices[0].message.content = "A different answer".into();
+        let (served, duplicate) = dedup_by_id(&state, "key", repeat);
+        assert!(duplicate);
+        assert_eq!(served.choices[0].message.content, first.choices[0].message.content);
+        assert_eq!(state.metrics.snapshot().id_dedup_hits, 1);
+
+        // a full map makes room by dropping the oldest id
+        for i in 0..ID_DEDUP_CAPACITY {
+            let _ = dedup_by_id(&state, "key", LLMResponse { id: format!("chatcmpl-{}", i), ..test_llm_response() });
+        }
+        assert_eq!(state.id_dedup.len(), ID_DEDUP_CAPACITY);
+        assert!(!state.id_dedup.contains_key(&(String::new(), "chatcmpl-repeat".to_string())));
+
+        let (_, duplicate) = dedup_by_id(&state, "key", LLMResponse { id: String::new(), ..test_llm_response() });
+        assert!(!duplicate, "Responses without an id are never deduplicated");
+
+    }
+
+    #[cfg(feature = "mock")]
+    #[tokio::test]
+    async fn test_dedup_by_id_keeps_tenants_apart() {
+
+        use crate::config::Config;
+        use crate::test_helpers::test_llm_response;
+
+        let state = AppState::new(Config::from_lookup(|_| None).unwrap()).await;
+        let tenant = |id: &str| AppState { tenant: Some(id.to_string()), ..state.clone() };
+
+        let answer = |content: &str| {
+            let mut response = LLMResponse { id: "chatcmpl-shared".to_string(), ..test_llm_response() };
+            response.choices[0].message.content = content.into();
+            response
+        };
+        let (_, duplicate) = dedup_by_id(&tenant("team-a"), "key", answer("Team A's answer"));
+        assert!(!duplicate);
+
+        let (served, duplicate) = dedup_by_id(&tenant("team-b"), "key", answer("Team B's answer"));
+        assert!(!duplicate, "The same id for another tenant isn't a repeat");
+        assert_eq!(served.choices[0].message.content, "Team B's answer".into());
+
+        let (served, duplicate) = dedup_by_id(&tenant("team-a"), "key", answer("Team A's second answer"));
+        assert!(duplicate);
+        assert_eq!(served.choices[0].message.content, "Team A's answer".into());
+
+    }
+
+    #[cfg(feature = "mock")]
+    #[tokio::test]
+    async fn test_explain_reports_tiers_without_side_effects() {
+
+        use crate::config::Config;
+        use crate::test_helpers::test_llm_request;
+
+        let config = Config::from_lookup(|name| match name {
+            "ADMIN_TOKEN" => Some("secret".to_string()),
+            _ => None
+        }).unwrap();
+        let state = AppState::new(config).await;
+        let mut headers = HeaderMap::new();
+        headers.insert("x-admin-token", header::HeaderValue::from_static("secret"));
+
+        let (status, _) = explain_handler(State(state.clone()), HeaderMap::new(), Json(test_llm_request())).await.unwrap_err();
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let Json(body) = explain_handler(State(state.clone()), headers.clone(), Json(test_llm_request())).await.unwrap();
+        assert!(body["would_serve_from"].is_null());
+        assert_eq!(body["tiers"]["exact"]["present"], false);
+        assert_eq!(body["ttl"], json!({"secs": 86400, "source": "temperature"}));
+
+        let _ = proxy_handler(State(state.clone()), HeaderMap::new(), Json(test_llm_request())).await.unwrap();
+
+        let Json(body) = explain_handler(State(state.clone()), headers.clone(), Json(test_llm_request())).await.unwrap();
+        assert_eq!(body["would_serve_from"], "exact");
+        assert_eq!(body["tiers"]["exact"]["present"], true);
+        assert!(body["tiers"]["exact"]["ttl_secs"].as_i64().unwrap() > 0);
+        let semantic = &body["tiers"]["semantic"];
+        assert_eq!(semantic["embedding"]["first_values"].as_array().unwrap().len(), EXPLAIN_EMBEDDING_PREVIEW);
+        assert_eq!(semantic["neighbors"][0]["servable"], true);
+
+        headers.insert("x-bypass-cache", header::HeaderValue::from_static("true"));
+        headers.insert("x-cache-ttl", header::HeaderValue::from_static("60"));
+        let Json(body) = explain_handler(State(state.clone()), headers, Json(test_llm_request())).await.unwrap();
+        assert!(body["would_serve_from"].is_null());
+        assert_eq!(body["ttl"], json!({"secs": 60, "source": "x-cache-ttl"}));
+
+        // nothing counted and nothing written beyond the one real request
+        let snapshot = state.metrics.snapshot();
+        assert_eq!((snapshot.exact_hits, snapshot.semantic_hits, snapshot.misses), (0, 0, 1));
+        assert_eq!(state.exact_cache.as_ref().unwrap().count_keys_matching("cache:exact:*").await.unwrap(), 1);
+
+    }
+
+    #[cfg(feature = "mock")]
+    #[tokio::test]
+    async fn test_explain_looks_only_where_the_request_would() {
+
+        use crate::config::Config;
+        use crate::models::Message;
+        use crate::test_helpers::test_llm_request;
+
+        let config = Config::from_lookup(|name| match name {
+            "ADMIN_TOKEN" => Some("secret".to_string()),
+            "TENANT_SOURCE" => Some("header".to_string()),
+            _ => None
+        }).unwrap();
+        let state = AppState::new(config).await;
+        let as_tenant = |tenant: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert("x-admin-token", header::HeaderValue::from_static("secret"));
+            headers.insert(TENANT_HEADER, header::HeaderValue::from_static(tenant));
+            headers
+        };
+
+        let team_a = AppState { tenant: Some("team-a".to_string()), ..state.clone() };
+        let _ = proxy_handler(State(team_a), HeaderMap::new(), Json(test_llm_request())).await.unwrap();
+
+        let Json(body) = explain_handler(State(state.clone()), as_tenant("team-a"), Json(test_llm_request())).await.unwrap();
+        assert_eq!(body["would_serve_from"], "exact");
+        let Json(body) = explain_handler(State(state.clone()), as_tenant("team-b"), Json(test_llm_request())).await.unwrap();
+        assert!(body["would_serve_from"].is_null());
+        assert_eq!(body["tiers"]["semantic"]["neighbors"], json!([]), "team-a's entry isn't team-b's neighbour");
+
+        let content = serde_json::from_value(json!([
+            {"type": "text", "text": "What is Rust?"},
+            {"type": "image_url", "image_url": {"url": "https://example.com/cat.png"}}
+        ])).unwrap();
+        let with_image = LLMRequest { messages: vec![Message { role: "user".to_string(), content, ..Default::default() }], ..test_llm_request() };
+        let Json(body) = explain_handler(State(state.clone()), as_tenant("team-a"), Json(with_image)).await.unwrap();
+        assert_eq!(body["tiers"]["semantic"]["eligible"], false);
+        assert!(body["tiers"]["semantic"].get("neighbors").is_none());
+
+    }
+
+    #[cfg(feature = "mock")]
+    #[tokio::test]
+    async fn test_copy_keys_single_and_bulk() {
+
+        use crate::config::Config;
+
+        let config = Config::from_lookup(|name| match name {
+            "ADMIN_TOKEN" => Some("secret".to_string()),
+            _ => None
+        }).unwrap();
+        let state = AppState::new(config).await;
+        let mut headers = HeaderMap::new();
+        headers.insert("x-admin-token", "secret".parse().unwrap());
+
+        let redis = state.exact_cache.as_ref().unwrap();
+        redis.set_with_ttl("cache:v1:exact:a", "A", 100).await.unwrap();
+        redis.set_with_ttl("cache:v1:exact:b", "B", 100).await.unwrap();
+        redis.set_with_ttl("cache:v1:exact:c", "C", 100).await.unwrap();
+        redis.set_with_ttl("cache:v2:exact:c", "newer C", 100).await.unwrap();
+
+        let copy = |source: &str, dest: &str, reset_ttl: bool| Json(CopyKeyRequest {
+            source_key: source.to_string(), dest_key: dest.to_string(), reset_ttl, replace: false
+        });
+
+        // COPY keeps the remaining TTL; reset_ttl starts a full DEFAULT_TTL_SECS
+        let Json(body) = admin_copy_key(State(state.clone()), headers.clone(), copy("cache:v1:exact:a", "cache:v2:exact:a", false)).await.unwrap();
+        assert_eq!(body["copied"], true);
+        assert!((99..=100).contains(&body["dest_ttl"].as_i64().unwrap()));
+        let Json(body) = admin_copy_key(State(state.clone()), headers.clone(), copy("cache:v1:exact:b", "cache:v2:exact:b", true)).await.unwrap();
+        assert!(body["dest_ttl"].as_u64().unwrap() + 1 >= state.runtime.load().default_ttl_secs);
+
+        // an existing key is left alone without replace
+        let Json(body) = admin_copy_key(State(state.clone()), headers.clone(), copy("cache:v1:exact:c", "cache:v2:exact:c", false)).await.unwrap();
+        assert_eq!(body["copied"], false);
+        assert_eq!(redis.get("cache:v2:exact:c").await.unwrap().as_deref(), Some("newer C"));
+
+        let bulk = |source: &str, dest: &str| Json(BulkCopyRequest {
+            source_pattern: source.to_string(), dest_pattern: dest.to_string(), reset_ttl: false, replace: false
+        });
+        let Json(body) = admin_copy_keys_bulk(State(state.clone()), headers.clone(), bulk("cache:v1:exact:*", "cache:v3:exact:*")).await.unwrap();
+        assert_eq!((body["scanned"].as_u64(), body["copied"].as_u64()), (Some(3), Some(3)));
+        assert_eq!(redis.get("cache:v3:exact:b").await.unwrap().as_deref(), Some("B"));
+
+        for (source, dest) in [("cache:v1:*:a", "cache:v3:*"), ("cache:*", "cache:v4:*")] {
+            let (status, _) = admin_copy_keys_bulk(State(state.clone()), headers.clone(), bulk(source, dest)).await.unwrap_err();
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{} -> {}", source, dest);
+        }
+
+    }
+
+    #[cfg(feature = "mock")]
+    #[tokio::test]
+    async fn test_hot_keys_ranks_exact_hits() {
+
+        use crate::config::Config;
+        use crate::test_helpers::{test_llm_request, user_message};
+
+        let config = Config::from_lookup(|name| match name {
+            "ADMIN_TOKEN" => Some("secret".to_string()),
+            _ => None
+        }).unwrap();
+        let state = AppState::new(config).await;
+        let mut headers = HeaderMap::new();
+        headers.insert("x-admin-token", "secret".parse().unwrap());
+
+        let other = LLMRequest { messages: vec![user_message("Something unrelated entirely")], ..test_llm_request() };
+        for request in [test_llm_request(), test_llm_request(), test_llm_request(), other.clone(), other] {
+            let _ = proxy_handler(State(state.clone()), HeaderMap::new(), Json(request)).await.unwrap();
+        }
+
+        let Json(body) = admin_hot_keys(State(state.clone()), headers.clone(), Query(HotKeysQuery { limit: None })).await.unwrap();
+        assert_eq!(body["tracked"], 2);
+        assert_eq!(body["keys"][0]["hits"], 2);
+        assert_eq!(body["keys"][1]["hits"], 1);
+        assert!(body["keys"][0]["ttl_secs"].as_i64().unwrap() > 0);
+        let preview = body["keys"][0]["response_preview"].as_str().unwrap();
+        assert!(!preview.is_empty() && preview.chars().count() <= HOT_KEY_PREVIEW_CHARS);
+
+        let Json(body) = admin_hot_keys(State(state.clone()), headers, Query(HotKeysQuery { limit: Some(1) })).await.unwrap();
+        assert_eq!(body["count"], 1);
+
+    }
+
+    #[cfg(feature = "mock")]
+    #[tokio::test]
+    async fn test_entries_are_listed_page_by_page() {
+
+        use crate::config::Config;
+        use crate::test_helpers::test_llm_request;
+
+        let state = AppState::new(Config::from_lookup(|name| match name {
+            "ADMIN_TOKEN" => Some("secret".to_string()),
+            _ => None
+        }).unwrap()).await;
+        let mut headers = HeaderMap::new();
+        headers.insert("x-admin-token", "secret".parse().unwrap());
+
+        let mut key = String::new();
+        for _ in 0..3 {
+            let (response_headers, _) = proxy_handler(State(state.clone()), HeaderMap::new(), Json(test_llm_request())).await.unwrap();
+            key = response_headers[X_CACHE_KEY_HEADER].to_str().unwrap().to_string();
+        }
+        let redis_cache = state.exact_cache.as_ref().unwrap();
+        for i in 0..150 {
+            redis_cache.set_with_ttl(&format!("{}filler-{}", state.config.exact_key_prefix(), i), "{}", 60).await.unwrap();
+        }
+
+        let mut listed = Vec::new();
+        let mut cursor = None;
+        loop {
+            let Json(page) = admin_list_entries(State(state.clone()), headers.clone(), Query(EntriesQuery { cursor })).await.unwrap();
+            listed.extend(page["entries"].as_array().unwrap().iter().cloned());
+            match page["next_cursor"].as_u64() {
+                Some(next) => cursor = Some(next),
+                None => break
+            }
+        }
+        assert_eq!(listed.len(), 151);
+
+        let entry = listed.iter().find(|entry| entry["key"] == key.as_str()).unwrap();
+        assert_eq!(entry["hits"], 2);
+        assert_eq!(entry["model"], "gpt-4");
+        assert!(entry["size_bytes"].as_u64().unwrap() > 0);
+        assert!(entry["ttl_secs"].as_i64().unwrap() > 0);
+
+        let Json(full) = admin_get_entry(State(state.clone()), headers.clone(), Path(key)).await.unwrap();
+        assert_eq!(full["hits"], 2);
+        assert!(full["response"]["choices"].is_array());
+
+        let missing = admin_get_entry(State(state), headers, Path("cache:exact:missing".to_string())).await;
+        assert_eq!(missing.unwrap_err().0, StatusCode::NOT_FOUND);
+
+    }
+
+    #[cfg(feature = "mock")]
+    #[tokio::test]
+    async fn test_export_streams_before_the_scan_finishes() {
+
+        use crate::config::Config;
+        use crate::test_helpers::test_llm_response;
+        use tokio_stream::StreamExt;
+
+        let config = Config::from_lookup(|name| match name {
+            "ADMIN_TOKEN" => Some("secret".to_string()),
+            _ => None
+        }).unwrap();
+        let state = AppState::new(config).await;
+        let mut headers = HeaderMap::new();
+        headers.insert("x-admin-token", "secret".parse().unwrap());
+
+        let redis = state.exact_cache.as_ref().unwrap();
+        let cached = serde_json::to_string(&test_llm_response()).unwrap();
+        for i in 0..10_000 {
+            redis.set(&format!("{}{:05}:model", state.config.exact_key_prefix(), i), &cached).await.unwrap();
+        }
+        // not part of the exact tier
+        redis.set_refresh_request("other", "{}", 60).await.unwrap();
+
+        let started = std::time::Instant::now();
+        let response = admin_export_cache(State(state.clone()), headers).await.unwrap();
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/x-ndjson");
+
+        let mut body = response.into_body().into_data_stream();
+        let first = tokio::time::timeout(Duration::from_millis(500), body.next()).await
+            .expect("first line should arrive within 500ms")
+            .unwrap()
+            .unwrap();
+        assert!(started.elapsed() < Duration::from_millis(500));
+
+        let line: serde_json::Value = serde_json::from_slice(&first).unwrap();
+        assert_eq!(line["key"], format!("{}00000:model", state.config.exact_key_prefix()));
+        assert_eq!(line["response"], serde_json::to_value(test_llm_response()).unwrap());
+
+        let mut lines = 1;
+        while let Some(chunk) = body.next().await {
+            lines += chunk.unwrap().iter().filter(|b| **b == b'\n').count();
+        }
+        assert_eq!(lines, 10_000);
+
+    }
+
+    #[cfg(feature = "mock")]
+    #[tokio::test]
+    async fn test_write_dedup_refreshes_near_duplicates() {
+
+        use crate::config::Config;
+        use crate::mock::fake_embedding;
+        use crate::test_helpers::test_params;
+
+        let paraphrases = ["What is Rust?", "what is rust", "What is  Rust ?"];
+
+        for (dedup, expected_points) in [("0.98", 1), ("0", 3)] {
+            let config = Config::from_lookup(|name| match name {
+                "SEMANTIC_WRITE_DEDUP_THRESHOLD" => Some(dedup.to_string()),
+                _ => None
+            }).unwrap();
+            let state = AppState::new(config).await;
+
+            for (i, prompt) in paraphrases.iter().enumerate() {
+                let response = format!("answer {}", i);
+                let semantic = Some((*prompt, fake_embedding(prompt)));
+                store_in_caches(&state, &format!("key_{}", i), &response, semantic, &test_params(0.0), 60, 5, "request").await;
+            }
+
+            let qdrant = state.semantic_cache.as_ref().unwrap();
+            assert_eq!(qdrant.collection_points(&qdrant.collection_name()).await.unwrap(), expected_points);
+            let hit = qdrant.search_similar(fake_embedding("What is Rust?"), 0.9, &test_params(0.0)).await.unwrap().unwrap();
+            if expected_points == 1 {
+                assert_eq!((hit.response.as_str(), hit.cache_key.as_str()), ("answer 2", "key_2"));
+            }
+        }
+
+    }
+
+    #[cfg(feature = "mock")]
+    #[tokio::test]
+    async fn test_semantic_hit_reports_original_latency() {
+
+        use crate::config::Config;
+        use crate::test_helpers::{test_llm_request, user_message};
+
+        let config = Config::from_lookup(|name| match name {
+            "ADMIN_TOKEN" => Some("secret".to_string()),
+            "EXACT_CACHE_ENABLED" => Some("false".to_string()),
+            _ => None
+        }).unwrap();
+        let state = AppState::new(config).await;
+        let mut admin = HeaderMap::new();
+        admin.insert("x-admin-token", "secret".parse().unwrap());
+
+        let Json(body) = admin_cache_latency_stats(State(state.clone()), admin.clone()).await.unwrap();
+        assert_eq!(body, json!({"entries": 0}));
+
+        let (headers, _) = proxy_handler(State(state.clone()), HeaderMap::new(), Json(test_llm_request())).await.unwrap();
+        assert!(headers.get(ORIGINAL_LATENCY_HEADER).is_none());
+
+        let similar = LLMRequest { messages: vec![user_message("what is rust")], ..test_llm_request() };
+        let (headers, _) = proxy_handler(State(state.clone()), HeaderMap::new(), Json(similar)).await.unwrap();
+        let latency: u64 = headers[ORIGINAL_LATENCY_HEADER].to_str().unwrap().parse().unwrap();
+
+        let Json(body) = admin_cache_latency_stats(State(state.clone()), admin).await.unwrap();
+        assert_eq!(body["entries"], 1);
+        assert_eq!(body["p95_ms"], latency);
+
+    }
+
+    #[cfg(feature = "mock")]
+    #[tokio::test]
+    async fn test_same_text_with_another_image_is_not_a_semantic_hit() {
+
+        use crate::config::Config;
+        use crate::models::Message;
+        use crate::test_helpers::test_llm_request;
+
+        let config = Config::from_lookup(|name| match name {
+            "EXACT_CACHE_ENABLED" => Some("false".to_string()),
+            _ => None
+        }).unwrap();
+        let state = AppState::new(config).await;
+        let with_image = |url: &str| {
+            let content = serde_json::from_value(json!([
+                {"type": "text", "text": "What is in this picture?"},
+                {"type": "image_url", "image_url": {"url": url}}
+            ])).unwrap();
+            LLMRequest { messages: vec![Message { role: "user".to_string(), content, ..Default::default() }], ..test_llm_request() }
+        };
+
+        let (headers, _) = proxy_handler(State(state.clone()), HeaderMap::new(), Json(with_image("https://example.com/cat.png"))).await.unwrap();
+        assert_eq!(headers[X_CACHE_HEADER], "MISS");
+        let (headers, _) = proxy_handler(State(state.clone()), HeaderMap::new(), Json(with_image("https://example.com/dog.png"))).await.unwrap();
+        assert_eq!(headers[X_CACHE_HEADER], "MISS");
+        assert!(!headers.contains_key(SERVED_FROM_CACHE_HEADER));
+
+    }
+
+    #[cfg(feature = "mock")]
+    #[tokio::test]
+    async fn test_semantic_threshold_header_overrides_the_default() {
+
+        use crate::config::Config;
+        use crate::test_helpers::{test_llm_request, user_message};
+
+        let config = Config::from_lookup(|name| match name {
+            "EXACT_CACHE_ENABLED" => Some("false".to_string()),
+            _ => None
+        }).unwrap();
+        let state = AppState::new(config).await;
+        let with_threshold = |value: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(SEMANTIC_THRESHOLD_HEADER, value.parse().unwrap());
+            headers
+        };
+
+        let (headers, _) = proxy_handler(State(state.clone()), HeaderMap::new(), Json(test_llm_request())).await.unwrap();
+        assert_eq!(headers[SEMANTIC_THRESHOLD_HEADER], "0.9");
+
+        // both about 0.87 similar to "What is Rust?", and below the 0.9 default
+        let related = LLMRequest { messages: vec![user_message("What is Rust language?")], ..test_llm_request() };
+        let (headers, _) = proxy_handler(State(state.clone()), with_threshold("0.8"), Json(related.clone())).await.unwrap();
+        assert!(headers.contains_key(SERVED_FROM_CACHE_HEADER));
+        assert_eq!(headers[SEMANTIC_THRESHOLD_HEADER], "0.8");
+
+        let other = LLMRequest { messages: vec![user_message("What is Rust programming?")], ..test_llm_request() };
+        let (headers, _) = proxy_handler(State(state.clone()), with_threshold("0.95"), Json(other)).await.unwrap();
+        assert!(!headers.contains_key(SERVED_FROM_CACHE_HEADER));
+
+        for invalid in ["1.5", "-0.1", "high"] {
+            let (status, Json(error)) = proxy_handler(State(state.clone()), with_threshold(invalid), Json(related.clone())).await.unwrap_err();
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+            assert_eq!(error.error.code.as_deref(), Some("invalid_semantic_threshold"));
+        }
+
+    }
+
+    #[cfg(feature = "mock")]
+    #[tokio::test]
+    async fn test_reembed_resumes_and_switches_collection() {
+
+        use crate::config::Config;
+        use crate::reembed::ReembedState;
+        use crate::test_helpers::{test_llm_request, user_message};
+
+        let config = Config::from_lookup(|name| match name {
+            "ADMIN_TOKEN" => Some("secret".to_string()),
+            _ => None
+        }).unwrap();
+        let state = AppState::new(config).await;
+        let mut headers = HeaderMap::new();
+        headers.insert("x-admin-token", "secret".parse().unwrap());
+
+        for prompt in ["What is Rust?", "How do I bake bread?"] {
+            let request = LLMRequest { messages: vec![user_message(prompt)], ..test_llm_request() };
+            let _ = proxy_handler(State(state.clone()), HeaderMap::new(), Json(request)).await.unwrap();
+        }
+
+        let run = |request: ReembedRequest| {
+            let state = state.clone();
+            let headers = headers.clone();
+            async move {
+                let (status, _) = admin_start_reembed(State(state.clone()), headers.clone(), Json(request)).await.unwrap();
+                assert_eq!(status, StatusCode::ACCEPTED);
+                loop {
+                    let Json(body) = admin_reembed_status(State(state.clone()), headers.clone()).await.unwrap();
+                    if body["state"] != "running" {
+                        return body;
+                    }
+                    tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+                }
+            }
+        };
+        let target = || Some("llm_cache_v2".to_string());
+
+        // a dry run only counts
+        let body = run(ReembedRequest { target: target(), dry_run: true, ..Default::default() }).await;
+        assert_eq!(body["state"], "completed");
+        assert_eq!((body["total"].as_u64(), body["processed"].as_u64()), (Some(2), Some(2)));
+        assert_eq!(body["switched"], false);
+
+        let body = run(ReembedRequest { target: target(), batch_size: Some(1), max_points: Some(1), ..Default::default() }).await;
+        assert_eq!(body["state"], "paused");
+        assert_eq!(body["processed"], 1);
+        assert_eq!(state.semantic_cache.as_ref().unwrap().collection_name().as_str(), "llm_cache");
+
+        let body = run(ReembedRequest { target: target(), batch_size: Some(1), ..Default::default() }).await;
+        assert_eq!(body["state"], "completed");
+        assert_eq!((body["processed"].as_u64(), body["failures"].as_u64()), (Some(2), Some(0)));
+        assert_eq!(body["switched"], true);
+        let qdrant = state.semantic_cache.as_ref().unwrap();
+        assert_eq!(qdrant.collection_name().as_str(), "llm_cache_v2");
+        assert_eq!(qdrant.collection_points("llm_cache_v2").await.unwrap(), 2);
+        assert_eq!(state.reembed.lock().unwrap().state, ReembedState::Completed);
+
+        let same = ReembedRequest { source: target(), target: target(), ..Default::default() };
+        let (status, _) = admin_start_reembed(State(state.clone()), headers.clone(), Json(same)).await.unwrap_err();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    }
+
+    #[cfg(feature = "mock")]
+    #[tokio::test]
+    async fn test_model_aliases_share_a_cache_entry() {
+
+        use crate::config::Config;
+        use crate::test_helpers::test_llm_request;
+
+        let config = Config::from_lookup(|name| match name {
+            "MODEL_ALIASES" => Some(r#"{"fast": "llama-3.1-8b-instant"}"#.to_string()),
+            "MODEL_ALLOWLIST" => Some("llama-3.1-8b-instant".to_string()),
+            "PRESERVE_CLIENT_MODEL_NAME" => Some("true".to_string()),
+            "SEMANTIC_CACHE_ENABLED" => Some("false".to_string()),
+            _ => None
+        }).unwrap();
+        let state = AppState::new(config).await;
+
+        for model in ["fast", "Llama-3.1-8B-Instant"] {
+            let request = LLMRequest { model: model.to_string(), ..test_llm_request() };
+            let (_, Json(response)) = proxy_handler(State(state.clone()), HeaderMap::new(), Json(request)).await.unwrap();
+            assert_eq!(response.model, model);
+        }
+        let snapshot = state.metrics.snapshot();
+        assert_eq!((snapshot.exact_hits, snapshot.misses), (1, 1));
+
+        // rejected before any cache or upstream work
+        let request = LLMRequest { model: "gpt-4".to_string(), ..test_llm_request() };
+        let (status, Json(body)) = proxy_handler(State(state.clone()), HeaderMap::new(), Json(request)).await.unwrap_err();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body.error.code.as_deref(), Some("model_not_found"));
+        assert_eq!(state.metrics.snapshot().misses, 1);
+
+    }
+
+    #[cfg(feature = "mock")]
+    #[tokio::test]
+    async fn test_cost_is_added_to_responses_but_not_cached() {
+
+        use crate::config::Config;
+        use crate::test_helpers::test_llm_request;
+
+        let config = Config::from_lookup(|name| match name {
+            "MODEL_ALIASES" => Some(r#"{"fast": "llama-3.1-8b-instant"}"#.to_string()),
+            "PRESERVE_CLIENT_MODEL_NAME" => Some("true".to_string()),
+            "INCLUDE_COST_IN_RESPONSE" => Some("true".to_string()),
+            "SEMANTIC_CACHE_ENABLED" => Some("false".to_string()),
+            _ => None
+        }).unwrap();
+        let state = AppState::new(config).await;
+
+        for _ in 0..2 {
+            let request = LLMRequest { model: "fast".to_string(), ..test_llm_request() };
+            let response = chat_completions(State(state.clone()), None, None, None, None, HeaderMap::new(), Json(request)).await.unwrap();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+            assert_eq!(body["model"], "fast");
+            let tokens = body["usage"]["total_tokens"].as_u64().unwrap();
+            // priced as the model the alias points at
+            let expected = calculate_cost("llama-3.1-8b-instant", tokens);
+            assert!((body["usage"]["cost_usd"].as_f64().unwrap() - expected).abs() < 1e-12, "{}", body["usage"]);
+        }
+        assert_eq!(state.metrics.snapshot().exact_hits, 1);
+
+        let redis = state.exact_cache.as_ref().unwrap();
+        let (_, keys) = redis.scan_page(&format!("{}*", state.config.exact_key_prefix()), 0).await.unwrap();
+        let cached = redis.get(&keys[0]).await.unwrap().unwrap();
+        assert!(!cached.contains("cost_usd"), "{}", cached);
+
+    }
+
+    #[cfg(feature = "mock")]
+    #[tokio::test]
+    async fn test_streamed_miss_is_cached_and_replayed() {
+
+        use crate::config::Config;
+        use crate::test_helpers::test_llm_request;
+
+        let config = Config::from_lookup(|name| match name {
+            "SEMANTIC_CACHE_ENABLED" => Some("false".to_string()),
+            _ => None
+        }).unwrap();
+        let state = AppState::new(config).await;
+
+        let stream_once = || async {
+            let request = LLMRequest { stream: Some(true), ..test_llm_request() };
+            let response = chat_completions(State(state.clone()), None, None, None, None, HeaderMap::new(), Json(request)).await.unwrap();
+            assert_eq!(response.headers()[header::CONTENT_TYPE], "text/event-stream");
+            let served_from_cache = response.headers().contains_key(SERVED_FROM_CACHE_HEADER);
+
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            assert!(body.ends_with(b"data: [DONE]\n\n"));
+            let mut parser = stream::SseParser::default();
+            let mut assembler = stream::StreamAssembler::default();
+            for event in parser.feed(&body) {
+                assembler.apply(&event);
+            }
+            (assembler.finish().unwrap(), served_from_cache)
+        };
+
+        let (streamed, served_from_cache) = stream_once().await;
+        assert!(!served_from_cache);
+
+        // the assembled completion is written once the stream has been sent
+        let redis = state.exact_cache.as_ref().unwrap();
+        let pattern = format!("{}*", state.config.exact_key_prefix());
+        for _ in 0..50 {
+            if !redis.scan_page(&pattern, 0).await.unwrap().1.is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let (_, keys) = redis.scan_page(&pattern, 0).await.unwrap();
+        let cached: LLMResponse = serde_json::from_str(&redis.get(&keys[0]).await.unwrap().unwrap()).unwrap();
+        assert_eq!(cached.choices[0].message.content, streamed.choices[0].message.content);
+        assert_eq!(cached.usage.total_tokens, streamed.usage.total_tokens);
+
+        let (replayed, served_from_cache) = stream_once().await;
+        assert!(served_from_cache);
+        assert_eq!(replayed.choices[0].message.content, streamed.choices[0].message.content);
+
+        // the same request without "stream" gets the cached entry as one body
+        let (headers, Json(response)) = proxy_handler(State(state.clone()), HeaderMap::new(), Json(test_llm_request())).await.unwrap();
+        assert!(headers.contains_key(SERVED_FROM_CACHE_HEADER));
+        assert_eq!(response.choices[0].message.content, streamed.choices[0].message.content);
+
+        let snapshot = state.metrics.snapshot();
+        assert_eq!((snapshot.misses, snapshot.exact_hits), (1, 2));
+
+    }
+
+    #[cfg(feature = "mock")]
+    #[tokio::test]
+    async fn test_refresh_popular_entries() {
+
+        use crate::config::Config;
+        use crate::test_helpers::test_llm_request;
+
+        let refresh_state = |max_cost: &'static str| async move {
+            let config = Config::from_lookup(|name| match name {
+                "CACHE_REFRESH_ENABLED" => Some("true".to_string()),
+                "CACHE_REFRESH_MIN_HITS" => Some("2".to_string()),
+                "CACHE_REFRESH_MAX_COST_USD" => Some(max_cost.to_string()),
+                "SEMANTIC_CACHE_ENABLED" => Some("false".to_string()),
+                _ => None
+            }).unwrap();
+            AppState::new(config).await
+        };
+
+        for (max_cost, expected) in [("0.10", 1), ("0", 0)] {
+            let state = refresh_state(max_cost).await;
+            let cache_key = generate_cache_key(&test_llm_request(), &state.config.key_normalization, &state.config.exact_key_prefix());
+
+            // a short-lived entry, then enough hits to make it popular
+            let mut headers = HeaderMap::new();
+            headers.insert("x-cache-ttl", header::HeaderValue::from_static("10"));
+            let _ = proxy_handler(State(state.clone()), headers, Json(test_llm_request())).await.unwrap();
+            for _ in 0..2 {
+                let _ = proxy_handler(State(state.clone()), HeaderMap::new(), Json(test_llm_request())).await.unwrap();
+            }
+            // hit counting happens on a spawned task
+            tokio::time::sleep(Duration::from_millis(50)).await;
+
+            assert_eq!(refresh_popular_entries(&state).await, expected, "max_cost={}", max_cost);
+            assert_eq!(state.metrics.snapshot().refreshes, expected);
+
+            let redis_cache = state.exact_cache.as_ref().unwrap();
+            let (_, ttl) = redis_cache.get_with_ttl(&cache_key).await.unwrap().unwrap();
+            if expected == 1 {
+                assert!(ttl > 10, "refresh should extend the TTL, got {}s", ttl);
+                // the hit count starts over, so the next pass has nothing to do
+                assert_eq!(refresh_popular_entries(&state).await, 0);
+            } else {
+                assert!(ttl <= 10);
+            }
+        }
+
+    }
+
+}
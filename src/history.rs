@@ -0,0 +1,208 @@
+// Time-bucketed metrics for /metrics/history. Every minute
+// `background::spawn_metrics_history` samples the global counters into a
+// ring buffer holding the last 24 hours; a query adds up the change between
+// consecutive samples into buckets of the requested width. The history lives
+// in memory, so it starts over with the process.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use crate::metrics::MetricsSnapshot;
+
+// how often a sample is taken, and so the narrowest bucket
+pub const HISTORY_RESOLUTION: Duration = Duration::from_secs(60);
+// 24 hours of samples
+const HISTORY_CAPACITY: usize = 1440;
+
+/// The running totals at one point in time
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Sample {
+    at: DateTime<Utc>,
+    requests: u64,
+    hits: u64,
+    misses: u64,
+    tokens_used: u64,
+    tokens_saved: u64
+}
+
+impl Sample {
+
+    fn of(at: DateTime<Utc>, snapshot: &MetricsSnapshot) -> Self {
+        Sample {
+            at,
+            requests: snapshot.total_requests,
+            hits: snapshot.total_hits(),
+            misses: snapshot.misses,
+            tokens_used: snapshot.tokens_used,
+            tokens_saved: snapshot.tokens_saved
+        }
+    }
+
+}
+
+/// What happened within one bucket; `start` is inclusive
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct HistoryBucket {
+    pub start: String,
+    pub requests: u64,
+    pub hits: u64,
+    pub misses: u64,
+    // None for a bucket without requests
+    pub hit_rate_percent: Option<f64>,
+    pub tokens_used: u64,
+    pub tokens_saved: u64,
+    pub cost_spent_usd: f64,
+    pub cost_saved_usd: f64
+}
+
+#[derive(Debug, Default)]
+pub struct MetricsHistory {
+    samples: Mutex<VecDeque<Sample>>
+}
+
+impl MetricsHistory {
+
+    /// Adds a sample of the running totals, dropping the oldest past 24 hours
+    pub fn record(&self, snapshot: &MetricsSnapshot) {
+        self.record_at(Utc::now(), snapshot);
+    }
+
+    fn record_at(&self, at: DateTime<Utc>, snapshot: &MetricsSnapshot) {
+
+        let mut samples = self.samples.lock().unwrap();
+        if samples.len() == HISTORY_CAPACITY {
+            samples.pop_front();
+        }
+        samples.push_back(Sample::of(at, snapshot));
+
+    }
+
+    /// Buckets `bucket` wide covering the `window` up to now, oldest first.
+    /// `current` is taken as a final sample, so the latest bucket is up to date.
+    /// `cost_per_token` prices the tokens
+    pub fn buckets(&self, window: Duration, bucket: Duration, current: &MetricsSnapshot, cost_per_token: f64) -> Vec<HistoryBucket> {
+        self.buckets_at(Utc::now(), window, bucket, current, cost_per_token)
+    }
+
+    fn buckets_at(
+        &self,
+        now: DateTime<Utc>,
+        window: Duration,
+        bucket: Duration,
+        current: &MetricsSnapshot,
+        cost_per_token: f64
+    ) -> Vec<HistoryBucket> {
+
+        let width = chrono::Duration::from_std(bucket).unwrap_or(chrono::Duration::MAX);
+        let count = (window.as_secs() / bucket.as_secs().max(1)).max(1) as i32;
+        let start = now - width * count;
+
+        let mut buckets: Vec<HistoryBucket> = (0..count)
+            .map(|i| HistoryBucket { start: (start + width * i).to_rfc3339(), ..Default::default() })
+            .collect();
+
+        let mut samples: Vec<Sample> = self.samples.lock().unwrap().iter().copied().collect();
+        samples.push(Sample::of(now, current));
+
+        // a change is counted in the bucket of the sample that saw it
+        for pair in samples.windows(2) {
+            let (before, after) = (pair[0], pair[1]);
+            if after.at <= start {
+                continue;
+            }
+            let index = ((after.at - start).num_milliseconds() - 1) / width.num_milliseconds().max(1);
+            let Some(bucket) = buckets.get_mut(index.max(0) as usize) else {
+                continue;
+            };
+            bucket.requests += after.requests.saturating_sub(before.requests);
+            bucket.hits += after.hits.saturating_sub(before.hits);
+            bucket.misses += after.misses.saturating_sub(before.misses);
+            bucket.tokens_used += after.tokens_used.saturating_sub(before.tokens_used);
+            bucket.tokens_saved += after.tokens_saved.saturating_sub(before.tokens_saved);
+        }
+
+        for bucket in &mut buckets {
+            bucket.hit_rate_percent = (bucket.requests > 0)
+                .then(|| (bucket.hits as f64 / bucket.requests as f64 * 10000.0).round() / 100.0);
+            bucket.cost_spent_usd = bucket.tokens_used as f64 * cost_per_token;
+            bucket.cost_saved_usd = bucket.tokens_saved as f64 * cost_per_token;
+        }
+        buckets
+
+    }
+
+}
+
+/// Parses a span like `30s`, `5m`, `24h` or `1d`
+pub fn parse_span(value: &str) -> Option<Duration> {
+
+    let value = value.trim();
+    let split = value.len().checked_sub(1)?;
+    let (number, unit) = value.split_at(split);
+    let number: u64 = number.parse().ok()?;
+    let secs = match unit {
+        "s" => number,
+        "m" => number.checked_mul(60)?,
+        "h" => number.checked_mul(3600)?,
+        "d" => number.checked_mul(86400)?,
+        _ => return None
+    };
+    (secs > 0).then(|| Duration::from_secs(secs))
+
+}
+
+/// The longest window `/metrics/history` can cover
+pub fn max_window() -> Duration {
+    HISTORY_RESOLUTION * HISTORY_CAPACITY as u32
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn totals(requests: u64, hits: u64, tokens_used: u64) -> MetricsSnapshot {
+        MetricsSnapshot { total_requests: requests, exact_hits: hits, misses: requests - hits, tokens_used, ..Default::default() }
+    }
+
+    #[test]
+    fn test_changes_are_added_up_per_bucket() {
+
+        let history = MetricsHistory::default();
+        let now = DateTime::parse_from_rfc3339("2026-10-15T12:00:00Z").unwrap().with_timezone(&Utc);
+        let minutes_ago = |m: i64| now - chrono::Duration::minutes(m);
+
+        history.record_at(minutes_ago(12), &totals(0, 0, 0));
+        history.record_at(minutes_ago(9), &totals(4, 1, 300));
+        history.record_at(minutes_ago(7), &totals(10, 5, 500));
+        history.record_at(minutes_ago(1), &totals(10, 5, 500));
+
+        let buckets = history.buckets_at(now, Duration::from_secs(600), Duration::from_secs(300), &totals(12, 7, 500), 0.001);
+
+        assert_eq!(buckets.len(), 2);
+        assert_eq!(buckets[0].start, "2026-10-15T11:50:00+00:00");
+        assert_eq!((buckets[0].requests, buckets[0].hits, buckets[0].tokens_used), (10, 5, 500));
+        assert_eq!(buckets[0].hit_rate_percent, Some(50.0));
+        assert!((buckets[0].cost_spent_usd - 0.5).abs() < 1e-9);
+        assert_eq!((buckets[1].requests, buckets[1].hits), (2, 2), "The current totals fill the latest bucket");
+
+        let quiet = history.buckets_at(now, Duration::from_secs(60), Duration::from_secs(60), &totals(10, 5, 500), 0.001);
+        assert_eq!(quiet[0].hit_rate_percent, None);
+
+    }
+
+    #[test]
+    fn test_parse_span() {
+
+        assert_eq!(parse_span("24h"), Some(Duration::from_secs(86400)));
+        assert_eq!(parse_span("5m"), Some(Duration::from_secs(300)));
+        assert_eq!(parse_span("1d"), Some(Duration::from_secs(86400)));
+        assert_eq!(parse_span("0m"), None);
+        assert_eq!(parse_span("5 minutes"), None);
+        assert_eq!(parse_span(""), None);
+
+    }
+
+}
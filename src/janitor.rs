@@ -0,0 +1,126 @@
+// Periodic cache maintenance, run every JANITOR_INTERVAL_SECS by
+// `background::spawn_janitor`. Each pass deletes expired semantic points,
+// trims the collection to SEMANTIC_MAX_POINTS, re-reads the entry and
+// point counts reported under "maintenance" in /metrics, and logs a summary.
+
+use std::time::Instant;
+use chrono::Utc;
+use serde::Serialize;
+use crate::AppState;
+use crate::metrics::ErrorCategory;
+
+/// What one maintenance pass did. A figure is None when its tier is
+/// disabled or the step failed (the failure is recorded as an error)
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct MaintenanceSummary {
+    pub ran_at: String,
+    pub duration_ms: u64,
+    pub expired_points_deleted: Option<u64>,
+    pub points_trimmed: Option<u64>,
+    pub exact_entries: Option<u64>,
+    pub semantic_points: Option<u64>
+}
+
+/// Runs one maintenance pass and keeps its summary in `AppState::maintenance`
+pub async fn run_maintenance(state: &AppState) -> MaintenanceSummary {
+
+    let started = Instant::now();
+    let mut summary = MaintenanceSummary { ran_at: Utc::now().to_rfc3339(), ..Default::default() };
+
+    if let Some(semantic_cache) = &state.semantic_cache {
+        let collection = semantic_cache.collection_name();
+        let qdrant_error = |e: &dyn std::fmt::Display| {
+            state.metrics.record_error(ErrorCategory::QdrantError, format!("Maintenance failed: {}", e), None);
+        };
+
+        // Qdrant doesn't report how many points a filtered delete removed
+        let expired = async {
+            let before = semantic_cache.collection_points(&collection).await?;
+            semantic_cache.delete_expired().await?;
+            let after = semantic_cache.collection_points(&collection).await?;
+            Ok::<_, crate::cache::CacheError>((before.saturating_sub(after), after))
+        }.await;
+
+        match expired {
+            Ok((deleted, remaining)) => {
+                summary.expired_points_deleted = Some(deleted);
+                summary.semantic_points = Some(remaining);
+            }
+            Err(e) => qdrant_error(&e)
+        }
+
+        let max_points = state.config.semantic_max_points;
+        if max_points > 0 && summary.semantic_points.is_some_and(|points| points > max_points) {
+            match semantic_cache.trim_to(max_points).await {
+                Ok(trimmed) => {
+                    summary.points_trimmed = Some(trimmed);
+                    summary.semantic_points = summary.semantic_points.map(|points| points.saturating_sub(trimmed));
+                }
+                Err(e) => qdrant_error(&e)
+            }
+        }
+
+        // refreshes the counts /admin/stats reads without a round trip
+        if let Err(e) = semantic_cache.collection_stats().await {
+            qdrant_error(&e);
+        }
+    }
+
+    if let Some(exact_cache) = &state.exact_cache {
+        match exact_cache.count_keys_matching(&format!("{}*", state.config.exact_key_prefix())).await {
+            Ok(entries) => summary.exact_entries = Some(entries),
+            Err(e) => state.metrics.record_error(ErrorCategory::RedisError, format!("Maintenance failed: {}", e), None)
+        }
+    }
+
+    summary.duration_ms = started.elapsed().as_millis() as u64;
+
+    let figure = |value: Option<u64>| value.map_or("-".to_string(), |value| value.to_string());
+    tracing::info!(
+        "Maintenance: {} expired points deleted, {} trimmed, {} exact entries, {} semantic points ({}ms)",
+        figure(summary.expired_points_deleted), figure(summary.points_trimmed),
+        figure(summary.exact_entries), figure(summary.semantic_points), summary.duration_ms
+    );
+
+    *state.maintenance.lock().unwrap() = Some(summary.clone());
+    summary
+
+}
+
+#[cfg(all(test, feature = "mock"))]
+mod tests {
+
+    use super::*;
+    use crate::config::Config;
+    use crate::mock::fake_embedding;
+    use crate::test_helpers::test_params;
+
+    #[tokio::test]
+    async fn test_maintenance_expires_and_trims_points() {
+
+        let state = AppState::new(Config::from_lookup(|name| match name {
+            "SEMANTIC_MAX_POINTS" => Some("2".to_string()),
+            _ => None
+        }).unwrap()).await;
+        let semantic_cache = state.semantic_cache.as_ref().unwrap();
+
+        for (key, ttl) in [("expired", 0), ("a", 60), ("b", 120), ("c", 180)] {
+            semantic_cache.store(key, key, fake_embedding(key), "{}", &test_params(0.0), 10, ttl).await.unwrap();
+        }
+        state.exact_cache.as_ref().unwrap().set_with_ttl(&format!("{}a", state.config.exact_key_prefix()), "{}", 60).await.unwrap();
+
+        let summary = run_maintenance(&state).await;
+
+        assert_eq!(summary.expired_points_deleted, Some(1));
+        assert_eq!(summary.points_trimmed, Some(1));
+        assert_eq!(summary.semantic_points, Some(2));
+        assert_eq!(summary.exact_entries, Some(1));
+        assert!(state.maintenance.lock().unwrap().is_some());
+
+        // the point closest to expiring went first
+        let remaining = semantic_cache.search_paginated(fake_embedding("a"), 0.99, 10, None, None).await.unwrap();
+        assert!(remaining.is_empty());
+
+    }
+
+}
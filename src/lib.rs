@@ -0,0 +1,8 @@
+// Library surface of the proxy: the OpenAI-compatible request/response
+// types and Groq pricing shared with the binary and, behind the
+// `client-sdk` feature, a typed client for services that call a running proxy.
+
+pub mod models;
+pub mod pricing;
+#[cfg(feature = "client-sdk")]
+pub mod client_sdk;
@@ -0,0 +1,46 @@
+// In-process embeddings for EMBEDDING_PROVIDER=local, built with the
+// `local-embeddings` feature. all-MiniLM-L6-v2 runs through ONNX Runtime,
+// which is loaded from ORT_DYLIB_PATH (or the system library path) at
+// startup. The model itself is downloaded to FASTEMBED_CACHE_DIR on first
+// use. It produces the same 384-dim vectors as the HTTP embedding service.
+
+use std::sync::{Mutex, OnceLock};
+use fastembed::{EmbeddingModel, TextEmbedding, TextInitOptions};
+
+type EmbeddingError = Box<dyn std::error::Error + Send + Sync>;
+
+// loaded once; a failed load is kept too, so every call reports it rather than retrying the download
+static MODEL: OnceLock<Result<Mutex<TextEmbedding>, String>> = OnceLock::new();
+
+fn model() -> Result<&'static Mutex<TextEmbedding>, EmbeddingError> {
+
+    MODEL.get_or_init(|| {
+        let mut options = TextInitOptions::new(EmbeddingModel::AllMiniLML6V2).with_show_download_progress(false);
+        if let Ok(cache_dir) = std::env::var("FASTEMBED_CACHE_DIR") {
+            options = options.with_cache_dir(cache_dir.into());
+        }
+        tracing::info!("Loading local embedding model all-MiniLM-L6-v2");
+        TextEmbedding::try_new(options)
+            .map(Mutex::new)
+            .map_err(|e| format!("Failed to load the local embedding model: {}", e))
+    }).as_ref().map_err(|e| e.clone().into())
+
+}
+
+/// Embeds `text` on a blocking thread, as inference holds the CPU for milliseconds
+pub async fn embed(text: &str) -> Result<Vec<f32>, EmbeddingError> {
+
+    let text = text.to_string();
+    tokio::task::spawn_blocking(move || {
+        let mut model = model()?.lock().unwrap();
+        model.embed([text], None)?
+            .pop()
+            .ok_or_else(|| "No embedding in the model output".into())
+    }).await?
+
+}
+
+/// Whether the model is loaded, loading it on the first call
+pub async fn health() -> bool {
+    tokio::task::spawn_blocking(|| model().is_ok()).await.unwrap_or(false)
+}
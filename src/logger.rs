@@ -1,30 +1,615 @@
-use std::fs::OpenOptions;
-use std::io::Write;
-use chrono::Utc;
-
-pub fn log_request(
-    cache_status: &str,
-    model: &str,
-    tokens: u64,
-    cost: f64,
-) {
-    let timestamp = Utc::now().format("%Y-%m-%d %H:%M:%S");
-    let log_entry = format!(
-        "{} | {:13} | {:30} | {:8} tokens | ${:.5}\n",
-        timestamp, cache_status, model, tokens, cost
-    );
-
-    // Use /app/requests.log in Docker, ./requests.log locally
-    let log_path = std::env::var("LOG_PATH")
-        .unwrap_or_else(|_| "./requests.log".to_string());
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tracing_subscriber::EnvFilter;
+use tracing_subscriber::fmt::{SubscriberBuilder, format::{DefaultFields, Format, Json, JsonFields}, format::FmtSpan};
+
+/// When the request log is rotated: past a size, on an hourly or daily
+/// boundary (UTC), or both. Past `retain_files` the oldest rotated files are deleted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LogRotation {
+    // 0 is no size limit
+    pub max_bytes: u64,
+    pub every: RotateEvery,
+    // 0 keeps every rotated file
+    pub retain_files: usize
+}
+
+impl Default for LogRotation {
+    fn default() -> Self {
+        LogRotation { max_bytes: 100 * 1024 * 1024, every: RotateEvery::Daily, retain_files: 7 }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RotateEvery {
+    Never,
+    Hourly,
+    #[default]
+    Daily
+}
+
+impl RotateEvery {
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "never" | "off" => Some(RotateEvery::Never),
+            "hourly" => Some(RotateEvery::Hourly),
+            "daily" => Some(RotateEvery::Daily),
+            _ => None
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RotateEvery::Never => "never",
+            RotateEvery::Hourly => "hourly",
+            RotateEvery::Daily => "daily"
+        }
+    }
+
+    // which hour or day `at` falls in; a change means it's time to rotate
+    fn period_of(&self, at: DateTime<Utc>) -> Option<i64> {
+        match self {
+            RotateEvery::Never => None,
+            RotateEvery::Hourly => Some(at.timestamp().div_euclid(3600)),
+            RotateEvery::Daily => Some(at.timestamp().div_euclid(86400))
+        }
+    }
+
+}
+
+/// One line of the request log. Cache hits, misses and background refreshes
+/// all get one; `request_id` and `latency_ms` are absent for background work
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RequestLogEntry {
+    pub timestamp: String,
+    pub request_id: Option<String>,
+    pub tenant: Option<String>,
+    // the PROXY_API_KEYS name, never the key
+    pub api_key: Option<String>,
+    // e.g. "EXACT_HIT", "MISS" or "REFRESH"
+    pub cache_status: String,
+    pub model: String,
+    pub latency_ms: Option<u64>,
+    pub tokens: u64,
+    pub cost_usd: f64
+}
+
+struct OpenLog {
+    file: File,
+    size: u64,
+    period: Option<i64>
+}
+
+/// The request log at LOG_PATH: a line of JSON per request, rotated to
+/// `<LOG_PATH>.<timestamp>` as `LogRotation` says
+pub struct RequestLog {
+    path: PathBuf,
+    rotation: LogRotation,
+    // opened on the first write
+    open: Mutex<Option<OpenLog>>
+}
+
+impl RequestLog {
+
+    pub fn new(path: impl Into<PathBuf>, rotation: LogRotation) -> Self {
+        RequestLog { path: path.into(), rotation, open: Mutex::new(None) }
+    }
+
+    pub fn write(&self, entry: &RequestLogEntry) {
+        self.write_at(entry, Utc::now());
+    }
+
+    fn write_at(&self, entry: &RequestLogEntry, now: DateTime<Utc>) {
+
+        let Ok(mut line) = serde_json::to_string(entry) else {
+            tracing::error!("Failed to serialize request log entry");
+            return;
+        };
+        line.push('\n');
+
+        let mut open = self.open.lock().unwrap();
+        let period = self.rotation.every.period_of(now);
+        let full = |log: &OpenLog| self.rotation.max_bytes > 0 && log.size > 0 && log.size + line.len() as u64 > self.rotation.max_bytes;
+
+        if open.is_none() {
+            *open = self.open_file();
+        }
+        // an empty file is never rotated, only taken over for the current period
+        if let Some(log) = open.as_mut()
+            && log.size == 0 {
+            log.period = period;
+        }
+        if let Some(log) = open.as_ref()
+            && (full(log) || log.period != period) {
+            *open = None;
+            self.rotate(now);
+            *open = self.open_file();
+        }
+
+        let Some(log) = open.as_mut() else {
+            tracing::error!("Failed to write to log file: {}", self.path.display());
+            return;
+        };
+        if log.file.write_all(line.as_bytes()).is_ok() {
+            log.size += line.len() as u64;
+        }
+
+    }
+
+    // the period is that of the file's last write, so a restart the next day still rotates
+    fn open_file(&self) -> Option<OpenLog> {
+
+        let file = OpenOptions::new().create(true).append(true).open(&self.path).ok()?;
+        let metadata = file.metadata().ok()?;
+        let modified = metadata.modified().map(DateTime::<Utc>::from).unwrap_or_else(|_| Utc::now());
+        Some(OpenLog { file, size: metadata.len(), period: self.rotation.every.period_of(modified) })
+
+    }
+
+    // moves the current file aside, then deletes the oldest rotated files past `retain_files`
+    fn rotate(&self, now: DateTime<Utc>) {
+
+        let rotated = format!("{}.{}", self.path.display(), now.format("%Y%m%dT%H%M%S%.3fZ"));
+        if let Err(e) = std::fs::rename(&self.path, &rotated) {
+            tracing::error!("Failed to rotate {}: {}", self.path.display(), e);
+            return;
+        }
+
+        let rotated = self.rotated_files();
+        if self.rotation.retain_files > 0 && rotated.len() > self.rotation.retain_files {
+            for old in &rotated[..rotated.len() - self.rotation.retain_files] {
+                if let Err(e) = std::fs::remove_file(old) {
+                    tracing::warn!("Failed to delete old request log {}: {}", old.display(), e);
+                }
+            }
+        }
+
+    }
+
+    /// Rotated files, oldest first
+    pub fn rotated_files(&self) -> Vec<PathBuf> {
+
+        let Some(name) = self.path.file_name().and_then(|name| name.to_str()) else {
+            return Vec::new();
+        };
+        let prefix = format!("{}.", name);
+        let dir = match self.path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+            _ => PathBuf::from(".")
+        };
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            return Vec::new();
+        };
+
+        // the timestamps sort in time order
+        let mut rotated: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_name().to_str().is_some_and(|file| file.starts_with(&prefix)))
+            .map(|entry| entry.path())
+            .collect();
+        rotated.sort();
+        rotated
+
+    }
+
+}
+
+/// Subscriber for the `tracing` spans on cache, client and handler functions.
+/// Spans are debug level, so `RUST_LOG=debug` shows every function entry
+/// and exit (with its duration); without `RUST_LOG` only info and above is shown
+pub fn tracing_subscriber(filter: EnvFilter) -> SubscriberBuilder<DefaultFields, Format, EnvFilter> {
+
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_span_events(FmtSpan::NEW | FmtSpan::CLOSE)
+        .with_target(false)
+
+}
+
+/// LOG_FORMAT: one JSON object per line (the default) for log aggregation,
+/// or `text` for reading in a terminal
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    #[default]
+    Json,
+    Text
+}
+
+impl LogFormat {
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "json" => Some(LogFormat::Json),
+            "text" => Some(LogFormat::Text),
+            _ => None
+        }
+    }
+
+}
+
+/// Like `tracing_subscriber`, with each event written as a line of JSON. `span`
+/// holds the innermost span's fields and `spans` every span the event is in, so
+/// a line logged while serving a request carries its method, path and id
+pub fn json_tracing_subscriber(filter: EnvFilter) -> SubscriberBuilder<JsonFields, Format<Json>, EnvFilter> {
+
+    tracing_subscriber::fmt()
+        .json()
+        .with_span_list(true)
+        .with_env_filter(filter)
+        .with_span_events(FmtSpan::NEW | FmtSpan::CLOSE)
+        .with_target(false)
+
+}
+
+// read from the environment directly, like RUST_LOG, since logging starts before the config is loaded
+pub fn init_tracing() {
+
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let format = std::env::var("LOG_FORMAT").ok();
+    match format.as_deref().map(LogFormat::parse) {
+        Some(Some(LogFormat::Text)) => tracing_subscriber(filter).init(),
+        Some(None) => {
+            json_tracing_subscriber(filter).init();
+            tracing::warn!("Unknown LOG_FORMAT '{}', using json", format.unwrap_or_default());
+        }
+        _ => json_tracing_subscriber(filter).init()
+    }
+
+}
+
+/// One admin operation, written as a line of JSON to the audit log
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub timestamp: String,
+    // e.g. "PUT /admin/config"
+    pub endpoint: String,
+    pub params: serde_json::Value,
+    // masked admin token, never the token itself
+    pub token_id: Option<String>,
+    pub client_ip: Option<String>,
+    pub status: u16,
+    pub outcome: String
+}
+
+// admin calls whose entries may wait for the writer before new ones are dropped
+const AUDIT_QUEUE: usize = 1024;
+// how much of the audit log is read at a time, from the end, by `read_audit`
+const AUDIT_TAIL_CHUNK: u64 = 64 * 1024;
+
+/// The audit log at AUDIT_LOG_PATH. Entries are handed to a blocking task that
+/// appends them, so an admin call never waits on the disk
+pub struct AuditLog {
+    path: String,
+    sender: mpsc::Sender<AuditEntry>
+}
+
+impl AuditLog {
+
+    /// Starts the writer; it stops once the `AuditLog` is dropped
+    pub fn new(path: &str) -> Self {
+
+        let (sender, mut receiver) = mpsc::channel::<AuditEntry>(AUDIT_QUEUE);
+        let writer_path = path.to_string();
+        tokio::task::spawn_blocking(move || {
+            while let Some(entry) = receiver.blocking_recv() {
+                log_audit(&writer_path, &entry);
+            }
+        });
+        AuditLog { path: path.to_string(), sender }
+
+    }
+
+    pub fn write(&self, entry: AuditEntry) {
+        if let Err(e) = self.sender.try_send(entry) {
+            tracing::error!(path = %self.path, "audit entry dropped: {}", e);
+        }
+    }
+
+    /// `read_audit` off the async runtime
+    pub async fn recent(&self, limit: usize) -> Vec<AuditEntry> {
+        let path = self.path.clone();
+        tokio::task::spawn_blocking(move || read_audit(&path, limit)).await.unwrap_or_default()
+    }
+
+}
+
+/// Appends an entry to the audit log. The file is only ever appended to
+pub fn log_audit(path: &str, entry: &AuditEntry) {
+
+    let Ok(mut line) = serde_json::to_string(entry) else {
+        tracing::error!("Failed to serialize audit entry for {}", entry.endpoint);
+        return;
+    };
+    line.push('\n');
 
     if let Ok(mut file) = OpenOptions::new()
         .create(true)
         .append(true)
-        .open(&log_path)
+        .open(path)
     {
-        let _ = file.write_all(log_entry.as_bytes());
+        let _ = file.write_all(line.as_bytes());
     } else {
-        eprintln!("Failed to write to log file: {}", log_path);
+        tracing::error!("Failed to write to audit log: {}", path);
+    }
+
+}
+
+/// Returns up to `limit` of the most recent audit entries, newest first.
+/// The file is read backwards a chunk at a time, stopping once `limit` are found
+pub fn read_audit(path: &str, limit: usize) -> Vec<AuditEntry> {
+
+    let Ok(mut file) = File::open(path) else {
+        return Vec::new();
+    };
+    let mut position = file.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+    let mut entries = Vec::new();
+    // the start of a line whose beginning is in the chunk before
+    let mut partial: Vec<u8> = Vec::new();
+
+    while position > 0 && entries.len() < limit {
+        let read = AUDIT_TAIL_CHUNK.min(position);
+        position -= read;
+        let mut chunk = vec![0; read as usize];
+        if file.seek(SeekFrom::Start(position)).and_then(|_| file.read_exact(&mut chunk)).is_err() {
+            break;
+        }
+        chunk.append(&mut partial);
+
+        // before the first newline is only part of a line, unless this is the start of the file
+        let complete_from = match chunk.iter().position(|byte| *byte == b'\n') {
+            _ if position == 0 => 0,
+            Some(newline) => newline + 1,
+            None => {
+                partial = chunk;
+                continue;
+            }
+        };
+        let lines = chunk[complete_from..].split(|byte| *byte == b'\n').rev();
+        entries.extend(lines.filter_map(|line| serde_json::from_slice::<AuditEntry>(line).ok()).take(limit - entries.len()));
+        chunk.truncate(complete_from);
+        partial = chunk;
+    }
+
+    entries
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct Capture(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Capture {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_trace_level_shows_spans() {
+
+        let capture = Capture::default();
+        let writer = capture.clone();
+        let subscriber = tracing_subscriber(EnvFilter::new("trace"))
+            .with_ansi(false)
+            .with_writer(move || writer.clone())
+            .finish();
+
+        // same as running with RUST_LOG=trace. Nothing listens on port 9, so the
+        // call fails fast but its span still opens and closes
+        let _guard = tracing::subscriber::set_default(subscriber);
+        let _ = crate::cache::get_embedding(&reqwest::Client::new(), &crate::config::EmbeddingEndpoint::http("http://127.0.0.1:9/embed"), "hello").await;
+
+        let output = String::from_utf8(capture.0.lock().unwrap().clone()).unwrap();
+        assert!(output.contains("get_embedding"), "Expected the span name in: {}", output);
+        assert!(output.contains("close"), "Expected the span exit in: {}", output);
+
+    }
+
+    #[tokio::test]
+    async fn test_json_logs_carry_the_request_span() {
+
+        use axum::{Router, body::Body, http::Request, routing::get};
+        use tower::ServiceExt;
+
+        let capture = Capture::default();
+        let writer = capture.clone();
+        let subscriber = json_tracing_subscriber(EnvFilter::new("info"))
+            .with_writer(move || writer.clone())
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let app = Router::new()
+            .route("/ping", get(|| async {
+                tracing::info!(cache = "hit", "cache hit");
+                "pong"
+            }))
+            .layer(axum::middleware::from_fn(crate::middleware::request_span));
+        let response = app.oneshot(Request::get("/ping").header("x-request-id", "req-42").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.headers()["x-request-id"], "req-42");
+
+        let output = String::from_utf8(capture.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<serde_json::Value> = output.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+
+        let hit = lines.iter().find(|line| line["fields"]["message"] == "cache hit").expect("the handler's event");
+        assert_eq!(hit["fields"]["cache"], "hit");
+        assert_eq!(hit["level"], "INFO");
+        let span = &hit["spans"][0];
+        assert_eq!((span["name"].as_str(), span["request_id"].as_str(), span["path"].as_str()), (Some("request"), Some("req-42"), Some("/ping")));
+
+        let close = lines.iter().find(|line| line["fields"]["message"] == "close").expect("the span's close event");
+        assert_eq!(close["span"]["status"], 200);
+
+    }
+
+    #[test]
+    fn test_log_format_parse() {
+
+        assert_eq!(LogFormat::parse("JSON"), Some(LogFormat::Json));
+        assert_eq!(LogFormat::parse("text"), Some(LogFormat::Text));
+        assert_eq!(LogFormat::parse("pretty"), None);
+
+    }
+
+    fn entry(cache_status: &str) -> RequestLogEntry {
+        RequestLogEntry {
+            timestamp: Utc::now().to_rfc3339(),
+            request_id: Some("req-1".to_string()),
+            tenant: Some("team-a".to_string()),
+            api_key: None,
+            cache_status: cache_status.to_string(),
+            model: "llama-3.1-8b-instant".to_string(),
+            latency_ms: Some(12),
+            tokens: 30,
+            cost_usd: 0.00002
+        }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_request_log_rotates_by_size_and_keeps_the_newest_files() {
+
+        let dir = std::env::temp_dir().join(format!("request_log_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("requests.log");
+        let line_len = serde_json::to_string(&entry("MISS")).unwrap().len() as u64 + 1;
+        let log = RequestLog::new(&path, LogRotation { max_bytes: line_len * 2, every: RotateEvery::Never, retain_files: 2 });
+
+        let start = Utc::now();
+        for i in 0..7 {
+            log.write_at(&entry(if i == 6 { "EXACT_HIT" } else { "MISS" }), start + chrono::Duration::seconds(i));
+        }
+
+        // 7 lines at 2 per file: 3 rotations, of which the 2 newest are kept
+        let rotated = log.rotated_files();
+        let current = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_dir_all(&dir).ok();
+
+        assert_eq!(rotated.len(), 2);
+        assert_eq!(current.lines().count(), 1);
+        let last: RequestLogEntry = serde_json::from_str(current.trim()).unwrap();
+        assert_eq!((last.cache_status.as_str(), last.tenant.as_deref(), last.latency_ms), ("EXACT_HIT", Some("team-a"), Some(12)));
+
+    }
+
+    #[test]
+    fn test_request_log_rotates_when_the_day_changes() {
+
+        let dir = std::env::temp_dir().join(format!("request_log_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("requests.log");
+        let log = RequestLog::new(&path, LogRotation { max_bytes: 0, ..LogRotation::default() });
+
+        let evening = DateTime::parse_from_rfc3339("2026-10-15T23:59:00Z").unwrap().with_timezone(&Utc);
+        log.write_at(&entry("MISS"), evening);
+        log.write_at(&entry("MISS"), evening + chrono::Duration::seconds(30));
+        log.write_at(&entry("MISS"), evening + chrono::Duration::minutes(2));
+
+        let rotated = log.rotated_files();
+        let current = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_dir_all(&dir).ok();
+
+        assert_eq!(rotated.len(), 1);
+        assert!(rotated[0].to_str().unwrap().ends_with("requests.log.20261016T000100.000Z"), "{:?}", rotated);
+        assert_eq!(current.lines().count(), 1);
+
+    }
+
+    #[test]
+    fn test_audit_log_round_trip() {
+
+        let path = std::env::temp_dir().join(format!("audit_test_{}.log", uuid::Uuid::new_v4()));
+        let path = path.to_str().unwrap();
+
+        for status in [200, 401, 500] {
+            log_audit(path, &AuditEntry {
+                timestamp: Utc::now().to_rfc3339(),
+                endpoint: "POST /admin/cache/clear".to_string(),
+                params: serde_json::Value::Null,
+                token_id: Some("****abcd".to_string()),
+                client_ip: Some("127.0.0.1".to_string()),
+                status,
+                outcome: if status < 400 { "success" } else { "failure" }.to_string()
+            });
+        }
+
+        let recent = read_audit(path, 2);
+        std::fs::remove_file(path).ok();
+
+        assert_eq!(recent.len(), 2);
+        assert_eq!(recent[0].status, 500, "Newest entry should come first");
+        assert_eq!(recent[1].status, 401);
+
+    }
+
+    #[test]
+    fn test_read_audit_tails_past_a_chunk() {
+
+        let path = std::env::temp_dir().join(format!("audit_test_{}.log", uuid::Uuid::new_v4()));
+        let path = path.to_str().unwrap();
+
+        // about 1.5 MB of entries, so lines straddle many chunk boundaries
+        for status in 0..6000u16 {
+            log_audit(path, &AuditEntry {
+                timestamp: Utc::now().to_rfc3339(),
+                endpoint: "PUT /admin/config".to_string(),
+                params: serde_json::json!({"query": "x".repeat(status as usize % 100)}),
+                token_id: None,
+                client_ip: None,
+                status,
+                outcome: "success".to_string()
+            });
+        }
+
+        let recent = read_audit(path, 5000);
+        std::fs::remove_file(path).ok();
+
+        assert_eq!(recent.len(), 5000);
+        assert!(recent.iter().zip((1000..6000).rev()).all(|(entry, status)| entry.status == status));
+        assert_eq!(read_audit(path, 10).len(), 0, "A missing file has no entries");
+
+    }
+
+    #[tokio::test]
+    async fn test_audit_log_writes_in_the_background() {
+
+        let path = std::env::temp_dir().join(format!("audit_test_{}.log", uuid::Uuid::new_v4()));
+        let audit = AuditLog::new(path.to_str().unwrap());
+
+        audit.write(AuditEntry {
+            timestamp: Utc::now().to_rfc3339(),
+            endpoint: "GET /admin/stats".to_string(),
+            params: serde_json::Value::Null,
+            token_id: None,
+            client_ip: None,
+            status: 200,
+            outcome: "success".to_string()
+        });
+
+        let mut recent = Vec::new();
+        for _ in 0..50 {
+            recent = audit.recent(10).await;
+            if !recent.is_empty() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        std::fs::remove_file(&path).ok();
+        assert_eq!(recent.len(), 1);
+
+    }
+
+}
@@ -1,17 +1,67 @@
-mod models;
 mod handlers;
+mod backend;
+mod semantic;
+#[cfg_attr(feature = "mock", allow(dead_code))]
+mod anthropic;
+#[cfg_attr(feature = "mock", allow(dead_code))]
 mod client;
+#[cfg_attr(feature = "mock", allow(dead_code))]
 mod cache;
 mod metrics;
 mod logger;
+mod background;
+mod config;
+mod middleware;
+mod migrate;
+mod reembed;
+mod selftest;
+mod stream;
+mod coalesce;
+mod ratelimit;
+mod quota;
+mod budget;
+mod breaker;
+mod janitor;
+mod metrics_store;
+mod history;
+mod request_store;
+#[cfg(feature = "local-embeddings")]
+#[cfg_attr(feature = "mock", allow(dead_code))]
+mod local_embedding;
+#[cfg(feature = "mock")]
+mod mock;
+#[cfg(test)]
+mod test_helpers;
 
-use std::sync::Arc;
-use axum::{routing::{get, post, Router}};
+// the request/response types live in the library so client_sdk shares them
+use llm_cache_proxy::models;
+use std::sync::{Arc, Mutex};
+use std::num::NonZeroUsize;
+use std::time::{Duration, Instant};
+use arc_swap::ArcSwap;
+use dashmap::DashMap;
+use axum::{routing::{any, delete, get, post, Router}, error_handling::HandleErrorLayer, extract::DefaultBodyLimit};
+use tower::ServiceBuilder;
 use std::net::SocketAddr;
 use tokio::net::TcpListener;
+use backend::{ExactCache, MemoryBackend};
+use semantic::SemanticCache;
+use coalesce::InFlightRequests;
+use ratelimit::RateLimiter;
+use quota::QuotaTracker;
+use budget::BudgetTracker;
+use breaker::CircuitBreaker;
+use cache::{EntryParams, HotKeyTracker, InMemoryCache, check_embedding_service, get_embedding};
+#[cfg(not(feature = "mock"))]
 use cache::{RedisCache, QdrantCache};
+#[cfg(feature = "mock")]
+use mock::{MockRedisCache as RedisCache, MockQdrantCache as QdrantCache};
 use reqwest::Client;
-use metrics::Metrics;
+use metrics::{Metrics, MetricsSnapshot, StageLatencies};
+use history::MetricsHistory;
+use logger::{AuditLog, RequestLog};
+use request_store::RequestStore;
+use config::{CacheMode, Config, ExactCacheBackend, ConfigChange, ConfigSource, RuntimeConfig, mask_secret, mask_secret_keeping};
 
 // share the cache and http client with all the handles
 // http client is shared to avoid creating a new 
@@ -19,69 +69,1320 @@ use metrics::Metrics;
 // Also sharing the Qdrant cache
 #[derive(Clone)]
 pub struct AppState {
-    pub redis_cache: RedisCache,
-    pub qdrant_cache: QdrantCache,
+    // None when the tier is disabled (EXACT_CACHE_ENABLED / SEMANTIC_CACHE_ENABLED)
+    pub exact_cache: Option<ExactCache>,
+    pub semantic_cache: Option<SemanticCache>,
+    // in-process LRU checked before Redis; None when TIER0_CACHE_SIZE=0 or the exact tier is off
+    pub tier0_cache: Option<Arc<InMemoryCache>>,
+    // exact-match hits per key, for GET /admin/cache/hot
+    pub hot_keys: Arc<HotKeyTracker>,
     pub http_client: Client,
-    pub groq_api_key: String,
-    pub embedding_url: String,
-    pub metrics: Arc<Metrics>
+    pub metrics: Arc<Metrics>,
+    // latency histograms of the Redis, embedding, Qdrant and upstream calls
+    pub latencies: Arc<StageLatencies>,
+    // upstream response ids seen recently, for handlers::dedup_by_id
+    pub id_dedup: Arc<handlers::IdDedup>,
+    // upstream calls in progress, joined by identical misses
+    pub in_flight: InFlightRequests,
+    // exact-match keys being revalidated in the background, so a hot key is re-run once
+    pub revalidating: Arc<DashMap<String, ()>>,
+    // request path -> that route's share of `metrics`, for /metrics "endpoints"
+    pub per_endpoint: Arc<DashMap<String, Arc<Metrics>>>,
+    // the current request's entry in `per_endpoint`; None outside a tracked route
+    pub endpoint_metrics: Option<Arc<Metrics>>,
+    // metrics by proxy API key name, when PROXY_API_KEYS is set
+    pub per_api_key: Arc<DashMap<String, Arc<Metrics>>>,
+    // the current request's entry in `per_api_key`
+    pub key_metrics: Option<Arc<Metrics>>,
+    // request and token buckets per caller (RATE_LIMIT_*)
+    pub rate_limiter: Arc<RateLimiter>,
+    // who the current request is counted against, set by middleware::rate_limit
+    pub rate_limit_caller: Option<String>,
+    // the client's own upstream key under BYOK_ENABLED, sent in place of the configured one
+    pub upstream_key: Option<String>,
+    // the current request's tenant under TENANT_SOURCE, set by middleware::resolve_tenant
+    pub tenant: Option<String>,
+    // metrics by tenant, and the current request's entry
+    pub per_tenant: Arc<DashMap<String, Arc<Metrics>>>,
+    pub tenant_metrics: Option<Arc<Metrics>>,
+    // today's upstream usage per tenant, checked against TENANT_DAILY_*_QUOTA
+    pub quotas: Arc<QuotaTracker>,
+    // upstream spend this BUDGET_PERIOD, globally and per API key
+    pub budget: Arc<BudgetTracker>,
+    // name of the proxy API key the current request used
+    pub api_key_name: Option<String>,
+    // fails upstream calls fast while the provider is down (CIRCUIT_BREAKER_*)
+    pub circuit_breaker: Arc<CircuitBreaker>,
+    // immutable settings; everything hot-reloadable lives in `runtime`
+    pub config: Arc<Config>,
+    // hot-reloadable settings, swapped by PUT /admin/config and SIGHUP
+    pub runtime: Arc<ArcSwap<RuntimeConfig>>,
+    // last Redis/Qdrant size measurement for /admin/stats
+    pub storage_stats: Arc<Mutex<Option<(Instant, serde_json::Value)>>>,
+    // progress of the current or last POST /admin/cache/reembed job
+    pub reembed: Arc<Mutex<reembed::ReembedStatus>>,
+    // what the janitor's last pass did, for /metrics
+    pub maintenance: Arc<Mutex<Option<janitor::MaintenanceSummary>>>,
+    // the counters restored from METRICS_PERSIST at startup, subtracted for /metrics?scope=process
+    pub restored_metrics: Arc<Mutex<Option<MetricsSnapshot>>>,
+    // per-minute samples of `metrics` for /metrics/history
+    pub history: Arc<MetricsHistory>,
+    // the JSON request log at LOG_PATH
+    pub request_log: Arc<RequestLog>,
+    // the admin audit log at AUDIT_LOG_PATH, written in the background
+    pub audit_log: Arc<AuditLog>,
+    // request records for /admin/requests, when REQUEST_STORE_URL is set
+    pub request_store: Option<Arc<RequestStore>>
+}
+
+impl AppState {
+
+    /// Connects to every backing service described by the configuration.
+    /// With the `mock` feature the caches are in-memory and nothing external is contacted.
+    pub async fn new(mut config: Config) -> Self {
+
+        tracing::info!("Upstream: {}", config.upstream_base_url);
+
+        // reqwest honors HTTP_PROXY / HTTPS_PROXY / NO_PROXY from the environment
+        let http_client = Client::builder()
+            .timeout(config.reqwest_timeout())
+            .build()
+            .expect("Failed to build HTTP client");
+        if config.reqwest_timeout_secs >= config.request_timeout_secs {
+            tracing::warn!(
+                "REQWEST_TIMEOUT_SECS ({}) is not below REQUEST_TIMEOUT_SECS ({}) - slow upstream calls will be cut off by the request deadline",
+                config.reqwest_timeout_secs, config.request_timeout_secs
+            );
+        }
+
+        // create caches, skipping the backend of a disabled tier entirely.
+        // Under Docker Compose the services may still be starting, so each is retried
+        let exact_cache: Option<ExactCache> = match (config.exact_cache_enabled, config.exact_cache_backend) {
+            (false, _) => {
+                tracing::info!("Exact cache disabled - not connecting to Redis");
+                None
+            }
+            (true, ExactCacheBackend::Memory) => {
+                tracing::info!("Exact cache in memory ({} entries max) - not connecting to Redis", config.memory_cache_max_entries);
+                Some(Arc::new(MemoryBackend::new(config.memory_cache_max_entries)))
+            }
+            (true, ExactCacheBackend::Redis) => {
+                Some(Arc::new(connect_with_retry(&config, "Redis", || RedisCache::new(&config.redis_url)).await))
+            }
+        };
+
+        let semantic_cache: Option<SemanticCache> = if config.semantic_cache_enabled {
+            connect_with_retry(&config, "Embedding service", || async {
+                if check_embedding_service(&http_client, &config.embedding).await {
+                    Ok(())
+                } else {
+                    Err(format!("{} did not pass its health check", config.embedding.url))
+                }
+            }).await;
+            // the Qdrant collection is then created (or recreated) at that size
+            if config.detects_embedding_dim() {
+                match get_embedding(&http_client, &config.embedding, "dimension probe").await {
+                    Ok(embedding) if !embedding.is_empty() => {
+                        tracing::info!("Detected {}-dim embeddings from {}", embedding.len(), config.embedding.url);
+                        config.embedding_dim = embedding.len();
+                    }
+                    Ok(_) => tracing::warn!("Empty probe embedding - keeping EMBEDDING_DIM={}", config.embedding_dim),
+                    Err(e) => tracing::warn!("Embedding dimension probe failed: {} - keeping EMBEDDING_DIM={}", e, config.embedding_dim)
+                }
+            }
+            let semantic_cache = connect_with_retry(&config, "Qdrant", || {
+                QdrantCache::with_collection(&config.qdrant_url, &config.qdrant_collection, config.embedding_dim, config.qdrant_max_connections)
+            }).await;
+            Some(Arc::new(semantic_cache))
+        } else {
+            tracing::info!("Semantic cache disabled - not connecting to Qdrant or the embedding service");
+            None
+        };
+
+        let tier0_cache = NonZeroUsize::new(config.tier0_cache_size)
+            .filter(|_| config.exact_cache_enabled)
+            .map(|size| Arc::new(InMemoryCache::new(size, Duration::from_secs(config.tier0_ttl_secs))));
+
+        let hot_keys = Arc::new(HotKeyTracker::new(
+            NonZeroUsize::new(config.hot_key_tracker_size).expect("HOT_KEY_TRACKER_SIZE is at least 1")
+        ));
+
+        let metrics = Arc::new(Metrics::new());
+
+        let request_store = match &config.request_store_url {
+            Some(url) => Some(Arc::new(connect_with_retry(&config, "Request store", || RequestStore::connect(url)).await)),
+            None => None
+        };
+
+        if config.cache_mode == CacheMode::Shadow {
+            tracing::info!("Running in shadow mode - cache lookups are recorded but never served");
+        }
+
+        AppState {
+            exact_cache,
+            semantic_cache,
+            tier0_cache,
+            hot_keys,
+            http_client,
+            metrics,
+            latencies: Arc::new(StageLatencies::default()),
+            id_dedup: Arc::new(DashMap::new()),
+            in_flight: InFlightRequests::default(),
+            revalidating: Arc::new(DashMap::new()),
+            per_endpoint: Arc::new(DashMap::new()),
+            endpoint_metrics: None,
+            per_api_key: Arc::new(DashMap::new()),
+            key_metrics: None,
+            rate_limiter: Arc::new(RateLimiter::new(config.rate_limits)),
+            rate_limit_caller: None,
+            upstream_key: None,
+            tenant: None,
+            per_tenant: Arc::new(DashMap::new()),
+            tenant_metrics: None,
+            quotas: Arc::new(QuotaTracker::new(config.tenant_quotas)),
+            budget: Arc::new(BudgetTracker::new(config.budget)),
+            api_key_name: None,
+            circuit_breaker: Arc::new(CircuitBreaker::new(config.circuit_breaker)),
+            runtime: Arc::new(ArcSwap::from_pointee(config.runtime.clone())),
+            storage_stats: Arc::new(Mutex::new(None)),
+            reembed: Arc::new(Mutex::new(reembed::ReembedStatus::default())),
+            maintenance: Arc::new(Mutex::new(None)),
+            restored_metrics: Arc::new(Mutex::new(None)),
+            history: Arc::new(MetricsHistory::default()),
+            request_log: Arc::new(RequestLog::new(&config.log_path, config.log_rotation)),
+            audit_log: Arc::new(AuditLog::new(&config.audit_log_path)),
+            request_store,
+            config: Arc::new(config)
+        }
+
+    }
+
+    /// Prefix of the exact-match keys this request reads and writes. Each
+    /// tenant gets its own partition, and under BYOK so does each client key
+    /// within it, unless BYOK_SHARED_CACHE is set
+    pub fn cache_key_prefix(&self) -> String {
+
+        let prefix = self.config.exact_key_prefix();
+        match self.cache_partition() {
+            Some(partition) => format!("{}{}", prefix, partition),
+            None => prefix
+        }
+
+    }
+
+    /// Whether an entry stored under `cache_key` may be served to this request
+    pub fn in_cache_partition(&self, cache_key: &str) -> bool {
+
+        let Some(rest) = cache_key.strip_prefix(&self.config.exact_key_prefix()) else {
+            return self.cache_partition().is_none();
+        };
+        let rest = match self.cache_partition() {
+            Some(partition) => match rest.strip_prefix(&partition) {
+                Some(rest) => rest,
+                None => return false
+            },
+            None => rest
+        };
+        // nothing may follow but the hash, not a narrower partition
+        !rest.starts_with(cache::BYOK_PARTITION_PREFIX) && !rest.starts_with(cache::TENANT_PARTITION_PREFIX)
+
+    }
+
+    fn cache_partition(&self) -> Option<String> {
+
+        let tenant = self.tenant.as_deref().map(cache::tenant_partition);
+        let byok = self.upstream_key.as_deref()
+            .filter(|_| !self.config.byok.shared_cache)
+            .map(cache::byok_partition);
+
+        match (tenant, byok) {
+            (None, None) => None,
+            (tenant, byok) => Some(format!("{}{}", tenant.unwrap_or_default(), byok.unwrap_or_default()))
+        }
+
+    }
+
+    /// The parameters a semantic match for `request` must have been stored
+    /// with, including this request's tenant
+    pub fn entry_params(&self, request: &models::LLMRequest) -> EntryParams {
+
+        EntryParams { tenant: self.tenant.clone(), ..EntryParams::of(request) }
+
+    }
+
+    /// Records into the global metrics and, within a tracked route, into that
+    /// route's entry too, as well as the entries of the API key and tenant the
+    /// request used
+    pub fn record(&self, record: impl Fn(&Metrics)) {
+
+        record(&self.metrics);
+        if let Some(endpoint) = &self.endpoint_metrics {
+            record(endpoint);
+        }
+        if let Some(key) = &self.key_metrics {
+            record(key);
+        }
+        if let Some(tenant) = &self.tenant_metrics {
+            record(tenant);
+        }
+
+    }
+
+    /// Swaps in a new runtime config, logging every changed value and who changed it
+    pub fn update_runtime(&self, updated: RuntimeConfig, actor: &str) -> Vec<ConfigChange> {
+
+        let previous = self.runtime.swap(Arc::new(updated));
+        let changes = previous.changes(&self.runtime.load());
+
+        for change in &changes {
+            tracing::info!("Config: {} changed {} from {} to {}", actor, change.key, change.before, change.after);
+        }
+
+        changes
+
+    }
+
+}
+
+/// Runs `connect` until it succeeds, making up to STARTUP_RETRIES attempts
+/// STARTUP_RETRY_DELAY_SECS apart. Exits the process once they run out, as
+/// the proxy can't serve without a service its enabled tiers depend on
+async fn connect_with_retry<T, E, F, Fut>(config: &Config, service: &str, mut connect: F) -> T
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = Result<T, E>>,
+    E: std::fmt::Display
+{
+
+    let attempts = config.startup_retries;
+    for attempt in 1..=attempts {
+        match connect().await {
+            Ok(connected) => return connected,
+            Err(e) => {
+                tracing::warn!(%service, attempt, attempts, error = %e, "service not ready");
+                if attempt < attempts {
+                    tokio::time::sleep(config.startup_retry_delay()).await;
+                }
+            }
+        }
+    }
+
+    tracing::error!(
+        "Giving up on {} after {} attempts - check that it is running and reachable, or raise STARTUP_RETRIES",
+        service, attempts
+    );
+    std::process::exit(1);
+
+}
+
+// characters of the API key left visible in the startup banner
+const BANNER_KEY_VISIBLE_CHARS: usize = 6;
+
+// "redis://:pass@host:6379/" -> "host:6379", so credentials never reach the log
+fn display_host(url: &str) -> &str {
+    let without_scheme = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let without_credentials = without_scheme.rsplit_once('@').map_or(without_scheme, |(_, host)| host);
+    without_credentials.trim_end_matches('/')
+}
+
+/// Multi-line startup banner with secrets redacted. A tier's services are
+/// only listed as connected when present, since `AppState::new` fails otherwise
+impl std::fmt::Display for AppState {
+
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+
+        let config = &self.config;
+        writeln!(f, "LLM Cache Proxy v{}", env!("CARGO_PKG_VERSION"))?;
+        writeln!(f, "  {:<13}{} ({})", "Provider:", config.provider.name(), config.upstream_base_url)?;
+        if let Some(path) = &config.config_file {
+            writeln!(f, "  {:<13}{}", "Config File:", path)?;
+        }
+
+        match &self.exact_cache {
+            Some(cache) if cache.name() == "memory" => writeln!(f, "  {:<13}in memory [{} entries max]", "Redis:", config.memory_cache_max_entries)?,
+            Some(_) => writeln!(f, "  {:<13}{} [connected]", "Redis:", display_host(&config.redis_url))?,
+            None => writeln!(f, "  {:<13}disabled", "Redis:")?
+        }
+
+        match &self.semantic_cache {
+            Some(qdrant) => match qdrant.cached_collection_stats() {
+                Some(stats) => writeln!(f, "  {:<13}{} [connected, {} vectors]", "Qdrant:", display_host(&config.qdrant_url), stats.vectors_count)?,
+                None => writeln!(f, "  {:<13}{} [connected]", "Qdrant:", display_host(&config.qdrant_url))?
+            },
+            None => writeln!(f, "  {:<13}disabled", "Qdrant:")?
+        }
+
+        if self.semantic_cache.is_some() {
+            writeln!(f, "  {:<13}{} [{}-dim]", "Embeddings:", config.embedding.url, config.embedding_dim)?;
+            writeln!(f, "  {:<13}threshold={:.2}, metric=cosine", "Semantic:", self.runtime.load().semantic_threshold)?;
+        } else {
+            writeln!(f, "  {:<13}disabled", "Embeddings:")?;
+            writeln!(f, "  {:<13}disabled", "Semantic:")?;
+        }
+
+        let key_state = if matches!(config.sources.get("API_KEY"), Some(ConfigSource::Env | ConfigSource::File)) { "set" } else { "not set" };
+        writeln!(f, "  {:<13}{} [{}]", "API Key:", mask_secret_keeping(&config.api_key, BANNER_KEY_VISIBLE_CHARS), key_state)?;
+
+        if config.api_keys.enabled() {
+            writeln!(f, "  {:<13}{}", "Proxy Keys:", config.api_keys.names().join(", "))?;
+        } else {
+            writeln!(f, "  {:<13}disabled - any client can call the upstream", "Proxy Keys:")?;
+        }
+
+        match &config.admin_token {
+            Some(token) => write!(f, "  {:<13}admin token {}", "Auth:", mask_secret(token)),
+            None => write!(f, "  {:<13}disabled", "Auth:")
+        }
+
+    }
+
 }
 
 #[tokio::main]
 async fn main() {
 
     dotenvy::dotenv().ok();
+    logger::init_tracing();
 
-    let groq_api_key = std::env::var("GROQ_API_KEY")
-        .expect("GROQ_API_KEY must be set");
+    // create app state. Background tasks only hold a Weak reference to it,
+    // so they stop once main drops this Arc on shutdown
+    // --check runs the self-test probes and exits; --check-upstream adds a 1-token upstream call
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let check_only = args.iter().any(|arg| arg == "--check");
+    let check_upstream = args.iter().any(|arg| arg == "--check-upstream");
 
-    let redis_url = std::env::var("REDIS_URL")
-        .unwrap_or_else(|_| "redis://127.0.0.1:6379".to_string());
+    let config = Config::from_env().unwrap_or_else(|e| panic!("{}", e));
+    llm_cache_proxy::pricing::set_custom_pricing(config.pricing.clone());
+    let state = Arc::new(AppState::new(config).await);
 
-    let qdrant_url = std::env::var("QDRANT_URL")
-        .unwrap_or_else(|_| "http://127.0.0.1:6334".to_string());
+    if check_only || state.config.self_test_on_start {
+        let results = selftest::run(&state, check_upstream).await;
+        selftest::print_report(&results);
+        if !selftest::all_passed(&results) {
+            std::process::exit(1);
+        }
+        if check_only {
+            return;
+        }
+    }
 
-    let embedding_url = std::env::var("EMBEDDING_URL")
-        .unwrap_or_else(|_| "http://127.0.0.1:8001/embed".to_string());
+    // fetched once so the banner can show the vector count
+    if let Some(qdrant) = &state.semantic_cache
+        && let Err(e) = qdrant.collection_stats().await {
+        tracing::warn!("Couldn't read Qdrant collection stats: {}", e);
+    }
+    tracing::info!("{}", state);
 
-    // create caches
-    let redis_cache = RedisCache::new(&redis_url)
-        .await
-        .expect("Failed to connect to Redis");
+    // before serving, so the new namespace is warm for the first requests
+    migrate::migrate_on_startup(&state).await;
+    metrics_store::restore(&state).await;
 
-    let qdrant_cache = QdrantCache::new(&qdrant_url)
-        .await
-        .expect("Failed to connect to Qdrant");
+    background::spawn_health_monitor(&state);
+    background::spawn_tier0_counter_reset(&state);
+    background::spawn_id_dedup_cleanup(&state);
+    background::spawn_rate_limit_cleanup(&state);
+    background::spawn_cache_refresher(&state);
+    background::spawn_janitor(&state);
+    background::spawn_metrics_persist(&state);
+    background::spawn_metrics_history(&state);
+    #[cfg(unix)]
+    background::spawn_sighup_reload(&state);
 
-    let http_client = Client::new();
+    let app = build_router(&state);
 
-    let metrics = Arc::new(Metrics::new());
-
-    // create app state
-    let state = AppState {
-        redis_cache,
-        qdrant_cache,
-        http_client,
-        groq_api_key,
-        embedding_url,
-        metrics
-    };
-    
-    let app = Router::new()
-        .route("/health", get(handlers::health_check))
-        .route("/dashboard", get(handlers::dashboard))
-        .route("/metrics", get(handlers::metrics))
-        .route("/v1/chat/completions", post(handlers::proxy_handler))
-        .route("/admin/cache/clear", post(handlers::admin_clear_cache))
-        .route("/admin/stats", get(handlers::admin_stats))
-        .with_state(state); // share the app state 
-
-    let addr: SocketAddr = ([0, 0, 0, 0], 3000).into();
+    let addr = state.config.bind_address;
     let listener = TcpListener::bind(addr).await
-        .expect("Failed to bind to port 3000");
-    println!("listening on {}", listener.local_addr()
+        .unwrap_or_else(|e| panic!("Failed to bind to {}: {}", addr, e));
+    tracing::info!("listening on {}", listener.local_addr()
         .expect("Failed to get local address"));
-    axum::serve(listener, app).await
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await
         .expect("Server failed");
 
+}
+
+/// Every route with its middleware and the shared state
+fn build_router(state: &Arc<AppState>) -> Router {
+
+    // one generous deadline for every route, so a slow upstream can't hold a
+    // connection forever, and a short one for the health and metrics routes.
+    // The deadline ends once the response headers are sent, so a streamed body
+    // is bounded by REQWEST_TIMEOUT_SECS on the upstream call it relays instead
+    let request_timeout = state.config.request_timeout();
+    let short_timeout = state.config.health_timeout();
+
+    let request_timeout_layer = ServiceBuilder::new()
+        .layer(HandleErrorLayer::new(move |err| handlers::handle_timeout_error(err, request_timeout, "upstream_timeout")))
+        .timeout(request_timeout);
+
+    let short_timeout_layer = ServiceBuilder::new()
+        .layer(HandleErrorLayer::new(move |err| handlers::handle_timeout_error(err, short_timeout, "request_timeout")))
+        .timeout(short_timeout);
+
+    // every admin call is audited, whether or not it succeeds
+    let admin_routes = Router::new()
+        .route("/admin/cache/clear", post(handlers::admin_clear_cache))
+        .route("/admin/cache/size", get(handlers::admin_cache_size))
+        .route("/admin/cache/export", get(handlers::admin_export_cache))
+        .route("/admin/cache/latency_stats", get(handlers::admin_cache_latency_stats))
+        .route("/admin/cache/hot", get(handlers::admin_hot_keys))
+        .route("/admin/cache/copy", post(handlers::admin_copy_key))
+        .route("/admin/cache/copy/bulk", post(handlers::admin_copy_keys_bulk))
+        .route("/admin/cache/reembed", post(handlers::admin_start_reembed))
+        .route("/admin/cache/reembed/status", get(handlers::admin_reembed_status))
+        .route("/admin/cache/quarantine", get(handlers::admin_list_quarantine))
+        .route("/admin/cache/quarantine/purge", post(handlers::admin_purge_quarantine))
+        .route("/admin/cache", delete(handlers::admin_invalidate_by_request))
+        .route("/admin/cache/invalidate", post(handlers::admin_invalidate_model))
+        .route("/admin/cache/purge_similar", post(handlers::admin_purge_similar))
+        .route("/admin/cache/:key", delete(handlers::admin_invalidate_cache_key))
+        .route("/admin/cache/inspect", get(handlers::admin_inspect_cache))
+        .route("/admin/cache/entries", get(handlers::admin_list_entries))
+        .route("/admin/cache/entries/:key", get(handlers::admin_get_entry))
+        .route("/admin/cache/inspect/:key", get(handlers::admin_inspect_cache_key))
+        .route("/admin/config", get(handlers::admin_config).put(handlers::admin_update_config))
+        .route("/admin/errors", get(handlers::admin_errors))
+        .route("/admin/audit", get(handlers::admin_audit))
+        .route("/admin/requests", get(handlers::admin_requests))
+        .route("/admin/stats", get(handlers::admin_stats).layer(short_timeout_layer.clone()))
+        .route("/v1/chat/completions/explain", post(handlers::explain_handler))
+        // keeps unknown admin paths away from the passthrough route
+        .route("/admin/*rest", any(handlers::admin_not_found))
+        .layer(axum::middleware::from_fn_with_state(state.as_ref().clone(), middleware::audit_admin));
+
+    let compression_layer = middleware::compression_layer(state.config.compression);
+    // per-path metrics for the routes that serve or forward completions
+    let track_endpoint = axum::middleware::from_fn_with_state(state.as_ref().clone(), middleware::track_endpoint);
+    // the routes that spend upstream budget need a proxy API key when PROXY_API_KEYS is set.
+    // Outermost on each route, so a rejected request isn't counted or decompressed
+    let require_api_key = axum::middleware::from_fn_with_state(state.as_ref().clone(), middleware::require_api_key);
+    // inside require_api_key, so callers are limited by key rather than IP when keys are set
+    let rate_limit = axum::middleware::from_fn_with_state(state.as_ref().clone(), middleware::rate_limit);
+    // inside require_api_key, which names the key a tenant may be derived from,
+    // and outside the quota and budget checks
+    let resolve_tenant = axum::middleware::from_fn_with_state(state.as_ref().clone(), middleware::resolve_tenant);
+    // inside resolve_tenant; lookups spend nothing, so they go on past a quota
+    let enforce_tenant_quota = axum::middleware::from_fn_with_state(state.as_ref().clone(), middleware::enforce_tenant_quota);
+    // innermost, inside require_api_key and resolve_tenant, so spend is counted against the key
+    let enforce_budget = axum::middleware::from_fn_with_state(state.as_ref().clone(), middleware::enforce_budget);
+
+    Router::new()
+        .route("/health", get(handlers::health_check).layer(short_timeout_layer.clone()))
+        .route("/dashboard", get(handlers::dashboard))
+        .route("/metrics/tenants", get(handlers::tenant_metrics).layer(short_timeout_layer.clone()))
+        .route("/metrics/history", get(handlers::metrics_history).layer(short_timeout_layer.clone()))
+        .route("/metrics", get(handlers::metrics).layer(ServiceBuilder::new().layer(compression_layer.clone()).layer(short_timeout_layer.clone())))
+        .route("/v1/chat/completions", post(handlers::chat_completions).layer(compression_layer).layer(enforce_budget.clone()).layer(enforce_tenant_quota.clone()).layer(resolve_tenant.clone()).layer(track_endpoint.clone()).layer(rate_limit.clone()).layer(require_api_key.clone()))
+        .route("/v1/cache/lookup", post(handlers::cache_lookup).layer(resolve_tenant.clone()).layer(rate_limit.clone()).layer(require_api_key.clone()))
+        .merge(admin_routes)
+        // anything not matched above is forwarded to the upstream uncached
+        .route("/*path", any(handlers::passthrough_handler).layer(DefaultBodyLimit::max(middleware::MAX_BODY_BYTES)).layer(track_endpoint).layer(rate_limit.clone()).layer(require_api_key.clone()))
+        // covers every route added above
+        .layer(request_timeout_layer)
+        // no timeout: a large prefill batch can legitimately run for minutes
+        .route("/v1/chat/completions/prefill", post(handlers::prefill_cache).layer(enforce_budget).layer(enforce_tenant_quota).layer(resolve_tenant).layer(rate_limit).layer(require_api_key))
+        .layer(axum::middleware::from_fn(middleware::validate_content_length))
+        // outermost, so even a rejected request is logged under its id
+        .layer(axum::middleware::from_fn(middleware::request_span))
+        .with_state(state.as_ref().clone()) // share the app state
+
+}
+
+// these build a non-mock AppState with both cache tiers off, so the module is skipped under mock
+#[cfg(all(test, not(feature = "mock")))]
+mod tests {
+
+    use super::*;
+    use axum::Json;
+    use axum::body::Body;
+    use axum::http::{HeaderMap, Request, StatusCode};
+    use tower::ServiceExt;
+
+    // serves `upstream` on a free local port, returning its base URL
+    async fn spawn_stub_upstream(upstream: Router) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, upstream).await });
+        base_url
+    }
+
+    // POST /v1/chat/completions asking `model` one user `prompt`, with `headers` added
+    fn chat_request<'a>(model: &str, prompt: &str, headers: impl IntoIterator<Item = (&'a str, String)>) -> Request<Body> {
+        let mut request = Request::post("/v1/chat/completions").header("content-type", "application/json");
+        for (name, value) in headers {
+            request = request.header(name, value);
+        }
+        let body = serde_json::json!({"model": model, "messages": [{"role": "user", "content": prompt}]});
+        request.body(Body::from(body.to_string())).unwrap()
+    }
+
+    // a config from `vars` alone, with GROQ_API_KEY defaulting to "test-key"
+    fn test_config(vars: &[(&str, &str)]) -> Result<Config, String> {
+        Config::from_lookup(|name| vars.iter()
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value.to_string())
+            .or_else(|| (name == "GROQ_API_KEY").then(|| "test-key".to_string())))
+    }
+
+    #[tokio::test]
+    async fn test_metrics_break_requests_down_by_endpoint() {
+
+        let completion = serde_json::to_string(&test_helpers::test_llm_response()).unwrap();
+        let upstream = Router::new()
+            .route("/chat/completions", post(move || async move { completion }))
+            // the stub's base URL has no /v1, so passthrough forwards the full path
+            .route("/v1/embeddings", post(|| async { r#"{"data": []}"# }));
+        let base_url = spawn_stub_upstream(upstream).await;
+
+        let config = test_config(&[
+            ("UPSTREAM_BASE_URL", &base_url),
+            ("EXACT_CACHE_ENABLED", "false"),
+            ("SEMANTIC_CACHE_ENABLED", "false")
+        ]).unwrap();
+        let state = Arc::new(AppState::new(config).await);
+        let app = build_router(&state);
+
+        for path in ["/v1/chat/completions", "/v1/chat/completions", "/v1/embeddings"] {
+            let request = Request::post(path)
+                .header("content-type", "application/json")
+                .body(Body::from(r#"{"model": "llama-3.1-8b-instant", "messages": [{"role": "user", "content": "Hi"}]}"#))
+                .unwrap();
+            let response = app.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK, "{}", path);
+        }
+
+        let response = app.oneshot(Request::get("/metrics").body(Body::empty()).unwrap()).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+        let chat = &body["endpoints"]["/v1/chat/completions"];
+        assert_eq!((chat["requests"].as_u64(), chat["misses"].as_u64()), (Some(2), Some(2)));
+        assert!(chat["tokens_used"].as_u64().unwrap() > 0);
+        let embeddings = &body["endpoints"]["/v1/embeddings"];
+        assert_eq!((embeddings["requests"].as_u64(), embeddings["passthrough"].as_u64()), (Some(1), Some(1)));
+        // the global counters still see everything
+        assert_eq!(body["cache_performance"]["total_requests"], 2);
+        assert_eq!(body["passthrough_requests"], 1);
+
+    }
+
+    #[tokio::test]
+    async fn test_metrics_history_buckets_recent_requests() {
+
+        let completion = serde_json::to_string(&test_helpers::test_llm_response()).unwrap();
+        let upstream = Router::new().route("/chat/completions", post(move || async move { completion }));
+        let base_url = spawn_stub_upstream(upstream).await;
+
+        let config = test_config(&[
+            ("UPSTREAM_BASE_URL", &base_url),
+            ("EXACT_CACHE_BACKEND", "memory"),
+            ("SEMANTIC_CACHE_ENABLED", "false")
+        ]).unwrap();
+        let state = Arc::new(AppState::new(config).await);
+        let app = build_router(&state);
+
+        // the startup sample
+        state.history.record(&state.metrics.snapshot());
+        for _ in 0..2 {
+            let request = chat_request("llama-3.1-8b-instant", "Hi", []);
+            assert_eq!(app.clone().oneshot(request).await.unwrap().status(), StatusCode::OK);
+        }
+
+        let response = app.clone().oneshot(Request::get("/metrics/history?window=1h&bucket=15m").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+        let buckets = body["buckets"].as_array().unwrap();
+        assert_eq!(buckets.len(), 4);
+        let latest = &buckets[3];
+        assert_eq!((latest["requests"].as_u64(), latest["hits"].as_u64()), (Some(2), Some(1)));
+        assert_eq!(latest["hit_rate_percent"], 50.0);
+        assert!(latest["cost_spent_usd"].as_f64().unwrap() > 0.0);
+        assert_eq!(buckets[0]["hit_rate_percent"], serde_json::Value::Null);
+
+        for bad in ["/metrics/history?window=2d", "/metrics/history?bucket=10s", "/metrics/history?window=soon"] {
+            let response = app.clone().oneshot(Request::get(bad).body(Body::empty()).unwrap()).await.unwrap();
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", bad);
+        }
+
+    }
+
+    #[tokio::test]
+    async fn test_identical_concurrent_misses_share_one_upstream_call() {
+
+        let calls = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let completion = serde_json::to_string(&test_helpers::test_llm_response()).unwrap();
+        let upstream_calls = calls.clone();
+        let upstream = Router::new().route("/chat/completions", post(move || async move {
+            upstream_calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            // long enough for every request below to arrive while the first is upstream
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            completion
+        }));
+        let base_url = spawn_stub_upstream(upstream).await;
+
+        let config = test_config(&[
+            ("UPSTREAM_BASE_URL", &base_url),
+            ("EXACT_CACHE_ENABLED", "false"),
+            ("SEMANTIC_CACHE_ENABLED", "false")
+        ]).unwrap();
+        let state = Arc::new(AppState::new(config).await);
+        let app = build_router(&state);
+
+        let requests: Vec<_> = (0..10)
+            .map(|_| {
+                let request = chat_request("llama-3.1-8b-instant", "Hi", []);
+                tokio::spawn(app.clone().oneshot(request))
+            })
+            .collect();
+
+        let mut coalesced = 0;
+        for request in requests {
+            let response = request.await.unwrap().unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            coalesced += response.headers().contains_key("x-coalesced") as usize;
+        }
+
+        assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 1);
+        assert_eq!(coalesced, 9);
+        assert_eq!(state.metrics.snapshot().coalesced_requests, 9);
+
+    }
+
+    #[tokio::test]
+    async fn test_proxy_api_keys_are_required_and_not_forwarded() {
+
+        let completion = serde_json::to_string(&test_helpers::test_llm_response()).unwrap();
+        // answers only when the proxy's own upstream key arrives, not the client's proxy key
+        let upstream = Router::new().route("/chat/completions", post(move |headers: HeaderMap| async move {
+            match headers.get("authorization").and_then(|v| v.to_str().ok()) {
+                Some("Bearer test-key") => (StatusCode::OK, completion),
+                _ => (StatusCode::UNAUTHORIZED, String::new())
+            }
+        }));
+        let base_url = spawn_stub_upstream(upstream).await;
+
+        let config = test_config(&[
+            ("UPSTREAM_BASE_URL", &base_url),
+            ("PROXY_API_KEYS", r#"{"web-app": "sk-proxy-web"}"#),
+            ("EXACT_CACHE_ENABLED", "false"),
+            ("SEMANTIC_CACHE_ENABLED", "false")
+        ]).unwrap();
+        let state = Arc::new(AppState::new(config).await);
+        let app = build_router(&state);
+
+        let completion_request = |key: Option<&str>| chat_request("llama-3.1-8b-instant", "Hi", key.map(|key| ("authorization", format!("Bearer {}", key))));
+
+        for key in [None, Some("sk-wrong")] {
+            let response = app.clone().oneshot(completion_request(key)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED, "{:?}", key);
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(body["error"]["code"], "invalid_api_key");
+        }
+
+        let response = app.clone().oneshot(completion_request(Some("sk-proxy-web"))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // health checks and metrics stay open
+        let response = app.clone().oneshot(Request::get("/metrics").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["api_keys"]["web-app"]["requests"], 1);
+        assert_eq!(body["api_keys"]["web-app"]["misses"], 1);
+
+    }
+
+    #[tokio::test]
+    async fn test_rate_limits_apply_per_api_key() {
+
+        let completion = serde_json::to_string(&test_helpers::test_llm_response()).unwrap();
+        let upstream = Router::new().route("/chat/completions", post(move || async move { completion }));
+        let base_url = spawn_stub_upstream(upstream).await;
+
+        let app_with_limits = |limit: &'static str, value: &'static str| {
+            let base_url = base_url.clone();
+            async move {
+                let config = test_config(&[
+                    ("UPSTREAM_BASE_URL", &base_url),
+                    ("PROXY_API_KEYS", r#"{"alice": "sk-alice", "bob": "sk-bob"}"#),
+                    ("EXACT_CACHE_ENABLED", "false"),
+                    ("SEMANTIC_CACHE_ENABLED", "false"),
+                    (limit, value)
+                ]).unwrap();
+                build_router(&Arc::new(AppState::new(config).await))
+            }
+        };
+        let completion_request = |key: &str| chat_request("llama-3.1-8b-instant", "Hi", [("authorization", format!("Bearer {}", key))]);
+
+        let app = app_with_limits("RATE_LIMIT_REQUESTS_PER_MIN", "2").await;
+        let statuses = [
+            app.clone().oneshot(completion_request("sk-alice")).await.unwrap(),
+            app.clone().oneshot(completion_request("sk-alice")).await.unwrap(),
+            app.clone().oneshot(completion_request("sk-alice")).await.unwrap(),
+            app.clone().oneshot(completion_request("sk-bob")).await.unwrap()
+        ].map(|response| (response.status(), response.headers().get("retry-after").cloned()));
+
+        assert_eq!(statuses[0].0, StatusCode::OK);
+        assert_eq!(statuses[1].0, StatusCode::OK);
+        assert_eq!(statuses[2], (StatusCode::TOO_MANY_REQUESTS, Some("30".parse().unwrap())));
+        assert_eq!(statuses[3].0, StatusCode::OK, "Another key has its own allowance");
+
+        // each completion uses 20 tokens, which overdraws a 10 tokens/min allowance
+        let app = app_with_limits("RATE_LIMIT_TOKENS_PER_MIN", "10").await;
+        assert_eq!(app.clone().oneshot(completion_request("sk-alice")).await.unwrap().status(), StatusCode::OK);
+        let response = app.clone().oneshot(completion_request("sk-alice")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["error"]["code"], "rate_limit_exceeded");
+
+    }
+
+    #[tokio::test]
+    async fn test_memory_backend_serves_exact_hits_without_redis() {
+
+        let completion = serde_json::to_string(&test_helpers::test_llm_response()).unwrap();
+        let upstream = Router::new().route("/chat/completions", post(move || async move { completion }));
+        let base_url = spawn_stub_upstream(upstream).await;
+
+        let config = test_config(&[
+            ("UPSTREAM_BASE_URL", &base_url),
+            ("EXACT_CACHE_BACKEND", "memory"),
+            // nothing listens here, so a Redis connection attempt would fail the test
+            ("REDIS_URL", "redis://127.0.0.1:1"),
+            ("STARTUP_RETRIES", "1"),
+            ("SEMANTIC_CACHE_ENABLED", "false")
+        ]).unwrap();
+        let state = Arc::new(AppState::new(config).await);
+        let app = build_router(&state);
+
+        let mut served_from_cache = Vec::new();
+        for _ in 0..2 {
+            let request = chat_request("llama-3.1-8b-instant", "Hi", []);
+            let response = app.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            served_from_cache.push(response.headers().contains_key("x-served-from-cache"));
+        }
+
+        assert_eq!(served_from_cache, [false, true]);
+        assert_eq!(state.exact_cache.as_ref().unwrap().name(), "memory");
+
+        let err = test_config(&[("EXACT_CACHE_BACKEND", "memcached")]).unwrap_err();
+        assert!(err.contains("EXACT_CACHE_BACKEND"), "{}", err);
+
+    }
+
+    #[tokio::test]
+    async fn test_a_reused_response_id_never_answers_another_prompt() {
+
+        // every answer carries the same id and echoes the prompt back
+        let upstream = Router::new().route("/chat/completions", post(|Json(request): Json<serde_json::Value>| async move {
+            let mut response = models::LLMResponse { id: "chatcmpl-shared".to_string(), ..test_helpers::test_llm_response() };
+            response.choices[0].message.content = request["messages"][0]["content"].as_str().unwrap_or_default().into();
+            Json(response)
+        }));
+        let base_url = spawn_stub_upstream(upstream).await;
+
+        let config = test_config(&[
+            ("UPSTREAM_BASE_URL", &base_url),
+            ("EXACT_CACHE_BACKEND", "memory"),
+            ("SEMANTIC_CACHE_ENABLED", "false")
+        ]).unwrap();
+        let state = Arc::new(AppState::new(config).await);
+        let app = build_router(&state);
+
+        let mut answers = Vec::new();
+        for prompt in ["Hi", "Bye", "Bye"] {
+            let request = chat_request("llama-3.1-8b-instant", prompt, []);
+            let response = app.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            answers.push(body["choices"][0]["message"]["content"].as_str().unwrap().to_string());
+        }
+
+        assert_eq!(answers, ["Hi", "Bye", "Bye"], "The second prompt, fresh or cached, gets its own answer");
+        assert_eq!(state.metrics.snapshot().id_dedup_hits, 0);
+
+    }
+
+    #[tokio::test]
+    async fn test_byok_keys_are_sent_upstream_and_partition_the_cache() {
+
+        // the upstream answers with the key it was called with
+        let upstream = Router::new().route("/chat/completions", post(|headers: HeaderMap| async move {
+            let mut response = test_helpers::test_llm_response();
+            response.id = uuid::Uuid::new_v4().to_string();
+            response.choices[0].message.content = headers["authorization"].to_str().unwrap().into();
+            Json(response)
+        }));
+        let base_url = spawn_stub_upstream(upstream).await;
+
+        let config = test_config(&[
+            ("GROQ_API_KEY", "proxy-key"),
+            ("UPSTREAM_BASE_URL", &base_url),
+            ("BYOK_ENABLED", "true"),
+            ("EXACT_CACHE_BACKEND", "memory"),
+            ("SEMANTIC_CACHE_ENABLED", "false")
+        ]).unwrap();
+        let app = build_router(&Arc::new(AppState::new(config).await));
+
+        let complete = |key: Option<&'static str>| {
+            let app = app.clone();
+            async move {
+                let request = chat_request("llama-3.1-8b-instant", "Hi", key.map(|key| ("authorization", format!("Bearer {}", key))));
+                let response = app.oneshot(request).await.unwrap();
+                assert_eq!(response.status(), StatusCode::OK);
+                let cached = response.headers().contains_key("x-served-from-cache");
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+                (body["choices"][0]["message"]["content"].as_str().unwrap().to_string(), cached)
+            }
+        };
+
+        assert_eq!(complete(Some("sk-alice")).await, ("Bearer sk-alice".to_string(), false));
+        assert_eq!(complete(Some("sk-bob")).await, ("Bearer sk-bob".to_string(), false), "Bob mustn't get Alice's entry");
+        assert_eq!(complete(None).await, ("Bearer proxy-key".to_string(), false));
+        assert_eq!(complete(Some("sk-alice")).await, ("Bearer sk-alice".to_string(), true));
+
+        let err = test_config(&[
+            ("GROQ_API_KEY", "proxy-key"),
+            ("BYOK_ENABLED", "true"),
+            ("PROXY_API_KEYS", r#"{"web-app": "sk-proxy-web"}"#)
+        ]).unwrap_err();
+        assert!(err.contains("BYOK_ENABLED"), "{}", err);
+
+    }
+
+    #[tokio::test]
+    async fn test_tenants_get_separate_cache_partitions() {
+
+        let upstream = Router::new().route("/chat/completions", post(|| async {
+            let mut response = test_helpers::test_llm_response();
+            response.id = uuid::Uuid::new_v4().to_string();
+            Json(response)
+        }));
+        let base_url = spawn_stub_upstream(upstream).await;
+
+        let config = test_config(&[
+            ("UPSTREAM_BASE_URL", &base_url),
+            ("TENANT_SOURCE", "header"),
+            ("EXACT_CACHE_BACKEND", "memory"),
+            ("SEMANTIC_CACHE_ENABLED", "false")
+        ]).unwrap();
+        let app = build_router(&Arc::new(AppState::new(config).await));
+
+        let complete = |tenant: Option<&'static str>| {
+            let app = app.clone();
+            async move {
+                let request = chat_request("llama-3.1-8b-instant", "Hi", tenant.map(|tenant| ("x-tenant-id", tenant.to_string())));
+                let response = app.oneshot(request).await.unwrap();
+                (response.status(), response.headers().contains_key("x-served-from-cache"))
+            }
+        };
+
+        assert_eq!(complete(Some("team-a")).await, (StatusCode::OK, false));
+        assert_eq!(complete(Some("team-b")).await, (StatusCode::OK, false), "team-b mustn't get team-a's entry");
+        assert_eq!(complete(None).await, (StatusCode::OK, false));
+        assert_eq!(complete(Some("team-a")).await, (StatusCode::OK, true));
+        assert_eq!(complete(None).await, (StatusCode::OK, true));
+        assert_eq!(complete(Some("team:a")).await.0, StatusCode::BAD_REQUEST);
+
+        let err = test_config(&[("TENANT_SOURCE", "api_key")]).unwrap_err();
+        assert!(err.contains("PROXY_API_KEYS"), "{}", err);
+
+    }
+
+    #[tokio::test]
+    async fn test_tenant_quota_returns_429_and_usage_is_reported() {
+
+        let upstream = Router::new().route("/chat/completions", post(|| async { Json(test_helpers::test_llm_response()) }));
+        let base_url = spawn_stub_upstream(upstream).await;
+
+        let config = test_config(&[
+            ("UPSTREAM_BASE_URL", &base_url),
+            ("TENANT_SOURCE", "header"),
+            ("TENANT_DAILY_TOKEN_QUOTA", "1"),
+            ("EXACT_CACHE_BACKEND", "memory"),
+            ("SEMANTIC_CACHE_ENABLED", "false"),
+            ("ADMIN_TOKEN", "admin-token")
+        ]).unwrap();
+        let app = build_router(&Arc::new(AppState::new(config).await));
+
+        let complete = |tenant: &'static str| {
+            let app = app.clone();
+            async move {
+                let request = chat_request("llama-3.1-8b-instant", "Hi", [("x-tenant-id", tenant.to_string())]);
+                let response = app.oneshot(request).await.unwrap();
+                let status = response.status();
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                (status, serde_json::from_slice::<serde_json::Value>(&body).unwrap())
+            }
+        };
+
+        assert_eq!(complete("team-a").await.0, StatusCode::OK);
+        let (status, body) = complete("team-a").await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(body["error"]["code"], "quota_exceeded");
+        assert_eq!(body["quota"]["quota"], "daily_tokens");
+        assert_eq!(complete("team-b").await.0, StatusCode::OK, "Quotas are per tenant");
+
+        let tenant_metrics = |admin_token: Option<&'static str>| {
+            let app = app.clone();
+            async move {
+                let mut request = Request::get("/metrics/tenants");
+                if let Some(token) = admin_token {
+                    request = request.header("x-admin-token", token);
+                }
+                let response = app.oneshot(request.body(Body::empty()).unwrap()).await.unwrap();
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                serde_json::from_slice::<serde_json::Value>(&body).unwrap()
+            }
+        };
+
+        let body = tenant_metrics(None).await;
+        assert!(body.get("tenants").is_none(), "Tenant ids and spend need the admin token: {}", body);
+        assert_eq!((body["tenant_count"].as_u64(), body["quotas"]["daily_tokens"].as_u64()), (Some(2), Some(1)));
+
+        let body = tenant_metrics(Some("admin-token")).await;
+        assert_eq!(body["tenants"]["team-a"]["misses"], 1);
+        assert_eq!(body["tenants"]["team-a"]["today"]["tokens_remaining"], 0);
+        assert_eq!(body["quotas"]["daily_tokens"], 1);
+
+        let err = test_config(&[("TENANT_DAILY_COST_QUOTA_USD", "5")]).unwrap_err();
+        assert!(err.contains("TENANT_SOURCE"), "{}", err);
+
+    }
+
+    #[tokio::test]
+    async fn test_admin_cache_clear_needs_the_admin_token() {
+
+        let config = test_config(&[
+            ("EXACT_CACHE_BACKEND", "memory"),
+            ("SEMANTIC_CACHE_ENABLED", "false"),
+            ("ADMIN_TOKEN", "admin-token")
+        ]).unwrap();
+        let state = Arc::new(AppState::new(config).await);
+        let app = build_router(&state);
+
+        for token in [None, Some("wrong-token")] {
+            let mut request = Request::post("/admin/cache/clear?tier=all");
+            if let Some(token) = token {
+                request = request.header("x-admin-token", token);
+            }
+            let response = app.clone().oneshot(request.body(Body::empty()).unwrap()).await.unwrap();
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED, "{:?}", token);
+        }
+
+        let request = Request::post("/admin/cache/clear?tier=all").header("x-admin-token", "admin-token").body(Body::empty()).unwrap();
+        assert_eq!(app.oneshot(request).await.unwrap().status(), StatusCode::OK);
+
+    }
+
+    #[tokio::test]
+    async fn test_admin_errors_and_stats_need_the_admin_token() {
+
+        let config = test_config(&[
+            ("EXACT_CACHE_BACKEND", "memory"),
+            ("SEMANTIC_CACHE_ENABLED", "false"),
+            ("ADMIN_TOKEN", "admin-token")
+        ]).unwrap();
+        let state = Arc::new(AppState::new(config).await);
+        let app = build_router(&state);
+
+        for path in ["/admin/errors", "/admin/stats"] {
+            let response = app.clone().oneshot(Request::get(path).body(Body::empty()).unwrap()).await.unwrap();
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED, "{}", path);
+            let request = Request::get(path).header("x-admin-token", "admin-token").body(Body::empty()).unwrap();
+            assert_eq!(app.clone().oneshot(request).await.unwrap().status(), StatusCode::OK, "{}", path);
+        }
+
+    }
+
+    #[tokio::test]
+    async fn test_unknown_admin_paths_are_not_forwarded_upstream() {
+
+        let forwarded = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let upstream = Router::new().fallback({
+            let forwarded = forwarded.clone();
+            move || async move { forwarded.fetch_add(1, std::sync::atomic::Ordering::SeqCst); "{}" }
+        });
+        let base_url = spawn_stub_upstream(upstream).await;
+
+        let config = test_config(&[
+            ("UPSTREAM_BASE_URL", &base_url),
+            ("EXACT_CACHE_BACKEND", "memory"),
+            ("SEMANTIC_CACHE_ENABLED", "false"),
+            ("ADMIN_TOKEN", "admin-token")
+        ]).unwrap();
+        let app = build_router(&Arc::new(AppState::new(config).await));
+
+        for path in ["/admin/flush", "/admin/nothing/here"] {
+            let request = Request::post(path).header("x-admin-token", "admin-token").body(Body::empty()).unwrap();
+            assert_eq!(app.clone().oneshot(request).await.unwrap().status(), StatusCode::NOT_FOUND, "{}", path);
+        }
+        assert_eq!(forwarded.load(std::sync::atomic::Ordering::SeqCst), 0);
+
+        // everything else still passes through
+        let response = app.oneshot(Request::get("/v1/models").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(forwarded.load(std::sync::atomic::Ordering::SeqCst), 1);
+
+    }
+
+    #[tokio::test]
+    async fn test_admin_bodies_are_audited_only_once_authorized() {
+
+        let path = std::env::temp_dir().join(format!("audit_{}.log", uuid::Uuid::new_v4()));
+        let path = path.to_str().unwrap().to_string();
+        let config = test_config(&[
+            ("EXACT_CACHE_BACKEND", "memory"),
+            ("SEMANTIC_CACHE_ENABLED", "false"),
+            ("ADMIN_TOKEN", "admin-token"),
+            ("AUDIT_LOG_PATH", &path)
+        ]).unwrap();
+        let app = build_router(&Arc::new(AppState::new(config).await));
+
+        let put_config = |token: Option<&str>, body: String| {
+            let mut request = Request::put("/admin/config").header("content-type", "application/json");
+            if let Some(token) = token {
+                request = request.header("x-admin-token", token);
+            }
+            request.body(Body::from(body)).unwrap()
+        };
+        let padding = "x".repeat(64 * 1024);
+        let large = format!(r#"{{"semantic_threshold": 0.8, "padding": "{}"}}"#, padding);
+
+        let response = app.clone().oneshot(put_config(None, large.clone())).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = app.clone().oneshot(put_config(Some("admin-token"), r#"{"semantic_threshold": 0.8}"#.to_string())).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let _ = app.oneshot(put_config(Some("admin-token"), large)).await.unwrap();
+
+        let mut entries = Vec::new();
+        for _ in 0..50 {
+            entries = logger::read_audit(&path, 10);
+            if entries.len() == 3 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+
+        // newest first
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[2].status, 401);
+        assert!(entries[2].params.get("body").is_none(), "An unauthenticated body is never recorded");
+        assert_eq!(entries[1].params["body"]["semantic_threshold"], 0.8);
+        assert!(entries[0].params["body"]["omitted_bytes"].as_u64().unwrap() > 64 * 1024);
+
+        std::fs::remove_file(&path).unwrap();
+
+    }
+
+    #[tokio::test]
+    async fn test_admin_requests_reports_spend_per_tenant() {
+
+        let completion = serde_json::to_string(&test_helpers::test_llm_response()).unwrap();
+        let upstream = Router::new().route("/chat/completions", post(move || async move { completion }));
+        let base_url = spawn_stub_upstream(upstream).await;
+
+        let config = test_config(&[
+            ("UPSTREAM_BASE_URL", &base_url),
+            ("EXACT_CACHE_BACKEND", "memory"),
+            ("SEMANTIC_CACHE_ENABLED", "false"),
+            ("TENANT_SOURCE", "header"),
+            ("ADMIN_TOKEN", "admin-token"),
+            ("REQUEST_STORE_URL", "sqlite::memory:")
+        ]).unwrap();
+        let state = Arc::new(AppState::new(config).await);
+        let app = build_router(&state);
+
+        // team-a misses then hits; team-b misses
+        for tenant in ["team-a", "team-a", "team-b"] {
+            let request = chat_request("llama-3.1-8b-instant", "Hi", [("x-tenant-id", tenant.to_string())]);
+            assert_eq!(app.clone().oneshot(request).await.unwrap().status(), StatusCode::OK);
+        }
+
+        let admin_get = |uri: &str| Request::get(uri).header("x-admin-token", "admin-token").body(Body::empty()).unwrap();
+        let mut body = serde_json::Value::Null;
+        // records are written in the background
+        for _ in 0..50 {
+            let response = app.clone().oneshot(admin_get("/admin/requests?group_by=tenant&from=1h")).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            body = serde_json::from_slice(&axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap()).unwrap();
+            if body["totals"]["requests"] == 3 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+
+        assert_eq!(body["totals"]["requests"], 3);
+        let team_a = body["groups"].as_array().unwrap().iter().find(|group| group["group"] == "team-a").unwrap();
+        assert_eq!((team_a["requests"].as_u64(), team_a["hits"].as_u64()), (Some(2), Some(1)));
+        assert!(team_a["cost_spent_usd"].as_f64().unwrap() > 0.0);
+
+        let response = app.clone().oneshot(admin_get("/admin/requests?tenant=team-b&cache_status=miss")).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap()).unwrap();
+        assert_eq!(body["count"], 1);
+        assert_eq!(body["requests"][0]["tenant"], "team-b");
+        assert!(body["requests"][0]["request_id"].is_string());
+
+        let response = app.oneshot(admin_get("/admin/requests?group_by=weekday")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    }
+
+    #[tokio::test]
+    async fn test_budget_warns_past_the_soft_limit_and_rejects_at_the_hard_one() {
+
+        let upstream = Router::new().route("/chat/completions", post(|| async { Json(test_helpers::test_llm_response()) }));
+        let base_url = spawn_stub_upstream(upstream).await;
+
+        // each miss costs about $0.0000013 (20 llama-3.1-8b-instant tokens)
+        let config = test_config(&[
+            ("UPSTREAM_BASE_URL", &base_url),
+            ("BUDGET_SOFT_LIMIT_USD", "0.000001"),
+            ("BUDGET_HARD_LIMIT_USD", "0.000002"),
+            ("BUDGET_REJECT_STATUS", "429"),
+            ("EXACT_CACHE_BACKEND", "memory"),
+            ("SEMANTIC_CACHE_ENABLED", "false")
+        ]).unwrap();
+        let app = build_router(&Arc::new(AppState::new(config).await));
+
+        let complete = |prompt: &'static str| {
+            let app = app.clone();
+            async move {
+                let request = chat_request("llama-3.1-8b-instant", prompt, []);
+                let response = app.oneshot(request).await.unwrap();
+                (response.status(), response.headers().contains_key(middleware::BUDGET_WARNING_HEADER))
+            }
+        };
+
+        assert_eq!(complete("first").await, (StatusCode::OK, false));
+        assert_eq!(complete("second").await, (StatusCode::OK, true));
+        assert_eq!(complete("third").await, (StatusCode::TOO_MANY_REQUESTS, false));
+
+        let response = app.clone().oneshot(Request::get("/metrics").body(Body::empty()).unwrap()).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["budget"]["period"], "monthly");
+        assert!(body["budget"]["spent_usd"].as_f64().unwrap() >= 0.000002);
+
+        let err = test_config(&[("BUDGET_KEY_HARD_LIMIT_USD", "5")]).unwrap_err();
+        assert!(err.contains("PROXY_API_KEYS"), "{}", err);
+
+    }
+
+    #[tokio::test]
+    async fn test_anthropic_prefix_is_translated_both_ways() {
+
+        let upstream = Router::new().route("/messages", post(|headers: HeaderMap, Json(body): Json<serde_json::Value>| async move {
+            assert_eq!(headers["x-api-key"], "sk-ant-key");
+            assert_eq!(body["model"], "claude-3-5-sonnet");
+            Json(serde_json::json!({
+                "id": "msg_01", "type": "message", "role": "assistant", "model": "claude-3-5-sonnet-20241022",
+                "content": [{"type": "text", "text": "Hello!"}],
+                "stop_reason": "end_turn",
+                "usage": {"input_tokens": 8, "output_tokens": 2}
+            }))
+        }));
+        let base_url = spawn_stub_upstream(upstream).await;
+
+        let config = test_config(&[
+            ("ANTHROPIC_API_KEY", "sk-ant-key"),
+            ("ANTHROPIC_BASE_URL", &base_url),
+            ("EXACT_CACHE_ENABLED", "false"),
+            ("SEMANTIC_CACHE_ENABLED", "false")
+        ]).unwrap();
+        let app = build_router(&Arc::new(AppState::new(config).await));
+
+        let request = chat_request("anthropic/claude-3-5-sonnet", "Hi", []);
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["object"], "chat.completion");
+        assert_eq!(body["choices"][0]["message"]["content"], "Hello!");
+        assert_eq!(body["choices"][0]["finish_reason"], "stop");
+        assert_eq!(body["usage"]["total_tokens"], 10);
+
+    }
+
+    #[tokio::test]
+    async fn test_startup_banner_redacts_secrets() {
+
+        let config = test_config(&[
+            ("GROQ_API_KEY", "gsk_supersecretabc123"),
+            ("ADMIN_TOKEN", "admin-secret-wxyz"),
+            ("EXACT_CACHE_ENABLED", "false"),
+            ("SEMANTIC_CACHE_ENABLED", "false")
+        ]).unwrap();
+        let banner = AppState::new(config).await.to_string();
+
+        assert!(banner.starts_with(&format!("LLM Cache Proxy v{}\n", env!("CARGO_PKG_VERSION"))));
+        assert!(banner.contains("  Provider:    groq (https://api.groq.com/openai/v1)"), "{}", banner);
+        assert!(banner.contains("  Redis:       disabled"), "{}", banner);
+        assert!(banner.contains("  API Key:     ****abc123 [set]"), "{}", banner);
+        assert!(banner.contains("  Auth:        admin token ****wxyz"), "{}", banner);
+        assert!(banner.contains("  Proxy Keys:  disabled"), "{}", banner);
+        assert!(!banner.contains("supersecret") && !banner.contains("admin-secret"));
+
+    }
+
+    #[tokio::test]
+    async fn test_connect_with_retry_outlasts_a_slow_start() {
+
+        let config = test_config(&[("STARTUP_RETRIES", "3"), ("STARTUP_RETRY_DELAY_SECS", "0")]).unwrap();
+
+        let attempts = std::sync::atomic::AtomicU32::new(0);
+        let connected = connect_with_retry(&config, "Redis", || async {
+            match attempts.fetch_add(1, std::sync::atomic::Ordering::SeqCst) {
+                0 | 1 => Err("connection refused"),
+                n => Ok(n)
+            }
+        }).await;
+        assert_eq!(connected, 2);
+
+    }
+
+    #[test]
+    fn test_display_host_drops_scheme_and_credentials() {
+        assert_eq!(display_host("redis://:hunter2@cache.internal:6379/"), "cache.internal:6379");
+        assert_eq!(display_host("http://localhost:6334"), "localhost:6334");
+    }
+
+    // a stub upstream that never answers; both cache tiers are off so nothing else is needed
+    #[tokio::test]
+    async fn test_hung_upstream_times_out_with_504() {
+
+        let upstream = Router::new().route("/chat/completions", post(|| async {
+            tokio::time::sleep(Duration::from_secs(3600)).await;
+        }));
+        let base_url = spawn_stub_upstream(upstream).await;
+
+        let config = test_config(&[
+            ("UPSTREAM_BASE_URL", &base_url),
+            ("REQUEST_TIMEOUT_SECS", "1"),
+            ("EXACT_CACHE_ENABLED", "false"),
+            ("SEMANTIC_CACHE_ENABLED", "false")
+        ]).unwrap();
+        let app = build_router(&Arc::new(AppState::new(config).await));
+
+        let request = chat_request("llama-3.1-8b-instant", "Hi", []);
+
+        let started = Instant::now();
+        let response = app.oneshot(request).await.unwrap();
+        assert!(started.elapsed() < Duration::from_secs(3), "Took {:?}", started.elapsed());
+        assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["error"]["code"], "upstream_timeout");
+        assert_eq!(body["timeout_secs"], 1);
+
+    }
+
 }
\ No newline at end of file
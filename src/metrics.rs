@@ -1,27 +1,158 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Arc, Mutex};
 use std::sync::atomic::{AtomicU64, Ordering};
-use serde::Serialize;
+use std::time::Duration;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+const RECENT_ERRORS_CAPACITY: usize = 100;
+
+// stored in a rate-limit gauge until the upstream has reported a value
+const RATE_LIMIT_UNKNOWN: u64 = u64::MAX;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCategory {
+    Upstream4xx,
+    Upstream5xx,
+    UpstreamTimeout,
+    RedisError,
+    QdrantError,
+    EmbeddingError,
+    SerializationError
+}
+
+impl ErrorCategory {
+
+    pub const ALL: [ErrorCategory; 7] = [
+        ErrorCategory::Upstream4xx,
+        ErrorCategory::Upstream5xx,
+        ErrorCategory::UpstreamTimeout,
+        ErrorCategory::RedisError,
+        ErrorCategory::QdrantError,
+        ErrorCategory::EmbeddingError,
+        ErrorCategory::SerializationError
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCategory::Upstream4xx => "upstream_4xx",
+            ErrorCategory::Upstream5xx => "upstream_5xx",
+            ErrorCategory::UpstreamTimeout => "upstream_timeout",
+            ErrorCategory::RedisError => "redis_error",
+            ErrorCategory::QdrantError => "qdrant_error",
+            ErrorCategory::EmbeddingError => "embedding_error",
+            ErrorCategory::SerializationError => "serialization_error"
+        }
+    }
+
+    fn index(&self) -> usize {
+        *self as usize
+    }
+
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ErrorRecord {
+    pub timestamp: String,
+    pub category: &'static str,
+    pub message: String,
+    pub request_id: Option<String>
+}
+
+/// The `Metrics` entry for the route a request came in on, handed to
+/// handlers as an `Extension` by `middleware::track_endpoint`
+#[derive(Debug, Clone)]
+pub struct EndpointMetrics(pub Arc<Metrics>);
 
 #[derive(Debug, Default)]
 pub struct Metrics {
+    // served from the in-process tier 0, counted separately from exact_hits
+    pub tier0_hits: AtomicU64,
     pub exact_hits: AtomicU64,
     pub semantic_hits: AtomicU64,
+    // expired entries served because the upstream call failed (STALE_GRACE_SECS)
+    pub stale_hits: AtomicU64,
     pub misses: AtomicU64,
     pub total_requests: AtomicU64,
     pub tokens_saved: AtomicU64, 
+    pub exact_tokens_saved: AtomicU64,
+    pub semantic_tokens_saved: AtomicU64,
     pub tokens_used: AtomicU64,   
+    // shadow mode: what the cache would have done if it had been serving
+    pub shadow_exact_hits: AtomicU64,
+    pub shadow_semantic_hits: AtomicU64,
+    pub shadow_misses: AtomicU64,
+    pub shadow_similarity_total: AtomicU64, // sum of semantic scores, stored in millionths
+    pub shadow_answer_matches: AtomicU64,
+    pub shadow_answer_mismatches: AtomicU64,
+    // requests forwarded verbatim to the upstream, outside the cache
+    pub passthrough_requests: AtomicU64,
+    // popular entries re-run upstream by the background refresher
+    pub refreshes: AtomicU64,
+    pub refresh_tokens_used: AtomicU64,
+    // semantic-tier embeddings found in, or added to, the embedding cache
+    pub embedding_cache_hits: AtomicU64,
+    pub embedding_cache_misses: AtomicU64,
+    // exact hits near expiry re-run upstream in the background (SWR_WINDOW_SECS)
+    pub revalidations: AtomicU64,
+    pub revalidation_tokens_used: AtomicU64,
+    // POST /v1/cache/lookup checks, which count as neither hits nor misses above
+    pub lookups: AtomicU64,
+    pub lookup_hits: AtomicU64,
+    // upstream 2xx bodies that didn't deserialize into an LLMResponse
+    pub upstream_parse_errors: AtomicU64,
+    // upstream responses whose id had already been seen within the dedup window
+    pub id_dedup_hits: AtomicU64,
+    // misses that waited on an identical in-flight request instead of calling upstream
+    pub coalesced_requests: AtomicU64,
+    // requests turned away with 429 by the per-caller rate limiter
+    pub rate_limited_requests: AtomicU64,
+    // upstream calls repeated after a retryable failure, and calls that failed on every attempt
+    pub upstream_retries: AtomicU64,
+    pub upstream_retries_exhausted: AtomicU64,
+    // gauges from the latest upstream x-ratelimit-* headers, RATE_LIMIT_UNKNOWN until seen
+    pub rate_limit_remaining_tokens: AtomicU64,
+    pub rate_limit_remaining_requests: AtomicU64,
+    // error counters indexed by ErrorCategory, plus a ring buffer of the latest errors
+    pub errors: [AtomicU64; 7],
+    pub recent_errors: Mutex<VecDeque<ErrorRecord>>,
 }
 
 impl Metrics {
     pub fn new() -> Self {
 
-        Self::default()
+        Self {
+            rate_limit_remaining_tokens: AtomicU64::new(RATE_LIMIT_UNKNOWN),
+            rate_limit_remaining_requests: AtomicU64::new(RATE_LIMIT_UNKNOWN),
+            ..Self::default()
+        }
+
+    }
+
+    /// Tier 0 holds exact-match entries, so its tokens count as exact savings
+    pub fn record_tier0_hit(&self, tokens_saved: u64) {
+
+        self.tier0_hits.fetch_add(1, Ordering::Relaxed);
+        self.total_requests.fetch_add(1, Ordering::Relaxed);
+        self.tokens_saved.fetch_add(tokens_saved, Ordering::Relaxed);
+        self.exact_tokens_saved.fetch_add(tokens_saved, Ordering::Relaxed);
 
     }
 
-    pub fn record_exact_hit(&self) {
+    pub fn record_exact_hit(&self, tokens_saved: u64) {
 
         self.exact_hits.fetch_add(1, Ordering::Relaxed);
         self.total_requests.fetch_add(1, Ordering::Relaxed);
+        self.tokens_saved.fetch_add(tokens_saved, Ordering::Relaxed);
+        self.exact_tokens_saved.fetch_add(tokens_saved, Ordering::Relaxed);
+
+    }
+
+    pub fn record_stale_hit(&self, tokens_saved: u64) {
+
+        self.stale_hits.fetch_add(1, Ordering::Relaxed);
+        self.total_requests.fetch_add(1, Ordering::Relaxed);
+        self.tokens_saved.fetch_add(tokens_saved, Ordering::Relaxed);
 
     }
 
@@ -30,6 +161,7 @@ impl Metrics {
         self.semantic_hits.fetch_add(1, Ordering::Relaxed);
         self.total_requests.fetch_add(1, Ordering::Relaxed);
         self.tokens_saved.fetch_add(tokens_saved, Ordering::Relaxed);
+        self.semantic_tokens_saved.fetch_add(tokens_saved, Ordering::Relaxed);
 
     }
 
@@ -41,38 +173,364 @@ impl Metrics {
 
     }
 
+    pub fn record_passthrough(&self) {
+
+        self.passthrough_requests.fetch_add(1, Ordering::Relaxed);
+
+    }
+
+    pub fn record_refresh(&self, tokens_used: u64) {
+
+        self.refreshes.fetch_add(1, Ordering::Relaxed);
+        self.refresh_tokens_used.fetch_add(tokens_used, Ordering::Relaxed);
+
+    }
+
+    pub fn record_embedding_lookup(&self, hit: bool) {
+
+        if hit {
+            self.embedding_cache_hits.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.embedding_cache_misses.fetch_add(1, Ordering::Relaxed);
+        }
+
+    }
+
+    pub fn record_revalidation(&self, tokens_used: u64) {
+
+        self.revalidations.fetch_add(1, Ordering::Relaxed);
+        self.revalidation_tokens_used.fetch_add(tokens_used, Ordering::Relaxed);
+
+    }
+
+    pub fn record_lookup(&self, hit: bool) {
+
+        self.lookups.fetch_add(1, Ordering::Relaxed);
+        if hit {
+            self.lookup_hits.fetch_add(1, Ordering::Relaxed);
+        }
+
+    }
+
+    pub fn record_id_dedup_hit(&self) {
+
+        self.id_dedup_hits.fetch_add(1, Ordering::Relaxed);
+
+    }
+
+    /// A coalesced request saved the tokens of its own upstream call
+    pub fn record_coalesced(&self, tokens_saved: u64) {
+
+        self.coalesced_requests.fetch_add(1, Ordering::Relaxed);
+        self.total_requests.fetch_add(1, Ordering::Relaxed);
+        self.tokens_saved.fetch_add(tokens_saved, Ordering::Relaxed);
+
+    }
+
+    pub fn record_rate_limited(&self) {
+
+        self.rate_limited_requests.fetch_add(1, Ordering::Relaxed);
+
+    }
+
+    pub fn record_upstream_parse_error(&self) {
+        self.upstream_parse_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_upstream_retry(&self) {
+        self.upstream_retries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_upstream_retries_exhausted(&self) {
+        self.upstream_retries_exhausted.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Updates the rate-limit gauges; a missing header keeps the previous value
+    pub fn record_rate_limit(&self, remaining_tokens: Option<u64>, remaining_requests: Option<u64>) {
+
+        if let Some(tokens) = remaining_tokens {
+            self.rate_limit_remaining_tokens.store(tokens, Ordering::Relaxed);
+        }
+        if let Some(requests) = remaining_requests {
+            self.rate_limit_remaining_requests.store(requests, Ordering::Relaxed);
+        }
+
+    }
+
+    pub fn record_shadow_exact_hit(&self) {
+
+        self.shadow_exact_hits.fetch_add(1, Ordering::Relaxed);
+
+    }
+
+    pub fn record_shadow_semantic_hit(&self, similarity: f32) {
+
+        self.shadow_semantic_hits.fetch_add(1, Ordering::Relaxed);
+        let micros = (similarity.clamp(0.0, 1.0) as f64 * 1_000_000.0) as u64;
+        self.shadow_similarity_total.fetch_add(micros, Ordering::Relaxed);
+
+    }
+
+    pub fn record_shadow_miss(&self) {
+
+        self.shadow_misses.fetch_add(1, Ordering::Relaxed);
+
+    }
+
+    // whether a would-be cached answer agreed with the fresh upstream answer
+    pub fn record_shadow_comparison(&self, matched: bool) {
+
+        if matched {
+            self.shadow_answer_matches.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.shadow_answer_mismatches.fetch_add(1, Ordering::Relaxed);
+        }
+
+    }
+
+    pub fn record_error(&self, category: ErrorCategory, message: impl Into<String>, request_id: Option<&str>) {
+
+        self.errors[category.index()].fetch_add(1, Ordering::Relaxed);
+
+        let record = ErrorRecord {
+            timestamp: Utc::now().to_rfc3339(),
+            category: category.as_str(),
+            message: message.into(),
+            request_id: request_id.map(|id| id.to_string())
+        };
+
+        let mut recent = self.recent_errors.lock().unwrap();
+        if recent.len() >= RECENT_ERRORS_CAPACITY {
+            recent.pop_front();
+        }
+        recent.push_back(record);
+
+    }
+
+    pub fn error_counts(&self) -> Vec<(&'static str, u64)> {
+
+        ErrorCategory::ALL.iter()
+            .map(|c| (c.as_str(), self.errors[c.index()].load(Ordering::Relaxed)))
+            .collect()
+
+    }
+
+    /// Most recent errors first
+    pub fn recent_errors(&self) -> Vec<ErrorRecord> {
+
+        self.recent_errors.lock().unwrap().iter().rev().cloned().collect()
+
+    }
+
     pub fn snapshot(&self) -> MetricsSnapshot {
 
         MetricsSnapshot {
+            tier0_hits: self.tier0_hits.load(Ordering::Relaxed),
+            stale_hits: self.stale_hits.load(Ordering::Relaxed),
             exact_hits: self.exact_hits.load(Ordering::Relaxed),
             semantic_hits: self.semantic_hits.load(Ordering::Relaxed),
             misses: self.misses.load(Ordering::Relaxed),
             total_requests: self.total_requests.load(Ordering::Relaxed),
             tokens_saved: self.tokens_saved.load(Ordering::Relaxed),
+            exact_tokens_saved: self.exact_tokens_saved.load(Ordering::Relaxed),
+            semantic_tokens_saved: self.semantic_tokens_saved.load(Ordering::Relaxed),
             tokens_used: self.tokens_used.load(Ordering::Relaxed),
-
+            shadow_exact_hits: self.shadow_exact_hits.load(Ordering::Relaxed),
+            shadow_semantic_hits: self.shadow_semantic_hits.load(Ordering::Relaxed),
+            shadow_misses: self.shadow_misses.load(Ordering::Relaxed),
+            shadow_similarity_total: self.shadow_similarity_total.load(Ordering::Relaxed),
+            shadow_answer_matches: self.shadow_answer_matches.load(Ordering::Relaxed),
+            shadow_answer_mismatches: self.shadow_answer_mismatches.load(Ordering::Relaxed),
+            passthrough_requests: self.passthrough_requests.load(Ordering::Relaxed),
+            refreshes: self.refreshes.load(Ordering::Relaxed),
+            refresh_tokens_used: self.refresh_tokens_used.load(Ordering::Relaxed),
+            embedding_cache_hits: self.embedding_cache_hits.load(Ordering::Relaxed),
+            embedding_cache_misses: self.embedding_cache_misses.load(Ordering::Relaxed),
+            revalidations: self.revalidations.load(Ordering::Relaxed),
+            revalidation_tokens_used: self.revalidation_tokens_used.load(Ordering::Relaxed),
+            lookups: self.lookups.load(Ordering::Relaxed),
+            lookup_hits: self.lookup_hits.load(Ordering::Relaxed),
+            upstream_parse_errors: self.upstream_parse_errors.load(Ordering::Relaxed),
+            id_dedup_hits: self.id_dedup_hits.load(Ordering::Relaxed),
+            coalesced_requests: self.coalesced_requests.load(Ordering::Relaxed),
+            rate_limited_requests: self.rate_limited_requests.load(Ordering::Relaxed),
+            upstream_retries: self.upstream_retries.load(Ordering::Relaxed),
+            upstream_retries_exhausted: self.upstream_retries_exhausted.load(Ordering::Relaxed),
+            rate_limit_remaining_tokens: gauge(&self.rate_limit_remaining_tokens),
+            rate_limit_remaining_requests: gauge(&self.rate_limit_remaining_requests),
         }
     }
+
+    /// Adds counters saved by an earlier process, so totals carry on across
+    /// restarts. Gauges and errors aren't restored
+    pub fn restore(&self, saved: &MetricsSnapshot) {
+
+        self.tier0_hits.fetch_add(saved.tier0_hits, Ordering::Relaxed);
+        self.stale_hits.fetch_add(saved.stale_hits, Ordering::Relaxed);
+        self.exact_hits.fetch_add(saved.exact_hits, Ordering::Relaxed);
+        self.semantic_hits.fetch_add(saved.semantic_hits, Ordering::Relaxed);
+        self.misses.fetch_add(saved.misses, Ordering::Relaxed);
+        self.total_requests.fetch_add(saved.total_requests, Ordering::Relaxed);
+        self.tokens_saved.fetch_add(saved.tokens_saved, Ordering::Relaxed);
+        self.exact_tokens_saved.fetch_add(saved.exact_tokens_saved, Ordering::Relaxed);
+        self.semantic_tokens_saved.fetch_add(saved.semantic_tokens_saved, Ordering::Relaxed);
+        self.tokens_used.fetch_add(saved.tokens_used, Ordering::Relaxed);
+        self.shadow_exact_hits.fetch_add(saved.shadow_exact_hits, Ordering::Relaxed);
+        self.shadow_semantic_hits.fetch_add(saved.shadow_semantic_hits, Ordering::Relaxed);
+        self.shadow_misses.fetch_add(saved.shadow_misses, Ordering::Relaxed);
+        self.shadow_similarity_total.fetch_add(saved.shadow_similarity_total, Ordering::Relaxed);
+        self.shadow_answer_matches.fetch_add(saved.shadow_answer_matches, Ordering::Relaxed);
+        self.shadow_answer_mismatches.fetch_add(saved.shadow_answer_mismatches, Ordering::Relaxed);
+        self.passthrough_requests.fetch_add(saved.passthrough_requests, Ordering::Relaxed);
+        self.refreshes.fetch_add(saved.refreshes, Ordering::Relaxed);
+        self.refresh_tokens_used.fetch_add(saved.refresh_tokens_used, Ordering::Relaxed);
+        self.embedding_cache_hits.fetch_add(saved.embedding_cache_hits, Ordering::Relaxed);
+        self.embedding_cache_misses.fetch_add(saved.embedding_cache_misses, Ordering::Relaxed);
+        self.revalidations.fetch_add(saved.revalidations, Ordering::Relaxed);
+        self.revalidation_tokens_used.fetch_add(saved.revalidation_tokens_used, Ordering::Relaxed);
+        self.lookups.fetch_add(saved.lookups, Ordering::Relaxed);
+        self.lookup_hits.fetch_add(saved.lookup_hits, Ordering::Relaxed);
+        self.upstream_parse_errors.fetch_add(saved.upstream_parse_errors, Ordering::Relaxed);
+        self.id_dedup_hits.fetch_add(saved.id_dedup_hits, Ordering::Relaxed);
+        self.coalesced_requests.fetch_add(saved.coalesced_requests, Ordering::Relaxed);
+        self.rate_limited_requests.fetch_add(saved.rate_limited_requests, Ordering::Relaxed);
+        self.upstream_retries.fetch_add(saved.upstream_retries, Ordering::Relaxed);
+        self.upstream_retries_exhausted.fetch_add(saved.upstream_retries_exhausted, Ordering::Relaxed);
+
+    }
 }
 
-#[derive(Debug, Serialize)]
+fn gauge(value: &AtomicU64) -> Option<u64> {
+    Some(value.load(Ordering::Relaxed)).filter(|v| *v != RATE_LIMIT_UNKNOWN)
+}
+
+// also what's persisted by METRICS_PERSIST; fields added later default to 0
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct MetricsSnapshot {
+    pub tier0_hits: u64,
+    pub stale_hits: u64,
     pub exact_hits: u64,
     pub semantic_hits: u64,
     pub misses: u64,
     pub total_requests: u64,
     pub tokens_saved: u64,
+    pub exact_tokens_saved: u64,
+    pub semantic_tokens_saved: u64,
     pub tokens_used: u64,
+    pub shadow_exact_hits: u64,
+    pub shadow_semantic_hits: u64,
+    pub shadow_misses: u64,
+    pub shadow_similarity_total: u64,
+    pub shadow_answer_matches: u64,
+    pub shadow_answer_mismatches: u64,
+    pub passthrough_requests: u64,
+    pub refreshes: u64,
+    pub refresh_tokens_used: u64,
+    pub embedding_cache_hits: u64,
+    pub embedding_cache_misses: u64,
+    pub revalidations: u64,
+    pub revalidation_tokens_used: u64,
+    pub lookups: u64,
+    pub lookup_hits: u64,
+    pub upstream_parse_errors: u64,
+    pub id_dedup_hits: u64,
+    pub coalesced_requests: u64,
+    pub rate_limited_requests: u64,
+    pub upstream_retries: u64,
+    pub upstream_retries_exhausted: u64,
+    // None until the upstream has sent the header
+    pub rate_limit_remaining_tokens: Option<u64>,
+    pub rate_limit_remaining_requests: Option<u64>,
 }
 
 impl MetricsSnapshot {
+
+    /// The counters accumulated since `baseline` was taken; gauges are kept as they are
+    pub fn since(&self, baseline: &MetricsSnapshot) -> MetricsSnapshot {
+
+        MetricsSnapshot {
+            tier0_hits: self.tier0_hits.saturating_sub(baseline.tier0_hits),
+            stale_hits: self.stale_hits.saturating_sub(baseline.stale_hits),
+            exact_hits: self.exact_hits.saturating_sub(baseline.exact_hits),
+            semantic_hits: self.semantic_hits.saturating_sub(baseline.semantic_hits),
+            misses: self.misses.saturating_sub(baseline.misses),
+            total_requests: self.total_requests.saturating_sub(baseline.total_requests),
+            tokens_saved: self.tokens_saved.saturating_sub(baseline.tokens_saved),
+            exact_tokens_saved: self.exact_tokens_saved.saturating_sub(baseline.exact_tokens_saved),
+            semantic_tokens_saved: self.semantic_tokens_saved.saturating_sub(baseline.semantic_tokens_saved),
+            tokens_used: self.tokens_used.saturating_sub(baseline.tokens_used),
+            shadow_exact_hits: self.shadow_exact_hits.saturating_sub(baseline.shadow_exact_hits),
+            shadow_semantic_hits: self.shadow_semantic_hits.saturating_sub(baseline.shadow_semantic_hits),
+            shadow_misses: self.shadow_misses.saturating_sub(baseline.shadow_misses),
+            shadow_similarity_total: self.shadow_similarity_total.saturating_sub(baseline.shadow_similarity_total),
+            shadow_answer_matches: self.shadow_answer_matches.saturating_sub(baseline.shadow_answer_matches),
+            shadow_answer_mismatches: self.shadow_answer_mismatches.saturating_sub(baseline.shadow_answer_mismatches),
+            passthrough_requests: self.passthrough_requests.saturating_sub(baseline.passthrough_requests),
+            refreshes: self.refreshes.saturating_sub(baseline.refreshes),
+            refresh_tokens_used: self.refresh_tokens_used.saturating_sub(baseline.refresh_tokens_used),
+            embedding_cache_hits: self.embedding_cache_hits.saturating_sub(baseline.embedding_cache_hits),
+            embedding_cache_misses: self.embedding_cache_misses.saturating_sub(baseline.embedding_cache_misses),
+            revalidations: self.revalidations.saturating_sub(baseline.revalidations),
+            revalidation_tokens_used: self.revalidation_tokens_used.saturating_sub(baseline.revalidation_tokens_used),
+            lookups: self.lookups.saturating_sub(baseline.lookups),
+            lookup_hits: self.lookup_hits.saturating_sub(baseline.lookup_hits),
+            upstream_parse_errors: self.upstream_parse_errors.saturating_sub(baseline.upstream_parse_errors),
+            id_dedup_hits: self.id_dedup_hits.saturating_sub(baseline.id_dedup_hits),
+            coalesced_requests: self.coalesced_requests.saturating_sub(baseline.coalesced_requests),
+            rate_limited_requests: self.rate_limited_requests.saturating_sub(baseline.rate_limited_requests),
+            upstream_retries: self.upstream_retries.saturating_sub(baseline.upstream_retries),
+            upstream_retries_exhausted: self.upstream_retries_exhausted.saturating_sub(baseline.upstream_retries_exhausted),
+            rate_limit_remaining_tokens: self.rate_limit_remaining_tokens,
+            rate_limit_remaining_requests: self.rate_limit_remaining_requests
+        }
+
+    }
+
     pub fn cache_hit_rate(&self) -> f64 {
 
         if self.total_requests == 0 {
             return 0.0;
         }
-        let total_hits = self.exact_hits + self.semantic_hits;
-        (total_hits as f64 / self.total_requests as f64) * 100.0
+        (self.total_hits() as f64 / self.total_requests as f64) * 100.0
+
+    }
+
+    pub fn total_hits(&self) -> u64 {
+        self.tier0_hits + self.exact_hits + self.semantic_hits
+    }
+
+    pub fn shadow_requests(&self) -> u64 {
+        self.shadow_exact_hits + self.shadow_semantic_hits + self.shadow_misses
+    }
+
+    pub fn shadow_hit_rate(&self) -> f64 {
+
+        let total = self.shadow_requests();
+        if total == 0 {
+            return 0.0;
+        }
+        let would_be_hits = self.shadow_exact_hits + self.shadow_semantic_hits;
+        (would_be_hits as f64 / total as f64) * 100.0
+
+    }
+
+    pub fn shadow_avg_similarity(&self) -> f64 {
+
+        if self.shadow_semantic_hits == 0 {
+            return 0.0;
+        }
+        (self.shadow_similarity_total as f64 / 1_000_000.0) / self.shadow_semantic_hits as f64
+
+    }
+
+    pub fn shadow_answer_match_rate(&self) -> f64 {
+
+        let compared = self.shadow_answer_matches + self.shadow_answer_mismatches;
+        if compared == 0 {
+            return 0.0;
+        }
+        (self.shadow_answer_matches as f64 / compared as f64) * 100.0
 
     }
 
@@ -86,4 +544,279 @@ impl MetricsSnapshot {
     pub fn cost_spent_usd(&self) -> f64 {
         (self.tokens_used as f64 / 1000.0) * 0.001
     }
-}
\ No newline at end of file
+}
+
+/// The steps of serving a request whose latency is histogrammed under
+/// "latency" in /metrics
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    RedisLookup,
+    // calls to the embedding service; embeddings found in the Redis cache aren't timed
+    Embedding,
+    QdrantSearch,
+    // non-streaming LLM calls, start to parsed response
+    Upstream
+}
+
+impl Stage {
+
+    pub const ALL: [Stage; 4] = [Stage::RedisLookup, Stage::Embedding, Stage::QdrantSearch, Stage::Upstream];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Stage::RedisLookup => "redis_lookup",
+            Stage::Embedding => "embedding",
+            Stage::QdrantSearch => "qdrant_search",
+            Stage::Upstream => "upstream"
+        }
+    }
+
+}
+
+// bucket i holds latencies up to 10µs * 2^(i/4), so each is about 19% wider
+// than the last and the top one ends near 170s. Percentiles are reported as
+// their bucket's upper bound
+const HISTOGRAM_BUCKETS: usize = 97;
+const HISTOGRAM_BASE_MICROS: f64 = 10.0;
+const HISTOGRAM_BUCKETS_PER_DOUBLING: f64 = 4.0;
+
+/// A fixed-bucket latency histogram that can be recorded into concurrently
+#[derive(Debug)]
+pub struct LatencyHistogram {
+    buckets: [AtomicU64; HISTOGRAM_BUCKETS],
+    sum_micros: AtomicU64,
+    max_micros: AtomicU64
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        LatencyHistogram {
+            buckets: std::array::from_fn(|_| AtomicU64::new(0)),
+            sum_micros: AtomicU64::new(0),
+            max_micros: AtomicU64::new(0)
+        }
+    }
+}
+
+/// Count, mean and percentiles of one histogram, in milliseconds. The
+/// figures are None until something has been recorded
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LatencySnapshot {
+    pub count: u64,
+    pub mean_ms: Option<f64>,
+    pub p50_ms: Option<f64>,
+    pub p95_ms: Option<f64>,
+    pub p99_ms: Option<f64>,
+    pub max_ms: Option<f64>
+}
+
+impl LatencyHistogram {
+
+    pub fn record(&self, latency: Duration) {
+
+        let micros = latency.as_micros().min(u64::MAX as u128) as u64;
+        self.buckets[bucket_of(micros)].fetch_add(1, Ordering::Relaxed);
+        self.sum_micros.fetch_add(micros, Ordering::Relaxed);
+        self.max_micros.fetch_max(micros, Ordering::Relaxed);
+
+    }
+
+    pub fn snapshot(&self) -> LatencySnapshot {
+
+        let counts: Vec<u64> = self.buckets.iter().map(|bucket| bucket.load(Ordering::Relaxed)).collect();
+        let count: u64 = counts.iter().sum();
+        let max_micros = self.max_micros.load(Ordering::Relaxed);
+        let ms = |micros: f64| (micros / 10.0).round() / 100.0;
+
+        // the bucket bound can overshoot the slowest latency actually seen
+        let percentile = |q: f64| -> Option<f64> {
+            let rank = ((count as f64 * q).ceil() as u64).max(1);
+            let mut seen = 0;
+            counts.iter().position(|n| {
+                seen += n;
+                seen >= rank
+            }).map(|bucket| ms(bucket_bound(bucket).min(max_micros as f64)))
+        };
+
+        LatencySnapshot {
+            count,
+            mean_ms: (count > 0).then(|| ms(self.sum_micros.load(Ordering::Relaxed) as f64 / count as f64)),
+            p50_ms: percentile(0.50),
+            p95_ms: percentile(0.95),
+            p99_ms: percentile(0.99),
+            max_ms: (count > 0).then(|| ms(max_micros as f64))
+        }
+
+    }
+
+}
+
+fn bucket_of(micros: u64) -> usize {
+
+    if micros as f64 <= HISTOGRAM_BASE_MICROS {
+        return 0;
+    }
+    let bucket = ((micros as f64 / HISTOGRAM_BASE_MICROS).log2() * HISTOGRAM_BUCKETS_PER_DOUBLING).ceil() as usize;
+    bucket.min(HISTOGRAM_BUCKETS - 1)
+
+}
+
+fn bucket_bound(bucket: usize) -> f64 {
+    HISTOGRAM_BASE_MICROS * (bucket as f64 / HISTOGRAM_BUCKETS_PER_DOUBLING).exp2()
+}
+
+/// One latency histogram per `Stage`
+#[derive(Debug, Default)]
+pub struct StageLatencies {
+    stages: [LatencyHistogram; 4]
+}
+
+impl StageLatencies {
+
+    pub fn record(&self, stage: Stage, latency: Duration) {
+        self.stages[stage as usize].record(latency);
+    }
+
+    pub fn snapshot(&self) -> BTreeMap<&'static str, LatencySnapshot> {
+        Stage::ALL.iter().map(|stage| (stage.as_str(), self.stages[*stage as usize].snapshot())).collect()
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_latency_percentiles_within_bucket_resolution() {
+
+        let histogram = LatencyHistogram::default();
+        assert_eq!(histogram.snapshot().p50_ms, None);
+
+        // 1..=100ms, one each
+        for ms in 1..=100 {
+            histogram.record(Duration::from_millis(ms));
+        }
+
+        let snapshot = histogram.snapshot();
+        assert_eq!(snapshot.count, 100);
+        assert_eq!(snapshot.max_ms, Some(100.0));
+        assert!((snapshot.mean_ms.unwrap() - 50.5).abs() < 0.01);
+        for (reported, actual) in [(snapshot.p50_ms, 50.0), (snapshot.p95_ms, 95.0), (snapshot.p99_ms, 99.0)] {
+            let reported = reported.unwrap();
+            assert!(reported >= actual && reported <= actual * 1.2, "{} for {}", reported, actual);
+        }
+
+        let stages = StageLatencies::default();
+        stages.record(Stage::Embedding, Duration::from_millis(12));
+        let snapshot = stages.snapshot();
+        assert_eq!(snapshot["embedding"].count, 1);
+        assert_eq!(snapshot["upstream"].count, 0);
+
+    }
+
+    #[test]
+    fn test_exact_hits_count_towards_tokens_saved() {
+
+        let metrics = Metrics::new();
+        metrics.record_exact_hit(120);
+        metrics.record_semantic_hit(80);
+        metrics.record_miss(50);
+
+        let snapshot = metrics.snapshot();
+
+        assert_eq!(snapshot.tokens_saved, 200);
+        assert_eq!(snapshot.exact_tokens_saved, 120);
+        assert_eq!(snapshot.semantic_tokens_saved, 80);
+        assert_eq!(snapshot.tokens_used, 50);
+
+    }
+
+    #[test]
+    fn test_tier0_hits_are_separate_from_exact_hits() {
+
+        let metrics = Metrics::new();
+        metrics.record_tier0_hit(30);
+        metrics.record_exact_hit(30);
+        metrics.record_miss(30);
+
+        let snapshot = metrics.snapshot();
+
+        assert_eq!(snapshot.tier0_hits, 1);
+        assert_eq!(snapshot.exact_hits, 1);
+        assert_eq!(snapshot.total_hits(), 2);
+        assert_eq!(snapshot.exact_tokens_saved, 60);
+        assert!((snapshot.cache_hit_rate() - 66.67).abs() < 0.01);
+
+    }
+
+    #[test]
+    fn test_rate_limit_gauges() {
+
+        let metrics = Metrics::new();
+        assert_eq!(metrics.snapshot().rate_limit_remaining_tokens, None);
+
+        metrics.record_rate_limit(Some(5800), Some(14370));
+        metrics.record_rate_limit(Some(5000), None);
+
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.rate_limit_remaining_tokens, Some(5000));
+        assert_eq!(snapshot.rate_limit_remaining_requests, Some(14370), "A missing header keeps the last value");
+
+    }
+
+    #[test]
+    fn test_shadow_rates_are_separate_from_real_hit_rate() {
+
+        let metrics = Metrics::new();
+        metrics.record_shadow_exact_hit();
+        metrics.record_shadow_semantic_hit(0.95);
+        metrics.record_shadow_miss();
+        metrics.record_miss(100);
+        metrics.record_miss(100);
+        metrics.record_miss(100);
+
+        let snapshot = metrics.snapshot();
+
+        assert_eq!(snapshot.cache_hit_rate(), 0.0, "Shadow hits must not count as real hits");
+        assert_eq!(snapshot.shadow_requests(), 3);
+        assert!((snapshot.shadow_hit_rate() - 66.666).abs() < 0.01);
+        assert!((snapshot.shadow_avg_similarity() - 0.95).abs() < 1e-4);
+
+    }
+
+    #[test]
+    fn test_error_ring_buffer_is_bounded() {
+
+        let metrics = Metrics::new();
+        for i in 0..(RECENT_ERRORS_CAPACITY + 5) {
+            metrics.record_error(ErrorCategory::RedisError, format!("error {}", i), None);
+        }
+        metrics.record_error(ErrorCategory::Upstream5xx, "bad gateway", Some("req-1"));
+
+        let recent = metrics.recent_errors();
+        let counts = metrics.error_counts();
+
+        assert_eq!(recent.len(), RECENT_ERRORS_CAPACITY);
+        assert_eq!(recent[0].category, "upstream_5xx", "Newest error should come first");
+        assert_eq!(recent[0].request_id.as_deref(), Some("req-1"));
+        assert!(counts.contains(&("redis_error", (RECENT_ERRORS_CAPACITY + 5) as u64)));
+
+    }
+
+    #[test]
+    fn test_shadow_answer_match_rate() {
+
+        let metrics = Metrics::new();
+        metrics.record_shadow_comparison(true);
+        metrics.record_shadow_comparison(true);
+        metrics.record_shadow_comparison(false);
+
+        let snapshot = metrics.snapshot();
+
+        assert!((snapshot.shadow_answer_match_rate() - 66.666).abs() < 0.01);
+
+    }
+
+}
@@ -0,0 +1,162 @@
+// Keeps /metrics counters across restarts (METRICS_PERSIST). A snapshot of
+// the global counters is saved every METRICS_PERSIST_INTERVAL_SECS, to Redis
+// (without expiry, and again right after a cache clear flushes it) or a JSON
+// file, and added back on startup. What was restored is kept so
+// `/metrics?scope=process` can still report this process on its own.
+// Per-endpoint, per-key and per-tenant breakdowns, gauges and error counts
+// start over with each process.
+
+use std::path::Path;
+use crate::AppState;
+use crate::cache::METRICS_SNAPSHOT_PREFIX;
+use crate::config::MetricsPersist;
+use crate::metrics::{ErrorCategory, MetricsSnapshot};
+
+/// Saves the current counters to the configured store
+pub async fn save(state: &AppState) -> Result<(), String> {
+
+    let snapshot = state.metrics.snapshot();
+    let json = serde_json::to_string(&snapshot).map_err(|e| e.to_string())?;
+
+    match state.config.metrics_persist {
+        MetricsPersist::Off => Ok(()),
+        MetricsPersist::Redis => {
+            let redis = state.exact_cache.as_ref().ok_or("the exact-match tier is disabled")?;
+            redis.set_persistent(&snapshot_key(state), &json).await.map_err(|e| e.to_string())
+        }
+        // written aside and renamed, so a crash mid-write can't leave half a file
+        MetricsPersist::File => {
+            let path = Path::new(&state.config.metrics_persist_path);
+            let temp = path.with_extension("tmp");
+            tokio::fs::write(&temp, json).await.map_err(|e| e.to_string())?;
+            tokio::fs::rename(&temp, path).await.map_err(|e| e.to_string())
+        }
+    }
+
+}
+
+/// Adds the counters saved by the previous process, if any, and remembers
+/// them as the baseline for `scope=process`
+pub async fn restore(state: &AppState) {
+
+    let saved = match load(state).await {
+        Ok(Some(saved)) => saved,
+        Ok(None) => return,
+        Err(e) => {
+            tracing::warn!("Couldn't restore saved metrics: {} - starting from zero", e);
+            return;
+        }
+    };
+
+    state.metrics.restore(&saved);
+    tracing::info!("Restored metrics: {} requests, {} hits from earlier runs", saved.total_requests, saved.total_hits());
+    *state.restored_metrics.lock().unwrap() = Some(saved);
+
+}
+
+async fn load(state: &AppState) -> Result<Option<MetricsSnapshot>, String> {
+
+    let json = match state.config.metrics_persist {
+        MetricsPersist::Off => None,
+        MetricsPersist::Redis => match &state.exact_cache {
+            Some(redis) => redis.get(&snapshot_key(state)).await.map_err(|e| e.to_string())?,
+            None => None
+        },
+        MetricsPersist::File => match tokio::fs::read_to_string(&state.config.metrics_persist_path).await {
+            Ok(json) => Some(json),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e.to_string())
+        }
+    };
+
+    json.map(|json| serde_json::from_str(&json).map_err(|e| e.to_string())).transpose()
+
+}
+
+// one key per METRICS_INSTANCE_ID, so proxies sharing a Redis don't add up each other's counters
+fn snapshot_key(state: &AppState) -> String {
+    format!("{}{}", METRICS_SNAPSHOT_PREFIX, state.config.metrics_instance_id)
+}
+
+/// Saves the counters, recording a failure as an error rather than returning it
+pub async fn save_or_record(state: &AppState) {
+
+    if let Err(e) = save(state).await {
+        let category = match state.config.metrics_persist {
+            MetricsPersist::Redis => ErrorCategory::RedisError,
+            _ => ErrorCategory::SerializationError
+        };
+        state.metrics.record_error(category, format!("Saving metrics failed: {}", e), None);
+    }
+
+}
+
+#[cfg(all(test, feature = "mock"))]
+mod tests {
+
+    use super::*;
+    use crate::config::Config;
+
+    #[tokio::test]
+    async fn test_counters_survive_a_restart_through_a_file() {
+
+        let path = std::env::temp_dir().join(format!("metrics_{}.json", uuid::Uuid::new_v4()));
+        let path = path.to_str().unwrap().to_string();
+        let config = || Config::from_lookup(|name| match name {
+            "METRICS_PERSIST" => Some("file".to_string()),
+            "METRICS_PERSIST_PATH" => Some(path.clone()),
+            _ => None
+        }).unwrap();
+
+        let before = AppState::new(config()).await;
+        restore(&before).await;
+        assert!(before.restored_metrics.lock().unwrap().is_none(), "Nothing saved yet");
+        before.metrics.record_exact_hit(40);
+        before.metrics.record_miss(60);
+        save(&before).await.unwrap();
+
+        let after = AppState::new(config()).await;
+        restore(&after).await;
+        after.metrics.record_miss(10);
+
+        let snapshot = after.metrics.snapshot();
+        assert_eq!(snapshot.total_requests, 3);
+        assert_eq!(snapshot.tokens_used, 70);
+
+        let process = snapshot.since(after.restored_metrics.lock().unwrap().as_ref().unwrap());
+        assert_eq!(process.total_requests, 1);
+        assert_eq!(process.exact_hits, 0);
+
+        std::fs::remove_file(&path).unwrap();
+
+    }
+
+    #[tokio::test]
+    async fn test_redis_snapshot_never_expires_and_survives_a_cache_clear() {
+
+        use axum::{extract::{Query, State}, http::HeaderMap};
+        use crate::handlers::admin_clear_cache;
+
+        let state = AppState::new(Config::from_lookup(|name| match name {
+            "METRICS_PERSIST" => Some("redis".to_string()),
+            "METRICS_INSTANCE_ID" => Some("node-1".to_string()),
+            "ADMIN_TOKEN" => Some("secret".to_string()),
+            _ => None
+        }).unwrap()).await;
+        let redis = state.exact_cache.as_ref().unwrap();
+
+        state.metrics.record_miss(60);
+        save(&state).await.unwrap();
+        let (_, ttl) = redis.get_with_ttl("cache:metrics:node-1").await.unwrap().unwrap();
+        assert_eq!(ttl, -1);
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-admin-token", "secret".parse().unwrap());
+        let _ = admin_clear_cache(State(state.clone()), headers, Query::try_from_uri(&"/?tier=redis".parse().unwrap()).unwrap()).await.unwrap();
+
+        let saved = load(&state).await.unwrap().expect("The snapshot is saved again after the flush");
+        assert_eq!(saved.total_requests, 1);
+
+    }
+
+}
@@ -0,0 +1,651 @@
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use axum::{Json, body::Body, extract::{ConnectInfo, Request, State}, http::{StatusCode, header}, middleware::Next, response::{IntoResponse, Response}};
+use chrono::Utc;
+use serde_json::{json, Value};
+use crate::AppState;
+use crate::metrics::{EndpointMetrics, Metrics};
+use tower_http::compression::{CompressionLayer, Predicate, predicate::{NotForContentType, SizeAbove}};
+use crate::config::{CompressionConfig, TenantSource, mask_secret};
+use crate::handlers::{provided_admin_token, require_admin};
+use crate::logger::AuditEntry;
+use crate::models::{ApiError, LLMRequest, REDACTED};
+use tracing::Instrument;
+
+// upper bound on a buffered request body
+pub const MAX_BODY_BYTES: usize = 10 * 1024 * 1024;
+
+// a larger admin request body is audited by its size alone
+const MAX_AUDITED_BODY_BYTES: usize = 8 * 1024;
+
+// a client-chosen request id is kept, so its logs can be matched to the caller's
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// Runs every request inside an info-level `request` span carrying its
+/// method, path and request id, so each log line it produces can be tied back
+/// to it. The span's close event records the status and how long it took.
+/// The id is the caller's `x-request-id` when it's usable, a new UUID otherwise,
+/// and is echoed on the response
+pub async fn request_span(mut request: Request, next: Next) -> Response {
+
+    let id = request.headers().get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|value| !value.is_empty() && value.len() <= MAX_REQUEST_ID_LEN)
+        .map(str::to_string)
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+    let header_value = header::HeaderValue::from_str(&id).ok();
+    if let Some(value) = &header_value {
+        request.headers_mut().insert(REQUEST_ID_HEADER, value.clone());
+    }
+
+    let span = tracing::info_span!(
+        "request",
+        method = %request.method(),
+        path = %request.uri().path(),
+        request_id = %id,
+        status = tracing::field::Empty
+    );
+
+    let mut response = next.run(request).instrument(span.clone()).await;
+    span.record("status", response.status().as_u16());
+    if let Some(value) = header_value {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    response
+
+}
+
+/// The id `request_span` gave this request, or a new one outside the router
+pub fn request_id(headers: &header::HeaderMap) -> String {
+    headers.get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string)
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string())
+}
+
+/// Rejects requests whose body length differs from their `Content-Length`
+/// header before any JSON parsing happens. Requests without the header
+/// (chunked transfer encoding) pass through untouched
+pub async fn validate_content_length(request: Request, next: Next) -> Response {
+
+    let Some(declared) = request.headers().get(header::CONTENT_LENGTH) else {
+        return next.run(request).await;
+    };
+
+    let Some(declared) = declared.to_str().ok().and_then(|v| v.trim().parse::<usize>().ok()) else {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({"error": "invalid_content_length"}))
+        ).into_response();
+    };
+
+    let (parts, body) = request.into_parts();
+
+    let bytes = match axum::body::to_bytes(body, MAX_BODY_BYTES).await {
+        Ok(bytes) => bytes,
+        Err(e) => return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({"error": "invalid_body", "message": e.to_string()}))
+        ).into_response()
+    };
+
+    if bytes.len() != declared {
+        tracing::info!(declared, actual = bytes.len(), "rejected request: content-length mismatch");
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({"error": "content_length_mismatch", "declared": declared, "actual": bytes.len()}))
+        ).into_response();
+    }
+
+    next.run(Request::from_parts(parts, Body::from(bytes))).await
+
+}
+
+/// The proxy API key a request authenticated with: its configured name and
+/// its `/metrics` entry. Handed to handlers as an `Extension`
+#[derive(Debug, Clone)]
+pub struct ApiKeyIdentity {
+    pub name: String,
+    pub metrics: Arc<Metrics>
+}
+
+/// Rejects requests without a known proxy key in `Authorization: Bearer <key>`
+/// with 401 when PROXY_API_KEYS is set; does nothing otherwise. The key is
+/// the proxy's own credential, so it is removed before the request goes on
+/// and passthrough sends the upstream key in its place
+pub async fn require_api_key(State(state): State<AppState>, mut request: Request, next: Next) -> Response {
+
+    if !state.config.api_keys.enabled() {
+        return next.run(request).await;
+    }
+
+    let name = request.headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .and_then(|key| state.config.api_keys.identify(key.trim()))
+        .map(|name| name.to_string());
+
+    let Some(name) = name else {
+        tracing::info!(path = request.uri().path(), "rejected request: missing or unknown api key");
+        let error = ApiError::new("invalid_request_error", "Invalid or missing API key. Send it as 'Authorization: Bearer <key>'")
+            .with_code("invalid_api_key");
+        return (StatusCode::UNAUTHORIZED, Json(error)).into_response();
+    };
+
+    // one entry per configured key, so this can't grow past PROXY_API_KEYS
+    let metrics = state.per_api_key.entry(name.clone()).or_insert_with(|| Arc::new(Metrics::new())).clone();
+
+    request.headers_mut().remove(header::AUTHORIZATION);
+    request.extensions_mut().insert(ApiKeyIdentity { name, metrics });
+    next.run(request).await
+
+}
+
+/// Who a request's usage is counted against by the rate limiter
+#[derive(Debug, Clone)]
+pub struct RateLimitCaller(pub String);
+
+/// Answers 429 with `Retry-After` once the caller has used up its
+/// RATE_LIMIT_REQUESTS_PER_MIN or RATE_LIMIT_TOKENS_PER_MIN. Callers are told
+/// apart by proxy API key, so this runs after `require_api_key`, or by
+/// client IP when keys aren't configured. Under RATE_LIMIT_SHARED the request
+/// limit is counted in Redis rather than in this instance
+pub async fn rate_limit(State(state): State<AppState>, mut request: Request, next: Next) -> Response {
+
+    if !state.rate_limiter.enabled() {
+        return next.run(request).await;
+    }
+
+    let caller = match request.extensions().get::<ApiKeyIdentity>() {
+        Some(identity) => format!("key:{}", identity.name),
+        None => format!("ip:{}", client_ip(&request, &state.config.trusted_proxies).unwrap_or_else(|| "unknown".to_string()))
+    };
+
+    let mut allowed = state.rate_limiter.check(&caller);
+    if allowed.is_ok() && state.config.rate_limits.shared && let Some(cache) = &state.exact_cache {
+        allowed = state.rate_limiter.check_shared(cache.as_ref(), &caller).await;
+    }
+
+    if let Err(wait) = allowed {
+        let retry_after = wait.as_secs_f64().ceil().max(1.0) as u64;
+        tracing::info!(%caller, path = request.uri().path(), retry_after, "rate limited");
+        state.metrics.record_rate_limited();
+
+        let error = ApiError::new("rate_limit_error", format!("Rate limit exceeded. Retry in {}s", retry_after))
+            .with_code("rate_limit_exceeded");
+        return (StatusCode::TOO_MANY_REQUESTS, [(header::RETRY_AFTER, retry_after.to_string())], Json(error)).into_response();
+    }
+
+    request.extensions_mut().insert(RateLimitCaller(caller));
+    next.run(request).await
+
+}
+
+/// The tenant whose partition of the cache a request reads and writes, and
+/// its `/metrics/tenants` entry
+#[derive(Debug, Clone)]
+pub struct Tenant {
+    pub id: String,
+    pub metrics: Arc<Metrics>
+}
+
+// request header naming the tenant under TENANT_SOURCE=header
+pub const TENANT_HEADER: &str = "x-tenant-id";
+const MAX_TENANT_LEN: usize = 64;
+// tenants given their own /metrics/tenants entry; header tenants are chosen
+// by clients, so past this the rest share "other"
+pub const MAX_TRACKED_TENANTS: usize = 1000;
+const OTHER_TENANTS: &str = "other";
+
+/// Works out the request's tenant under TENANT_SOURCE and hands it to the
+/// handler as a `Tenant` extension: the `x-tenant-id` header, or the name of
+/// the proxy API key, so this runs after `require_api_key`. A request without
+/// the header is untenanted and only shares entries with other such requests.
+/// Ids become part of cache keys, so anything but letters, digits, `-`, `_`
+/// and `.` is rejected with 400
+pub async fn resolve_tenant(State(state): State<AppState>, mut request: Request, next: Next) -> Response {
+
+    let tenant = match state.config.tenant_source {
+        TenantSource::Off => None,
+        TenantSource::ApiKey => request.extensions().get::<ApiKeyIdentity>().map(|identity| identity.name.clone()),
+        TenantSource::Header => match request.headers().get(TENANT_HEADER) {
+            None => None,
+            Some(value) => match value.to_str().ok().map(str::trim).filter(|id| valid_tenant(id)) {
+                Some(id) => Some(id.to_string()),
+                None => {
+                    let error = ApiError::new(
+                        "invalid_request_error",
+                        format!("Invalid {} header: use up to {} letters, digits, '-', '_' or '.'", TENANT_HEADER, MAX_TENANT_LEN)
+                    ).with_code("invalid_tenant_id");
+                    return (StatusCode::BAD_REQUEST, Json(error)).into_response();
+                }
+            }
+        }
+    };
+
+    if let Some(id) = tenant {
+        let existing = state.per_tenant.get(&id).map(|metrics| metrics.clone());
+        let metrics = existing.unwrap_or_else(|| {
+            let key = if state.per_tenant.len() < MAX_TRACKED_TENANTS { id.as_str() } else { OTHER_TENANTS };
+            state.per_tenant.entry(key.to_string()).or_insert_with(|| Arc::new(Metrics::new())).clone()
+        });
+        request.extensions_mut().insert(Tenant { id, metrics });
+    }
+    next.run(request).await
+
+}
+
+/// Answers 429 once the request's tenant has used up its
+/// TENANT_DAILY_TOKEN_QUOTA or TENANT_DAILY_COST_QUOTA_USD, with `Retry-After`
+/// set to midnight UTC. Runs after `resolve_tenant`; untenanted requests pass
+pub async fn enforce_tenant_quota(State(state): State<AppState>, request: Request, next: Next) -> Response {
+
+    let Some(tenant) = request.extensions().get::<Tenant>() else {
+        return next.run(request).await;
+    };
+
+    if let Err(exceeded) = state.quotas.check(&tenant.id) {
+        let retry_after = (exceeded.resets_at - Utc::now()).num_seconds().max(1);
+        tracing::info!(tenant = %tenant.id, quota = exceeded.quota, used = exceeded.used, limit = exceeded.limit, "quota exceeded");
+        state.metrics.record_rate_limited();
+
+        let error = ApiError::new(
+            "rate_limit_error",
+            format!("Tenant '{}' has used its {} quota of {} for today. It resets at {}", tenant.id, exceeded.quota, exceeded.limit, exceeded.resets_at.to_rfc3339())
+        ).with_code("quota_exceeded");
+        let mut body = json!(error);
+        body["quota"] = json!({
+            "quota": exceeded.quota,
+            "limit": exceeded.limit,
+            "used": exceeded.used,
+            "resets_at": exceeded.resets_at.to_rfc3339()
+        });
+        return (StatusCode::TOO_MANY_REQUESTS, [(header::RETRY_AFTER, retry_after.to_string())], Json(body)).into_response();
+    }
+
+    next.run(request).await
+
+}
+
+pub fn valid_tenant(id: &str) -> bool {
+
+    !id.is_empty()
+        && id.len() <= MAX_TENANT_LEN
+        && id.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+
+}
+
+// names the soft budget limits a request was served past
+pub const BUDGET_WARNING_HEADER: &str = "x-budget-warning";
+
+/// Turns requests away with BUDGET_REJECT_STATUS once the global or their API
+/// key's spend has reached its hard limit. Past a soft limit they are served
+/// with an `x-budget-warning` header and a log line. Runs after `require_api_key`
+pub async fn enforce_budget(State(state): State<AppState>, request: Request, next: Next) -> Response {
+
+    if !state.budget.config().enabled() {
+        return next.run(request).await;
+    }
+
+    let key = request.extensions().get::<ApiKeyIdentity>().map(|identity| identity.name.clone());
+    let breaches = state.budget.check(key.as_deref());
+
+    if let Some(breach) = breaches.iter().find(|breach| breach.hard) {
+        let (_, resets_at) = state.budget.period();
+        let status = StatusCode::from_u16(state.budget.config().reject_status).unwrap_or(StatusCode::PAYMENT_REQUIRED);
+        tracing::warn!(scope = %breach.scope, "rejected request: {}", breach.describe());
+
+        let error = ApiError::new("budget_exceeded_error", format!("Budget exceeded: {}. It resets at {}", breach.describe(), resets_at.to_rfc3339()))
+            .with_code("budget_exceeded");
+        let mut body = json!(error);
+        body["budget"] = json!({
+            "scope": breach.scope,
+            "limit_usd": breach.limit_usd,
+            "spent_usd": breach.spent_usd,
+            "resets_at": resets_at.to_rfc3339()
+        });
+        let retry_after = (resets_at - Utc::now()).num_seconds().max(1);
+        return (status, [(header::RETRY_AFTER, retry_after.to_string())], Json(body)).into_response();
+    }
+
+    let mut response = next.run(request).await;
+    if !breaches.is_empty() {
+        let warning = breaches.iter().map(|breach| breach.describe()).collect::<Vec<_>>().join("; ");
+        tracing::warn!("budget warning: {}", warning);
+        if let Ok(value) = header::HeaderValue::from_str(&warning) {
+            response.headers_mut().insert(BUDGET_WARNING_HEADER, value);
+        }
+    }
+    response
+
+}
+
+// distinct paths given their own /metrics entry; the rest share "other",
+// since passthrough paths are chosen by clients
+pub const MAX_TRACKED_ENDPOINTS: usize = 50;
+const OTHER_ENDPOINTS: &str = "other";
+
+/// Looks up (or starts) the metrics entry for the request's path and hands it
+/// to the handler as an `EndpointMetrics` extension
+pub async fn track_endpoint(State(state): State<AppState>, mut request: Request, next: Next) -> Response {
+
+    let path = request.uri().path();
+    // the read guard is dropped before `entry` takes the write lock
+    let existing = state.per_endpoint.get(path).map(|metrics| metrics.clone());
+    let metrics = existing.unwrap_or_else(|| {
+        let key = if state.per_endpoint.len() < MAX_TRACKED_ENDPOINTS { path } else { OTHER_ENDPOINTS };
+        state.per_endpoint.entry(key.to_string()).or_insert_with(|| Arc::new(Metrics::new())).clone()
+    });
+
+    request.extensions_mut().insert(EndpointMetrics(metrics));
+    next.run(request).await
+
+}
+
+/// Writes an audit log entry for every `/admin/*` call, including ones that
+/// fail authentication or error out. Applied to the admin routes unconditionally.
+/// The request body is only read and recorded once the admin token checks out,
+/// so an unauthenticated caller can't grow the log with large bodies
+pub async fn audit_admin(State(state): State<AppState>, request: Request, next: Next) -> Response {
+
+    let endpoint = format!("{} {}", request.method(), request.uri().path());
+    let token_id = provided_admin_token(request.headers()).map(mask_secret);
+    let client_ip = client_ip(&request, &state.config.trusted_proxies);
+    let authorized = require_admin(&state, request.headers()).is_ok();
+
+    let query: Value = request.uri().query()
+        .map(url_params)
+        .unwrap_or(Value::Null);
+
+    let (body, response) = if authorized {
+        let (parts, body) = request.into_parts();
+        match axum::body::to_bytes(body, MAX_BODY_BYTES).await {
+            Ok(bytes) => {
+                let audited = audited_body(&bytes, state.config.redact_prompts_in_logs);
+                (audited, next.run(Request::from_parts(parts, Body::from(bytes))).await)
+            }
+            Err(e) => (
+                None,
+                (StatusCode::BAD_REQUEST, Json(json!({"error": "invalid_body", "message": e.to_string()}))).into_response()
+            )
+        }
+    } else {
+        (None, next.run(request).await)
+    };
+
+    let status = response.status();
+
+    state.audit_log.write(AuditEntry {
+        timestamp: Utc::now().to_rfc3339(),
+        endpoint,
+        params: match body {
+            Some(body) => json!({"query": query, "body": body}),
+            None => json!({"query": query})
+        },
+        token_id,
+        client_ip,
+        status: status.as_u16(),
+        outcome: if status.is_success() { "success" } else { "failure" }.to_string()
+    });
+
+    response
+
+}
+
+// the connecting peer's address. X-Forwarded-For is only believed when the peer
+// is one of TRUSTED_PROXIES, and then the right-most hop that isn't itself a
+// trusted proxy is taken, as anything left of it could have been sent by the client
+fn client_ip(request: &Request, trusted: &[IpAddr]) -> Option<String> {
+
+    let peer = request.extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip().to_canonical())?;
+    if !trusted.contains(&peer) {
+        return Some(peer.to_string());
+    }
+
+    let hops: Vec<&str> = request.headers()
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(str::trim)
+        .filter(|hop| !hop.is_empty())
+        .collect();
+
+    let client = hops.iter()
+        .rev()
+        .find(|hop| !hop.parse::<IpAddr>().is_ok_and(|ip| trusted.contains(&ip)))
+        // every hop is a trusted proxy, so the left-most is as far back as it goes
+        .or(hops.first())
+        .map(|hop| hop.to_string());
+    Some(client.unwrap_or_else(|| peer.to_string()))
+
+}
+
+// the JSON body as sent, or just its size past MAX_AUDITED_BODY_BYTES. With
+// REDACT_PROMPTS_IN_LOGS, chat requests keep only their shape and a purge query is hidden
+fn audited_body(bytes: &[u8], redact_prompts: bool) -> Option<Value> {
+
+    if bytes.is_empty() {
+        return None;
+    }
+    if bytes.len() > MAX_AUDITED_BODY_BYTES {
+        return Some(json!({"omitted_bytes": bytes.len()}));
+    }
+    let mut body: Value = serde_json::from_slice(bytes).ok()?;
+    if !redact_prompts {
+        return Some(body);
+    }
+
+    if body.get("messages").is_some() {
+        return match LLMRequest::try_from(body).map(|request| serde_json::to_value(request.strip_sensitive_fields())) {
+            Ok(Ok(sanitized)) => Some(sanitized),
+            _ => Some(json!({"messages": REDACTED}))
+        };
+    }
+    if let Some(query) = body.get_mut("query").filter(|query| query.is_string()) {
+        *query = json!(REDACTED);
+    }
+    Some(body)
+
+}
+
+// "a=1&b=2" -> {"a": "1", "b": "2"}
+fn url_params(query: &str) -> Value {
+
+    let params: serde_json::Map<String, Value> = query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (key.to_string(), json!(value))
+        })
+        .collect();
+
+    Value::Object(params)
+
+}
+
+/// Compresses responses for clients that send `Accept-Encoding`. Bodies under
+/// `min_bytes` and Server-Sent Events streams are always sent as-is
+pub fn compression_layer(config: CompressionConfig) -> CompressionLayer<impl Predicate> {
+
+    CompressionLayer::new()
+        .gzip(config.gzip)
+        .br(config.br)
+        .compress_when(SizeAbove::new(config.min_bytes).and(NotForContentType::SSE))
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use axum::{Router, routing::{get, post}};
+    use tower::ServiceExt;
+
+    fn app() -> Router {
+        Router::new()
+            .route("/", post(|body: String| async move { body }))
+            .layer(axum::middleware::from_fn(validate_content_length))
+    }
+
+    fn request(declared: Option<&str>, body: &'static str) -> Request {
+        let mut builder = Request::post("/");
+        if let Some(declared) = declared {
+            builder = builder.header(header::CONTENT_LENGTH, declared);
+        }
+        builder.body(Body::from(body)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_matching_length_passes() {
+
+        let response = app().oneshot(request(Some("5"), "hello")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+    }
+
+    #[tokio::test]
+    async fn test_missing_length_passes() {
+
+        let response = app().oneshot(request(None, "hello")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+    }
+
+    #[tokio::test]
+    async fn test_mismatched_length_rejected() {
+
+        let response = app().oneshot(request(Some("1000"), "hello")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body, json!({"error": "content_length_mismatch", "declared": 1000, "actual": 5}));
+
+    }
+
+    fn compressed_app() -> Router {
+        let large = "x".repeat(4096);
+        Router::new()
+            .route("/large", get(move || async move { large }))
+            .route("/small", get(|| async { "ok" }))
+            .route("/stream", get(|| async {
+                let chunks = (0..100).map(|_| Ok::<_, std::convert::Infallible>("x".repeat(64)));
+                Body::from_stream(tokio_stream::iter(chunks))
+            }))
+            .route("/events", get(|| async {
+                ([(header::CONTENT_TYPE, "text/event-stream")], "data: x\n\n".repeat(1000))
+            }))
+            .layer(compression_layer(CompressionConfig::default()))
+    }
+
+    async fn content_encoding(path: &str, accept: Option<&str>) -> Option<String> {
+        let mut builder = Request::get(path);
+        if let Some(accept) = accept {
+            builder = builder.header(header::ACCEPT_ENCODING, accept);
+        }
+        let response = compressed_app().oneshot(builder.body(Body::empty()).unwrap()).await.unwrap();
+        response.headers().get(header::CONTENT_ENCODING).map(|v| v.to_str().unwrap().to_string())
+    }
+
+    #[tokio::test]
+    async fn test_large_response_gzipped_when_requested() {
+
+        assert_eq!(content_encoding("/large", Some("gzip")).await.as_deref(), Some("gzip"));
+        assert_eq!(content_encoding("/large", None).await, None, "No Accept-Encoding means identity");
+
+    }
+
+    #[tokio::test]
+    async fn test_gzip_shrinks_large_response() {
+
+        // the Content-Length sent and the bytes actually received
+        let sizes = |path: &'static str, accept: Option<&'static str>| async move {
+            let mut builder = Request::get(path);
+            if let Some(accept) = accept {
+                builder = builder.header(header::ACCEPT_ENCODING, accept);
+            }
+            let response = compressed_app().oneshot(builder.body(Body::empty()).unwrap()).await.unwrap();
+            let content_length = response.headers().get(header::CONTENT_LENGTH).map(|v| v.to_str().unwrap().parse::<usize>().unwrap());
+            (content_length, axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap().len())
+        };
+
+        assert_eq!(sizes("/large", None).await, (Some(4096), 4096));
+
+        let (content_length, gzipped) = sizes("/large", Some("gzip")).await;
+        assert_eq!(content_length, None, "A compressed body is streamed without a Content-Length");
+        assert!(gzipped < 4096 / 10, "4KB of repeated bytes should compress well, got {} bytes", gzipped);
+
+        for accept in [None, Some("gzip")] {
+            assert_eq!(sizes("/stream", accept).await.0, None, "A streamed body has no Content-Length ({:?})", accept);
+        }
+
+    }
+
+    #[tokio::test]
+    async fn test_small_and_sse_responses_not_compressed() {
+
+        assert_eq!(content_encoding("/small", Some("gzip")).await, None);
+        assert_eq!(content_encoding("/events", Some("gzip")).await, None);
+
+    }
+
+    #[test]
+    fn test_audited_bodies_are_redacted_when_asked() {
+
+        let request = br#"{"model": "gpt-4", "messages": [{"role": "user", "content": "my secret prompt"}]}"#;
+        assert_eq!(audited_body(request, false).unwrap()["messages"][0]["content"], "my secret prompt");
+
+        let redacted = audited_body(request, true).unwrap();
+        assert_eq!(redacted["model"], "gpt-4");
+        assert_eq!(redacted["messages"][0]["content"], REDACTED);
+        assert!(!redacted.to_string().contains("secret"));
+
+        let purge = audited_body(br#"{"query": "my secret prompt", "dry_run": true}"#, true).unwrap();
+        assert_eq!(purge, json!({"query": REDACTED, "dry_run": true}));
+        let malformed = audited_body(br#"{"messages": "my secret prompt"}"#, true).unwrap();
+        assert_eq!(malformed, json!({"messages": REDACTED}));
+
+    }
+
+    fn from_peer(peer: &str, forwarded_for: Option<&str>) -> Request {
+        let mut builder = Request::get("/");
+        if let Some(forwarded_for) = forwarded_for {
+            builder = builder.header("x-forwarded-for", forwarded_for);
+        }
+        let mut request = builder.body(Body::empty()).unwrap();
+        request.extensions_mut().insert(ConnectInfo(SocketAddr::new(peer.parse().unwrap(), 40000)));
+        request
+    }
+
+    #[test]
+    fn test_client_ip_ignores_forwarded_for_from_untrusted_peers() {
+
+        let spoofed = from_peer("203.0.113.9", Some("1.2.3.4"));
+        assert_eq!(client_ip(&spoofed, &[]).as_deref(), Some("203.0.113.9"));
+        assert_eq!(client_ip(&spoofed, &["10.0.0.1".parse().unwrap()]).as_deref(), Some("203.0.113.9"));
+
+    }
+
+    #[test]
+    fn test_client_ip_takes_the_right_most_untrusted_hop() {
+
+        let trusted: Vec<IpAddr> = vec!["10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap()];
+
+        // the client prepended a fake address; the load balancers appended the real one
+        let request = from_peer("10.0.0.1", Some("1.2.3.4, 198.51.100.7, 10.0.0.2"));
+        assert_eq!(client_ip(&request, &trusted).as_deref(), Some("198.51.100.7"));
+
+        let all_trusted = from_peer("10.0.0.1", Some("10.0.0.2"));
+        assert_eq!(client_ip(&all_trusted, &trusted).as_deref(), Some("10.0.0.2"));
+        let no_header = from_peer("10.0.0.1", None);
+        assert_eq!(client_ip(&no_header, &trusted).as_deref(), Some("10.0.0.1"));
+
+    }
+
+}
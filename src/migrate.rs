@@ -0,0 +1,148 @@
+// Copies exact-match entries from one key namespace to another. Run before
+// serving when CACHE_NAMESPACE_VERSION is bumped with CACHE_MIGRATE_ON_STARTUP
+// set, so the new namespace doesn't start cold, and by
+// POST /admin/cache/copy/bulk. Entries keep their remaining TTL unless a new
+// one is given, and the old namespace is left in place to expire on its own.
+
+use std::sync::Arc;
+use serde::Serialize;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use crate::AppState;
+use crate::backend::{BackendError, ExactCache};
+
+// COPY calls in flight at once
+pub const MIGRATION_WORKERS: usize = 10;
+
+// keys between two progress lines
+const PROGRESS_EVERY: u64 = 1000;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct CopyReport {
+    // SCAN can return a key twice, so this may count some keys more than once
+    pub scanned: u64,
+    pub copied: u64
+}
+
+/// Copies every key under `source_prefix` to the same key under `dest_prefix`.
+/// `dest_prefix` must not fall inside `source_prefix`, or the copies would be
+/// scanned again
+pub async fn copy_prefix(
+    redis: &ExactCache,
+    source_prefix: &str,
+    dest_prefix: &str,
+    reset_ttl: Option<u64>,
+    replace: bool
+) -> Result<CopyReport, BackendError> {
+
+    let pattern = format!("{}*", source_prefix);
+    let permits = Arc::new(Semaphore::new(MIGRATION_WORKERS));
+    let mut report = CopyReport::default();
+    let mut cursor = 0;
+
+    loop {
+        let (next, keys) = redis.scan_page(&pattern, cursor).await?;
+
+        let mut tasks = JoinSet::new();
+        for key in keys {
+            let dest = format!("{}{}", dest_prefix, &key[source_prefix.len()..]);
+            let redis = redis.clone();
+            let permits = permits.clone();
+            tasks.spawn(async move {
+                let _permit = permits.acquire_owned().await;
+                redis.copy_key(&key, &dest, reset_ttl, replace).await
+            });
+        }
+
+        let before = report.scanned;
+        while let Some(outcome) = tasks.join_next().await {
+            // a panicked copy task counts as a key that wasn't copied
+            let copied = match outcome {
+                Ok(result) => result?.copied,
+                Err(_) => false
+            };
+            report.scanned += 1;
+            report.copied += copied as u64;
+        }
+        if report.scanned / PROGRESS_EVERY > before / PROGRESS_EVERY {
+            tracing::info!("Migration {} -> {}: {} keys scanned, {} copied", source_prefix, dest_prefix, report.scanned, report.copied);
+        }
+
+        cursor = next;
+        if cursor == 0 {
+            break;
+        }
+    }
+
+    Ok(report)
+
+}
+
+/// Copies the previous namespace into the current one with `COPY ... REPLACE`.
+/// Does nothing unless CACHE_MIGRATE_ON_STARTUP is set and the exact tier is on
+pub async fn migrate_on_startup(state: &AppState) {
+
+    let (true, Some(redis)) = (state.config.cache_migrate_on_startup, &state.exact_cache) else {
+        return;
+    };
+
+    let source_prefix = crate::cache::exact_key_prefix(state.config.cache_previous_namespace_version.as_deref());
+    let dest_prefix = state.config.exact_key_prefix();
+    if dest_prefix.starts_with(&source_prefix) {
+        tracing::info!("Migration skipped: {} falls inside {}", dest_prefix, source_prefix);
+        return;
+    }
+
+    tracing::info!("Migrating cache entries {}* -> {}*", source_prefix, dest_prefix);
+    let started = std::time::Instant::now();
+    match copy_prefix(redis, &source_prefix, &dest_prefix, None, true).await {
+        Ok(report) => tracing::info!(
+            "Migration done: {} of {} keys copied in {:.1}s",
+            report.copied, report.scanned, started.elapsed().as_secs_f64()
+        ),
+        // the new namespace just starts colder; serving isn't blocked on it
+        Err(e) => tracing::warn!("Migration stopped after a Redis error: {}", e)
+    }
+
+}
+
+// only the in-memory Redis can be filled with 10K keys here
+#[cfg(all(test, feature = "mock"))]
+mod tests {
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_migrate_on_startup_copies_the_previous_namespace() {
+
+        use crate::config::Config;
+
+        let config = Config::from_lookup(|name| match name {
+            "CACHE_NAMESPACE_VERSION" => Some("v2".to_string()),
+            "CACHE_PREVIOUS_NAMESPACE_VERSION" => Some("v1".to_string()),
+            "CACHE_MIGRATE_ON_STARTUP" => Some("true".to_string()),
+            _ => None
+        }).unwrap();
+        let state = AppState::new(config).await;
+        let redis = state.exact_cache.as_ref().unwrap();
+
+        for i in 0..10_000 {
+            redis.set_with_ttl(&format!("cache:v1:exact:{:05}:model", i), "cached", 600).await.unwrap();
+        }
+        // already written by the new version and overwritten, as COPY ... REPLACE does
+        redis.set_with_ttl("cache:v2:exact:00000:model", "stale", 600).await.unwrap();
+        redis.set_with_ttl("cache:exact:unversioned:model", "cached", 600).await.unwrap();
+
+        let started = std::time::Instant::now();
+        migrate_on_startup(&state).await;
+        assert!(started.elapsed().as_secs() < 5, "Took {:?}", started.elapsed());
+
+        assert_eq!(redis.count_keys_matching("cache:v2:exact:*").await.unwrap(), 10_000);
+        assert_eq!(redis.get("cache:v2:exact:00000:model").await.unwrap().as_deref(), Some("cached"));
+        let (_, ttl) = redis.get_with_ttl("cache:v2:exact:09999:model").await.unwrap().unwrap();
+        assert!((590..=600).contains(&ttl), "The remaining TTL should carry over, got {}", ttl);
+        assert_eq!(redis.count_keys_matching("cache:v1:exact:*").await.unwrap(), 10_000, "The old namespace is left to expire");
+
+    }
+
+}
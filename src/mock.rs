@@ -0,0 +1,566 @@
+// In-memory stand-ins for Redis, Qdrant, the embedding service and the LLM.
+// Enabled with `cargo run --features mock` so the proxy can run without any
+// external processes. The public API mirrors the real implementations.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use sha2::{Sha256, Digest};
+use uuid::Uuid;
+use async_trait::async_trait;
+use crate::backend::MemoryBackend;
+use crate::cache::{
+    CacheError, CollectionStats, CollectionValidation, EntryParams, QdrantPoolStats, QdrantUsage, QuarantinedEntry, SemanticHit, StoredPoint,
+    cosine_similarity, expires_at
+};
+use crate::models::{LLMRequest, LLMResponse};
+use crate::semantic::SemanticStore;
+
+const MOCK_EMBEDDING_DIM: usize = 384;
+
+struct MockPoint {
+    id: String,
+    embedding: Vec<f32>,
+    cache_key: String,
+    prompt: String,
+    response: String,
+    temperature: Option<f32>,
+    model: Option<String>,
+    max_tokens: Option<u32>,
+    tenant: Option<String>,
+    original_latency_ms: Option<u64>,
+    // unix seconds, as in the real payload
+    expires_at: Option<i64>,
+    // (reason, quarantined_at) once quarantined
+    quarantine: Option<(Option<String>, String)>
+}
+
+impl MockPoint {
+
+    fn is_expired(&self) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= chrono::Utc::now().timestamp())
+    }
+
+    // the same payload fields the real store writes
+    fn to_stored(&self) -> StoredPoint {
+
+        let mut payload = serde_json::Map::new();
+        payload.insert("cache_key".to_string(), self.cache_key.clone().into());
+        payload.insert("prompt".to_string(), self.prompt.clone().into());
+        payload.insert("response".to_string(), self.response.clone().into());
+        if let Some(temperature) = self.temperature {
+            payload.insert("temperature".to_string(), (temperature as f64).into());
+        }
+        if let Some(model) = &self.model {
+            payload.insert("model".to_string(), model.clone().into());
+        }
+        if let Some(max_tokens) = self.max_tokens {
+            payload.insert("max_tokens".to_string(), max_tokens.into());
+        }
+        if let Some(tenant) = &self.tenant {
+            payload.insert("tenant".to_string(), tenant.clone().into());
+        }
+        if let Some(ms) = self.original_latency_ms {
+            payload.insert("original_latency_ms".to_string(), ms.into());
+        }
+        if let Some(expires_at) = self.expires_at {
+            payload.insert("expires_at".to_string(), expires_at.into());
+        }
+        if let Some((reason, at)) = &self.quarantine {
+            payload.insert("quarantined".to_string(), true.into());
+            payload.insert("quarantine_reason".to_string(), reason.clone().unwrap_or_default().into());
+            payload.insert("quarantined_at".to_string(), at.clone().into());
+        }
+        StoredPoint { id: self.id.clone(), payload }
+
+    }
+
+    fn from_stored(stored: StoredPoint, embedding: Vec<f32>) -> Self {
+
+        let field = |key: &str| stored.payload.get(key).and_then(|v| v.as_str()).unwrap_or_default().to_string();
+        let quarantined = stored.payload.get("quarantined").and_then(|v| v.as_bool()).unwrap_or(false);
+
+        MockPoint {
+            id: stored.id.clone(),
+            embedding,
+            cache_key: field("cache_key"),
+            prompt: field("prompt"),
+            response: field("response"),
+            temperature: stored.payload.get("temperature").and_then(|v| v.as_f64()).map(|t| t as f32),
+            model: stored.payload.get("model").and_then(|v| v.as_str()).map(str::to_string),
+            max_tokens: stored.payload.get("max_tokens").and_then(|v| v.as_u64()).and_then(|n| u32::try_from(n).ok()),
+            tenant: stored.payload.get("tenant").and_then(|v| v.as_str()).map(str::to_string),
+            original_latency_ms: stored.payload.get("original_latency_ms").and_then(|v| v.as_u64()),
+            expires_at: stored.payload.get("expires_at").and_then(|v| v.as_i64()),
+            quarantine: quarantined.then(|| (Some(field("quarantine_reason")).filter(|r| !r.is_empty()), field("quarantined_at")))
+        }
+
+    }
+
+}
+
+/// Stands in for `RedisCache::new`: under mock the exact tier is always in memory
+pub struct MockRedisCache;
+
+impl MockRedisCache {
+
+    // returns the backend itself, so AppState::new reads the same under mock
+    #[allow(clippy::new_ret_no_self)]
+    pub async fn new(_redis_url: &str) -> Result<MemoryBackend, redis::RedisError> {
+
+        tracing::info!("Mock: using in-memory Redis cache");
+        Ok(MemoryBackend::default())
+
+    }
+
+}
+
+#[derive(Clone, Default)]
+pub struct MockQdrantCache {
+    // the active collection
+    points: Arc<Mutex<Vec<MockPoint>>>,
+    // every other collection, by name
+    other_collections: Arc<Mutex<HashMap<String, Vec<MockPoint>>>>,
+    collection_name: Arc<Mutex<Arc<String>>>,
+    pool_size: usize
+}
+
+impl MockQdrantCache {
+
+    #[cfg(test)]
+    pub async fn new(qdrant_url: &str) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+
+        Self::with_collection(qdrant_url, "llm_cache", MOCK_EMBEDDING_DIM, crate::cache::DEFAULT_QDRANT_MAX_CONNECTIONS).await
+
+    }
+
+    pub async fn with_collection(
+        _qdrant_url: &str,
+        collection_name: &str,
+        _embedding_dim: usize,
+        max_connections: usize
+    ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+
+        tracing::info!("Mock: using in-memory Qdrant cache");
+        Ok(MockQdrantCache {
+            collection_name: Arc::new(Mutex::new(Arc::new(collection_name.to_string()))),
+            pool_size: max_connections.max(1),
+            ..Self::default()
+        })
+
+    }
+
+    // runs `f` on the points of any collection, creating it if needed
+    fn with_points<R>(&self, collection: &str, f: impl FnOnce(&mut Vec<MockPoint>) -> R) -> R {
+
+        let current = self.collection_name.lock().unwrap();
+        if current.as_str() == collection {
+            f(&mut self.points.lock().unwrap())
+        } else {
+            f(self.other_collections.lock().unwrap().entry(collection.to_string()).or_default())
+        }
+
+    }
+
+}
+
+#[async_trait]
+impl SemanticStore for MockQdrantCache {
+
+    fn name(&self) -> &'static str {
+        "mock"
+    }
+
+    // nothing is pooled in memory; reports the configured size so /admin/stats looks the same
+    fn pool_stats(&self) -> QdrantPoolStats {
+        QdrantPoolStats { pool_size: self.pool_size, in_flight_requests: 0 }
+    }
+
+    async fn store(
+        &self,
+        cache_key: &str,
+        prompt: &str,
+        embedding: Vec<f32>,
+        cached_response: &str,
+        params: &EntryParams,
+        original_latency_ms: u64,
+        ttl: u64,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+
+        self.points.lock().unwrap().push(MockPoint {
+            id: Uuid::new_v4().to_string(),
+            embedding,
+            cache_key: cache_key.to_string(),
+            prompt: prompt.to_string(),
+            response: cached_response.to_string(),
+            temperature: Some(params.temperature),
+            model: Some(params.model.clone()),
+            max_tokens: params.max_tokens,
+            tenant: params.tenant.clone(),
+            original_latency_ms: Some(original_latency_ms),
+            expires_at: Some(expires_at(ttl)),
+            quarantine: None
+        });
+        Ok(())
+
+    }
+
+    async fn refresh_point(
+        &self,
+        point_id: &str,
+        cache_key: &str,
+        cached_response: &str,
+        params: &EntryParams,
+        original_latency_ms: u64,
+        ttl: u64,
+    ) -> Result<(), CacheError> {
+
+        if let Some(point) = self.points.lock().unwrap().iter_mut().find(|point| point.id == point_id) {
+            point.cache_key = cache_key.to_string();
+            point.response = cached_response.to_string();
+            point.temperature = Some(params.temperature);
+            point.original_latency_ms = Some(original_latency_ms);
+            point.expires_at = Some(expires_at(ttl));
+        }
+        Ok(())
+
+    }
+
+    async fn health_check(&self) -> bool {
+        true
+    }
+
+    fn collection_name(&self) -> Arc<String> {
+        self.collection_name.lock().unwrap().clone()
+    }
+
+    fn switch_collection(&self, collection: &str, _embedding_dim: usize) {
+
+        let mut current = self.collection_name.lock().unwrap();
+        if current.as_str() == collection {
+            return;
+        }
+
+        let mut others = self.other_collections.lock().unwrap();
+        let incoming = others.remove(collection).unwrap_or_default();
+        let outgoing = std::mem::replace(&mut *self.points.lock().unwrap(), incoming);
+        others.insert(current.to_string(), outgoing);
+        *current = Arc::new(collection.to_string());
+
+    }
+
+    async fn ensure_collection(&self, collection: &str, _dim: usize) -> Result<(), CacheError> {
+        self.with_points(collection, |_| ());
+        Ok(())
+    }
+
+    async fn collection_stats(&self) -> Result<CollectionStats, CacheError> {
+        Ok(self.cached_collection_stats().unwrap_or_default())
+    }
+
+    // nothing to cache in memory, so this is always current
+    fn cached_collection_stats(&self) -> Option<CollectionStats> {
+
+        let points_count = self.points.lock().unwrap().len() as u64;
+        Some(CollectionStats { vectors_count: points_count, indexed_vectors_count: points_count, points_count, segments_count: 1 })
+
+    }
+
+    async fn collection_points(&self, collection: &str) -> Result<u64, CacheError> {
+        Ok(self.with_points(collection, |points| points.len() as u64))
+    }
+
+    async fn scroll_points(
+        &self,
+        collection: &str,
+        offset: Option<&str>,
+        limit: u32
+    ) -> Result<(Vec<StoredPoint>, Option<String>), CacheError> {
+
+        Ok(self.with_points(collection, |points| {
+            let start = offset
+                .and_then(|offset| points.iter().position(|point| point.id == offset))
+                .unwrap_or(0);
+            let page: Vec<StoredPoint> = points.iter().skip(start).take(limit as usize).map(MockPoint::to_stored).collect();
+            let next = points.get(start + limit as usize).map(|point| point.id.clone());
+            (page, next)
+        }))
+
+    }
+
+    async fn upsert_points(&self, collection: &str, points: Vec<(StoredPoint, Vec<f32>)>) -> Result<(), CacheError> {
+
+        self.with_points(collection, |existing| {
+            for (stored, embedding) in points {
+                let point = MockPoint::from_stored(stored, embedding);
+                match existing.iter_mut().find(|p| p.id == point.id) {
+                    Some(slot) => *slot = point,
+                    None => existing.push(point)
+                }
+            }
+        });
+        Ok(())
+
+    }
+
+    async fn original_latencies(&self) -> Result<Vec<u64>, CacheError> {
+        Ok(self.points.lock().unwrap().iter().filter_map(|point| point.original_latency_ms).collect())
+    }
+
+    async fn delete_by_cache_key(&self, cache_key: &str) -> Result<(), CacheError> {
+        self.points.lock().unwrap().retain(|point| point.cache_key != cache_key);
+        Ok(())
+    }
+
+    async fn recreate_collection(&self) -> Result<(), CacheError> {
+        self.points.lock().unwrap().clear();
+        Ok(())
+    }
+
+    async fn delete_points(&self, point_ids: &[String]) -> Result<(), CacheError> {
+        self.points.lock().unwrap().retain(|point| !point_ids.contains(&point.id));
+        Ok(())
+    }
+
+    async fn delete_by_model(&self, model: &str) -> Result<(), CacheError> {
+        self.points.lock().unwrap().retain(|point| point.model.as_deref() != Some(model));
+        Ok(())
+    }
+
+    async fn quarantine_by_cache_key(&self, cache_key: &str, reason: Option<&str>) -> Result<(), CacheError> {
+
+        let quarantined_at = chrono::Utc::now().to_rfc3339();
+        for point in self.points.lock().unwrap().iter_mut().filter(|p| p.cache_key == cache_key) {
+            point.quarantine = Some((reason.map(|r| r.to_string()), quarantined_at.clone()));
+        }
+        Ok(())
+
+    }
+
+    async fn quarantined(&self, limit: u32) -> Result<Vec<QuarantinedEntry>, CacheError> {
+
+        Ok(self.points.lock().unwrap().iter()
+            .filter_map(|point| point.quarantine.as_ref().map(|(reason, at)| QuarantinedEntry {
+                cache_key: point.cache_key.clone(),
+                prompt: Some(point.prompt.clone()),
+                response: point.response.clone(),
+                reason: reason.clone(),
+                quarantined_at: at.clone()
+            }))
+            .take(limit as usize)
+            .collect())
+
+    }
+
+    async fn purge_quarantined(&self) -> Result<(), CacheError> {
+        self.points.lock().unwrap().retain(|point| point.quarantine.is_none());
+        Ok(())
+    }
+
+    async fn delete_expired(&self) -> Result<(), CacheError> {
+        self.points.lock().unwrap().retain(|point| !point.is_expired());
+        Ok(())
+    }
+
+    async fn trim_to(&self, max_points: u64) -> Result<u64, CacheError> {
+
+        let mut points = self.points.lock().unwrap();
+        let excess = points.len().saturating_sub(max_points as usize);
+        // points without an expiry are the oldest, as in the real store
+        points.sort_by_key(|point| point.expires_at.unwrap_or(i64::MIN));
+        points.drain(..excess);
+        Ok(excess as u64)
+
+    }
+
+    fn validation(&self) -> &CollectionValidation {
+        &CollectionValidation::Valid
+    }
+
+    async fn usage(&self) -> Result<QdrantUsage, CacheError> {
+
+        let points = self.points.lock().unwrap();
+
+        Ok(QdrantUsage {
+            points_count: points.len() as u64,
+            vector_bytes: points.iter().map(|point| point.embedding.len() as u64 * 4).sum(),
+            estimated_payload_bytes: points.iter()
+                .map(|point| (point.cache_key.len() + point.prompt.len() + point.response.len()) as u64)
+                .sum()
+        })
+
+    }
+
+    async fn search_paginated(
+        &self,
+        embedding: Vec<f32>,
+        similarity_threshold: f32,
+        limit: usize,
+        offset: Option<u64>,
+        params: Option<&EntryParams>,
+    ) -> Result<Vec<SemanticHit>, CacheError> {
+
+        let points = self.points.lock().unwrap();
+
+        let mut hits: Vec<SemanticHit> = points.iter()
+            .filter(|point| point.quarantine.is_none() && !point.is_expired())
+            .map(|point| SemanticHit {
+                point_id: point.id.clone(),
+                cache_key: point.cache_key.clone(),
+                response: point.response.clone(),
+                score: cosine_similarity(&embedding, &point.embedding),
+                temperature: point.temperature,
+                model: point.model.clone(),
+                max_tokens: point.max_tokens,
+                tenant: point.tenant.clone(),
+                original_latency_ms: point.original_latency_ms
+            })
+            .filter(|hit| hit.score >= similarity_threshold)
+            .filter(|hit| params.is_none_or(|params| params.matches(hit)))
+            .collect();
+
+        hits.sort_by(|a, b| b.score.total_cmp(&a.score));
+
+        Ok(hits.into_iter()
+            .skip(offset.unwrap_or(0) as usize)
+            .take(limit)
+            .collect())
+
+    }}
+
+/// Deterministic bag-of-words embedding: each lowercase word is hashed into
+/// one of 384 buckets, so prompts sharing words land close together
+pub fn fake_embedding(text: &str) -> Vec<f32> {
+
+    let mut embedding = vec![0.0f32; MOCK_EMBEDDING_DIM];
+
+    for word in text.split_whitespace() {
+        let word = word.trim_matches(|c: char| !c.is_alphanumeric()).to_lowercase();
+        if word.is_empty() {
+            continue;
+        }
+        let hash = Sha256::digest(word.as_bytes());
+        let bucket = u16::from_le_bytes([hash[0], hash[1]]) as usize % MOCK_EMBEDDING_DIM;
+        let sign = if hash[2] & 1 == 0 { 1.0 } else { -1.0 };
+        embedding[bucket] += sign;
+    }
+
+    let norm: f32 = embedding.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        embedding.iter_mut().for_each(|x| *x /= norm);
+    }
+
+    embedding
+
+}
+
+/// Returns the fixture response, echoing the requested model with a fresh id
+pub fn mock_llm_response(request: &LLMRequest) -> LLMResponse {
+
+    let mut response: LLMResponse = serde_json::from_str(include_str!("../fixtures/mock_llm_response.json"))
+        .expect("Invalid mock LLM response fixture");
+
+    response.id = format!("chatcmpl-mock-{}", Uuid::new_v4());
+    response.model = request.model.clone();
+    response.created = chrono::Utc::now().timestamp();
+
+    response
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::backend::CacheBackend;
+    use crate::test_helpers::test_params;
+
+    #[test]
+    fn test_fake_embedding_is_deterministic() {
+
+        let a = fake_embedding("What is Rust?");
+        let b = fake_embedding("what is rust");
+
+        assert_eq!(a.len(), MOCK_EMBEDDING_DIM);
+        assert!((cosine_similarity(&a, &b) - 1.0).abs() < 1e-6, "Case and punctuation should not change the embedding");
+
+    }
+
+    #[tokio::test]
+    async fn test_mock_qdrant_search() {
+
+        let qdrant = MockQdrantCache::new("").await.unwrap();
+        qdrant.store("key", "What is Rust?", fake_embedding("What is Rust?"), "Rust is a language", &test_params(0.0), 120, 3600).await.unwrap();
+
+        let hit = qdrant.search_similar(fake_embedding("what is rust"), 0.9, &test_params(0.0)).await.unwrap();
+        assert_eq!(hit.map(|h| h.response), Some("Rust is a language".to_string()));
+
+        let miss = qdrant.search_similar(fake_embedding("Explain Python decorators"), 0.9, &test_params(0.0)).await.unwrap();
+        assert!(miss.is_none());
+
+    }
+
+    #[tokio::test]
+    async fn test_mock_qdrant_search_paginated() {
+
+        let qdrant = MockQdrantCache::new("").await.unwrap();
+        for i in 0..5 {
+            qdrant.store(&format!("key_{}", i), "What is Rust?", fake_embedding("What is Rust?"), "Rust", &test_params(0.0), 120, 3600).await.unwrap();
+        }
+
+        let page_two = qdrant.search_paginated(fake_embedding("What is Rust?"), 0.9, 2, Some(2), None).await.unwrap();
+        let last_page = qdrant.search_paginated(fake_embedding("What is Rust?"), 0.9, 2, Some(4), None).await.unwrap();
+
+        assert_eq!(page_two.len(), 2);
+        assert_eq!(last_page.len(), 1);
+
+    }
+
+    #[tokio::test]
+    async fn test_mock_quarantine_hides_entry() {
+
+        let redis = MemoryBackend::default();
+        let qdrant = MockQdrantCache::new("").await.unwrap();
+        redis.set("key", "Rust is a language").await.unwrap();
+        qdrant.store("key", "What is Rust?", fake_embedding("What is Rust?"), "Rust is a language", &test_params(0.0), 120, 3600).await.unwrap();
+
+        assert!(redis.quarantine("key", Some("outdated"), 60).await.unwrap());
+        qdrant.quarantine_by_cache_key("key", Some("outdated")).await.unwrap();
+
+        assert_eq!(redis.get("key").await.unwrap(), None);
+        assert!(qdrant.search_similar(fake_embedding("What is Rust?"), 0.9, &test_params(0.0)).await.unwrap().is_none());
+
+        let quarantined = qdrant.quarantined(10).await.unwrap();
+        assert_eq!(quarantined[0].prompt.as_deref(), Some("What is Rust?"));
+        assert_eq!(redis.quarantined().await.unwrap()[0].reason.as_deref(), Some("outdated"));
+
+        assert_eq!(redis.purge_quarantined().await.unwrap(), 1);
+        qdrant.purge_quarantined().await.unwrap();
+        assert!(qdrant.quarantined(10).await.unwrap().is_empty());
+
+    }
+
+    #[tokio::test]
+    async fn test_mock_expired_points_are_skipped_and_deleted() {
+
+        let qdrant = MockQdrantCache::new("").await.unwrap();
+        qdrant.store("fresh", "What is Rust?", fake_embedding("What is Rust?"), "Rust", &test_params(0.0), 120, 3600).await.unwrap();
+        qdrant.store("expired", "What is Rust?", fake_embedding("What is Rust?"), "Rust", &test_params(0.0), 120, 0).await.unwrap();
+
+        // a point from before expiry was tracked has no expires_at and stays
+        let collection = qdrant.collection_name();
+        let (points, _) = qdrant.scroll_points(&collection, None, 10).await.unwrap();
+        let mut legacy = points[0].clone();
+        legacy.id = "legacy".to_string();
+        legacy.payload.remove("expires_at");
+        qdrant.upsert_points(&collection, vec![(legacy, fake_embedding("What is Rust?"))]).await.unwrap();
+
+        let hits = qdrant.search_paginated(fake_embedding("What is Rust?"), 0.9, 10, None, None).await.unwrap();
+        assert_eq!(hits.len(), 2);
+        assert!(hits.iter().all(|hit| hit.cache_key != "expired"), "The expired point shouldn't be returned");
+
+        qdrant.delete_expired().await.unwrap();
+        assert_eq!(qdrant.collection_points(&collection).await.unwrap(), 2);
+
+    }
+
+}
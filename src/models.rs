@@ -1,39 +1,848 @@
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
 pub struct Message {
     pub role: String,
-    pub content: String
+    // null on an assistant turn that only calls tools; read as "" and sent back as null
+    #[serde(default, deserialize_with = "null_as_empty", serialize_with = "empty_as_null")]
+    pub content: MessageContent,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<ToolCall>>,
+    // on a "tool" message, the id of the call it answers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+/// A function call the model asked for
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct ToolCall {
+    pub id: String,
+    #[serde(rename = "type", default = "function_tool_type")]
+    pub kind: String,
+    pub function: FunctionCall
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct FunctionCall {
+    pub name: String,
+    // JSON-encoded arguments, exactly as the model wrote them
+    #[serde(default)]
+    pub arguments: String
+}
+
+fn function_tool_type() -> String {
+    "function".to_string()
+}
+
+fn null_as_empty<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<MessageContent, D::Error> {
+    Ok(Option::<MessageContent>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+fn empty_as_null<S: serde::Serializer>(content: &MessageContent, serializer: S) -> Result<S::Ok, S::Error> {
+    match content {
+        MessageContent::Text(text) if text.is_empty() => serializer.serialize_none(),
+        content => content.serialize(serializer)
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
 pub struct LLMRequest {
     pub messages: Vec<Message>,
     pub model: String,
     pub temperature: Option<f32>,
-    pub max_tokens: Option<u32>
+    pub max_tokens: Option<u32>,
+    // "stream": true asks for server-sent events; not part of the cache key,
+    // so a streamed and a non-streamed request share an entry
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stream: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    // a string or an array of up to 4 strings
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stop: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frequency_penalty: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub presence_penalty: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub n: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<Value>,
+    // function definitions the model may call, and whether/which it must
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tools: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_choice: Option<Value>,
+    // fields the proxy doesn't model (e.g. logit_bias, user) forwarded upstream as-is
+    #[serde(flatten)]
+    pub extra: Option<Map<String, Value>>
+}
+
+/// OpenAI message content: a plain string or an array of typed parts
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum MessageContent {
+    Text(String),
+    Parts(Vec<ContentPart>)
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentPart {
+    Text { text: String },
+    ImageUrl { image_url: ImageUrl }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct ImageUrl {
+    // an http(s) URL or a base64 data URL
+    pub url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>
+}
+
+impl MessageContent {
+
+    /// The text that gets embedded: text parts joined with spaces, images
+    /// skipped. Content with no text parts gives an empty string
+    pub fn as_text(&self) -> String {
+        match self {
+            MessageContent::Text(text) => text.clone(),
+            MessageContent::Parts(parts) => parts.iter()
+                .filter_map(|part| match part {
+                    ContentPart::Text { text } => Some(text.as_str()),
+                    ContentPart::ImageUrl { .. } => None
+                })
+                .collect::<Vec<_>>()
+                .join(" ")
+        }
+    }
+
+    pub fn images(&self) -> impl Iterator<Item = &ImageUrl> {
+        let parts = match self {
+            MessageContent::Text(_) => &[][..],
+            MessageContent::Parts(parts) => parts.as_slice()
+        };
+        parts.iter().filter_map(|part| match part {
+            ContentPart::ImageUrl { image_url } => Some(image_url),
+            ContentPart::Text { .. } => None
+        })
+    }
+
+    /// No text beyond whitespace and no images
+    pub fn is_blank(&self) -> bool {
+        self.as_text().trim().is_empty() && self.images().next().is_none()
+    }
+
+}
+
+impl Default for MessageContent {
+    fn default() -> Self {
+        MessageContent::Text(String::new())
+    }
+}
+
+impl From<String> for MessageContent {
+    fn from(text: String) -> Self {
+        MessageContent::Text(text)
+    }
+}
+
+impl From<&str> for MessageContent {
+    fn from(text: &str) -> Self {
+        MessageContent::Text(text.to_string())
+    }
+}
+
+/// A request field that was missing or had the wrong type
+#[derive(Debug, Clone, PartialEq)]
+pub struct LLMRequestConversionError {
+    pub field: String,
+    pub reason: String
+}
+
+impl LLMRequestConversionError {
+    fn new(field: impl Into<String>, reason: impl Into<String>) -> Self {
+        LLMRequestConversionError { field: field.into(), reason: reason.into() }
+    }
+}
+
+impl std::fmt::Display for LLMRequestConversionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "field `{}`: {}", self.field, self.reason)
+    }
+}
+
+impl std::error::Error for LLMRequestConversionError {}
+
+// a required string, named by its full path for error messages
+fn required_str(object: &Map<String, Value>, key: &str, path: &str) -> Result<String, LLMRequestConversionError> {
+    match object.get(key) {
+        Some(Value::String(value)) => Ok(value.clone()),
+        Some(other) => Err(LLMRequestConversionError::new(path, format!("expected a string, got {}", json_type(other)))),
+        None => Err(LLMRequestConversionError::new(path, "missing"))
+    }
+}
+
+// every LLMRequest field except `extra`
+const MODELED_REQUEST_FIELDS: &[&str] = &[
+    "messages", "model", "temperature", "max_tokens", "stream", "top_p", "stop", "seed",
+    "frequency_penalty", "presence_penalty", "n", "response_format",
+    "tools", "tool_choice"
+];
+
+// an optional field of any type, with serde's complaint if it doesn't fit
+fn optional_field<T: serde::de::DeserializeOwned>(object: &Map<String, Value>, key: &str) -> Result<Option<T>, LLMRequestConversionError> {
+    match object.get(key) {
+        None | Some(Value::Null) => Ok(None),
+        Some(value) => serde_json::from_value(value.clone())
+            .map(Some)
+            .map_err(|e| LLMRequestConversionError::new(key, e.to_string()))
+    }
+}
+
+fn json_type(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "a boolean",
+        Value::Number(_) => "a number",
+        Value::String(_) => "a string",
+        Value::Array(_) => "an array",
+        Value::Object(_) => "an object"
+    }
+}
+
+/// A well-formed request whose messages still can't be sent upstream or cached
+#[derive(Debug, Clone, PartialEq)]
+pub enum MessageValidationError {
+    // content has no text beyond whitespace and no images, and the message calls no tools
+    EmptyContent { message_index: usize },
+    // the conversation ends on an assistant turn, so there's nothing to answer
+    TrailingAssistant { message_index: usize }
+}
+
+impl MessageValidationError {
+
+    /// Machine-readable error code for API responses
+    pub fn code(&self) -> &'static str {
+        match self {
+            MessageValidationError::EmptyContent { .. } => "empty_message_content",
+            MessageValidationError::TrailingAssistant { .. } => "trailing_assistant_message"
+        }
+    }
+
+    pub fn message_index(&self) -> usize {
+        match self {
+            MessageValidationError::EmptyContent { message_index }
+            | MessageValidationError::TrailingAssistant { message_index } => *message_index
+        }
+    }
+
+}
+
+impl std::fmt::Display for MessageValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MessageValidationError::EmptyContent { message_index } => write!(f, "messages[{}]: content is empty", message_index),
+            MessageValidationError::TrailingAssistant { message_index } => write!(f, "messages[{}]: last message is from the assistant", message_index)
+        }
+    }
+}
+
+impl std::error::Error for MessageValidationError {}
+
+impl LLMRequest {
+
+    /// Rejects empty message content (unless the message calls tools) and a
+    /// trailing assistant message
+    pub fn validate_messages(&self) -> Result<(), MessageValidationError> {
+
+        if let Some(message_index) = self.messages.iter().position(|m| m.content.is_blank() && m.tool_calls.is_none()) {
+            return Err(MessageValidationError::EmptyContent { message_index });
+        }
+        if let Some(last) = self.messages.last()
+            && last.role == "assistant" {
+            return Err(MessageValidationError::TrailingAssistant { message_index: self.messages.len() - 1 });
+        }
+
+        Ok(())
+
+    }
+
+}
+
+impl LLMRequest {
+
+    /// Whether the request offers tools or carries tool calls or results.
+    /// Its prompt text alone doesn't say what the answer depends on, so it
+    /// stays out of the semantic tier
+    pub fn uses_tools(&self) -> bool {
+        self.tools.is_some() || self.messages.iter().any(|m| m.tool_calls.is_some() || m.tool_call_id.is_some())
+    }
+
+    /// Whether any message carries an image. Only the text is embedded, so the
+    /// same words with a different image would otherwise match semantically
+    pub fn has_images(&self) -> bool {
+        self.messages.iter().any(|m| m.content.images().next().is_some())
+    }
+
+    /// Whether the request may be looked up in, or stored to, the semantic tier
+    pub fn semantic_eligible(&self) -> bool {
+        !self.uses_tools() && !self.has_images()
+    }
+
+}
+
+pub const REDACTED: &str = "[REDACTED]";
+
+/// A request with every message's content replaced, safe to write to logs
+#[derive(Debug, Clone, Serialize)]
+pub struct SanitizedRequest {
+    pub model: String,
+    pub temperature: Option<f32>,
+    pub max_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frequency_penalty: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub presence_penalty: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub n: Option<u32>,
+    pub messages: Vec<Message>,
+    // role -> number of messages with it
+    pub message_counts: BTreeMap<String, usize>
+}
+
+impl LLMRequest {
+
+    /// Keeps the shape of the request (model, sampling settings, roles) but none of the prompt text
+    pub fn strip_sensitive_fields(&self) -> SanitizedRequest {
+
+        let mut message_counts = BTreeMap::new();
+        for message in &self.messages {
+            *message_counts.entry(message.role.clone()).or_insert(0) += 1;
+        }
+
+        SanitizedRequest {
+            model: self.model.clone(),
+            temperature: self.temperature,
+            max_tokens: self.max_tokens,
+            top_p: self.top_p,
+            seed: self.seed,
+            frequency_penalty: self.frequency_penalty,
+            presence_penalty: self.presence_penalty,
+            n: self.n,
+            messages: self.messages.iter()
+                .map(|message| Message { role: message.role.clone(), content: REDACTED.into(), ..Default::default() })
+                .collect(),
+            message_counts
+        }
+
+    }
+
+}
+
+/// Field-by-field conversion with errors naming the offending field.
+/// Optional fields may be missing or null; unknown fields are kept in `extra`
+impl TryFrom<Value> for LLMRequest {
+    type Error = LLMRequestConversionError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+
+        let Value::Object(object) = value else {
+            return Err(LLMRequestConversionError::new("request", format!("expected an object, got {}", json_type(&value))));
+        };
+
+        let model = required_str(&object, "model", "model")?;
+
+        let messages = match object.get("messages") {
+            Some(Value::Array(messages)) => messages.iter().enumerate()
+                .map(|(i, message)| {
+                    let path = format!("messages[{}]", i);
+                    let Value::Object(message) = message else {
+                        return Err(LLMRequestConversionError::new(path, format!("expected an object, got {}", json_type(message))));
+                    };
+                    let tool_calls: Option<Vec<ToolCall>> = optional_field(message, "tool_calls")
+                        .map_err(|e| LLMRequestConversionError::new(format!("{}.tool_calls", path), e.reason))?;
+                    // an assistant turn that only calls tools has no content
+                    let content = match message.get("content") {
+                        None | Some(Value::Null) if tool_calls.is_some() => MessageContent::default(),
+                        Some(Value::String(text)) => MessageContent::Text(text.clone()),
+                        Some(Value::Array(_)) => optional_field(message, "content")
+                            .map_err(|e| LLMRequestConversionError::new(format!("{}.content", path), e.reason))?
+                            .unwrap_or_default(),
+                        Some(other) => return Err(LLMRequestConversionError::new(format!("{}.content", path), format!("expected a string or an array, got {}", json_type(other)))),
+                        None => return Err(LLMRequestConversionError::new(format!("{}.content", path), "missing"))
+                    };
+                    Ok(Message {
+                        role: required_str(message, "role", &format!("{}.role", path))?,
+                        content,
+                        tool_calls,
+                        tool_call_id: optional_field(message, "tool_call_id")
+                            .map_err(|e| LLMRequestConversionError::new(format!("{}.tool_call_id", path), e.reason))?,
+                        name: optional_field(message, "name")
+                            .map_err(|e| LLMRequestConversionError::new(format!("{}.name", path), e.reason))?
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+            Some(other) => return Err(LLMRequestConversionError::new("messages", format!("expected an array, got {}", json_type(other)))),
+            None => return Err(LLMRequestConversionError::new("messages", "missing"))
+        };
+
+        let temperature = match object.get("temperature") {
+            None | Some(Value::Null) => None,
+            Some(Value::Number(n)) => Some(n.as_f64().unwrap_or_default() as f32),
+            Some(other) => return Err(LLMRequestConversionError::new("temperature", format!("expected a number, got {}", json_type(other))))
+        };
+
+        let max_tokens = match object.get("max_tokens") {
+            None | Some(Value::Null) => None,
+            Some(Value::Number(n)) => match n.as_u64().and_then(|n| u32::try_from(n).ok()) {
+                Some(n) => Some(n),
+                None => return Err(LLMRequestConversionError::new("max_tokens", format!("expected a non-negative integer, got {}", n)))
+            },
+            Some(other) => return Err(LLMRequestConversionError::new("max_tokens", format!("expected a number, got {}", json_type(other))))
+        };
+
+        let stream = match object.get("stream") {
+            None | Some(Value::Null) => None,
+            Some(Value::Bool(stream)) => Some(*stream),
+            Some(other) => return Err(LLMRequestConversionError::new("stream", format!("expected a boolean, got {}", json_type(other))))
+        };
+
+        let extra: Map<String, Value> = object.iter()
+            .filter(|(key, _)| !MODELED_REQUEST_FIELDS.contains(&key.as_str()))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+
+        Ok(LLMRequest {
+            messages,
+            model,
+            temperature,
+            max_tokens,
+            stream,
+            top_p: optional_field(&object, "top_p")?,
+            stop: optional_field(&object, "stop")?,
+            seed: optional_field(&object, "seed")?,
+            frequency_penalty: optional_field(&object, "frequency_penalty")?,
+            presence_penalty: optional_field(&object, "presence_penalty")?,
+            n: optional_field(&object, "n")?,
+            response_format: optional_field(&object, "response_format")?,
+            tools: optional_field(&object, "tools")?,
+            tool_choice: optional_field(&object, "tool_choice")?,
+            extra: (!extra.is_empty()).then_some(extra)
+        })
+
+    }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+// usage and bookkeeping fields default when an upstream leaves them out,
+// so only a response with no usable choices fails to parse
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
 pub struct Usage {
+    #[serde(default)]
     pub prompt_tokens: u32,
+    #[serde(default)]
     pub completion_tokens: u32,
-    pub total_tokens: u32
+    #[serde(default)]
+    pub total_tokens: u32,
+    // provider-specific fields (e.g. Groq's queue_time, prompt_time) passed through as-is
+    #[serde(flatten)]
+    pub extra: Option<Map<String, Value>>
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct LLMResponse {
+    #[serde(default)]
     pub id: String,
+    #[serde(default)]
     pub object: String,
+    #[serde(default)]
     pub created: i64,
+    #[serde(default)]
     pub model: String,
     pub choices: Vec<Choice>,
-    pub usage: Usage
+    #[serde(default)]
+    pub usage: Usage,
+    // fields the proxy doesn't model (e.g. x_groq, system_fingerprint) passed through as-is
+    #[serde(flatten)]
+    pub extra: Option<Map<String, Value>>
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Choice {
     pub message: Message,
+    #[serde(default)]
     pub index: i32,
-    pub finish_reason: Option<String>
+    #[serde(default)]
+    pub finish_reason: Option<String>,
+    #[serde(flatten)]
+    pub extra: Option<Map<String, Value>>
+}
+
+/// `Usage` serialized with a `cost_usd` field next to the token counts.
+/// Only built when a response is sent, so cached entries never carry it
+#[derive(Debug, Serialize)]
+pub struct UsageWithCost<'a> {
+    #[serde(flatten)]
+    pub usage: &'a Usage,
+    // null when the model isn't in the pricing table
+    pub cost_usd: Option<f64>
+}
+
+impl Usage {
+
+    pub fn with_model_context(&self, model: &str) -> UsageWithCost<'_> {
+        let cost_usd = crate::pricing::known_model_pricing(model)
+            .map(|_| crate::pricing::calculate_cost(model, self.total_tokens as u64));
+        UsageWithCost { usage: self, cost_usd }
+    }
+
+}
+
+/// `LLMResponse` serialized with `usage.cost_usd`. `model` prices the tokens
+/// and can differ from `response.model` when a client alias is echoed back
+#[derive(Debug, Serialize)]
+pub struct LLMResponseWithCost<'a> {
+    pub id: &'a str,
+    pub object: &'a str,
+    pub created: i64,
+    pub model: &'a str,
+    pub choices: &'a [Choice],
+    pub usage: UsageWithCost<'a>,
+    #[serde(flatten)]
+    pub extra: &'a Option<Map<String, Value>>
+}
+
+impl LLMResponse {
+
+    pub fn with_cost(&self, model: &str) -> LLMResponseWithCost<'_> {
+        LLMResponseWithCost {
+            id: &self.id,
+            object: &self.object,
+            created: self.created,
+            model: &self.model,
+            choices: &self.choices,
+            usage: self.usage.with_model_context(model),
+            extra: &self.extra
+        }
+    }
+
+}
+
+/// OpenAI-style error body: `{"error": {"message", "type", "code"}}`
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct ApiError {
+    pub error: ApiErrorDetail
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct ApiErrorDetail {
+    pub message: String,
+    pub r#type: String,
+    pub code: Option<String>,
+    // the offending request field or upstream response path, when there is one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub param: Option<String>
+}
+
+impl ApiError {
+
+    pub fn new(r#type: &str, message: impl Into<String>) -> Self {
+        ApiError {
+            error: ApiErrorDetail { message: message.into(), r#type: r#type.to_string(), code: None, param: None }
+        }
+    }
+
+    pub fn with_code(mut self, code: &str) -> Self {
+        self.error.code = Some(code.to_string());
+        self
+    }
+
+    pub fn with_param(mut self, param: impl Into<String>) -> Self {
+        self.error.param = Some(param.into());
+        self
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_usage_with_model_context_adds_cost() {
+
+        let usage = Usage { prompt_tokens: 400_000, completion_tokens: 600_000, total_tokens: 1_000_000, extra: None };
+
+        let value = serde_json::to_value(usage.with_model_context("llama-3.1-8b-instant")).unwrap();
+        assert_eq!(value["total_tokens"], 1_000_000);
+        assert!((value["cost_usd"].as_f64().unwrap() - 0.065).abs() < 1e-12, "{}", value);
+
+        // an unpriced model gets null rather than a guess
+        let value = serde_json::to_value(usage.with_model_context("gpt-4")).unwrap();
+        assert!(value["cost_usd"].is_null());
+
+    }
+
+    #[test]
+    fn test_unknown_response_fields_round_trip() {
+
+        let upstream = serde_json::json!({
+            "id": "chatcmpl-1",
+            "object": "chat.completion",
+            "created": 1735689600,
+            "model": "llama-3.3-70b-versatile",
+            "system_fingerprint": "fp_abc",
+            "choices": [{
+                "message": {"role": "assistant", "content": "Hi"},
+                "index": 0,
+                "finish_reason": "stop",
+                "logprobs": null
+            }],
+            "usage": {
+                "prompt_tokens": 1,
+                "completion_tokens": 1,
+                "total_tokens": 2,
+                "queue_time": 0.02
+            },
+            "x_groq": {"id": "req_123"}
+        });
+
+        let response: LLMResponse = serde_json::from_value(upstream.clone()).unwrap();
+        assert_eq!(serde_json::to_value(&response).unwrap(), upstream);
+
+    }
+
+    #[test]
+    fn test_request_try_from_value() {
+
+        let request = LLMRequest::try_from(serde_json::json!({
+            "model": "llama-3.3-70b-versatile",
+            "messages": [{"role": "user", "content": "Hi"}],
+            "temperature": null
+        })).unwrap();
+        assert_eq!(request.model, "llama-3.3-70b-versatile");
+        assert_eq!(request.messages[0].content.as_text(), "Hi");
+        assert_eq!(request.temperature, None);
+        assert_eq!(request.max_tokens, None);
+
+        let request = LLMRequest::try_from(serde_json::json!({
+            "model": "llama-3.3-70b-versatile",
+            "messages": [{"role": "user", "content": "Hi"}],
+            "top_p": 0.9,
+            "stop": ["\n"],
+            "seed": 7,
+            "response_format": {"type": "json_object"},
+            "logit_bias": {"50256": -100}
+        })).unwrap();
+        assert_eq!((request.top_p, request.seed), (Some(0.9), Some(7)));
+        assert_eq!(request.stop, Some(serde_json::json!(["\n"])));
+        assert_eq!(request.response_format, Some(serde_json::json!({"type": "json_object"})));
+        assert_eq!(request.extra.unwrap()["logit_bias"], serde_json::json!({"50256": -100}));
+
+    }
+
+    #[test]
+    fn test_unmodeled_request_fields_are_forwarded() {
+
+        let body = serde_json::json!({
+            "model": "llama-3.3-70b-versatile",
+            "messages": [{"role": "user", "content": "Hi"}],
+            "presence_penalty": 0.5,
+            "n": 2,
+            "user": "user-42"
+        });
+        let request: LLMRequest = serde_json::from_value(body.clone()).unwrap();
+        assert_eq!(request.n, Some(2));
+        assert_eq!(request.extra.as_ref().unwrap()["user"], "user-42");
+
+        let forwarded = serde_json::to_value(&request).unwrap();
+        assert_eq!(forwarded["presence_penalty"], 0.5);
+        assert_eq!(forwarded["user"], "user-42");
+        assert!(forwarded.get("top_p").is_none(), "Unset parameters aren't sent as null");
+
+    }
+
+    #[test]
+    fn test_tool_calls_round_trip() {
+
+        let request = LLMRequest::try_from(serde_json::json!({
+            "model": "gpt-4o",
+            "messages": [
+                {"role": "user", "content": "Weather in Paris?"},
+                {"role": "assistant", "content": null, "tool_calls": [
+                    {"id": "call_1", "type": "function", "function": {"name": "get_weather", "arguments": "{\"city\":\"Paris\"}"}}
+                ]},
+                {"role": "tool", "tool_call_id": "call_1", "content": "18C and sunny"}
+            ],
+            "tools": [{"type": "function", "function": {"name": "get_weather", "parameters": {"type": "object"}}}],
+            "tool_choice": "auto"
+        })).unwrap();
+        assert!(request.uses_tools());
+        assert!(request.extra.is_none(), "tools and tool_choice are modeled fields");
+        assert_eq!(request.validate_messages(), Ok(()));
+        assert_eq!(request.messages[1].tool_calls.as_ref().unwrap()[0].function.name, "get_weather");
+        assert_eq!(request.messages[2].tool_call_id.as_deref(), Some("call_1"));
+
+        let forwarded = serde_json::to_value(&request).unwrap();
+        assert!(forwarded["messages"][1]["content"].is_null(), "A tool-call turn goes upstream with null content");
+        assert_eq!(forwarded["messages"][1]["tool_calls"][0]["type"], "function");
+        assert_eq!(forwarded["tool_choice"], "auto");
+        assert!(forwarded["messages"][0].get("tool_calls").is_none());
+
+        let upstream = serde_json::json!({
+            "id": "chatcmpl-1",
+            "choices": [{"index": 0, "finish_reason": "tool_calls", "message": {
+                "role": "assistant", "content": null,
+                "tool_calls": [{"id": "call_2", "type": "function", "function": {"name": "get_weather", "arguments": "{}"}}]
+            }}]
+        });
+        let response: LLMResponse = serde_json::from_value(upstream.clone()).unwrap();
+        let round_tripped = serde_json::to_value(&response).unwrap();
+        assert_eq!(round_tripped["choices"][0]["message"], upstream["choices"][0]["message"]);
+
+    }
+
+    #[test]
+    fn test_request_try_from_missing_fields() {
+
+        let err = LLMRequest::try_from(serde_json::json!({
+            "messages": [{"role": "user", "content": "Hi"}]
+        })).unwrap_err();
+        assert_eq!(err, LLMRequestConversionError::new("model", "missing"));
+
+        let err = LLMRequest::try_from(serde_json::json!({
+            "model": "llama-3.3-70b-versatile",
+            "messages": [{"role": "user", "content": "Hi"}, {"role": "user"}]
+        })).unwrap_err();
+        assert_eq!(err.field, "messages[1].content");
+        assert_eq!(err.reason, "missing");
+
+    }
+
+    #[test]
+    fn test_request_try_from_invalid_types() {
+
+        let base = serde_json::json!({
+            "model": "llama-3.3-70b-versatile",
+            "messages": [{"role": "user", "content": "Hi"}]
+        });
+
+        let cases = [
+            ("temperature", serde_json::json!("hot")),
+            ("max_tokens", serde_json::json!(-1)),
+            ("model", serde_json::json!(42)),
+            ("messages", serde_json::json!("Hi")),
+            ("seed", serde_json::json!("seven")),
+            ("n", serde_json::json!(-2))
+        ];
+        for (field, value) in cases {
+            let mut request = base.clone();
+            request[field] = value;
+            let err = LLMRequest::try_from(request).unwrap_err();
+            assert_eq!(err.field, field);
+        }
+
+        assert_eq!(LLMRequest::try_from(serde_json::json!([])).unwrap_err().field, "request");
+
+        let mut request = base.clone();
+        request["messages"][0]["content"] = serde_json::json!([{"type": "video", "url": "x"}]);
+        assert_eq!(LLMRequest::try_from(request).unwrap_err().field, "messages[0].content");
+
+    }
+
+    #[test]
+    fn test_message_content_as_text() {
+
+        let parse = |content: Value| serde_json::from_value::<MessageContent>(content).unwrap();
+        let image = serde_json::json!({"type": "image_url", "image_url": {"url": "https://example.com/cat.png"}});
+
+        let text = parse(serde_json::json!("What is Rust?"));
+        assert_eq!(text, MessageContent::Text("What is Rust?".to_string()));
+        assert_eq!(text.as_text(), "What is Rust?");
+
+        let mixed = parse(serde_json::json!([
+            {"type": "text", "text": "What is"},
+            image,
+            {"type": "text", "text": "in this picture?"}
+        ]));
+        assert_eq!(mixed.as_text(), "What is in this picture?");
+
+        let image_only = parse(serde_json::json!([image, image]));
+        assert!(matches!(&image_only, MessageContent::Parts(parts) if parts.len() == 2));
+        assert_eq!(image_only.as_text(), "");
+
+    }
+
+    #[test]
+    fn test_strip_sensitive_fields() {
+
+        let request = LLMRequest {
+            messages: vec![
+                Message { role: "system".to_string(), content: "You are terse".into(), ..Default::default() },
+                Message { role: "user".to_string(), content: "My card is 4111 1111".into(), ..Default::default() },
+                Message { role: "assistant".to_string(), content: "Noted".into(), ..Default::default() },
+                Message { role: "user".to_string(), content: "What is it?".into(), ..Default::default() }
+            ],
+            model: "llama-3.1-8b-instant".to_string(),
+            temperature: Some(0.2),
+            max_tokens: Some(64),
+            ..Default::default()
+        };
+
+        let sanitized = request.strip_sensitive_fields();
+        let logged = serde_json::to_string(&sanitized).unwrap();
+
+        assert_eq!(sanitized.model, "llama-3.1-8b-instant");
+        assert_eq!((sanitized.temperature, sanitized.max_tokens), (Some(0.2), Some(64)));
+        assert_eq!(sanitized.message_counts, BTreeMap::from([
+            ("assistant".to_string(), 1), ("system".to_string(), 1), ("user".to_string(), 2)
+        ]));
+        assert!(sanitized.messages.iter().all(|m| m.content.as_text() == REDACTED));
+        for message in &request.messages {
+            let content = message.content.as_text();
+            assert!(!logged.contains(&content), "'{}' leaked into {}", content, logged);
+        }
+
+    }
+
+    #[test]
+    fn test_validate_messages() {
+
+        let message = |role: &str, content: &str| Message { role: role.to_string(), content: content.into(), ..Default::default() };
+        let request = |messages| LLMRequest { messages, model: "llama-3.3-70b-versatile".to_string(), ..Default::default() };
+
+        let valid = request(vec![message("user", "Hi"), message("assistant", "Hello"), message("user", "Bye")]);
+        assert_eq!(valid.validate_messages(), Ok(()));
+
+        let empty = request(vec![message("system", "Be brief"), message("user", "  \n")]);
+        let err = empty.validate_messages().unwrap_err();
+        assert_eq!(err, MessageValidationError::EmptyContent { message_index: 1 });
+        assert_eq!(err.code(), "empty_message_content");
+
+        // an image is content even without any text
+        let image = serde_json::from_value(serde_json::json!([{"type": "image_url", "image_url": {"url": "https://example.com/cat.png"}}])).unwrap();
+        let image_only = request(vec![Message { role: "user".to_string(), content: image, ..Default::default() }]);
+        assert_eq!(image_only.validate_messages(), Ok(()));
+        assert!(image_only.has_images() && !image_only.semantic_eligible());
+        assert!(!valid.has_images() && valid.semantic_eligible());
+
+        let trailing = request(vec![message("user", "Hi"), message("assistant", "Hello")]);
+        let err = trailing.validate_messages().unwrap_err();
+        assert_eq!(err, MessageValidationError::TrailingAssistant { message_index: 1 });
+        assert_eq!(err.code(), "trailing_assistant_message");
+
+    }
+
 }
\ No newline at end of file
@@ -0,0 +1,84 @@
+// Groq list prices, used for the savings figures in /metrics and /admin/stats
+// and for the optional cost_usd field on responses. MODEL_PRICING adds models
+// or overrides these.
+
+use std::collections::BTreeMap;
+use std::sync::OnceLock;
+use serde::{Deserialize, Serialize};
+
+/// One model's price in USD per 1M tokens
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub struct ModelPrice {
+    pub input: f64,
+    pub output: f64
+}
+
+static CUSTOM_PRICING: OnceLock<BTreeMap<String, ModelPrice>> = OnceLock::new();
+
+/// Parses MODEL_PRICING, a JSON object of model -> `{"input": .., "output": ..}`
+pub fn parse_model_pricing(raw: &str) -> Result<BTreeMap<String, ModelPrice>, String> {
+
+    let prices = serde_json::from_str::<BTreeMap<String, ModelPrice>>(raw)
+        .map_err(|e| format!("MODEL_PRICING must be a JSON object of model -> {{\"input\": .., \"output\": ..}}: {}", e))?;
+
+    prices.into_iter()
+        .map(|(model, price)| {
+            if !(price.input >= 0.0 && price.output >= 0.0) {
+                return Err(format!("MODEL_PRICING: prices for '{}' can't be negative", model));
+            }
+            Ok((model.trim().to_lowercase(), price))
+        })
+        .collect()
+
+}
+
+/// Puts MODEL_PRICING ahead of the built-in table. Only the first call counts
+pub fn set_custom_pricing(prices: BTreeMap<String, ModelPrice>) {
+    let _ = CUSTOM_PRICING.set(prices);
+}
+
+/// Returns (input_cost_per_1m_tokens, output_cost_per_1m_tokens) for a known
+/// model, or `None` when the model isn't in the table
+pub fn known_model_pricing(model: &str) -> Option<(f64, f64)> {
+    if let Some(price) = CUSTOM_PRICING.get().and_then(|prices| prices.get(model)) {
+        return Some((price.input, price.output));
+    }
+    let pricing = match model {
+        // Llama models
+        "llama-3.3-70b-versatile" => (0.59, 0.79),
+        "llama-3.1-8b-instant" => (0.05, 0.08),
+        "llama-4-scout" => (0.11, 0.34),
+        "llama-4-maverick" => (0.20, 0.60),
+        
+        // Qwen models
+        "qwen3-32b" => (0.29, 0.59),
+        
+        // Kimi models
+        "kimi-k2-0905-1t" => (1.00, 3.00),
+        
+        // GPT OSS models
+        "gpt-oss-20b" => (0.075, 0.30),
+        "gpt-oss-safeguard-20b" => (0.075, 0.30),
+        "gpt-oss-120b" => (0.15, 0.60),
+
+        _ => return None
+    };
+    Some(pricing)
+}
+
+/// Like `known_model_pricing`, but unknown models fall back to Llama 3.3 70B
+/// pricing (the most common)
+pub fn get_groq_model_pricing(model: &str) -> (f64, f64) {
+    known_model_pricing(model).unwrap_or_else(|| {
+        tracing::warn!("Unknown model '{}', using Llama 3.3 70B pricing", model);
+        (0.59, 0.79)
+    })
+}
+
+pub fn calculate_cost(model: &str, tokens: u64) -> f64 {
+
+    let (input_price, output_price) = get_groq_model_pricing(model);
+    let avg_price = (input_price + output_price) / 2.0;
+    (tokens as f64 / 1_000_000.0) * avg_price
+
+}
@@ -0,0 +1,152 @@
+// Daily usage quotas per tenant (TENANT_DAILY_TOKEN_QUOTA and
+// TENANT_DAILY_COST_QUOTA_USD). Usage is what a tenant's upstream calls cost;
+// cache hits are free. Counts restart at midnight UTC. A tenant's usage is
+// only known once the upstream answers, so the request that crosses a quota
+// still completes and it's the next one that is turned away.
+
+use chrono::{DateTime, NaiveDate, Utc};
+use dashmap::DashMap;
+use std::sync::Mutex;
+
+/// TENANT_DAILY_TOKEN_QUOTA and TENANT_DAILY_COST_QUOTA_USD; `None` is unlimited
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Quotas {
+    pub daily_tokens: Option<u64>,
+    pub daily_cost_usd: Option<f64>
+}
+
+/// What a tenant's upstream calls have used so far today
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TenantUsage {
+    pub tokens: u64,
+    pub cost_usd: f64
+}
+
+/// Why a tenant was turned away: which quota it used up, and when it resets
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuotaExceeded {
+    pub quota: &'static str,
+    pub limit: f64,
+    pub used: f64,
+    pub resets_at: DateTime<Utc>
+}
+
+#[derive(Debug)]
+pub struct QuotaTracker {
+    quotas: Quotas,
+    // the UTC day `usage` was counted on
+    day: Mutex<NaiveDate>,
+    usage: DashMap<String, TenantUsage>
+}
+
+impl QuotaTracker {
+
+    pub fn new(quotas: Quotas) -> Self {
+        QuotaTracker { quotas, day: Mutex::new(Utc::now().date_naive()), usage: DashMap::new() }
+    }
+
+    pub fn quotas(&self) -> Quotas {
+        self.quotas
+    }
+
+    /// Whether `tenant` may make another request today
+    pub fn check(&self, tenant: &str) -> Result<(), QuotaExceeded> {
+        self.check_on(tenant, Utc::now().date_naive())
+    }
+
+    fn check_on(&self, tenant: &str, today: NaiveDate) -> Result<(), QuotaExceeded> {
+
+        let usage = self.usage_on(tenant, today);
+        let exceeded = |quota, limit: f64, used: f64| QuotaExceeded { quota, limit, used, resets_at: resets_at(today) };
+
+        if let Some(limit) = self.quotas.daily_tokens
+            && usage.tokens >= limit {
+            return Err(exceeded("daily_tokens", limit as f64, usage.tokens as f64));
+        }
+        if let Some(limit) = self.quotas.daily_cost_usd
+            && usage.cost_usd >= limit {
+            return Err(exceeded("daily_cost_usd", limit, usage.cost_usd));
+        }
+        Ok(())
+
+    }
+
+    /// Adds an upstream call's tokens and estimated cost to `tenant`'s usage
+    pub fn record(&self, tenant: &str, tokens: u64, cost_usd: f64) {
+        self.record_on(tenant, tokens, cost_usd, Utc::now().date_naive());
+    }
+
+    fn record_on(&self, tenant: &str, tokens: u64, cost_usd: f64, today: NaiveDate) {
+
+        self.roll_over(today);
+        let mut usage = self.usage.entry(tenant.to_string()).or_default();
+        usage.tokens += tokens;
+        usage.cost_usd += cost_usd;
+
+    }
+
+    /// `tenant`'s usage so far today
+    pub fn usage(&self, tenant: &str) -> TenantUsage {
+        self.usage_on(tenant, Utc::now().date_naive())
+    }
+
+    fn usage_on(&self, tenant: &str, today: NaiveDate) -> TenantUsage {
+
+        self.roll_over(today);
+        self.usage.get(tenant).map(|usage| *usage).unwrap_or_default()
+
+    }
+
+    /// When today's counts start over
+    pub fn resets_at(&self) -> DateTime<Utc> {
+        resets_at(*self.day.lock().unwrap())
+    }
+
+    // forgets yesterday's usage once the day has changed
+    fn roll_over(&self, today: NaiveDate) {
+
+        let mut day = self.day.lock().unwrap();
+        if *day != today {
+            *day = today;
+            self.usage.clear();
+        }
+
+    }
+
+}
+
+fn resets_at(day: NaiveDate) -> DateTime<Utc> {
+    day.succ_opt().unwrap_or(day).and_hms_opt(0, 0, 0).unwrap_or_default().and_utc()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_quota_blocks_until_the_next_day() {
+
+        let tracker = QuotaTracker::new(Quotas { daily_tokens: Some(1000), daily_cost_usd: Some(0.50) });
+        let today = NaiveDate::from_ymd_opt(2026, 10, 15).unwrap();
+
+        assert!(tracker.check_on("team-a", today).is_ok());
+        tracker.record_on("team-a", 1200, 0.01, today);
+
+        let exceeded = tracker.check_on("team-a", today).unwrap_err();
+        assert_eq!(exceeded.quota, "daily_tokens");
+        assert_eq!(exceeded.used, 1200.0);
+        assert_eq!(exceeded.resets_at.to_rfc3339(), "2026-10-16T00:00:00+00:00");
+
+        // tenants don't share usage
+        assert!(tracker.check_on("team-b", today).is_ok());
+        tracker.record_on("team-b", 10, 0.75, today);
+        assert_eq!(tracker.check_on("team-b", today).unwrap_err().quota, "daily_cost_usd");
+
+        let tomorrow = today.succ_opt().unwrap();
+        assert!(tracker.check_on("team-a", tomorrow).is_ok());
+        assert_eq!(tracker.usage_on("team-b", tomorrow), TenantUsage::default());
+
+    }
+
+}
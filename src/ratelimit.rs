@@ -0,0 +1,252 @@
+// Per-caller rate limits, so one client can't exhaust the upstream quota.
+// Each caller (its proxy API key name, or its IP without PROXY_API_KEYS) has
+// a token bucket for requests and one for upstream tokens, each refilled
+// continuously up to its per-minute limit. Requests are taken on arrival;
+// tokens are only known once the upstream answers, so they are taken
+// afterwards and a large completion can push the bucket below zero, holding
+// the caller's next requests until it has refilled.
+// Under RATE_LIMIT_SHARED the request limit is instead a fixed one-minute
+// window counted in Redis, so every instance sharing it enforces one limit;
+// the token limit stays per instance.
+
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use dashmap::DashMap;
+
+use crate::backend::CacheBackend;
+use crate::cache::RATE_LIMIT_PREFIX;
+
+/// RATE_LIMIT_REQUESTS_PER_MIN and RATE_LIMIT_TOKENS_PER_MIN; `None` is
+/// unlimited. `shared` is RATE_LIMIT_SHARED
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RateLimits {
+    pub requests_per_min: Option<u64>,
+    pub tokens_per_min: Option<u64>,
+    pub shared: bool
+}
+
+impl RateLimits {
+
+    pub fn enabled(&self) -> bool {
+        self.requests_per_min.is_some() || self.tokens_per_min.is_some()
+    }
+
+    // the request limit kept in this process's buckets, None when it's shared
+    fn local_requests_per_min(&self) -> Option<u64> {
+        self.requests_per_min.filter(|_| !self.shared)
+    }
+
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    level: f64,
+    updated: Instant
+}
+
+impl Bucket {
+
+    fn full(per_min: u64, now: Instant) -> Self {
+        Bucket { level: per_min as f64, updated: now }
+    }
+
+    fn refill(&mut self, per_min: u64, now: Instant) {
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.level = (self.level + elapsed * per_min as f64 / 60.0).min(per_min as f64);
+        self.updated = now;
+    }
+
+    // how long until the level reaches `needed`
+    fn wait_for(&self, needed: f64, per_min: u64) -> Duration {
+        Duration::from_secs_f64(((needed - self.level) * 60.0 / per_min as f64).max(0.0))
+    }
+
+}
+
+#[derive(Debug, Clone, Copy)]
+struct CallerBuckets {
+    requests: Bucket,
+    tokens: Bucket
+}
+
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    limits: RateLimits,
+    callers: DashMap<String, CallerBuckets>
+}
+
+impl RateLimiter {
+
+    pub fn new(limits: RateLimits) -> Self {
+        RateLimiter { limits, callers: DashMap::new() }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.limits.enabled()
+    }
+
+    /// Takes one request from `caller`'s allowance, or returns how long it
+    /// has to wait when either bucket is empty
+    pub fn check(&self, caller: &str) -> Result<(), Duration> {
+        self.check_at(caller, Instant::now())
+    }
+
+    fn check_at(&self, caller: &str, now: Instant) -> Result<(), Duration> {
+
+        let mut buckets = self.buckets(caller, now);
+
+        let mut wait = Duration::ZERO;
+        if let Some(per_min) = self.limits.local_requests_per_min() {
+            buckets.requests.refill(per_min, now);
+            wait = wait.max(buckets.requests.wait_for(1.0, per_min));
+        }
+        // any tokens left let a request through; it's the overdraft that blocks
+        if let Some(per_min) = self.limits.tokens_per_min {
+            buckets.tokens.refill(per_min, now);
+            if buckets.tokens.level <= 0.0 {
+                wait = wait.max(buckets.tokens.wait_for(1.0, per_min));
+            }
+        }
+
+        if !wait.is_zero() {
+            return Err(wait);
+        }
+        if self.limits.local_requests_per_min().is_some() {
+            buckets.requests.level -= 1.0;
+        }
+        Ok(())
+
+    }
+
+    /// Takes one request from `caller`'s shared allowance, counted per minute
+    /// in `backend` so it holds across instances. Returns how long is left of
+    /// the minute once it's used up. A backend error lets the request through
+    pub async fn check_shared(&self, backend: &dyn CacheBackend, caller: &str) -> Result<(), Duration> {
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        self.check_shared_at(backend, caller, now).await
+
+    }
+
+    async fn check_shared_at(&self, backend: &dyn CacheBackend, caller: &str, now: u64) -> Result<(), Duration> {
+
+        let Some(per_min) = self.limits.requests_per_min.filter(|_| self.limits.shared) else {
+            return Ok(());
+        };
+
+        let key = format!("{}{}:{}", RATE_LIMIT_PREFIX, caller, now / 60);
+        match backend.atomic_increment_or_init(&key, 60).await {
+            Ok(count) if count as u64 > per_min => Err(Duration::from_secs(60 - now % 60)),
+            Ok(_) => Ok(()),
+            Err(e) => {
+                tracing::warn!(%caller, "Shared rate limit check failed, letting the request through: {}", e);
+                Ok(())
+            }
+        }
+
+    }
+
+    /// Takes the tokens an upstream call used from `caller`'s allowance
+    pub fn record_tokens(&self, caller: &str, tokens: u64) {
+
+        if let Some(per_min) = self.limits.tokens_per_min {
+            let now = Instant::now();
+            let mut buckets = self.buckets(caller, now);
+            buckets.tokens.refill(per_min, now);
+            buckets.tokens.level -= tokens as f64;
+        }
+
+    }
+
+    /// Forgets callers whose buckets have refilled completely, as they'd
+    /// start out full anyway. Returns how many were dropped
+    pub fn prune(&self) -> usize {
+
+        let now = Instant::now();
+        let before = self.callers.len();
+        self.callers.retain(|_, buckets| {
+            let requests_full = self.limits.local_requests_per_min().is_none_or(|per_min| {
+                buckets.requests.refill(per_min, now);
+                buckets.requests.level >= per_min as f64
+            });
+            let tokens_full = self.limits.tokens_per_min.is_none_or(|per_min| {
+                buckets.tokens.refill(per_min, now);
+                buckets.tokens.level >= per_min as f64
+            });
+            !(requests_full && tokens_full)
+        });
+        before - self.callers.len()
+
+    }
+
+    fn buckets(&self, caller: &str, now: Instant) -> dashmap::mapref::one::RefMut<'_, String, CallerBuckets> {
+
+        self.callers.entry(caller.to_string()).or_insert_with(|| CallerBuckets {
+            requests: Bucket::full(self.limits.local_requests_per_min().unwrap_or(0), now),
+            tokens: Bucket::full(self.limits.tokens_per_min.unwrap_or(0), now)
+        })
+
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_request_bucket_refills_over_time() {
+
+        let limiter = RateLimiter::new(RateLimits { requests_per_min: Some(2), tokens_per_min: None, shared: false });
+        let start = Instant::now();
+
+        assert!(limiter.check_at("alice", start).is_ok());
+        assert!(limiter.check_at("alice", start).is_ok());
+        let wait = limiter.check_at("alice", start).unwrap_err();
+        assert_eq!(wait.as_secs(), 30, "One request comes back every 30s at 2/min");
+
+        // callers don't share buckets
+        assert!(limiter.check_at("bob", start).is_ok());
+
+        assert!(limiter.check_at("alice", start + Duration::from_secs(30)).is_ok());
+        assert!(limiter.check_at("alice", start + Duration::from_secs(30)).is_err());
+
+    }
+
+    #[test]
+    fn test_token_overdraft_blocks_until_refilled() {
+
+        let limiter = RateLimiter::new(RateLimits { requests_per_min: None, tokens_per_min: Some(600), shared: false });
+
+        assert!(limiter.check("alice").is_ok());
+        limiter.record_tokens("alice", 1200);
+
+        let wait = limiter.check("alice").unwrap_err();
+        assert!(wait > Duration::from_secs(59) && wait <= Duration::from_secs(61), "{:?}", wait);
+        assert_eq!(limiter.prune(), 0, "A caller in overdraft must be kept");
+
+        assert!(limiter.check("bob").is_ok());
+        assert_eq!(limiter.prune(), 1, "An untouched caller is dropped");
+
+    }
+
+    #[tokio::test]
+    async fn test_shared_request_limit_spans_instances() {
+
+        let backend = crate::backend::MemoryBackend::default();
+        let limits = RateLimits { requests_per_min: Some(2), tokens_per_min: None, shared: true };
+        let (first, second) = (RateLimiter::new(limits), RateLimiter::new(limits));
+        let now = 600 + 15;
+
+        assert!(first.check_shared_at(&backend, "alice", now).await.is_ok());
+        assert!(second.check_shared_at(&backend, "alice", now).await.is_ok());
+        let wait = first.check_shared_at(&backend, "alice", now).await.unwrap_err();
+        assert_eq!(wait.as_secs(), 45, "The window ends on the minute");
+        assert!(first.check("alice").is_ok(), "The local buckets leave the request limit to the backend");
+
+        assert!(second.check_shared_at(&backend, "bob", now).await.is_ok());
+        assert!(second.check_shared_at(&backend, "alice", now + 45).await.is_ok(), "A new minute is a new window");
+
+    }
+
+}
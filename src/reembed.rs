@@ -0,0 +1,262 @@
+// Moves the semantic cache to a new embedding model. Every stored prompt is
+// re-embedded with the current embedding backend and written, payload and
+// point id unchanged, into a new collection sized for the new vectors. Once
+// every page has been copied the live QdrantCache switches over to it.
+//
+// One job runs at a time. A run stops after `max_points` and can be resumed
+// from where it left off by starting it again with the same source and
+// target. Because point ids are kept, re-running a page overwrites it.
+
+use axum::http::StatusCode;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinSet;
+use crate::AppState;
+use crate::cache::{StoredPoint, get_embedding};
+
+const DEFAULT_BATCH_SIZE: u32 = 64;
+const MAX_BATCH_SIZE: u32 = 512;
+const DEFAULT_MAX_POINTS: u64 = 10_000;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ReembedRequest {
+    // defaults to the active collection
+    pub source: Option<String>,
+    // defaults to "<source>_reembed"
+    pub target: Option<String>,
+    // count what would be migrated without embedding or writing anything
+    #[serde(default)]
+    pub dry_run: bool,
+    // points per scroll page, all embedded concurrently
+    pub batch_size: Option<u32>,
+    // upper bound on embedding calls for this run; the job pauses after it
+    pub max_points: Option<u64>
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReembedState {
+    #[default]
+    Idle,
+    Running,
+    // stopped at max_points; starting again resumes
+    Paused,
+    Completed,
+    // stopped by a Qdrant error; starting again resumes
+    Failed
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ReembedStatus {
+    pub state: ReembedState,
+    pub source: Option<String>,
+    pub target: Option<String>,
+    pub dry_run: bool,
+    // points in the source collection when the job started
+    pub total: u64,
+    pub processed: u64,
+    // points whose prompt couldn't be embedded; they are left out of the target
+    pub failures: u64,
+    // points stored without prompt text can't be re-embedded
+    pub missing_prompt: u64,
+    pub embedding_dim: Option<usize>,
+    // whether the live cache now reads and writes the target collection
+    pub switched: bool,
+    // the page the next run starts from
+    pub next_offset: Option<String>,
+    pub error: Option<String>,
+    pub started_at: Option<String>,
+    pub finished_at: Option<String>
+}
+
+impl ReembedStatus {
+
+    // a stopped job over the same collections picks up where it stopped
+    fn resumes(&self, source: &str, target: &str, dry_run: bool) -> bool {
+        matches!(self.state, ReembedState::Paused | ReembedState::Failed)
+            && self.source.as_deref() == Some(source)
+            && self.target.as_deref() == Some(target)
+            && self.dry_run == dry_run
+    }
+
+}
+
+/// Starts (or resumes) a migration in the background and returns its initial status
+pub async fn start(state: &AppState, request: ReembedRequest) -> Result<ReembedStatus, (StatusCode, String)> {
+
+    let Some(qdrant) = &state.semantic_cache else {
+        return Err((StatusCode::CONFLICT, "The semantic cache tier is disabled".to_string()));
+    };
+
+    let source = request.source.unwrap_or_else(|| qdrant.collection_name().to_string());
+    let target = request.target.unwrap_or_else(|| format!("{}_reembed", source));
+    if source == target {
+        return Err((StatusCode::BAD_REQUEST, "source and target collections must differ".to_string()));
+    }
+
+    let total = qdrant.collection_points(&source).await
+        .map_err(|e| (StatusCode::BAD_GATEWAY, format!("Cannot read collection '{}': {}", source, e)))?;
+
+    let status = {
+        let mut status = state.reembed.lock().unwrap();
+        if status.state == ReembedState::Running {
+            return Err((StatusCode::CONFLICT, "A re-embed job is already running".to_string()));
+        }
+
+        if status.resumes(&source, &target, request.dry_run) {
+            tracing::info!("Re-embed: resuming '{}' -> '{}' at {}/{}", source, target, status.processed, status.total);
+        } else {
+            *status = ReembedStatus {
+                source: Some(source.clone()),
+                target: Some(target.clone()),
+                dry_run: request.dry_run,
+                total,
+                started_at: Some(Utc::now().to_rfc3339()),
+                ..ReembedStatus::default()
+            };
+        }
+        status.state = ReembedState::Running;
+        status.error = None;
+        status.finished_at = None;
+        status.clone()
+    };
+
+    let batch_size = request.batch_size.unwrap_or(DEFAULT_BATCH_SIZE).clamp(1, MAX_BATCH_SIZE);
+    let max_points = request.max_points.unwrap_or(DEFAULT_MAX_POINTS).max(1);
+
+    let state = state.clone();
+    tokio::spawn(async move {
+        run(&state, &source, &target, batch_size, max_points).await;
+    });
+
+    Ok(status)
+
+}
+
+async fn run(state: &AppState, source: &str, target: &str, batch_size: u32, max_points: u64) {
+
+    let Some(qdrant) = &state.semantic_cache else {
+        return;
+    };
+
+    let (dry_run, mut offset, mut embedding_dim) = {
+        let status = state.reembed.lock().unwrap();
+        (status.dry_run, status.next_offset.clone(), status.embedding_dim)
+    };
+    let mut run_processed: u64 = 0;
+
+    loop {
+        if run_processed >= max_points {
+            finish(state, ReembedState::Paused, None);
+            tracing::info!("Re-embed: paused after {} points this run", run_processed);
+            return;
+        }
+
+        let limit = batch_size.min((max_points - run_processed) as u32);
+        let (page, next) = match qdrant.scroll_points(source, offset.as_deref(), limit).await {
+            Ok(page) => page,
+            Err(e) => {
+                finish(state, ReembedState::Failed, Some(format!("Scroll failed: {}", e)));
+                return;
+            }
+        };
+
+        let page_len = page.len() as u64;
+        let (with_prompt, missing_prompt): (Vec<StoredPoint>, Vec<StoredPoint>) =
+            page.into_iter().partition(|point| point.prompt().is_some());
+
+        let mut failures = 0;
+        if !dry_run {
+            let (embedded, failed) = embed_points(state, with_prompt).await;
+            failures = failed;
+
+            if let Some(dim) = embedded.first().map(|(_, embedding)| embedding.len())
+                && embedding_dim.is_none() {
+                if let Err(e) = qdrant.ensure_collection(target, dim).await {
+                    finish(state, ReembedState::Failed, Some(format!("Cannot create '{}': {}", target, e)));
+                    return;
+                }
+                embedding_dim = Some(dim);
+            }
+
+            if let Err(e) = qdrant.upsert_points(target, embedded).await {
+                finish(state, ReembedState::Failed, Some(format!("Write to '{}' failed: {}", target, e)));
+                return;
+            }
+        }
+
+        run_processed += page_len;
+        {
+            let mut status = state.reembed.lock().unwrap();
+            status.processed += page_len;
+            status.failures += failures;
+            status.missing_prompt += missing_prompt.len() as u64;
+            status.embedding_dim = embedding_dim;
+            status.next_offset = next.clone();
+        }
+
+        offset = next;
+        if offset.is_none() {
+            break;
+        }
+    }
+
+    if dry_run {
+        finish(state, ReembedState::Completed, None);
+        return;
+    }
+
+    match embedding_dim {
+        Some(dim) => {
+            qdrant.switch_collection(target, dim);
+            state.reembed.lock().unwrap().switched = true;
+            finish(state, ReembedState::Completed, None);
+        }
+        None => finish(state, ReembedState::Completed, Some("No points with prompt text - collection not switched".to_string()))
+    }
+
+}
+
+// embeds every point's prompt concurrently, returning the successes and a failure count
+async fn embed_points(state: &AppState, points: Vec<StoredPoint>) -> (Vec<(StoredPoint, Vec<f32>)>, u64) {
+
+    let mut tasks = JoinSet::new();
+    for point in points {
+        let http_client = state.http_client.clone();
+        let endpoint = state.config.embedding.clone();
+        tasks.spawn(async move {
+            let prompt = point.prompt().unwrap_or_default().to_string();
+            let embedding = get_embedding(&http_client, &endpoint, &prompt).await;
+            (point, embedding)
+        });
+    }
+
+    let mut embedded = Vec::new();
+    let mut failures = 0;
+    while let Some(outcome) = tasks.join_next().await {
+        match outcome {
+            Ok((point, Ok(embedding))) => embedded.push((point, embedding)),
+            Ok((point, Err(e))) => {
+                tracing::warn!("Re-embed: point {} failed: {}", point.id, e);
+                failures += 1;
+            }
+            Err(_) => failures += 1
+        }
+    }
+
+    (embedded, failures)
+
+}
+
+fn finish(state: &AppState, outcome: ReembedState, error: Option<String>) {
+
+    let mut status = state.reembed.lock().unwrap();
+    status.state = outcome;
+    status.error = error;
+    status.finished_at = Some(Utc::now().to_rfc3339());
+    tracing::info!(
+        "Re-embed: {:?} - {}/{} processed, {} failed, {} without prompt",
+        outcome, status.processed, status.total, status.failures, status.missing_prompt
+    );
+
+}
@@ -0,0 +1,356 @@
+// Request records in SQLite or Postgres (REQUEST_STORE_URL), for
+// GET /admin/requests. Every line of the request log is also inserted here,
+// in the background so no client waits on the database. Both databases go
+// through sqlx's `Any` driver, so the SQL sticks to what they share: `$n`
+// placeholders, BIGINT/DOUBLE PRECISION/TEXT columns, and casts on aggregates
+// (Postgres sums BIGINTs into NUMERIC, which `Any` can't decode).
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::any::{AnyArguments, AnyPoolOptions, AnyRow};
+use sqlx::{AnyPool, Arguments, Row};
+use crate::logger::RequestLogEntry;
+
+// cache hits as /metrics counts them; their cost is what the upstream would have charged
+const HIT_STATUSES: &str = "'TIER0_HIT', 'EXACT_HIT', 'SEMANTIC_HIT'";
+// the statuses of upstream calls, whose cost was spent
+const SPEND_STATUSES: &str = "'MISS', 'STREAM_MISS', 'DEDUP_BY_ID', 'PREFILL', 'REVALIDATE', 'REFRESH'";
+
+const MAX_LIST_LIMIT: usize = 1000;
+
+/// What to break `/admin/requests` totals down by
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GroupBy {
+    Tenant,
+    Model,
+    CacheStatus,
+    ApiKey,
+    // UTC days
+    Day
+}
+
+impl GroupBy {
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "tenant" => Some(GroupBy::Tenant),
+            "model" => Some(GroupBy::Model),
+            "cache_status" | "status" => Some(GroupBy::CacheStatus),
+            "api_key" => Some(GroupBy::ApiKey),
+            "day" => Some(GroupBy::Day),
+            _ => None
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            GroupBy::Tenant => "tenant",
+            GroupBy::Model => "model",
+            GroupBy::CacheStatus => "cache_status",
+            GroupBy::ApiKey => "api_key",
+            GroupBy::Day => "day"
+        }
+    }
+
+    fn expression(&self) -> &'static str {
+        match self {
+            GroupBy::Tenant => "tenant",
+            GroupBy::Model => "model",
+            GroupBy::CacheStatus => "cache_status",
+            GroupBy::ApiKey => "api_key",
+            GroupBy::Day => "CAST(ts_ms / 86400000 AS BIGINT)"
+        }
+    }
+
+}
+
+/// Which records a query covers; every field left `None` matches anything.
+/// `from` is inclusive and `to` exclusive
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RequestFilter {
+    pub model: Option<String>,
+    pub cache_status: Option<String>,
+    pub tenant: Option<String>,
+    pub api_key: Option<String>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>
+}
+
+/// One stored request, as listed by `/admin/requests`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RequestRecord {
+    pub timestamp: String,
+    pub request_id: Option<String>,
+    pub tenant: Option<String>,
+    pub api_key: Option<String>,
+    pub cache_status: String,
+    pub model: String,
+    pub latency_ms: Option<u64>,
+    pub tokens: u64,
+    pub cost_usd: f64
+}
+
+/// Totals over a set of requests. `group` is the value they share when grouped
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct RequestTotals {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    pub requests: u64,
+    pub hits: u64,
+    pub tokens: u64,
+    pub cost_spent_usd: f64,
+    pub cost_saved_usd: f64,
+    pub avg_latency_ms: Option<f64>
+}
+
+// SQL with `$n` placeholders numbered as values are bound
+struct Statement {
+    sql: String,
+    args: AnyArguments<'static>,
+    bound: usize
+}
+
+impl Statement {
+
+    fn new(sql: &str) -> Self {
+        Statement { sql: sql.to_string(), args: AnyArguments::default(), bound: 0 }
+    }
+
+    fn push(&mut self, sql: &str) -> &mut Self {
+        self.sql.push_str(sql);
+        self
+    }
+
+    fn bind<T>(&mut self, value: T) -> &mut Self
+    where
+        T: sqlx::Encode<'static, sqlx::Any> + sqlx::Type<sqlx::Any> + 'static
+    {
+        self.bound += 1;
+        self.sql.push_str(&format!("${}", self.bound));
+        // encoding a plain string or integer can't fail
+        let _ = self.args.add(value);
+        self
+    }
+
+    fn filtered(mut self, filter: &RequestFilter) -> Self {
+
+        self.push(" WHERE 1 = 1");
+        for (column, value) in [
+            ("model", &filter.model),
+            ("cache_status", &filter.cache_status),
+            ("tenant", &filter.tenant),
+            ("api_key", &filter.api_key)
+        ] {
+            if let Some(value) = value {
+                self.push(&format!(" AND {} = ", column)).bind(value.clone());
+            }
+        }
+        if let Some(from) = filter.from {
+            self.push(" AND ts_ms >= ").bind(from.timestamp_millis());
+        }
+        if let Some(to) = filter.to {
+            self.push(" AND ts_ms < ").bind(to.timestamp_millis());
+        }
+        self
+
+    }
+
+}
+
+#[derive(Debug, Clone)]
+pub struct RequestStore {
+    pool: AnyPool
+}
+
+impl RequestStore {
+
+    /// Connects to `url` (`sqlite:` or `postgres:`) and creates the table if needed
+    pub async fn connect(url: &str) -> Result<Self, sqlx::Error> {
+
+        sqlx::any::install_default_drivers();
+        // every connection to an in-memory SQLite database gets a database of its own
+        let max_connections = if url.contains(":memory:") { 1 } else { 5 };
+        let pool = AnyPoolOptions::new().max_connections(max_connections).connect(url).await?;
+
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS request_log (
+                ts_ms BIGINT NOT NULL,
+                request_id TEXT,
+                tenant TEXT,
+                api_key TEXT,
+                cache_status TEXT NOT NULL,
+                model TEXT NOT NULL,
+                latency_ms BIGINT,
+                tokens BIGINT NOT NULL,
+                cost_usd DOUBLE PRECISION NOT NULL
+            )"
+        ).execute(&pool).await?;
+        sqlx::query("CREATE INDEX IF NOT EXISTS request_log_ts ON request_log (ts_ms)").execute(&pool).await?;
+        sqlx::query("CREATE INDEX IF NOT EXISTS request_log_tenant_ts ON request_log (tenant, ts_ms)").execute(&pool).await?;
+
+        Ok(RequestStore { pool })
+
+    }
+
+    pub async fn insert(&self, entry: &RequestLogEntry) -> Result<(), sqlx::Error> {
+
+        let at = DateTime::parse_from_rfc3339(&entry.timestamp)
+            .map(|at| at.with_timezone(&Utc))
+            .unwrap_or_else(|_| Utc::now());
+
+        let mut statement = Statement::new("INSERT INTO request_log (ts_ms, request_id, tenant, api_key, cache_status, model, latency_ms, tokens, cost_usd) VALUES (");
+        statement.bind(at.timestamp_millis()).push(", ")
+            .bind(entry.request_id.clone()).push(", ")
+            .bind(entry.tenant.clone()).push(", ")
+            .bind(entry.api_key.clone()).push(", ")
+            .bind(entry.cache_status.clone()).push(", ")
+            .bind(entry.model.clone()).push(", ")
+            .bind(entry.latency_ms.map(|ms| ms as i64)).push(", ")
+            .bind(entry.tokens as i64).push(", ")
+            .bind(entry.cost_usd).push(")");
+
+        sqlx::query_with(&statement.sql, statement.args).execute(&self.pool).await?;
+        Ok(())
+
+    }
+
+    /// Up to `limit` of the matching requests, newest first
+    pub async fn list(&self, filter: &RequestFilter, limit: usize) -> Result<Vec<RequestRecord>, sqlx::Error> {
+
+        let mut statement = Statement::new(
+            "SELECT ts_ms, request_id, tenant, api_key, cache_status, model, latency_ms, tokens, cost_usd FROM request_log"
+        ).filtered(filter);
+        statement.push(" ORDER BY ts_ms DESC LIMIT ").bind(limit.min(MAX_LIST_LIMIT) as i64);
+
+        let rows = sqlx::query_with(&statement.sql, statement.args).fetch_all(&self.pool).await?;
+        rows.iter().map(|row| {
+            Ok(RequestRecord {
+                timestamp: timestamp(row.try_get("ts_ms")?),
+                request_id: row.try_get("request_id")?,
+                tenant: row.try_get("tenant")?,
+                api_key: row.try_get("api_key")?,
+                cache_status: row.try_get("cache_status")?,
+                model: row.try_get("model")?,
+                latency_ms: row.try_get::<Option<i64>, _>("latency_ms")?.map(|ms| ms as u64),
+                tokens: row.try_get::<i64, _>("tokens")? as u64,
+                cost_usd: row.try_get("cost_usd")?
+            })
+        }).collect()
+
+    }
+
+    /// Totals over the matching requests, one per group when `group_by` is set
+    /// (largest spend first), or a single one otherwise
+    pub async fn totals(&self, filter: &RequestFilter, group_by: Option<GroupBy>) -> Result<Vec<RequestTotals>, sqlx::Error> {
+
+        let group = group_by.map(|group_by| format!("{} AS grp, ", group_by.expression())).unwrap_or_default();
+        let mut statement = Statement::new(&format!(
+            "SELECT {group}CAST(COUNT(*) AS BIGINT) AS requests,
+                CAST(SUM(CASE WHEN cache_status IN ({HIT_STATUSES}) THEN 1 ELSE 0 END) AS BIGINT) AS hits,
+                CAST(SUM(tokens) AS BIGINT) AS tokens,
+                CAST(SUM(CASE WHEN cache_status IN ({SPEND_STATUSES}) THEN cost_usd ELSE 0.0 END) AS DOUBLE PRECISION) AS spent,
+                CAST(SUM(CASE WHEN cache_status IN ({HIT_STATUSES}) THEN cost_usd ELSE 0.0 END) AS DOUBLE PRECISION) AS saved,
+                CAST(AVG(latency_ms) AS DOUBLE PRECISION) AS avg_latency_ms
+            FROM request_log"
+        )).filtered(filter);
+        if let Some(group_by) = group_by {
+            statement.push(&format!(" GROUP BY {} ORDER BY spent DESC", group_by.expression()));
+        }
+
+        let rows = sqlx::query_with(&statement.sql, statement.args).fetch_all(&self.pool).await?;
+        rows.iter().map(|row| totals_of(row, group_by)).collect()
+
+    }
+
+}
+
+fn totals_of(row: &AnyRow, group_by: Option<GroupBy>) -> Result<RequestTotals, sqlx::Error> {
+
+    let group = match group_by {
+        None => None,
+        Some(GroupBy::Day) => Some(timestamp(row.try_get::<i64, _>("grp")? * 86_400_000)[..10].to_string()),
+        // requests without a tenant or key are grouped under "none"
+        Some(_) => Some(row.try_get::<Option<String>, _>("grp")?.unwrap_or_else(|| "none".to_string()))
+    };
+    // SUM over no rows is NULL
+    let count = |column| row.try_get::<Option<i64>, _>(column).map(|value| value.unwrap_or(0) as u64);
+    let amount = |column| row.try_get::<Option<f64>, _>(column).map(|value| value.unwrap_or(0.0));
+
+    Ok(RequestTotals {
+        group,
+        requests: count("requests")?,
+        hits: count("hits")?,
+        tokens: count("tokens")?,
+        cost_spent_usd: amount("spent")?,
+        cost_saved_usd: amount("saved")?,
+        avg_latency_ms: row.try_get::<Option<f64>, _>("avg_latency_ms")?.map(|ms| (ms * 10.0).round() / 10.0)
+    })
+
+}
+
+fn timestamp(ms: i64) -> String {
+    DateTime::from_timestamp_millis(ms).unwrap_or_default().to_rfc3339()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn entry(at: &str, tenant: Option<&str>, cache_status: &str, tokens: u64, cost_usd: f64) -> RequestLogEntry {
+        RequestLogEntry {
+            timestamp: at.to_string(),
+            request_id: Some(uuid::Uuid::new_v4().to_string()),
+            tenant: tenant.map(str::to_string),
+            api_key: None,
+            cache_status: cache_status.to_string(),
+            model: "llama-3.1-8b-instant".to_string(),
+            latency_ms: Some(100),
+            tokens,
+            cost_usd
+        }
+    }
+
+    #[tokio::test]
+    async fn test_filters_and_totals_by_tenant() {
+
+        let store = RequestStore::connect("sqlite::memory:").await.unwrap();
+        for record in [
+            entry("2026-10-05T09:00:00Z", Some("team-a"), "MISS", 100, 0.10),
+            entry("2026-10-12T09:00:00Z", Some("team-a"), "MISS", 200, 0.20),
+            entry("2026-10-13T09:00:00Z", Some("team-a"), "EXACT_HIT", 200, 0.20),
+            entry("2026-10-13T10:00:00Z", Some("team-b"), "MISS", 50, 0.05),
+            entry("2026-10-14T10:00:00Z", None, "REFRESH", 10, 0.01)
+        ] {
+            store.insert(&record).await.unwrap();
+        }
+
+        let last_week = RequestFilter {
+            tenant: Some("team-a".to_string()),
+            from: Some("2026-10-12T00:00:00Z".parse().unwrap()),
+            to: Some("2026-10-19T00:00:00Z".parse().unwrap()),
+            ..RequestFilter::default()
+        };
+        let totals = store.totals(&last_week, None).await.unwrap();
+        assert_eq!((totals[0].requests, totals[0].hits, totals[0].tokens), (2, 1, 400));
+        assert!((totals[0].cost_spent_usd - 0.20).abs() < 1e-9);
+        assert!((totals[0].cost_saved_usd - 0.20).abs() < 1e-9);
+        assert_eq!(totals[0].avg_latency_ms, Some(100.0));
+
+        let listed = store.list(&last_week, 10).await.unwrap();
+        assert_eq!(listed.iter().map(|record| record.cache_status.as_str()).collect::<Vec<_>>(), ["EXACT_HIT", "MISS"]);
+        assert_eq!(listed[0].timestamp, "2026-10-13T09:00:00+00:00");
+
+        let by_tenant = store.totals(&RequestFilter::default(), Some(GroupBy::Tenant)).await.unwrap();
+        let groups: Vec<(&str, u64)> = by_tenant.iter().map(|totals| (totals.group.as_deref().unwrap(), totals.requests)).collect();
+        assert_eq!(groups, [("team-a", 3), ("team-b", 1), ("none", 1)]);
+
+        let by_day = store.totals(&RequestFilter::default(), Some(GroupBy::Day)).await.unwrap();
+        assert!(by_day.iter().any(|totals| totals.group.as_deref() == Some("2026-10-13") && totals.requests == 2));
+
+        let nothing = RequestFilter { model: Some("gpt-4o".to_string()), ..RequestFilter::default() };
+        assert_eq!(store.totals(&nothing, None).await.unwrap()[0], RequestTotals::default());
+
+    }
+
+}
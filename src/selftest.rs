@@ -0,0 +1,277 @@
+// End-to-end probes run by `--check` (print a report and exit) or with
+// SELF_TEST_ON_START=true (refuse to serve when any probe fails). Each
+// probe talks to one dependency the way real traffic would and removes
+// whatever it wrote, so running it against production data is safe.
+
+use std::future::Future;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+use crate::AppState;
+use crate::cache::{EntryParams, get_embedding};
+use crate::client::call_llm;
+use crate::models::{LLMRequest, Message};
+
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+// a cold upstream can take a while even for one token
+const UPSTREAM_PROBE_TIMEOUT: Duration = Duration::from_secs(30);
+
+const CANARY_TEXT: &str = "llm_cache_proxy self-test canary";
+// the cheapest Groq model, unless MODEL_ALLOWLIST rules it out
+const UPSTREAM_PROBE_MODEL: &str = "llama-3.1-8b-instant";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProbeStatus {
+    Pass,
+    Fail,
+    // the tier is disabled, or the probe wasn't requested
+    Skipped
+}
+
+impl ProbeStatus {
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ProbeStatus::Pass => "PASS",
+            ProbeStatus::Fail => "FAIL",
+            ProbeStatus::Skipped => "SKIP"
+        }
+    }
+
+}
+
+#[derive(Debug, Clone)]
+pub struct ProbeResult {
+    pub name: &'static str,
+    pub status: ProbeStatus,
+    pub detail: String,
+    pub elapsed: Duration
+}
+
+impl ProbeResult {
+
+    fn skipped(name: &'static str, detail: &str) -> Self {
+        ProbeResult { name, status: ProbeStatus::Skipped, detail: detail.to_string(), elapsed: Duration::ZERO }
+    }
+
+}
+
+/// Runs every probe in turn. The upstream probe spends one completion token,
+/// so it only runs when `include_upstream` is set
+pub async fn run(state: &AppState, include_upstream: bool) -> Vec<ProbeResult> {
+
+    let mut results = vec![
+        probe_redis(state).await,
+        probe_embedding(state).await,
+        probe_qdrant(state).await
+    ];
+
+    results.push(if include_upstream {
+        probe("upstream", UPSTREAM_PROBE_TIMEOUT, upstream_check(state)).await
+    } else {
+        ProbeResult::skipped("upstream", "pass --check-upstream to make a 1-token call")
+    });
+
+    results
+
+}
+
+pub fn all_passed(results: &[ProbeResult]) -> bool {
+    results.iter().all(|result| result.status != ProbeStatus::Fail)
+}
+
+pub fn print_report(results: &[ProbeResult]) {
+
+    println!("{:<10} {:<6} {:>8}  DETAIL", "PROBE", "STATUS", "TIME");
+    for result in results {
+        println!(
+            "{:<10} {:<6} {:>6}ms  {}",
+            result.name, result.status.as_str(), result.elapsed.as_millis(), result.detail
+        );
+    }
+    println!("Self-test {}", if all_passed(results) { "passed" } else { "FAILED" });
+
+}
+
+// times `check` out after `timeout`, turning its outcome into a row of the report
+async fn probe(name: &'static str, timeout: Duration, check: impl Future<Output = Result<String, String>>) -> ProbeResult {
+
+    let started = Instant::now();
+    let (status, detail) = match tokio::time::timeout(timeout, check).await {
+        Ok(Ok(detail)) => (ProbeStatus::Pass, detail),
+        Ok(Err(e)) => (ProbeStatus::Fail, e),
+        Err(_) => (ProbeStatus::Fail, format!("timed out after {}s", timeout.as_secs()))
+    };
+
+    ProbeResult { name, status, detail, elapsed: started.elapsed() }
+
+}
+
+async fn probe_redis(state: &AppState) -> ProbeResult {
+
+    let Some(redis_cache) = &state.exact_cache else {
+        return ProbeResult::skipped("redis", "exact cache disabled");
+    };
+
+    let key = format!("selftest:{}", Uuid::new_v4());
+    let result = probe("redis", PROBE_TIMEOUT, async {
+        redis_cache.set_with_ttl(&key, CANARY_TEXT, 60).await.map_err(|e| format!("SET failed: {}", e))?;
+        let value = redis_cache.get(&key).await.map_err(|e| format!("GET failed: {}", e))?;
+        if value.as_deref() != Some(CANARY_TEXT) {
+            return Err(format!("GET returned {:?} after SET", value));
+        }
+        Ok("set/get/delete round trip".to_string())
+    }).await;
+
+    // the canary also expires on its own if this fails or the probe timed out
+    let _ = tokio::time::timeout(PROBE_TIMEOUT, redis_cache.delete(&key)).await;
+    result
+
+}
+
+async fn probe_embedding(state: &AppState) -> ProbeResult {
+
+    if state.semantic_cache.is_none() {
+        return ProbeResult::skipped("embedding", "semantic cache disabled");
+    }
+
+    probe("embedding", PROBE_TIMEOUT, async {
+        let embedding = get_embedding(&state.http_client, &state.config.embedding, CANARY_TEXT).await
+            .map_err(|e| format!("{} unreachable: {}", state.config.embedding.url, e))?;
+        if embedding.len() != state.config.embedding_dim {
+            return Err(format!("got {}-dim vectors, EMBEDDING_DIM is {}", embedding.len(), state.config.embedding_dim));
+        }
+        Ok(format!("{}-dim vectors", embedding.len()))
+    }).await
+
+}
+
+// independent of the embedding probe: a synthetic unit vector stands in for a real embedding
+async fn probe_qdrant(state: &AppState) -> ProbeResult {
+
+    let Some(semantic_cache) = &state.semantic_cache else {
+        return ProbeResult::skipped("qdrant", "semantic cache disabled");
+    };
+
+    let dim = state.config.embedding_dim;
+    let collection = semantic_cache.collection_name();
+    let cache_key = format!("selftest:{}", Uuid::new_v4());
+    let vector = vec![1.0 / (dim as f32).sqrt(); dim];
+
+    let result = probe("qdrant", PROBE_TIMEOUT, async {
+        semantic_cache.ensure_collection(&collection, dim).await.map_err(|e| e.to_string())?;
+        let params = EntryParams { model: "selftest".to_string(), temperature: 0.0, max_tokens: None, tenant: None };
+        semantic_cache.store(&cache_key, CANARY_TEXT, vector.clone(), "{}", &params, 0, 60).await
+            .map_err(|e| format!("upsert failed: {}", e))?;
+        let hits = semantic_cache.search_paginated(vector.clone(), 0.99, 10, None, Some(&params)).await
+            .map_err(|e| format!("search failed: {}", e))?;
+        if !hits.iter().any(|hit| hit.cache_key == cache_key) {
+            return Err("probe point not found by search".to_string());
+        }
+        Ok(format!("upsert/search/delete in '{}'", collection))
+    }).await;
+
+    let _ = tokio::time::timeout(PROBE_TIMEOUT, semantic_cache.delete_by_cache_key(&cache_key)).await;
+    result
+
+}
+
+async fn upstream_check(state: &AppState) -> Result<String, String> {
+
+    let models = &state.config.models;
+    let model = models.resolve(UPSTREAM_PROBE_MODEL)
+        .or_else(|e| models.allowlist.as_ref().and_then(|allowed| allowed.first().cloned()).ok_or(e))?;
+
+    let request = LLMRequest {
+        messages: vec![Message { role: "user".to_string(), content: "Reply with OK".into(), ..Default::default() }],
+        model,
+        temperature: Some(0.0),
+        max_tokens: Some(1),
+        ..Default::default()
+    };
+    let model = request.model.clone();
+
+    call_llm(state, request).await
+        .map(|_| format!("1-token completion from {}", model))
+        .map_err(|e| e.to_string())
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::config::Config;
+
+    #[cfg(feature = "mock")]
+    #[tokio::test]
+    async fn test_self_test_passes_and_cleans_up() {
+
+        let state = AppState::new(Config::from_lookup(|_| None).unwrap()).await;
+
+        let results = run(&state, true).await;
+        let statuses: Vec<(&str, ProbeStatus)> = results.iter().map(|r| (r.name, r.status)).collect();
+        assert_eq!(statuses, [
+            ("redis", ProbeStatus::Pass),
+            ("embedding", ProbeStatus::Pass),
+            ("qdrant", ProbeStatus::Pass),
+            ("upstream", ProbeStatus::Pass)
+        ]);
+
+        let redis = state.exact_cache.as_ref().unwrap();
+        assert_eq!(redis.count_keys_matching("selftest:*").await.unwrap(), 0);
+        let qdrant = state.semantic_cache.as_ref().unwrap();
+        assert_eq!(qdrant.collection_points(&qdrant.collection_name()).await.unwrap(), 0);
+
+    }
+
+    #[cfg(feature = "mock")]
+    #[tokio::test]
+    async fn test_self_test_reports_dimension_mismatch() {
+
+        let config = Config::from_lookup(|name| match name {
+            "EMBEDDING_DIM" => Some("768".to_string()),
+            "EXACT_CACHE_ENABLED" => Some("false".to_string()),
+            _ => None
+        }).unwrap();
+        let state = AppState::new(config).await;
+
+        let results = run(&state, false).await;
+        assert_eq!(results[0].status, ProbeStatus::Skipped);
+        assert_eq!(results[1].status, ProbeStatus::Fail);
+        assert!(results[1].detail.contains("EMBEDDING_DIM is 768"), "{}", results[1].detail);
+        assert_eq!(results[3].status, ProbeStatus::Skipped);
+        assert!(!all_passed(&results));
+
+    }
+
+    // a local stub rejects the API key; both cache tiers are off so nothing else is needed
+    #[cfg(not(feature = "mock"))]
+    #[tokio::test]
+    async fn test_self_test_upstream_probe_fails_on_bad_key() {
+
+        use axum::http::StatusCode;
+
+        let upstream = axum::Router::new().route("/chat/completions", axum::routing::post(|| async {
+            (StatusCode::UNAUTHORIZED, r#"{"error": {"message": "Invalid API Key"}}"#)
+        }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, upstream).await });
+
+        let config = Config::from_lookup(|name| match name {
+            "GROQ_API_KEY" => Some("bad-key".to_string()),
+            "UPSTREAM_BASE_URL" => Some(base_url.clone()),
+            "EXACT_CACHE_ENABLED" | "SEMANTIC_CACHE_ENABLED" => Some("false".to_string()),
+            _ => None
+        }).unwrap();
+        let state = AppState::new(config).await;
+
+        assert!(all_passed(&run(&state, false).await), "Skipped probes shouldn't fail the run");
+
+        let results = run(&state, true).await;
+        assert_eq!(results[3].status, ProbeStatus::Fail);
+        assert!(results[3].detail.contains("Invalid API Key"), "{}", results[3].detail);
+
+    }
+
+}
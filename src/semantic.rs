@@ -0,0 +1,197 @@
+// Storage behind the semantic tier. `SemanticStore` is everything the proxy
+// asks of a vector store: `QdrantCache` (src/cache.rs) is the production
+// store and the `mock` feature swaps in `MockQdrantCache`. Another store
+// (pgvector, Milvus, an in-process HNSW index) implements this trait and is
+// built in `AppState::new`; handlers only ever see `SemanticCache`.
+
+use std::sync::Arc;
+use async_trait::async_trait;
+use crate::cache::{
+    CacheError, CollectionStats, CollectionValidation, EntryParams, QdrantPoolStats, QdrantUsage, QuarantinedEntry, SemanticHit, StoredPoint
+};
+
+/// The semantic tier as stored in `AppState`
+pub type SemanticCache = Arc<dyn SemanticStore>;
+
+#[async_trait]
+pub trait SemanticStore: Send + Sync {
+
+    /// Identifies the store, like `CacheBackend::name`
+    fn name(&self) -> &'static str;
+
+    /// Adds a point for `prompt` with the cached response as its payload,
+    /// expiring `ttl` seconds from now like its exact-match entry
+    #[allow(clippy::too_many_arguments)]
+    async fn store(
+        &self,
+        cache_key: &str,
+        prompt: &str,
+        embedding: Vec<f32>,
+        cached_response: &str,
+        params: &EntryParams,
+        original_latency_ms: u64,
+        ttl: u64
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+
+    /// Overwrites a stored point's response in place, keeping its prompt and
+    /// vector, and restarts its expiry. Used instead of `store` when a
+    /// near-identical prompt is already cached
+    async fn refresh_point(
+        &self,
+        point_id: &str,
+        cache_key: &str,
+        cached_response: &str,
+        params: &EntryParams,
+        original_latency_ms: u64,
+        ttl: u64
+    ) -> Result<(), CacheError>;
+
+    /// The closest match above the threshold that was cached with the same
+    /// model and max_tokens at a compatible temperature
+    async fn search_similar(
+        &self,
+        embedding: Vec<f32>,
+        similarity_threshold: f32,
+        params: &EntryParams
+    ) -> Result<Option<SemanticHit>, Box<dyn std::error::Error + Send + Sync>> {
+
+        let hits = self.search_paginated(embedding, similarity_threshold, 1, None, Some(params)).await?;
+
+        Ok(hits.into_iter()
+            .next()
+            .filter(|hit| params.matches(hit)))
+
+    }
+
+    /// Up to `limit` matches above the threshold, best first, skipping the
+    /// first `offset`. With `params`, only points those parameters may be
+    /// served from. Quarantined and expired points are never returned
+    async fn search_paginated(
+        &self,
+        embedding: Vec<f32>,
+        similarity_threshold: f32,
+        limit: usize,
+        offset: Option<u64>,
+        params: Option<&EntryParams>
+    ) -> Result<Vec<SemanticHit>, CacheError>;
+
+    async fn health_check(&self) -> bool;
+
+    /// Deletes every point stored under `cache_key`
+    async fn delete_by_cache_key(&self, cache_key: &str) -> Result<(), CacheError>;
+
+    /// Drops the active collection and creates it again empty, with the same vector size
+    async fn recreate_collection(&self) -> Result<(), CacheError>;
+
+    /// Deletes the points with these ids
+    async fn delete_points(&self, point_ids: &[String]) -> Result<(), CacheError>;
+
+    /// Deletes every point cached for `model`
+    async fn delete_by_model(&self, model: &str) -> Result<(), CacheError>;
+
+    /// Flags the points stored under `cache_key` so searches skip them,
+    /// keeping them for later analysis
+    async fn quarantine_by_cache_key(&self, cache_key: &str, reason: Option<&str>) -> Result<(), CacheError>;
+
+    /// Up to `limit` quarantined points with their prompt, response and reason
+    async fn quarantined(&self, limit: u32) -> Result<Vec<QuarantinedEntry>, CacheError>;
+
+    async fn purge_quarantined(&self) -> Result<(), CacheError>;
+
+    /// Deletes every point whose `expires_at` has passed. Points stored
+    /// before expiry was tracked don't have one and are kept
+    async fn delete_expired(&self) -> Result<(), CacheError>;
+
+    /// Deletes the points closest to expiring until at most `max_points`
+    /// remain in the active collection, returning how many were deleted
+    async fn trim_to(&self, max_points: u64) -> Result<u64, CacheError>;
+
+    /// Point count plus vector and payload size estimates
+    async fn usage(&self) -> Result<QdrantUsage, CacheError>;
+
+    /// The `original_latency_ms` of every point in the active collection
+    async fn original_latencies(&self) -> Result<Vec<u64>, CacheError>;
+
+    /// The collection currently being read and written
+    fn collection_name(&self) -> Arc<String>;
+
+    /// Points the store at another, existing collection holding
+    /// `embedding_dim`-sized vectors
+    fn switch_collection(&self, collection: &str, embedding_dim: usize);
+
+    async fn collection_stats(&self) -> Result<CollectionStats, CacheError>;
+
+    /// The last counts `collection_stats` fetched, however old, without a round trip
+    fn cached_collection_stats(&self) -> Option<CollectionStats>;
+
+    /// Creates `collection` with `dim`-sized vectors unless it already exists.
+    /// An existing collection of a different size is an error
+    async fn ensure_collection(&self, collection: &str, dim: usize) -> Result<(), CacheError>;
+
+    async fn collection_points(&self, collection: &str) -> Result<u64, CacheError>;
+
+    /// One page of points from `collection` starting at `offset`, along with
+    /// the offset of the next page (None once the end is reached)
+    async fn scroll_points(
+        &self,
+        collection: &str,
+        offset: Option<&str>,
+        limit: u32
+    ) -> Result<(Vec<StoredPoint>, Option<String>), CacheError>;
+
+    /// Writes points into `collection` under their existing ids, overwriting
+    /// rather than duplicating
+    async fn upsert_points(&self, collection: &str, points: Vec<(StoredPoint, Vec<f32>)>) -> Result<(), CacheError>;
+
+    /// What startup found when checking the collection
+    fn validation(&self) -> &CollectionValidation;
+
+    fn pool_stats(&self) -> QdrantPoolStats;
+
+}
+
+#[cfg(all(test, feature = "mock"))]
+mod tests {
+
+    use super::*;
+    use crate::mock::{MockQdrantCache, fake_embedding};
+    use crate::test_helpers::test_params;
+
+    #[tokio::test]
+    async fn test_search_similar_skips_incompatible_temperatures() {
+
+        let store: SemanticCache = Arc::new(MockQdrantCache::new("").await.unwrap());
+        store.store("key-a", "What is Rust?", fake_embedding("What is Rust?"), "{}", &test_params(0.2), 10, 3600).await.unwrap();
+
+        let hit = store.search_similar(fake_embedding("what is rust"), 0.9, &test_params(0.2)).await.unwrap();
+        assert_eq!(hit.map(|hit| hit.cache_key).as_deref(), Some("key-a"));
+
+        let hit = store.search_similar(fake_embedding("what is rust"), 0.9, &test_params(0.9)).await.unwrap();
+        assert!(hit.is_none(), "A response cached at 0.2 shouldn't be served at 0.9");
+
+    }
+
+    #[tokio::test]
+    async fn test_search_similar_matches_model_and_max_tokens() {
+
+        let store: SemanticCache = Arc::new(MockQdrantCache::new("").await.unwrap());
+        let capped = EntryParams { max_tokens: Some(100), ..test_params(0.0) };
+        store.store("key-a", "What is Rust?", fake_embedding("What is Rust?"), "{}", &test_params(0.0), 10, 3600).await.unwrap();
+        store.store("key-b", "What is Rust?", fake_embedding("What is Rust?"), "{}", &capped, 10, 3600).await.unwrap();
+
+        let found = |params: EntryParams| {
+            let store = store.clone();
+            async move { store.search_similar(fake_embedding("what is rust"), 0.9, &params).await.unwrap().map(|hit| hit.cache_key) }
+        };
+        assert_eq!(found(test_params(0.0)).await.as_deref(), Some("key-a"));
+        assert_eq!(found(capped.clone()).await.as_deref(), Some("key-b"));
+        assert_eq!(found(EntryParams { max_tokens: Some(50), ..capped }).await, None);
+        assert_eq!(found(EntryParams { model: "llama-3.3-70b-versatile".to_string(), ..test_params(0.0) }).await, None);
+
+        // neighbours for /explain come back whatever their parameters
+        let all = store.search_paginated(fake_embedding("what is rust"), 0.9, 10, None, None).await.unwrap();
+        assert_eq!(all.len(), 2);
+
+    }
+
+}
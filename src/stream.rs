@@ -0,0 +1,357 @@
+// Server-sent events for `"stream": true` requests. On a miss the upstream's
+// events are relayed to the client while `StreamAssembler` rebuilds the full
+// completion for the cache; on a hit `replay` turns the cached completion
+// back into the chunks an upstream stream would have sent.
+
+use std::collections::BTreeMap;
+use axum::body::Bytes;
+use serde_json::{json, Value};
+use crate::models::{Choice, LLMResponse, Message, ToolCall, Usage};
+
+const DONE: &str = "[DONE]";
+
+// characters of cached content sent per replayed chunk
+const REPLAY_CHUNK_CHARS: usize = 64;
+
+/// One `data:` payload of an SSE stream
+#[derive(Debug, Clone, PartialEq)]
+pub enum SseEvent {
+    Chunk(Value),
+    Done
+}
+
+impl SseEvent {
+
+    /// Encodes the event for the client, with `model` in place of the
+    /// upstream's model name when set (PRESERVE_CLIENT_MODEL_NAME)
+    pub fn encode(&self, model: Option<&str>) -> Bytes {
+        match self {
+            SseEvent::Chunk(chunk) => {
+                let mut chunk = chunk.clone();
+                if let Some(model) = model {
+                    chunk["model"] = json!(model);
+                }
+                Bytes::from(format!("data: {}\n\n", chunk))
+            }
+            SseEvent::Done => Bytes::from(format!("data: {}\n\n", DONE))
+        }
+    }
+
+}
+
+/// Splits an SSE byte stream into events. Network chunks can end mid-line
+/// (or mid-character), so the unfinished tail is kept for the next call
+#[derive(Debug, Default)]
+pub struct SseParser {
+    buffer: Vec<u8>
+}
+
+impl SseParser {
+
+    pub fn feed(&mut self, bytes: &[u8]) -> Vec<SseEvent> {
+
+        self.buffer.extend_from_slice(bytes);
+
+        let mut events = Vec::new();
+        while let Some(end) = self.buffer.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+
+            // comments (": keep-alive"), event names and blank separators carry no data
+            let Some(data) = line.trim_end().strip_prefix("data:") else {
+                continue;
+            };
+            let data = data.trim_start();
+            if data == DONE {
+                events.push(SseEvent::Done);
+            } else if let Ok(chunk) = serde_json::from_str(data) {
+                events.push(SseEvent::Chunk(chunk));
+            }
+        }
+
+        events
+
+    }
+
+}
+
+#[derive(Debug, Default)]
+struct AssembledChoice {
+    role: String,
+    content: String,
+    // by the index the deltas give; arguments arrive in fragments
+    tool_calls: BTreeMap<i64, ToolCall>,
+    finish_reason: Option<String>
+}
+
+/// Rebuilds a `chat.completion` from `chat.completion.chunk` events
+#[derive(Debug, Default)]
+pub struct StreamAssembler {
+    id: String,
+    created: i64,
+    model: String,
+    choices: BTreeMap<i64, AssembledChoice>,
+    usage: Option<Usage>,
+    done: bool
+}
+
+impl StreamAssembler {
+
+    pub fn apply(&mut self, event: &SseEvent) {
+
+        let chunk = match event {
+            SseEvent::Chunk(chunk) => chunk,
+            SseEvent::Done => {
+                self.done = true;
+                return;
+            }
+        };
+
+        if self.id.is_empty() && let Some(id) = chunk["id"].as_str() {
+            self.id = id.to_string();
+        }
+        if self.model.is_empty() && let Some(model) = chunk["model"].as_str() {
+            self.model = model.to_string();
+        }
+        if self.created == 0 && let Some(created) = chunk["created"].as_i64() {
+            self.created = created;
+        }
+
+        for choice in chunk["choices"].as_array().into_iter().flatten() {
+            let assembled = self.choices.entry(choice["index"].as_i64().unwrap_or(0)).or_default();
+            let delta = &choice["delta"];
+            if let Some(role) = delta["role"].as_str() {
+                assembled.role = role.to_string();
+            }
+            if let Some(content) = delta["content"].as_str() {
+                assembled.content.push_str(content);
+            }
+            for call in delta["tool_calls"].as_array().into_iter().flatten() {
+                let tool_call = assembled.tool_calls.entry(call["index"].as_i64().unwrap_or(0)).or_default();
+                if let Some(id) = call["id"].as_str() {
+                    tool_call.id = id.to_string();
+                }
+                if let Some(kind) = call["type"].as_str() {
+                    tool_call.kind = kind.to_string();
+                }
+                if let Some(name) = call["function"]["name"].as_str() {
+                    tool_call.function.name.push_str(name);
+                }
+                if let Some(arguments) = call["function"]["arguments"].as_str() {
+                    tool_call.function.arguments.push_str(arguments);
+                }
+            }
+            if let Some(finish_reason) = choice["finish_reason"].as_str() {
+                assembled.finish_reason = Some(finish_reason.to_string());
+            }
+        }
+
+        // OpenAI sends usage in a final chunk (with stream_options.include_usage),
+        // Groq under x_groq on the last content chunk
+        let usage = [&chunk["usage"], &chunk["x_groq"]["usage"]].into_iter()
+            .find(|usage| usage.is_object())
+            .and_then(|usage| serde_json::from_value(usage.clone()).ok());
+        if usage.is_some() {
+            self.usage = usage;
+        }
+
+    }
+
+    /// The completion the stream described, or `None` if it ended before
+    /// `[DONE]` or never produced a choice - a cut-off stream isn't cached
+    pub fn finish(self) -> Option<LLMResponse> {
+
+        if !self.done || self.choices.is_empty() {
+            return None;
+        }
+
+        let choices = self.choices.into_iter()
+            .map(|(index, choice)| Choice {
+                message: Message {
+                    role: if choice.role.is_empty() { "assistant".to_string() } else { choice.role },
+                    content: choice.content.into(),
+                    tool_calls: (!choice.tool_calls.is_empty()).then(|| choice.tool_calls.into_values()
+                        .map(|mut call| {
+                            if call.kind.is_empty() {
+                                call.kind = "function".to_string();
+                            }
+                            call
+                        })
+                        .collect()),
+                    ..Default::default()
+                },
+                index: index as i32,
+                finish_reason: choice.finish_reason,
+                extra: None
+            })
+            .collect();
+
+        Some(LLMResponse {
+            id: self.id,
+            object: "chat.completion".to_string(),
+            created: self.created,
+            model: self.model,
+            choices,
+            usage: self.usage.unwrap_or_default(),
+            extra: None
+        })
+
+    }
+
+}
+
+/// The events an upstream stream would have sent for `response`: the role,
+/// the content in `REPLAY_CHUNK_CHARS` pieces, any tool calls whole and the
+/// finish reason for each choice, then a usage chunk and `[DONE]`
+pub fn replay(response: &LLMResponse) -> Vec<SseEvent> {
+
+    let chunk = |choices: Value| json!({
+        "id": response.id,
+        "object": "chat.completion.chunk",
+        "created": response.created,
+        "model": response.model,
+        "choices": choices
+    });
+    let delta = |index: i32, delta: Value, finish_reason: Option<&str>| json!([{
+        "index": index,
+        "delta": delta,
+        "finish_reason": finish_reason
+    }]);
+
+    let mut events = Vec::new();
+    for choice in &response.choices {
+        events.push(SseEvent::Chunk(chunk(delta(choice.index, json!({"role": choice.message.role, "content": ""}), None))));
+
+        let content: Vec<char> = choice.message.content.as_text().chars().collect();
+        for piece in content.chunks(REPLAY_CHUNK_CHARS) {
+            let piece: String = piece.iter().collect();
+            events.push(SseEvent::Chunk(chunk(delta(choice.index, json!({"content": piece}), None))));
+        }
+
+        if let Some(tool_calls) = &choice.message.tool_calls {
+            let tool_calls: Vec<Value> = tool_calls.iter().enumerate()
+                .map(|(index, call)| {
+                    let mut call = json!(call);
+                    call["index"] = json!(index);
+                    call
+                })
+                .collect();
+            events.push(SseEvent::Chunk(chunk(delta(choice.index, json!({"tool_calls": tool_calls}), None))));
+        }
+
+        let finish_reason = choice.finish_reason.as_deref().unwrap_or("stop");
+        events.push(SseEvent::Chunk(chunk(delta(choice.index, json!({}), Some(finish_reason)))));
+    }
+
+    let mut usage = chunk(json!([]));
+    usage["usage"] = json!(response.usage);
+    events.push(SseEvent::Chunk(usage));
+    events.push(SseEvent::Done);
+
+    events
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::test_helpers::test_llm_response;
+
+    #[test]
+    fn test_parser_handles_split_lines_and_comments() {
+
+        let mut parser = SseParser::default();
+
+        let stream = "data: {\"id\":\"a\"}\n\n: keep-alive\n\ndata: {\"id\":\"é\"}\n\ndata: [DONE]\n\n".as_bytes();
+        // split inside the two-byte 'é'
+        let split = stream.iter().position(|b| *b == 0xc3).unwrap() + 1;
+
+        let mut events = parser.feed(&stream[..split]);
+        assert_eq!(events, [SseEvent::Chunk(json!({"id": "a"}))]);
+        events = parser.feed(&stream[split..]);
+        assert_eq!(events, [SseEvent::Chunk(json!({"id": "é"})), SseEvent::Done]);
+
+    }
+
+    #[test]
+    fn test_replay_reassembles_to_the_cached_response() {
+
+        let mut response = test_llm_response();
+        response.choices[0].message.content = "A long answer. ".repeat(20).into();
+
+        let mut parser = SseParser::default();
+        let mut assembler = StreamAssembler::default();
+        let replayed: Vec<u8> = replay(&response).iter().flat_map(|event| event.encode(None)).collect();
+        for event in parser.feed(&replayed) {
+            assembler.apply(&event);
+        }
+
+        let rebuilt = assembler.finish().unwrap();
+        assert_eq!(rebuilt.id, response.id);
+        assert_eq!(rebuilt.choices[0].message.content, response.choices[0].message.content);
+        assert_eq!(rebuilt.choices[0].finish_reason, response.choices[0].finish_reason);
+        assert_eq!(rebuilt.usage.total_tokens, response.usage.total_tokens);
+
+    }
+
+    #[test]
+    fn test_assembler_reads_groq_usage_and_rejects_cut_off_streams() {
+
+        let chunk = SseEvent::Chunk(json!({
+            "id": "chatcmpl-1", "model": "llama-3.1-8b-instant", "created": 1,
+            "choices": [{"index": 0, "delta": {"role": "assistant", "content": "Hi"}, "finish_reason": "stop"}],
+            "x_groq": {"usage": {"prompt_tokens": 5, "completion_tokens": 1, "total_tokens": 6}}
+        }));
+
+        let mut cut_off = StreamAssembler::default();
+        cut_off.apply(&chunk);
+        assert!(cut_off.finish().is_none(), "A stream without [DONE] shouldn't be cached");
+
+        let mut assembler = StreamAssembler::default();
+        assembler.apply(&chunk);
+        assembler.apply(&SseEvent::Done);
+        let response = assembler.finish().unwrap();
+        assert_eq!(response.usage.total_tokens, 6);
+        assert_eq!(response.choices[0].message.content.as_text(), "Hi");
+
+    }
+
+    #[test]
+    fn test_tool_call_deltas_assemble_and_replay() {
+
+        let delta = |delta: Value, finish_reason: Option<&str>| SseEvent::Chunk(json!({
+            "id": "chatcmpl-1", "model": "gpt-4o", "created": 1,
+            "choices": [{"index": 0, "delta": delta, "finish_reason": finish_reason}]
+        }));
+
+        let mut assembler = StreamAssembler::default();
+        for event in [
+            delta(json!({"role": "assistant", "content": null, "tool_calls": [
+                {"index": 0, "id": "call_1", "type": "function", "function": {"name": "get_weather", "arguments": ""}}
+            ]}), None),
+            delta(json!({"tool_calls": [{"index": 0, "function": {"arguments": "{\"city\":"}}]}), None),
+            delta(json!({"tool_calls": [{"index": 0, "function": {"arguments": "\"Paris\"}"}}]}), None),
+            delta(json!({}), Some("tool_calls")),
+            SseEvent::Done
+        ] {
+            assembler.apply(&event);
+        }
+
+        let response = assembler.finish().unwrap();
+        let tool_calls = response.choices[0].message.tool_calls.clone().unwrap();
+        assert_eq!(tool_calls.len(), 1);
+        assert_eq!((tool_calls[0].id.as_str(), tool_calls[0].kind.as_str()), ("call_1", "function"));
+        assert_eq!(tool_calls[0].function.name, "get_weather");
+        assert_eq!(tool_calls[0].function.arguments, "{\"city\":\"Paris\"}");
+        assert_eq!(response.choices[0].finish_reason.as_deref(), Some("tool_calls"));
+
+        let mut replayed = StreamAssembler::default();
+        for event in replay(&response) {
+            replayed.apply(&event);
+        }
+        assert_eq!(replayed.finish().unwrap().choices[0].message.tool_calls, Some(tool_calls));
+
+    }
+
+}
@@ -0,0 +1,60 @@
+// Shared fixtures for unit tests. Every helper returns a valid, fully-populated
+// value so tests only spell out the fields they care about, e.g.
+// `LLMRequest { temperature: Some(1.2), ..test_llm_request() }`.
+
+use crate::cache::EntryParams;
+use crate::models::{Choice, LLMRequest, LLMResponse, Message, Usage};
+
+pub fn user_message(content: &str) -> Message {
+    Message { role: "user".to_string(), content: content.into(), ..Default::default() }
+}
+
+pub fn test_llm_request() -> LLMRequest {
+    LLMRequest {
+        messages: vec![user_message("What is Rust?")],
+        model: "gpt-4".to_string(),
+        temperature: Some(0.7),
+        max_tokens: None,
+        ..Default::default()
+    }
+}
+
+/// What a point stored for `test_llm_request()` at `temperature` carries
+pub fn test_params(temperature: f32) -> EntryParams {
+    EntryParams { model: "gpt-4".to_string(), temperature, max_tokens: None, tenant: None }
+}
+
+pub fn test_llm_response() -> LLMResponse {
+    LLMResponse {
+        id: "chatcmpl-test".to_string(),
+        object: "chat.completion".to_string(),
+        created: 1735689600,
+        model: "gpt-4".to_string(),
+        choices: vec![Choice {
+            message: Message {
+                role: "assistant".to_string(),
+                content: "Rust is a systems programming language.".into(),
+                ..Default::default()
+            },
+            index: 0,
+            finish_reason: Some("stop".to_string()),
+            extra: None
+        }],
+        usage: Usage {
+            prompt_tokens: 12,
+            completion_tokens: 8,
+            total_tokens: 20,
+            extra: None
+        },
+        extra: None
+    }
+}
+
+/// Deterministic unit-length vector of `dim` dimensions
+pub fn test_embedding(dim: usize) -> Vec<f32> {
+
+    let embedding: Vec<f32> = (0..dim).map(|i| ((i + 1) as f32).sin()).collect();
+    let norm = embedding.iter().map(|x| x * x).sum::<f32>().sqrt();
+    embedding.into_iter().map(|x| x / norm).collect()
+
+}